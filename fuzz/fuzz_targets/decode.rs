@@ -0,0 +1,29 @@
+#![no_main]
+
+use arbitrary::Unstructured;
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryFrom;
+
+use yars_lib::instruction::Instruction;
+use yars_lib::memory::Memory;
+use yars_lib::processor::Processor;
+
+fuzz_target!(|data: &[u8]| {
+    let mut processor = Processor::<u32>::new(Memory::new(0x1000));
+
+    // Decoding and executing any word must never panic
+    for chunk in data.chunks_exact(4) {
+        let raw = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        if let Ok(inst) = Instruction::try_from(raw) {
+            assert_eq!(Instruction::try_from(u32::from(inst)), Ok(inst));
+            let _ = processor.execute(inst);
+        }
+    }
+
+    // Every generated instruction must survive an encode/decode round trip
+    let mut u = Unstructured::new(data);
+    while !u.is_empty() {
+        let inst = Instruction::random(|| u.arbitrary().unwrap_or(0));
+        assert_eq!(Instruction::try_from(u32::from(inst)), Ok(inst));
+    }
+});
@@ -0,0 +1,52 @@
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use yars_lib::cfg::ControlFlowGraph;
+use yars_lib::symbol::SymbolTable;
+
+/// Writes the basic-block and branch coverage of `cfg` as CSV, or as JSON
+/// if `path` ends in `.json`.
+pub fn write(cfg: &ControlFlowGraph, symbols: &SymbolTable, path: &Path) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => write_json(cfg, symbols, file),
+        _ => cfg.write_coverage_csv(symbols, file),
+    }
+}
+
+fn write_json<W: Write>(
+    cfg: &ControlFlowGraph,
+    symbols: &SymbolTable,
+    mut writer: W,
+) -> io::Result<()> {
+    let symbol = |address| symbols.lookup(address).map(|sym| sym.to_string());
+    let blocks = cfg
+        .blocks()
+        .iter()
+        .map(|block| {
+            json!({
+                "start": block.start,
+                "end": block.last() + 4,
+                "symbol": symbol(block.start),
+                "count": block.count,
+            })
+        })
+        .collect::<Vec<_>>();
+    let branches = cfg
+        .branches()
+        .iter()
+        .map(|branch| {
+            json!({
+                "pc": branch.pc,
+                "symbol": symbol(branch.pc),
+                "taken": branch.taken,
+                "not_taken": branch.not_taken,
+            })
+        })
+        .collect::<Vec<_>>();
+    let report = json!({ "blocks": blocks, "branches": branches });
+    serde_json::to_writer_pretty(&mut writer, &report)?;
+    writeln!(writer)
+}
@@ -0,0 +1,409 @@
+//! Checks `yars run` makes around every instruction. Each one is enabled by
+//! its own options, and any of them can stop the run.
+
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use yars_lib::assertion::Assertions;
+use yars_lib::canary::StackCanary;
+use yars_lib::device::Console;
+use yars_lib::fault::Fault;
+use yars_lib::instruction::{Category, Instruction};
+use yars_lib::printf::PrintfDecoder;
+use yars_lib::processor::RegisterWrite;
+use yars_lib::register::{IntRegister, IntRegisterSet};
+use yars_lib::shadow::ShadowStack;
+use yars_lib::simulator::Simulator;
+
+use crate::console::ConsoleInput;
+use crate::run::Outcome;
+
+const PROGRESS_PERIOD: Duration = Duration::from_millis(250);
+
+/// An instruction that just retired.
+pub struct Retired<'a> {
+    pub pc: u32,
+    pub inst: &'a Instruction,
+    /// Registers before the instruction executed.
+    pub before: &'a IntRegisterSet,
+    /// Whether it is an `ebreak` stopping at a breakpoint.
+    pub breakpoint: bool,
+    /// Whether the program stops after it, through a hang or `tohost`.
+    pub last: bool,
+}
+
+pub trait Checker<W: Write> {
+    /// Called before each instruction, with the instructions retired so far.
+    fn before_step(&mut self, _sim: &mut Simulator<W>, _instructions: u64) -> Option<Outcome> {
+        None
+    }
+
+    /// Called after each instruction that retired without the program
+    /// exiting or faulting on it.
+    fn after_step(&mut self, _sim: &mut Simulator<W>, _retired: &Retired) -> Option<Outcome> {
+        None
+    }
+
+    /// Why the run stopped, if this checker stopped it.
+    fn violation(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Passes host input to the guest console and copies its output back.
+pub struct ConsoleIo {
+    console: Console,
+    input: Option<ConsoleInput>,
+}
+
+impl ConsoleIo {
+    pub fn new(console: Console, input: Option<ConsoleInput>) -> Self {
+        Self { console, input }
+    }
+}
+
+impl<W: Write> Checker<W> for ConsoleIo {
+    fn before_step(&mut self, sim: &mut Simulator<W>, _instructions: u64) -> Option<Outcome> {
+        if let Some(input) = &self.input {
+            input.deliver(sim);
+        }
+        None
+    }
+
+    fn after_step(&mut self, _sim: &mut Simulator<W>, retired: &Retired) -> Option<Outcome> {
+        if retired.inst.category() == Category::Store {
+            write_console(&self.console);
+        }
+        None
+    }
+}
+
+/// Copies what the guest wrote to the console to stdout and stderr.
+fn write_console(console: &Console) {
+    let output = console.take_output();
+    if !output.is_empty() {
+        let mut stdout = io::stdout();
+        stdout.write_all(&output).unwrap();
+        stdout.flush().unwrap();
+    }
+    let errors = console.take_errors();
+    if !errors.is_empty() {
+        io::stderr().write_all(&errors).unwrap();
+    }
+}
+
+/// Stops the run once an assertion of `--assertions` fails.
+pub struct Assert {
+    assertions: Assertions,
+    violation: Option<String>,
+}
+
+impl Assert {
+    pub fn new(assertions: Assertions) -> Self {
+        Self {
+            assertions,
+            violation: None,
+        }
+    }
+}
+
+impl<W: Write> Checker<W> for Assert {
+    fn before_step(&mut self, sim: &mut Simulator<W>, _instructions: u64) -> Option<Outcome> {
+        let failed = self.assertions.check(sim.processor())?;
+        self.violation = Some(failed.describe(sim.symbols()));
+        Some(Outcome::Assertion)
+    }
+
+    fn violation(&self) -> Option<String> {
+        self.violation.clone()
+    }
+}
+
+/// Keeps a status line with the PC and execution speed on stderr.
+pub struct Progress {
+    start: Instant,
+    last_update: Instant,
+}
+
+impl Progress {
+    pub fn new(start: Instant) -> Self {
+        Self {
+            start,
+            last_update: start,
+        }
+    }
+}
+
+impl<W: Write> Checker<W> for Progress {
+    fn before_step(&mut self, sim: &mut Simulator<W>, instructions: u64) -> Option<Outcome> {
+        // Gated on the instructions, as jitter can skip any multiple of cycles
+        if instructions.is_multiple_of(4096) && self.last_update.elapsed() >= PROGRESS_PERIOD {
+            let elapsed = self.start.elapsed();
+            let mips = instructions as f64 / elapsed.as_secs_f64() / 1e6;
+            eprint!(
+                "\r\x1b[2K[PC={:08X}] {} instructions, {:.1} MIPS, {:.1}s",
+                sim.pc(),
+                instructions,
+                mips,
+                elapsed.as_secs_f64()
+            );
+            self.last_update = Instant::now();
+        }
+        None
+    }
+}
+
+/// Injects the faults of `--inject` once they are due.
+pub struct Inject(pub Vec<Fault>);
+
+impl<W: Write> Checker<W> for Inject {
+    fn before_step(&mut self, sim: &mut Simulator<W>, instructions: u64) -> Option<Outcome> {
+        for fault in self.0.iter().filter(|f| f.instret == instructions) {
+            if let Err(err) = sim.inject(fault) {
+                eprintln!("warning: cannot inject {}: {}", fault, err);
+            }
+        }
+        None
+    }
+}
+
+/// Prints the text formatted by calls to `printf` and `puts`.
+pub struct Printf(pub PrintfDecoder);
+
+impl<W: Write> Checker<W> for Printf {
+    fn after_step(&mut self, sim: &mut Simulator<W>, retired: &Retired) -> Option<Outcome> {
+        let memory = sim.processor().memory();
+        if let Some(text) = self.0.check(retired.pc, retired.before, memory) {
+            let mut stdout = io::stdout();
+            stdout.write_all(&text).unwrap();
+            stdout.flush().unwrap();
+        }
+        None
+    }
+}
+
+/// Reports returns to another address than the matching call left in `ra`.
+pub struct Shadow(pub ShadowStack);
+
+impl<W: Write> Checker<W> for Shadow {
+    fn after_step(&mut self, sim: &mut Simulator<W>, retired: &Retired) -> Option<Outcome> {
+        let mismatch = self
+            .0
+            .check(retired.pc, retired.inst, retired.before, sim.pc());
+        if let Some(mismatch) = mismatch {
+            eprintln!(
+                "Return address mismatch: {}",
+                mismatch.describe(sim.symbols())
+            );
+        }
+        None
+    }
+}
+
+/// Stops the run once the stack grows past `--stack-limit`.
+pub struct Canary {
+    canary: StackCanary,
+    violation: Option<String>,
+}
+
+impl Canary {
+    pub fn new(canary: StackCanary) -> Self {
+        Self {
+            canary,
+            violation: None,
+        }
+    }
+}
+
+impl<W: Write> Checker<W> for Canary {
+    fn after_step(&mut self, sim: &mut Simulator<W>, retired: &Retired) -> Option<Outcome> {
+        let sp = sim.read_reg(IntRegister::SP);
+        let memory = sim.processor().memory();
+        let overflow = self.canary.check(retired.pc, retired.inst, sp, memory)?;
+        self.violation = Some(overflow.describe(sim.symbols()));
+        Some(Outcome::StackOverflow)
+    }
+
+    fn violation(&self) -> Option<String> {
+        self.violation.clone()
+    }
+}
+
+/// Saves a snapshot to `path` every `every` retired instructions.
+pub struct Snapshots {
+    pub every: usize,
+    pub path: PathBuf,
+}
+
+impl<W: Write> Checker<W> for Snapshots {
+    fn after_step(&mut self, sim: &mut Simulator<W>, retired: &Retired) -> Option<Outcome> {
+        if !retired.last && sim.processor().instret().is_multiple_of(self.every) {
+            sim.save_snapshot(&self.path).unwrap();
+        }
+        None
+    }
+}
+
+/// Shows the registers and the top of the stack after each instruction
+/// while stepping, and waits for a line of `input`: `c` continues to the
+/// next breakpoint.
+pub struct Stepper<R> {
+    input: R,
+    stepping: bool,
+    numeric: bool,
+}
+
+impl<R: BufRead> Stepper<R> {
+    /// Starts stepping from the first instruction if `stepping` is set, or
+    /// else at the first breakpoint.
+    pub fn new(input: R, stepping: bool, numeric: bool) -> Self {
+        Self {
+            input,
+            stepping,
+            numeric,
+        }
+    }
+}
+
+impl<W: Write, R: BufRead> Checker<W> for Stepper<R> {
+    fn after_step(&mut self, sim: &mut Simulator<W>, retired: &Retired) -> Option<Outcome> {
+        self.stepping |= retired.breakpoint;
+        if !self.stepping || retired.last {
+            return None;
+        }
+        let registers = sim.processor().registers();
+        print_registers(registers, sim.processor().last_write(), self.numeric);
+        let sp = sim.read_reg(IntRegister::SP);
+        let memory = sim.processor().memory();
+        print!(
+            "{}",
+            memory
+                .hexdump(sp..sp.saturating_add(32))
+                .with_symbols(sim.symbols())
+        );
+        let mut command = String::new();
+        let read = self.input.read_line(&mut command).unwrap();
+        if read == 0 || command.trim() == "c" {
+            self.stepping = false;
+        }
+        None
+    }
+}
+
+/// Prints the register file with the register written by the last
+/// instruction highlighted, followed by its old and new values. Registers
+/// are named by number if `numeric` is set.
+fn print_registers(registers: &IntRegisterSet, write: Option<RegisterWrite>, numeric: bool) {
+    for row in (0..32).step_by(4) {
+        let line = (row..row + 4)
+            .map(|n| {
+                let reg = IntRegister::try_from(n).unwrap();
+                let name = match numeric {
+                    true => format!("{:#}", reg),
+                    false => reg.to_string(),
+                };
+                let cell = format!("{:>4}={:#010X}", name, registers.read(reg));
+                match write {
+                    Some(write) if write.reg == reg => format!("\x1b[1;33m{}\x1b[0m", cell),
+                    _ => cell,
+                }
+            })
+            .collect::<Vec<_>>();
+        println!("{}", line.join(" "));
+    }
+    if let Some(write) = write {
+        println!("{}: {:#010X} → {:#010X}", write.reg, write.old, write.new);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x1000;
+
+    /// Loads a minimal executable holding `code` at `BASE`.
+    fn simulator(code: &[u32]) -> Simulator<io::Sink> {
+        let size = (code.len() * 4) as u32;
+        let mut elf = vec![0x7F, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        for half in &[2u16, 243] {
+            elf.extend_from_slice(&half.to_le_bytes());
+        }
+        for word in &[1u32, BASE, 52, 0, 0] {
+            elf.extend_from_slice(&word.to_le_bytes());
+        }
+        for half in &[52u16, 32, 1, 40, 0, 0] {
+            elf.extend_from_slice(&half.to_le_bytes());
+        }
+        for word in &[1u32, 84, BASE, BASE, size, size, 7, 4] {
+            elf.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in code {
+            elf.extend_from_slice(&word.to_le_bytes());
+        }
+        Simulator::from_bytes(&elf, 0x10000, None, None).unwrap()
+    }
+
+    /// Executes one instruction and passes it to `checker`.
+    fn step<C: Checker<io::Sink>>(
+        checker: &mut C,
+        sim: &mut Simulator<io::Sink>,
+        instructions: u64,
+    ) -> Option<Outcome> {
+        if let Some(outcome) = checker.before_step(sim, instructions) {
+            return Some(outcome);
+        }
+        let before = *sim.processor().registers();
+        let retired = sim.step().unwrap();
+        let retired = Retired {
+            pc: retired.pc,
+            inst: &retired.inst,
+            before: &before,
+            breakpoint: false,
+            last: false,
+        };
+        checker.after_step(sim, &retired)
+    }
+
+    #[test]
+    fn inject_due_faults() {
+        // addi a0, a0, 1, twice
+        let mut sim = simulator(&[0x00150513, 0x00150513]);
+        let mut inject = Inject(vec!["a0:4@1".parse().unwrap()]);
+        assert_eq!(step(&mut inject, &mut sim, 0), None);
+        assert_eq!(sim.read_reg(IntRegister::A0), 1);
+        assert_eq!(step(&mut inject, &mut sim, 1), None);
+        assert_eq!(sim.read_reg(IntRegister::A0), 0x12);
+    }
+
+    #[test]
+    fn stop_stepping_on_continue() {
+        // addi a0, a0, 1, three times
+        let mut sim = simulator(&[0x00150513; 3]);
+        let mut stepper = Stepper::new(&b"\nc\n"[..], true, false);
+        for instructions in 0..3 {
+            assert_eq!(step(&mut stepper, &mut sim, instructions), None);
+        }
+        assert!(stepper.input.is_empty());
+        assert!(!stepper.stepping);
+    }
+
+    #[test]
+    fn save_snapshots_periodically() {
+        // addi a0, a0, 1, three times
+        let mut sim = simulator(&[0x00150513; 3]);
+        let path = std::env::temp_dir().join(format!("yars-checker-{}.snap", std::process::id()));
+        let mut snapshots = Snapshots {
+            every: 2,
+            path: path.clone(),
+        };
+        assert_eq!(step(&mut snapshots, &mut sim, 0), None);
+        assert!(!path.exists());
+        assert_eq!(step(&mut snapshots, &mut sim, 1), None);
+        let resumed = Simulator::<io::Sink>::resume_from(&path, None).unwrap();
+        assert_eq!(resumed.read_reg(IntRegister::A0), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use yars_lib::trace::reader;
+use yars_lib::trace::Commit;
+
+/// Number of matching instructions shown before a divergence.
+const CONTEXT: usize = 8;
+
+/// Commit stream of a reference simulator, checked against the instructions
+/// retired by yars.
+pub struct Reference {
+    commits: Box<dyn Iterator<Item = Commit>>,
+    history: VecDeque<Commit>,
+    matched: u64,
+}
+
+impl Reference {
+    /// Opens a Spike log, skipping the instructions it executed before
+    /// reaching `entry` (e.g. the boot ROM).
+    pub fn open(path: &Path, entry: u32) -> io::Result<Self> {
+        let commits = reader::spike_commits(BufReader::new(File::open(path)?))
+            .map_while(Result::ok)
+            .skip_while(move |commit| commit.pc != entry);
+
+        Ok(Self {
+            commits: Box::new(commits),
+            history: VecDeque::with_capacity(CONTEXT),
+            matched: 0,
+        })
+    }
+
+    /// Compares the next reference commit with `actual`. Once the reference
+    /// trace ends, every commit is accepted.
+    pub fn check(&mut self, actual: Commit) -> bool {
+        match self.commits.next() {
+            Some(expected) if !expected.matches(&actual) => {
+                eprintln!("Divergence after {} matching instructions:", self.matched);
+                for commit in &self.history {
+                    eprintln!("            {}", commit);
+                }
+                eprintln!("  expected: {}", expected);
+                eprintln!("  actual:   {}", actual);
+                false
+            }
+            Some(_) => {
+                if self.history.len() == CONTEXT {
+                    self.history.pop_front();
+                }
+                self.history.push_back(actual);
+                self.matched += 1;
+                true
+            }
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use yars_lib::simulator::Simulator;
+
+/// Exit status when Ctrl-C stops a program in raw mode, as for SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+const CTRL_C: u8 = 0x03;
+
+/// How input typed on the host reaches the guest console.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConsoleMode {
+    /// The terminal edits and echoes a whole line, which the guest receives
+    /// with its newline once Enter is pressed.
+    Line,
+    /// Every key reaches the guest as soon as it is pressed, without echo,
+    /// so guest shells and key-driven programs behave as on a serial port.
+    Raw,
+}
+
+impl fmt::Display for ConsoleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleMode::Line => write!(f, "line"),
+            ConsoleMode::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+impl FromStr for ConsoleMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "line" => Ok(ConsoleMode::Line),
+            "raw" => Ok(ConsoleMode::Raw),
+            _ => Err("expected \"line\" or \"raw\"".to_string()),
+        }
+    }
+}
+
+/// Reads standard input for the guest console from a background thread.
+/// In raw mode the terminal is restored when this is dropped.
+pub struct ConsoleInput {
+    terminal: Option<terminal::Saved>,
+    input: Receiver<Vec<u8>>,
+}
+
+impl ConsoleInput {
+    /// Starts reading input, which [`ConsoleInput::deliver`] passes on to
+    /// the guest. Unless `forward_ctrl_c` is set, Ctrl-C in raw mode stops
+    /// `yars` instead of reaching the guest.
+    pub fn start(mode: ConsoleMode, forward_ctrl_c: bool) -> Self {
+        let terminal = match mode {
+            ConsoleMode::Line => None,
+            ConsoleMode::Raw => {
+                let saved = terminal::enter_raw();
+                if saved.is_none() {
+                    eprintln!("warning: standard input is not a terminal, reading it as is");
+                }
+                saved
+            }
+        };
+
+        let (sender, input) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            let mut stdin = stdin.lock();
+            let mut buffer = Vec::new();
+            let mut chunk = [0u8; 64];
+            loop {
+                buffer.clear();
+                let read = match mode {
+                    ConsoleMode::Line => stdin.read_until(b'\n', &mut buffer),
+                    ConsoleMode::Raw => stdin
+                        .read(&mut chunk)
+                        .inspect(|&read| buffer.extend_from_slice(&chunk[..read])),
+                };
+                if !matches!(read, Ok(read) if read > 0) {
+                    break;
+                }
+                if mode == ConsoleMode::Raw && !forward_ctrl_c && buffer.contains(&CTRL_C) {
+                    if let Some(saved) = &terminal {
+                        terminal::restore(saved);
+                    }
+                    eprintln!("^C");
+                    process::exit(EXIT_INTERRUPTED);
+                }
+                if sender.send(buffer.clone()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { terminal, input }
+    }
+
+    /// Queues the input read so far on the console of `sim`, which records
+    /// it at the current instruction for `--record`.
+    pub fn deliver<W: io::Write>(&self, sim: &mut Simulator<W>) {
+        while let Ok(bytes) = self.input.try_recv() {
+            sim.push_console(&bytes);
+        }
+    }
+}
+
+impl Drop for ConsoleInput {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.terminal {
+            terminal::restore(saved);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    pub type Saved = libc::termios;
+
+    /// Switches standard input to raw mode and returns its previous
+    /// settings, or `None` if it is not a terminal. Output processing stays
+    /// on, so newlines still return the carriage.
+    pub fn enter_raw() -> Option<Saved> {
+        unsafe {
+            let mut saved = std::mem::zeroed::<libc::termios>();
+            if libc::isatty(libc::STDIN_FILENO) == 0
+                || libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0
+            {
+                return None;
+            }
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            match libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) {
+                0 => Some(saved),
+                _ => None,
+            }
+        }
+    }
+
+    pub fn restore(saved: &Saved) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod terminal {
+    pub type Saved = ();
+
+    /// Raw mode is only supported on Unix terminals.
+    pub fn enter_raw() -> Option<Saved> {
+        None
+    }
+
+    pub fn restore(_: &Saved) {}
+}
@@ -0,0 +1,147 @@
+use clap::Clap;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use yars_lib::processor::ProcessorError;
+use yars_lib::register::IntRegister;
+use yars_lib::simulator::Simulator;
+use yars_lib::trace::Commit;
+
+use crate::watch::parse_address;
+
+#[derive(Clap)]
+pub struct CosimOpts {
+    #[clap(short, long, value_name = "size", default_value = "32")]
+    #[clap(about = "Allocate <size> MiB for target memory")]
+    memory: u32,
+
+    #[clap(long, value_name = "address")]
+    #[clap(about = "Override program entry point")]
+    pc: Option<u32>,
+
+    #[clap(long, value_name = "path")]
+    #[clap(about = "Serves a single client on a Unix socket instead of stdin/stdout")]
+    socket: Option<PathBuf>,
+
+    #[clap(about = "Path to target RISC-V program")]
+    program: PathBuf,
+}
+
+pub fn run(opts: CosimOpts) {
+    let memory = opts.memory * 1048576;
+    let sim = match Simulator::<io::Sink>::new(&opts.program, memory, opts.pc, None) {
+        Ok(sim) => sim,
+        Err(err) => {
+            eprintln!("error: {:?}", err);
+            process::exit(2);
+        }
+    };
+
+    let result = match &opts.socket {
+        Some(path) => serve_socket(sim, path),
+        None => serve(sim, io::stdin().lock(), io::stdout().lock()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(unix)]
+fn serve_socket(sim: Simulator<io::Sink>, path: &Path) -> io::Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    let listener = UnixListener::bind(path)?;
+    let (stream, _) = listener.accept()?;
+    let result = serve(sim, BufReader::new(stream.try_clone()?), stream);
+    std::fs::remove_file(path)?;
+    result
+}
+
+#[cfg(not(unix))]
+fn serve_socket(_sim: Simulator<io::Sink>, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+/// Answers one request per line with one JSON object per line. `step [n]`
+/// retires up to `n` instructions, replying with the effects of each.
+fn serve<R: BufRead, W: Write>(
+    mut sim: Simulator<io::Sink>,
+    reader: R,
+    mut writer: W,
+) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        let mut args = line.split_whitespace();
+        let replies = match (args.next(), args.next()) {
+            (None, _) => continue,
+            (Some("quit"), _) => break,
+            (Some("step"), count) => match count.map(str::parse).unwrap_or(Ok(1)) {
+                Ok(count) => step(&mut sim, count),
+                Err(_) => vec![error("invalid count")],
+            },
+            (Some("pc"), None) => vec![json!({ "pc": sim.pc() })],
+            (Some("reg"), Some(name)) => match name.parse::<IntRegister>() {
+                Ok(reg) => vec![json!({ "reg": name, "value": sim.read_reg(reg) })],
+                Err(_) => vec![error("unknown register")],
+            },
+            (Some("mem"), Some(address)) => {
+                let mut bytes = [0u8; 4];
+                match parse_address(address).map(|a| (a, sim.read_mem(a, &mut bytes))) {
+                    Some((address, Ok(()))) => {
+                        let value = u32::from_le_bytes(bytes);
+                        vec![json!({ "address": address, "value": value })]
+                    }
+                    _ => vec![error("invalid address")],
+                }
+            }
+            (Some("cycles"), None) => vec![json!({ "cycles": sim.cycles() })],
+            _ => vec![error("unknown command")],
+        };
+
+        for reply in replies {
+            writeln!(writer, "{}", reply)?;
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+fn step(sim: &mut Simulator<io::Sink>, count: usize) -> Vec<Value> {
+    let mut replies = Vec::new();
+    for _ in 0..count {
+        let pc = sim.pc();
+        match sim.step() {
+            Ok(retired) => {
+                let commit = Commit::from_retired(&retired, sim.processor());
+                replies.push(json!({
+                    "pc": commit.pc,
+                    "insn": commit.raw,
+                    "rd": commit.rd.map(|(reg, value)| json!([reg as u8, value])),
+                    "store": commit.store.map(|(address, value)| json!([address, value])),
+                }));
+            }
+            Err(err) => {
+                let exit = match err {
+                    ProcessorError::Ecall => "ecall".to_string(),
+                    ProcessorError::Ebreak => "ebreak".to_string(),
+                    err => err.to_string(),
+                };
+                replies.push(json!({ "pc": pc, "exit": exit }));
+                break;
+            }
+        }
+    }
+    replies
+}
+
+fn error(message: &str) -> Value {
+    json!({ "error": message })
+}
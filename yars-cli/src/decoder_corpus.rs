@@ -0,0 +1,26 @@
+use clap::Clap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::process;
+
+use yars_lib::instruction;
+
+#[derive(Clap)]
+pub struct DecoderCorpusOpts {
+    #[clap(about = "Writes the corpus to <file> instead of the standard output")]
+    file: Option<PathBuf>,
+}
+
+pub fn run(opts: DecoderCorpusOpts) {
+    let result = match &opts.file {
+        Some(path) => {
+            File::create(path).and_then(|file| instruction::write_corpus(BufWriter::new(file)))
+        }
+        None => instruction::write_corpus(BufWriter::new(io::stdout().lock())),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,220 @@
+use clap::Clap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::process::{self, Command, Stdio};
+use std::thread;
+
+use yars_lib::entropy::Entropy;
+use yars_lib::instruction::Instruction;
+
+/// Number of words handed to the disassembler at once.
+const BATCH: usize = 1 << 16;
+
+#[derive(Clap)]
+pub struct DisasmCheckOpts {
+    #[clap(short = 'n', long, value_name = "count", default_value = "100000")]
+    #[clap(about = "Checks <count> words")]
+    count: u64,
+
+    #[clap(long, value_name = "seed", default_value = "0")]
+    #[clap(about = "Seeds the generator of random words")]
+    seed: u64,
+
+    #[clap(long, value_name = "opcode")]
+    #[clap(about = "Only checks words whose low 7 bits are <opcode>")]
+    opcode: Option<u8>,
+
+    #[clap(long, conflicts_with = "valid")]
+    #[clap(about = "Enumerates words in order instead of drawing random ones")]
+    exhaustive: bool,
+
+    #[clap(long)]
+    #[clap(about = "Only checks instructions yars can encode")]
+    valid: bool,
+
+    #[clap(long, value_name = "path", default_value = "llvm-mc")]
+    #[clap(about = "Path to the llvm-mc binary")]
+    llvm_mc: String,
+
+    #[clap(long, value_name = "count", default_value = "20")]
+    #[clap(about = "Prints at most <count> examples of each kind of mismatch")]
+    examples: usize,
+}
+
+#[derive(Default)]
+struct Report {
+    checked: u64,
+    agreed: u64,
+    rejected_by_both: u64,
+    /// Mnemonics llvm-mc decodes but yars does not, with their counts.
+    unsupported: BTreeMap<String, u64>,
+    /// Words yars decodes but llvm-mc does not.
+    accepted: Vec<(u32, String)>,
+    /// Words both decode differently, with the yars and llvm-mc texts.
+    different: Vec<(u32, String, String)>,
+}
+
+pub fn run(opts: DisasmCheckOpts) {
+    let mut entropy = Entropy::seeded(opts.seed);
+    let opcode = opts.opcode.map(|opcode| u32::from(opcode & 0x7F));
+    let mut next = 0u64;
+    let mut word = || {
+        let word = if opts.valid {
+            u32::from(Instruction::random(|| entropy.next_u32()))
+        } else if opts.exhaustive {
+            next += 1;
+            match opcode {
+                Some(_) => ((next - 1) << 7) as u32,
+                None => (next - 1) as u32,
+            }
+        } else {
+            entropy.next_u32()
+        };
+        match opcode {
+            Some(opcode) if !opts.valid => (word & !0x7F) | opcode,
+            _ => word,
+        }
+    };
+
+    let mut report = Report::default();
+    let mut remaining = opts.count;
+    while remaining > 0 {
+        let size = remaining.min(BATCH as u64);
+        let words: Vec<u32> = (0..size).map(|_| word()).collect();
+        let reference = match disassemble(&opts.llvm_mc, &words) {
+            Ok(reference) => reference,
+            Err(err) => {
+                eprintln!("error: {}: {}", opts.llvm_mc, err);
+                process::exit(2);
+            }
+        };
+
+        for word in words {
+            report.check(word, reference.get(&word));
+        }
+        remaining -= size;
+    }
+
+    report.print(opts.examples);
+    if !report.accepted.is_empty() || !report.different.is_empty() {
+        process::exit(1);
+    }
+}
+
+impl Report {
+    fn check(&mut self, word: u32, reference: Option<&String>) {
+        self.checked += 1;
+        let actual = Instruction::try_from(word)
+            .ok()
+            .map(|inst| normalize(&inst));
+
+        match (actual, reference) {
+            (None, None) => self.rejected_by_both += 1,
+            (None, Some(reference)) => {
+                let mnemonic = reference.split(' ').next().unwrap_or_default();
+                *self.unsupported.entry(mnemonic.to_string()).or_default() += 1;
+            }
+            (Some(actual), None) => self.accepted.push((word, actual)),
+            (Some(actual), Some(reference)) if actual == *reference => self.agreed += 1,
+            (Some(actual), Some(reference)) => {
+                self.different.push((word, actual, reference.clone()))
+            }
+        }
+    }
+
+    fn print(&self, examples: usize) {
+        println!(
+            "Checked {} words: {} agree, {} rejected by both",
+            self.checked, self.agreed, self.rejected_by_both
+        );
+
+        let unsupported: u64 = self.unsupported.values().sum();
+        println!("Decoded only by llvm-mc: {}", unsupported);
+        for (mnemonic, count) in &self.unsupported {
+            println!("  {:<12} {}", mnemonic, count);
+        }
+
+        println!("Decoded only by yars: {}", self.accepted.len());
+        for (word, actual) in self.accepted.iter().take(examples) {
+            println!("  {:08x}  {}", word, actual);
+        }
+
+        println!("Decoded differently: {}", self.different.len());
+        for (word, actual, reference) in self.different.iter().take(examples) {
+            println!(
+                "  {:08x}  yars: {:<28} llvm-mc: {}",
+                word, actual, reference
+            );
+        }
+    }
+}
+
+/// Disassembles `words` with llvm-mc, returning the normalized text of the
+/// ones it could decode.
+fn disassemble(llvm_mc: &str, words: &[u32]) -> io::Result<HashMap<u32, String>> {
+    let mut child = Command::new(llvm_mc)
+        .args(["--disassemble", "-triple=riscv32", "-mattr=+m"])
+        .args(["-M", "no-aliases", "--show-encoding"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let mut input = String::with_capacity(words.len() * 20);
+    for word in words {
+        let [a, b, c, d] = word.to_le_bytes();
+        // Brackets make llvm-mc skip the whole word when it is invalid,
+        // instead of resynchronizing two bytes later
+        input.push_str(&format!("[{:#04x} {:#04x} {:#04x} {:#04x}]\n", a, b, c, d));
+    }
+
+    // Written from another thread so llvm-mc never blocks on a full stdout
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer.join().unwrap()?;
+
+    let output = String::from_utf8_lossy(&output.stdout);
+    Ok(output.lines().filter_map(parse_line).collect())
+}
+
+/// Parses a line such as `addi a0, zero, 5 # encoding: [0x13,0x05,0x50,0x00]`.
+fn parse_line(line: &str) -> Option<(u32, String)> {
+    let (text, encoding) = line.split_once("# encoding: [")?;
+    let bytes = encoding
+        .trim_end_matches(']')
+        .split(',')
+        .map(|byte| u8::from_str_radix(byte.trim_start_matches("0x"), 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let word = u32::from_le_bytes(<[u8; 4]>::try_from(bytes.as_slice()).ok()?);
+    Some((word, normalize_text(text)))
+}
+
+/// Formats an instruction the way llvm-mc does, so both can be compared as
+/// strings.
+fn normalize(inst: &Instruction) -> String {
+    let text = match *inst {
+        // llvm-mc prints the 20-bit immediate as unsigned
+        Instruction::LUI { rd, imm } => format!("lui {}, {}", rd, imm as u32 & 0xFFFFF),
+        Instruction::AUIPC { rd, imm } => format!("auipc {}, {}", rd, imm as u32 & 0xFFFFF),
+        _ => inst.to_string(),
+    };
+
+    // Branch and jump offsets are printed as `pc+offset` when no address is
+    // known, while llvm-mc prints the bare offset
+    let text = normalize_text(&text).replace("pc+", "").replace("pc-", "-");
+    text.trim().to_string()
+}
+
+/// Collapses whitespace to one space after the mnemonic and after commas.
+fn normalize_text(text: &str) -> String {
+    let mut parts = text.split_whitespace();
+    let mnemonic = parts.next().unwrap_or_default();
+    let operands: String = parts.collect();
+    if operands.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, operands.replace(',', ", "))
+    }
+}
@@ -0,0 +1,69 @@
+use clap::Clap;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+use yars_lib::entropy::Entropy;
+use yars_lib::fault::{Campaign, Outcome};
+use yars_lib::simulator::Simulator;
+
+#[derive(Clap)]
+pub struct FaultCampaignOpts {
+    #[clap(short = 'n', long, value_name = "count", default_value = "1000")]
+    #[clap(about = "Runs the program <count> times with one fault each")]
+    runs: u64,
+
+    #[clap(long, value_name = "seed", default_value = "0")]
+    #[clap(about = "Derives the faults from <seed>")]
+    seed: u64,
+
+    #[clap(short, long, value_name = "size", default_value = "32")]
+    #[clap(about = "Allocate <size> MiB for target memory")]
+    memory: u32,
+
+    #[clap(short, long)]
+    #[clap(about = "Prints the fault injected in every run and its outcome")]
+    verbose: bool,
+
+    #[clap(about = "Path to the target RISC-V program")]
+    program: PathBuf,
+}
+
+pub fn run(opts: FaultCampaignOpts) {
+    let memory = opts.memory * 1048576;
+    let mut sim = match Simulator::new(&opts.program, memory, None, None::<io::Sink>) {
+        Ok(sim) => sim,
+        Err(err) => {
+            eprintln!("error: {}: {:?}", opts.program.display(), err);
+            process::exit(2);
+        }
+    };
+    sim.set_deterministic(Some(opts.seed));
+
+    let campaign = Campaign::new(sim);
+    println!(
+        "Fault-free run: {:?} with a0 = {} after {} instructions.",
+        campaign.golden().exit,
+        campaign.golden().a0,
+        campaign.instret()
+    );
+
+    let mut entropy = Entropy::seeded(opts.seed);
+    let mut outcomes = BTreeMap::new();
+    for _ in 0..opts.runs {
+        let fault = campaign.random_fault(&mut entropy);
+        let outcome = campaign.run(&fault);
+        if opts.verbose {
+            println!("{}: {}", fault, outcome);
+        }
+        *outcomes.entry(outcome).or_insert(0u64) += 1;
+    }
+
+    println!("\n{:<14} {:>8} {:>8}", "Outcome", "Runs", "Share");
+    for outcome in &Outcome::ALL {
+        let runs = outcomes.get(outcome).copied().unwrap_or(0);
+        let share = 100.0 * runs as f64 / opts.runs.max(1) as f64;
+        println!("{:<14} {:>8} {:>7.1}%", outcome.to_string(), runs, share);
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use yars_lib::heatmap::Heatmap;
+
+/// Buckets per row of the image.
+const COLUMNS: u32 = 64;
+/// Width and height of a bucket in pixels.
+const CELL: u32 = 8;
+
+/// Writes `heatmap` as CSV, or as a PNG image if `path` ends in `.png`.
+pub fn write(heatmap: &Heatmap, path: &Path) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => write_png(heatmap, file),
+        _ => heatmap.write_csv(file),
+    }
+}
+
+/// Draws each row of [`COLUMNS`] consecutive buckets as a strip of cells,
+/// from black (never accessed) through red to yellow (most accessed), on a
+/// logarithmic scale. Rows without any access are left out.
+fn write_png<W: io::Write>(heatmap: &Heatmap, writer: W) -> io::Result<()> {
+    let span = heatmap.bucket_size() as u64 * COLUMNS as u64;
+    let mut rows = heatmap
+        .buckets()
+        .map(|(start, _)| start as u64 / span)
+        .collect::<Vec<_>>();
+    rows.dedup();
+
+    let max = heatmap.buckets().map(|(_, a)| a.total()).max().unwrap_or(0);
+    let (width, height) = (COLUMNS * CELL, rows.len().max(1) as u32 * CELL);
+    let mut pixels = vec![0u8; (width * height * 3) as usize];
+
+    for (start, accesses) in heatmap.buckets() {
+        let row = rows.binary_search(&(start as u64 / span)).unwrap() as u32;
+        let column = ((start as u64 % span) / heatmap.bucket_size() as u64) as u32;
+        let heat = (accesses.total() as f64).ln_1p() / (max as f64).ln_1p();
+        let color = color(0.2 + 0.8 * heat);
+
+        for y in row * CELL..(row + 1) * CELL {
+            for x in column * CELL..(column + 1) * CELL {
+                let offset = ((y * width + x) * 3) as usize;
+                pixels[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(to_io)?;
+    writer.write_image_data(&pixels).map_err(to_io)
+}
+
+/// Maps `heat` in 0..=1 to black, red, yellow and white.
+fn color(heat: f64) -> [u8; 3] {
+    let channel = |offset: f64| ((3.0 * heat - offset).clamp(0.0, 1.0) * 255.0) as u8;
+    [channel(0.0), channel(1.0), channel(2.0)]
+}
+
+fn to_io(err: png::EncodingError) -> io::Error {
+    io::Error::other(err)
+}
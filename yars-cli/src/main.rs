@@ -1,9 +1,31 @@
 use clap::{crate_authors, crate_description, crate_version, Clap};
-use std::io::{self, prelude::*};
-use std::path::PathBuf;
 
-use yars_lib::processor::ProcessorError;
-use yars_lib::simulator::Simulator;
+mod block_coverage;
+mod checker;
+mod compare;
+mod console;
+mod cosim;
+mod decoder_corpus;
+mod disasm_check;
+mod fault_campaign;
+mod heatmap;
+mod roi;
+mod run;
+mod serve;
+mod test_suite;
+mod torture;
+mod trace_diff;
+mod watch;
+
+use cosim::CosimOpts;
+use decoder_corpus::DecoderCorpusOpts;
+use disasm_check::DisasmCheckOpts;
+use fault_campaign::FaultCampaignOpts;
+use run::RunOpts;
+use serve::ServeOpts;
+use test_suite::TestSuiteOpts;
+use torture::TortureOpts;
+use trace_diff::TraceDiffOpts;
 
 #[derive(Clap)]
 #[clap(name = "yars")]
@@ -11,51 +33,45 @@ use yars_lib::simulator::Simulator;
 #[clap(version = crate_version!())]
 #[clap(about = crate_description!())]
 struct Opts {
-    #[clap(short, long)]
-    #[clap(about = "Logs instruction execution")]
-    log: bool,
-
-    #[clap(short, long)]
-    #[clap(about = "Runs the program interactively")]
-    interactive: bool,
-
-    #[clap(short, long, value_name = "size", default_value = "32")]
-    #[clap(about = "Allocate <size> MiB for target memory")]
-    memory: u32,
-
-    #[clap(long, value_name = "address")]
-    #[clap(about = "Override program entry point")]
-    pc: Option<u32>,
+    #[clap(subcommand)]
+    command: Command,
+}
 
-    #[clap(about = "Path to target RISC-V program")]
-    program: PathBuf,
+#[derive(Clap)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    #[clap(about = "Runs one or more RISC-V programs")]
+    Run(RunOpts),
+    #[clap(about = "Runs the rv32ui/rv32um tests of riscv-tests in a directory")]
+    TestSuite(TestSuiteOpts),
+    #[clap(about = "Compares two instruction traces and prints where they differ")]
+    TraceDiff(TraceDiffOpts),
+    #[clap(about = "Serves lock-step cosimulation requests for a program")]
+    Cosim(CosimOpts),
+    #[clap(about = "Compares the decoder against llvm-mc on random or enumerated words")]
+    DisasmCheck(DisasmCheckOpts),
+    #[clap(
+        about = "Writes the golden corpus of decoded instructions the decoder tests compare against"
+    )]
+    DecoderCorpus(DecoderCorpusOpts),
+    #[clap(about = "Checks random programs against a reference model of RV32IM")]
+    Torture(TortureOpts),
+    #[clap(about = "Injects random bit flips into runs of a program and classifies the outcomes")]
+    FaultCampaign(FaultCampaignOpts),
+    #[clap(about = "Serves a JSON-RPC interface over HTTP to drive the simulator remotely")]
+    Serve(ServeOpts),
 }
 
 fn main() {
-    let opts = Opts::parse();
-    let stdout = io::stdout();
-
-    let memory = opts.memory * 1048576;
-    let logger = match opts.log {
-        true => Some(stdout.lock()),
-        false => None,
-    };
-
-    let mut sim = Simulator::new(opts.program, memory, opts.pc, logger).unwrap();
-    match opts.interactive {
-        false => sim.run(),
-        true => loop {
-            match sim.step() {
-                Ok(()) => {
-                    io::stdin().read_exact(&mut [0u8]).unwrap();
-                    continue;
-                }
-                Err(ProcessorError::Ecall) | Err(ProcessorError::Ebreak) => break Ok(()),
-                e => break e,
-            }
-        },
+    match Opts::parse().command {
+        Command::Run(opts) => run::run(opts),
+        Command::TestSuite(opts) => test_suite::run(opts),
+        Command::TraceDiff(opts) => trace_diff::run(opts),
+        Command::Cosim(opts) => cosim::run(opts),
+        Command::DisasmCheck(opts) => disasm_check::run(opts),
+        Command::DecoderCorpus(opts) => decoder_corpus::run(opts),
+        Command::Torture(opts) => torture::run(opts),
+        Command::FaultCampaign(opts) => fault_campaign::run(opts),
+        Command::Serve(opts) => serve::run(opts),
     }
-    .unwrap();
-
-    println!("Program finished (Total cycles: {}).", sim.cycles());
 }
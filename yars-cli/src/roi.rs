@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Counts of the instructions, cycles and wall time spent inside the
+/// region of interest, which may be entered several times.
+#[derive(Debug, Default)]
+pub struct Region {
+    /// Counts when the region was entered, while the program is inside it.
+    entered: Option<(u64, usize, BTreeMap<&'static str, u64>, Instant)>,
+    /// Whether the region was ever entered.
+    pub marked: bool,
+    pub instructions: u64,
+    pub cycles: usize,
+    pub mix: BTreeMap<&'static str, u64>,
+    pub elapsed: Duration,
+}
+
+impl Region {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enters the region with the counts so far, unless already inside.
+    pub fn enter(&mut self, instructions: u64, cycles: usize, mix: &BTreeMap<&'static str, u64>) {
+        if self.entered.is_none() {
+            self.marked = true;
+            self.entered = Some((instructions, cycles, mix.clone(), Instant::now()));
+        }
+    }
+
+    /// Leaves the region, adding what ran since it was entered.
+    pub fn leave(&mut self, instructions: u64, cycles: usize, mix: &BTreeMap<&'static str, u64>) {
+        let (start_instructions, start_cycles, start_mix, start) = match self.entered.take() {
+            Some(entered) => entered,
+            None => return,
+        };
+        self.instructions += instructions - start_instructions;
+        self.cycles += cycles - start_cycles;
+        for (&category, &count) in mix {
+            let before = start_mix.get(category).copied().unwrap_or(0);
+            if count > before {
+                *self.mix.entry(category).or_insert(0) += count - before;
+            }
+        }
+        self.elapsed += start.elapsed();
+    }
+}
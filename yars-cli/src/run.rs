@@ -0,0 +1,1542 @@
+use clap::Clap;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use yars_lib::assertion::Assertions;
+use yars_lib::callstack::CallStack;
+use yars_lib::canary::StackCanary;
+use yars_lib::cfg::ControlFlowGraph;
+use yars_lib::checkpoint::Checkpoints;
+use yars_lib::coverage::IsaCoverage;
+use yars_lib::device::{Console, Rng, Rtc, Stream};
+use yars_lib::entropy::{Entropy, TimeSource};
+use yars_lib::envcall::Marker;
+use yars_lib::explain::explain;
+use yars_lib::fault::Fault;
+use yars_lib::heap::HeapChecker;
+use yars_lib::heatmap::Heatmap;
+use yars_lib::instruction::{Category, Instruction};
+use yars_lib::interrupt;
+use yars_lib::isa::Isa;
+use yars_lib::latency::LatencyJitter;
+use yars_lib::lines::{LineCoverage, LineTable};
+use yars_lib::printf::PrintfDecoder;
+use yars_lib::processor::{Endianness, ProcessorError, RetiredInstruction, SmcPolicy};
+use yars_lib::profile::{CallGraph, FoldedStacks, FunctionProfile, PcSamples};
+use yars_lib::register::{IntRegister, IntRegisterSet};
+use yars_lib::replay::InputLog;
+use yars_lib::reset::ResetConfig;
+use yars_lib::shadow::ShadowStack;
+use yars_lib::signature;
+use yars_lib::simulator::{EbreakMode, ExitEvent, HookAction, RunOptions, Simulator};
+use yars_lib::stack::StackUsage;
+use yars_lib::trace::Commit;
+
+use crate::checker::{
+    Assert, Canary, Checker, ConsoleIo, Inject, Printf, Progress, Retired, Shadow, Snapshots,
+    Stepper,
+};
+use crate::compare::Reference;
+use crate::console::{ConsoleInput, ConsoleMode};
+use crate::roi::Region;
+use crate::watch::{parse_address, Watch};
+
+const RTC_BASE: u32 = 0xFFFF_0000;
+const RNG_BASE: u32 = 0xFFFF_0010;
+const CONSOLE_BASE: u32 = 0xFFFF_0020;
+
+const EXIT_FAULT: i32 = 1;
+const EXIT_CYCLE_LIMIT: i32 = 3;
+const EXIT_HANG: i32 = 4;
+const EXIT_TIMEOUT: i32 = 124;
+
+#[derive(Clap)]
+pub struct RunOpts {
+    #[clap(short, long)]
+    #[clap(about = "Logs instruction execution")]
+    log: bool,
+
+    #[clap(short, long, conflicts_with = "verbose")]
+    #[clap(about = "Only prints errors")]
+    quiet: bool,
+
+    #[clap(short, long, parse(from_occurrences))]
+    #[clap(about = "Prints load information (-v) and logs instructions (-vv)")]
+    verbose: u64,
+
+    #[clap(short, long)]
+    #[clap(about = "Logs pseudo-instructions when available")]
+    pseudo: bool,
+
+    #[clap(long)]
+    #[clap(about = "Describes in plain English what each instruction did")]
+    explain: bool,
+
+    #[clap(long)]
+    #[clap(
+        about = "Faults on loads and stores to addresses that are not a multiple of their size"
+    )]
+    strict_alignment: bool,
+
+    #[clap(long, value_name = "policy", default_value = "allow")]
+    #[clap(about = "Performs stores into code (allow) or faults on them (trap)")]
+    smc: SmcPolicy,
+
+    #[clap(long, value_name = "order", default_value = "little")]
+    #[clap(about = "Byte order of loads and stores (little or big)")]
+    endian: Endianness,
+
+    #[clap(long, value_name = "mode", default_value = "exit")]
+    #[clap(about = "Ends the program at an ebreak (exit) or stops at it as a breakpoint (break)")]
+    ebreak: EbreakMode,
+
+    #[clap(long)]
+    #[clap(about = "Warns about divisions by zero and signed division overflows")]
+    check_division: bool,
+
+    #[clap(long)]
+    #[clap(about = "Answers the yars environment call instead of exiting on it")]
+    env_calls: bool,
+
+    #[clap(long)]
+    #[clap(about = "Prints the registers and PC when the program stops")]
+    dump_regs: bool,
+
+    #[clap(long)]
+    #[clap(about = "Names registers x0-x31 in register dumps")]
+    numeric_registers: bool,
+
+    #[clap(long)]
+    #[clap(about = "Prints a hash of the registers and memory when the program stops")]
+    state_hash: bool,
+
+    #[clap(long)]
+    #[clap(about = "Prints where the program, stack, heap and devices are mapped")]
+    print_map: bool,
+
+    #[clap(long)]
+    #[clap(about = "Shows a status line with the PC and execution speed on stderr")]
+    progress: bool,
+
+    #[clap(short, long)]
+    #[clap(about = "Runs the program interactively")]
+    interactive: bool,
+
+    #[clap(short, long, value_name = "size", default_value = "32")]
+    #[clap(about = "Allocate <size> MiB for target memory")]
+    memory: u32,
+
+    #[clap(long, value_name = "address")]
+    #[clap(about = "Override program entry point")]
+    pc: Option<u32>,
+
+    #[clap(long, value_name = "mode", conflicts_with = "interactive")]
+    #[clap(about = "Feeds standard input to the guest console by line (line) or by key (raw)")]
+    console: Option<ConsoleMode>,
+
+    #[clap(long, requires = "console")]
+    #[clap(about = "Passes Ctrl-C to the guest in raw console mode instead of stopping")]
+    forward_ctrl_c: bool,
+
+    #[clap(long, value_name = "delta", allow_hyphen_values = true)]
+    #[clap(
+        about = "Loads the program <delta> bytes away from where it was linked (may be negative)"
+    )]
+    load_offset: Option<LoadOffset>,
+
+    #[clap(
+        long,
+        value_name = "dir",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[clap(about = "Looks for the shared objects a dynamically linked program needs in <dir>")]
+    library_path: Vec<PathBuf>,
+
+    #[clap(long, value_name = "count")]
+    #[clap(about = "Saves a snapshot to <program>.snap every <count> instructions")]
+    snapshot_every: Option<usize>,
+
+    #[clap(long, value_name = "snapshot")]
+    #[clap(about = "Resumes execution from a snapshot file")]
+    resume: Option<PathBuf>,
+
+    #[clap(long, value_name = "count")]
+    #[clap(about = "Keeps a checkpoint in memory every <count> instructions")]
+    checkpoint_interval: Option<usize>,
+
+    #[clap(long, value_name = "count", requires = "checkpoint-interval")]
+    #[clap(about = "Number of checkpoints kept in memory [default: 4]")]
+    checkpoints: Option<usize>,
+
+    #[clap(long, value_name = "count")]
+    #[clap(about = "Stops the program after <count> cycles")]
+    max_cycles: Option<usize>,
+
+    #[clap(long, value_name = "seconds")]
+    #[clap(about = "Stops the program after <seconds> of wall-clock time")]
+    timeout: Option<u64>,
+
+    #[clap(long)]
+    #[clap(about = "Prints the number of instructions retired of each kind")]
+    stats: bool,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes a JSON report of the run to <file>")]
+    stats_json: Option<PathBuf>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes the performance counters of the hart to <file> as JSON")]
+    counters: Option<PathBuf>,
+
+    #[clap(long, value_name = "address")]
+    #[clap(about = "Only counts instructions from <address> or symbol on in the statistics")]
+    roi_start: Option<String>,
+
+    #[clap(long, value_name = "address", requires = "roi-start")]
+    #[clap(about = "Stops counting instructions for the statistics at <address> or symbol")]
+    roi_end: Option<String>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Fails unless the guest prints exactly the contents of <file> to stdout")]
+    expect_stdout: Option<PathBuf>,
+
+    #[clap(long, value_name = "status", allow_hyphen_values = true)]
+    #[clap(about = "Fails unless the guest exits with <status> instead of 0")]
+    expect_exit: Option<i32>,
+
+    #[clap(long = "assert", value_name = "file")]
+    #[clap(about = "Fails when an assertion in <file> does not hold as its address is reached")]
+    assertions: Option<PathBuf>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes the test signature to <file> when the program stops")]
+    signature: Option<PathBuf>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes sampled guest call stacks to <file> for flame graphs")]
+    folded_stacks: Option<PathBuf>,
+
+    #[clap(long, value_name = "count", default_value = "100")]
+    #[clap(about = "Samples the call stack or the PC every <count> instructions")]
+    sample_every: u64,
+
+    #[clap(long, value_name = "file")]
+    #[clap(
+        about = "Writes the PC sampled every --sample-every instructions per function to <file>"
+    )]
+    pc_samples: Option<PathBuf>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes the calls between functions to <file> in callgrind format")]
+    callgraph: Option<PathBuf>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes load and store counts per memory bucket to <file> (CSV or .png)")]
+    heatmap: Option<PathBuf>,
+
+    #[clap(long, value_name = "bytes", default_value = "4096")]
+    #[clap(about = "Counts accesses for the heatmap in buckets of <bytes>")]
+    heatmap_bucket: u32,
+
+    #[clap(long)]
+    #[clap(about = "Prints the maximum stack usage and the largest frame of each function")]
+    stack_usage: bool,
+
+    #[clap(long)]
+    #[clap(about = "Follows malloc and free to report heap misuse and leaks")]
+    check_heap: bool,
+
+    #[clap(long)]
+    #[clap(about = "Prints what calls to printf and puts would print, found by symbol")]
+    decode_printf: bool,
+
+    #[clap(long)]
+    #[clap(about = "Reports returns that do not go back to their caller, as after stack smashing")]
+    check_returns: bool,
+
+    #[clap(long, value_name = "address")]
+    #[clap(about = "Stops with a stack overflow when the stack grows below <address> or symbol")]
+    stack_limit: Option<String>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes basic-block and branch execution counts to <file> (CSV or .json)")]
+    block_coverage: Option<PathBuf>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes the line coverage of the program to <file> in LCOV format")]
+    lcov: Option<PathBuf>,
+
+    #[clap(long, value_name = "count")]
+    #[clap(about = "Prints the <count> functions that took the most cycles")]
+    hot_functions: Option<usize>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes the control-flow graph of the executed code to <file> in DOT format")]
+    cfg: Option<PathBuf>,
+
+    #[clap(long, requires = "cfg")]
+    #[clap(about = "Labels the control-flow graph with execution counts")]
+    cfg_counts: bool,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes which instructions and operand cases were executed to <file>")]
+    isa_coverage: Option<PathBuf>,
+
+    #[clap(long, value_name = "log")]
+    #[clap(about = "Stops at the first instruction that differs from a Spike commit log")]
+    compare: Option<PathBuf>,
+
+    #[clap(long, value_name = "isa")]
+    #[clap(about = "Restricts execution to an ISA such as rv32i or rv32im")]
+    isa: Option<Isa>,
+
+    #[clap(long, value_name = "seed")]
+    #[clap(about = "Derives guest-visible time and random numbers from <seed>")]
+    seed: Option<u64>,
+
+    #[clap(long, value_name = "source", default_value = "host")]
+    #[clap(
+        about = "Advances guest-visible time per instruction, per cycle or with the host clock"
+    )]
+    time_source: TimeSource,
+
+    #[clap(long, value_name = "cycles")]
+    #[clap(
+        about = "Adds up to <instruction>[,<memory>] random extra cycles to every instruction and load or store"
+    )]
+    latency_jitter: Option<LatencyJitter>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Records the time and random numbers the program reads to <file>")]
+    record: Option<PathBuf>,
+
+    #[clap(long, value_name = "file", conflicts_with_all = &["record", "seed"])]
+    #[clap(about = "Feeds back the inputs recorded with --record to repeat a run")]
+    replay: Option<PathBuf>,
+
+    #[clap(long, value_name = "pattern|random")]
+    #[clap(about = "Fills registers and unloaded memory with <pattern> instead of zeros")]
+    poison: Option<Poison>,
+
+    #[clap(
+        long,
+        value_name = "KEY=VAL",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[clap(about = "Adds an environment variable to the program's envp")]
+    env: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "reg|addr[:size]",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[clap(about = "Prints a line whenever a register or memory location changes")]
+    watch: Vec<Watch>,
+
+    #[clap(long)]
+    #[clap(about = "Prints a line for every device access with the cycle it happened at")]
+    device_events: bool,
+
+    #[clap(
+        long,
+        value_name = "reg|addr|inst:bit@instret",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[clap(
+        about = "Flips a bit of a register, memory byte or fetched instruction after <instret> instructions"
+    )]
+    inject: Vec<Fault>,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Runs every program listed in <file>, one path per line")]
+    manifest: Option<PathBuf>,
+
+    #[clap(required_unless_present = "manifest")]
+    #[clap(about = "Paths to target RISC-V programs")]
+    programs: Vec<PathBuf>,
+
+    #[clap(last = true)]
+    #[clap(about = "Arguments passed to the program after --")]
+    args: Vec<String>,
+}
+
+/// Initial contents of registers and of memory outside the program, given
+/// as a 32-bit pattern such as `0xdeadbeef` or as `random`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Poison {
+    Pattern(u32),
+    Random,
+}
+
+impl FromStr for Poison {
+    type Err = String;
+
+    fn from_str(poison: &str) -> Result<Self, Self::Err> {
+        match poison {
+            "random" => Ok(Poison::Random),
+            _ => parse_address(poison)
+                .map(Poison::Pattern)
+                .ok_or_else(|| "expected a 32-bit pattern or \"random\"".to_string()),
+        }
+    }
+}
+
+/// Distance to move a program from the addresses it was linked at, such as
+/// `0x1000` or `-0x80000000`, wrapping around.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct LoadOffset(u32);
+
+impl FromStr for LoadOffset {
+    type Err = String;
+
+    fn from_str(offset: &str) -> Result<Self, Self::Err> {
+        let (negative, magnitude) = match offset.strip_prefix('-') {
+            Some(magnitude) => (true, magnitude),
+            None => (false, offset),
+        };
+        match (parse_address(magnitude), negative) {
+            (Some(delta), false) => Ok(LoadOffset(delta)),
+            (Some(delta), true) => Ok(LoadOffset(delta.wrapping_neg())),
+            (None, _) => Err("expected a 32-bit offset such as 0x1000 or -0x80000000".to_string()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Exited,
+    Fault(ProcessorError),
+    CycleLimit,
+    Timeout,
+    HostExit(u32),
+    Diverged,
+    Hang,
+    Assertion,
+    StackOverflow,
+}
+
+/// Summary of a single program run.
+pub struct Report {
+    pub program: PathBuf,
+    pub outcome: Outcome,
+    pub pc: u32,
+    pub a0: u32,
+    pub instructions: u64,
+    pub cycles: usize,
+    pub mix: BTreeMap<&'static str, u64>,
+    /// Whether the counts only cover the region of interest.
+    pub roi: bool,
+    pub state_hash: u64,
+    pub elapsed: Duration,
+    /// Description of the fault or of the failed assertion, if any.
+    pub error: Option<String>,
+    /// Memory the faulting instruction accessed, or else the top of the
+    /// stack, if the program faulted.
+    pub dump: Option<String>,
+    /// Expectations of `--expect-stdout` and `--expect-exit` the run did not
+    /// meet, if any were given.
+    pub unmet: Option<Vec<String>>,
+}
+
+impl Report {
+    /// A program passes when it exits through ecall/ebreak with a0 = 0, or
+    /// writes 1 to `tohost`. When expectations are given, it passes when it
+    /// meets all of them instead.
+    pub fn passed(&self) -> bool {
+        match (&self.unmet, self.outcome) {
+            (Some(unmet), _) => unmet.is_empty(),
+            (None, Outcome::Exited) => self.a0 == 0,
+            (None, Outcome::HostExit(value)) => value == 1,
+            (None, _) => false,
+        }
+    }
+
+    /// Exit status of a program that exited: `a0` at the final ecall or
+    /// ebreak, or the code a test wrote to `tohost`.
+    fn exit_status(&self) -> Option<i32> {
+        match self.outcome {
+            Outcome::Exited => Some(self.a0 as i32),
+            Outcome::HostExit(value) => Some((value >> 1) as i32),
+            _ => None,
+        }
+    }
+
+    /// Checks the run against `--expect-exit`, or else against a normal
+    /// exit, and the guest's standard output against `--expect-stdout`.
+    fn check(&self, opts: &RunOpts, output: Option<&[u8]>) -> Option<Vec<String>> {
+        let expected = opts
+            .expect_stdout
+            .as_ref()
+            .map(|path| (path, fs::read(path)));
+        if expected.is_none() && opts.expect_exit.is_none() {
+            return None;
+        }
+
+        let mut unmet = Vec::new();
+        match (opts.expect_exit, self.exit_status()) {
+            (Some(expected), Some(status)) if status == expected => {}
+            (Some(expected), Some(status)) => unmet.push(format!(
+                "exit status {} differs from the expected {}",
+                status, expected
+            )),
+            (Some(expected), None) => unmet.push(format!(
+                "expected exit status {}, but the program stopped with {}",
+                expected,
+                self.result()
+            )),
+            (None, _) if self.passed() => {}
+            (None, _) => unmet.push(format!("program did not pass: {}", self.result())),
+        }
+        if let Some((path, expected)) = expected {
+            match expected {
+                Ok(expected) => {
+                    let actual = output.unwrap_or_default();
+                    if let Some(diff) = diff_output(path, &expected, actual) {
+                        unmet.push(diff);
+                    }
+                }
+                Err(err) => unmet.push(format!("{}: {}", path.display(), err)),
+            }
+        }
+        Some(unmet)
+    }
+
+    /// The cycle count for the messages printed when the program stops.
+    fn total(&self) -> String {
+        match self.roi {
+            true => format!("Region of interest cycles: {}", self.cycles),
+            false => format!("Total cycles: {}", self.cycles),
+        }
+    }
+
+    fn result(&self) -> String {
+        if let Some(unmet) = self.unmet.as_ref().and_then(|unmet| unmet.first()) {
+            return format!("fail ({})", unmet.lines().next().unwrap_or_default());
+        }
+        match self.outcome {
+            Outcome::Exited if self.a0 == 0 => "pass".to_string(),
+            Outcome::Exited => format!("fail (a0={})", self.a0),
+            Outcome::Fault(_) => format!("fault ({})", self.error.as_deref().unwrap_or("")),
+            Outcome::CycleLimit => "cycle limit".to_string(),
+            Outcome::Timeout => "timeout".to_string(),
+            Outcome::HostExit(1) => "pass".to_string(),
+            Outcome::HostExit(value) => format!("fail (tohost={:#x})", value),
+            Outcome::Diverged => "diverged".to_string(),
+            Outcome::Hang => "hang".to_string(),
+            Outcome::Assertion | Outcome::StackOverflow => {
+                format!("fail ({})", self.error.as_deref().unwrap_or(""))
+            }
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let (reason, error) = match self.outcome {
+            Outcome::Exited => ("exited", None),
+            Outcome::Fault(_) => ("fault", self.error.clone()),
+            Outcome::CycleLimit => ("cycle_limit", None),
+            Outcome::Timeout => ("timeout", None),
+            Outcome::HostExit(_) => ("tohost", None),
+            Outcome::Diverged => ("diverged", None),
+            Outcome::Hang => ("hang", None),
+            Outcome::Assertion => ("assertion", self.error.clone()),
+            Outcome::StackOverflow => ("stack_overflow", self.error.clone()),
+        };
+        let tohost = match self.outcome {
+            Outcome::HostExit(value) => Some(value),
+            _ => None,
+        };
+        json!({
+            "program": self.program.display().to_string(),
+            "exit": {
+                "reason": reason,
+                "error": error,
+                "pc": self.pc,
+                "a0": self.a0,
+                "tohost": tohost,
+            },
+            "instructions": self.instructions,
+            "cycles": self.cycles,
+            "mix": self.mix,
+            "roi": self.roi,
+            "state_hash": format!("{:016x}", self.state_hash),
+            "wall_time": self.elapsed.as_secs_f64(),
+        })
+    }
+
+    /// Performance counters of the run. The simulator runs a single hart,
+    /// so they are those of hart 0.
+    fn counters_json(&self) -> Value {
+        json!({
+            "program": self.program.display().to_string(),
+            "hart": 0,
+            "instret": self.instructions,
+            "cycles": self.cycles,
+            "mix": self.mix,
+        })
+    }
+}
+
+pub fn run(opts: RunOpts) {
+    let mut programs = opts.programs.clone();
+    if let Some(manifest) = &opts.manifest {
+        let manifest = fs::read_to_string(manifest).unwrap();
+        programs.extend(
+            manifest
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(PathBuf::from),
+        );
+    }
+
+    let coverage = Arc::new(Mutex::new(IsaCoverage::new()));
+    match programs.as_slice() {
+        [program] if opts.manifest.is_none() => run_single(&opts, program, &coverage),
+        _ if opts.signature.is_some()
+            || opts.folded_stacks.is_some()
+            || opts.cfg.is_some()
+            || opts.hot_functions.is_some()
+            || opts.pc_samples.is_some()
+            || opts.heatmap.is_some()
+            || opts.stack_usage
+            || opts.check_heap
+            || opts.lcov.is_some()
+            || opts.block_coverage.is_some()
+            || opts.callgraph.is_some()
+            || opts.compare.is_some()
+            || opts.record.is_some()
+            || opts.replay.is_some() =>
+        {
+            eprintln!(
+                "error: --signature, --folded-stacks, --callgraph, --cfg, --hot-functions, \
+                 --pc-samples, --heatmap, --stack-usage, --check-heap, --lcov, \
+                 --block-coverage, --compare, --record and --replay require a single program"
+            );
+            process::exit(2);
+        }
+        _ => run_batch(&opts, &programs, &coverage),
+    }
+}
+
+fn run_single(opts: &RunOpts, program: &Path, coverage: &Arc<Mutex<IsaCoverage>>) {
+    let (report, signature) = execute(opts, program, coverage);
+    write_coverage(opts, coverage);
+
+    if let Some(path) = &opts.signature {
+        match signature {
+            Some(words) => signature::write(&words, File::create(path).unwrap()).unwrap(),
+            None => {
+                eprintln!("error: program has no begin_signature/end_signature symbols");
+                process::exit(2);
+            }
+        }
+    }
+
+    if let Some(path) = &opts.stats_json {
+        fs::write(path, format!("{:#}\n", report.to_json())).unwrap();
+    }
+
+    if let Some(path) = &opts.counters {
+        fs::write(path, format!("{:#}\n", report.counters_json())).unwrap();
+    }
+
+    if opts.state_hash {
+        println!("State hash: {:016x}", report.state_hash);
+    }
+
+    if opts.stats {
+        print_mix(std::slice::from_ref(&report));
+    }
+
+    if let Some(unmet) = &report.unmet {
+        for message in unmet {
+            eprintln!("{}", message);
+        }
+        match unmet.is_empty() {
+            true if !opts.quiet => {
+                println!("Program passed ({}).", report.total());
+            }
+            true => {}
+            false => process::exit(EXIT_FAULT),
+        }
+        return;
+    }
+
+    match report.outcome {
+        Outcome::Exited | Outcome::HostExit(1) => {
+            if !opts.quiet {
+                println!("Program finished ({}).", report.total());
+            }
+        }
+        Outcome::HostExit(value) => {
+            eprintln!("Program failed (tohost={:#x}).", value);
+            process::exit(EXIT_FAULT);
+        }
+        Outcome::Fault(err) => {
+            match err.pc() {
+                Some(_) => eprintln!("Program faulted: {}.", report.error.unwrap()),
+                None => eprintln!("Program faulted at {:#010x}: {}.", report.pc, err),
+            }
+            if let Some(dump) = &report.dump {
+                eprint!("{}", dump);
+            }
+            process::exit(EXIT_FAULT);
+        }
+        Outcome::CycleLimit => {
+            eprintln!("Cycle limit reached ({}).", report.total());
+            process::exit(EXIT_CYCLE_LIMIT);
+        }
+        Outcome::Diverged => process::exit(EXIT_FAULT),
+        Outcome::Assertion | Outcome::StackOverflow => {
+            eprintln!("Program failed: {}.", report.error.unwrap());
+            process::exit(EXIT_FAULT);
+        }
+        Outcome::Hang => {
+            eprintln!("Program hung at {:#010x} ({}).", report.pc, report.total());
+            process::exit(EXIT_HANG);
+        }
+        Outcome::Timeout => {
+            eprintln!("Timeout reached ({}).", report.total());
+            process::exit(EXIT_TIMEOUT);
+        }
+    }
+}
+
+fn run_batch(opts: &RunOpts, programs: &[PathBuf], coverage: &Arc<Mutex<IsaCoverage>>) {
+    let reports = programs
+        .iter()
+        .map(|program| execute(opts, program, coverage).0)
+        .collect::<Vec<_>>();
+    write_coverage(opts, coverage);
+
+    if let Some(path) = &opts.stats_json {
+        let reports = reports.iter().map(Report::to_json).collect::<Vec<_>>();
+        fs::write(path, format!("{:#}\n", Value::Array(reports))).unwrap();
+    }
+
+    if let Some(path) = &opts.counters {
+        let counters = reports
+            .iter()
+            .map(Report::counters_json)
+            .collect::<Vec<_>>();
+        fs::write(path, format!("{:#}\n", Value::Array(counters))).unwrap();
+    }
+
+    let width = programs
+        .iter()
+        .map(|program| program.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("Program".len());
+
+    let hash = |report: Option<&Report>| match (opts.state_hash, report) {
+        (false, _) => String::new(),
+        (true, Some(report)) => format!("{:016x}  ", report.state_hash),
+        (true, None) => format!("{:<16}  ", "State hash"),
+    };
+
+    if !opts.quiet {
+        println!(
+            "{:<w$}  {:>12}  {}Result",
+            "Program",
+            "Cycles",
+            hash(None),
+            w = width
+        );
+    }
+    for report in &reports {
+        if opts.quiet && report.passed() {
+            continue;
+        }
+        println!(
+            "{:<w$}  {:>12}  {}{}",
+            report.program.display().to_string(),
+            report.cycles,
+            hash(Some(report)),
+            report.result(),
+            w = width
+        );
+    }
+
+    let passed = reports.iter().filter(|report| report.passed()).count();
+    if !opts.quiet {
+        println!("\n{} passed, {} failed.", passed, reports.len() - passed);
+    }
+
+    if opts.stats {
+        println!();
+        print_mix(&reports);
+    }
+
+    if passed != reports.len() {
+        process::exit(EXIT_FAULT);
+    }
+}
+
+/// Prints the instructions retired by all `reports` grouped by kind, with
+/// their share of the total.
+fn print_mix(reports: &[Report]) {
+    let groups = Category::GROUPS.iter().map(|&group| {
+        let count = Category::ALL
+            .iter()
+            .filter(|category| category.group() == group)
+            .flat_map(|category| {
+                reports
+                    .iter()
+                    .filter_map(move |r| r.mix.get(category.name()))
+            })
+            .sum::<u64>();
+        (group, count)
+    });
+    let groups = groups.collect::<Vec<_>>();
+
+    let total = groups.iter().map(|(_, count)| count).sum::<u64>();
+    println!("{:<8} {:>12} {:>7}", "Kind", "Instructions", "%");
+    for (group, count) in groups {
+        let percent = match total {
+            0 => 0.0,
+            total => 100.0 * count as f64 / total as f64,
+        };
+        println!("{:<8} {:>12} {:>6.2}%", group, count, percent);
+    }
+    println!("{:<8} {:>12}", "total", total);
+}
+
+/// Writes the coverage of every program run so far, if requested.
+fn write_coverage(opts: &RunOpts, coverage: &Arc<Mutex<IsaCoverage>>) {
+    if let Some(path) = &opts.isa_coverage {
+        let file = BufWriter::new(File::create(path).unwrap());
+        coverage.lock().unwrap().write(file).unwrap();
+    }
+}
+
+/// Poisons memory and every register but the ones the ABI gives initial
+/// values to. Random values come from `seed` if given, or else differ on
+/// every run.
+fn reset_config(poison: Poison, seed: Option<u64>) -> ResetConfig {
+    let (poison, poison_seed) = match poison {
+        Poison::Pattern(pattern) => (pattern, None),
+        Poison::Random => {
+            let seed = seed.unwrap_or_else(|| {
+                let mut entropy = Entropy::host();
+                (entropy.next_u32() as u64) << 32 | entropy.next_u32() as u64
+            });
+            (0, Some(seed))
+        }
+    };
+    ResetConfig {
+        poison,
+        poison_seed,
+        poison_memory: true,
+        ..ResetConfig::default()
+    }
+}
+
+/// Reads the shared object `name` from the first `--library-path`
+/// directory holding it, or else from the directory of `program`.
+fn find_library(opts: &RunOpts, program: &Path, name: &str) -> Option<Vec<u8>> {
+    let dir = program.parent().unwrap_or_else(|| Path::new(""));
+    opts.library_path
+        .iter()
+        .map(PathBuf::as_path)
+        .chain(Some(dir))
+        .find_map(|dir| fs::read(dir.join(name)).ok())
+}
+
+/// Resolves the address or symbol given to `flag`, exiting if there is none.
+fn roi_address<W: Write>(sim: &Simulator<W>, flag: &str, roi: &str) -> u32 {
+    match parse_address(roi).or_else(|| sim.symbols().get(roi).map(|sym| sym.address)) {
+        Some(address) => address,
+        None => {
+            eprintln!("error: {}: no symbol named {}", flag, roi);
+            process::exit(2);
+        }
+    }
+}
+
+fn print_load_info<W: Write>(sim: &Simulator<W>, program: &Path) {
+    println!("Loaded {}", program.display());
+    for segment in sim.segments() {
+        println!(
+            "  segment {:#010x}..{:#010x} ({} bytes)",
+            segment.start,
+            segment.end,
+            segment.end - segment.start
+        );
+    }
+    println!("  entry   {:#010x}", sim.pc());
+    println!("  stack   {:#010x}", sim.read_reg(IntRegister::SP));
+    println!("  memory  {} bytes", sim.processor().memory().size());
+    println!("  symbols {}", sim.symbols().iter().count());
+}
+
+fn print_map<W: Write>(sim: &Simulator<W>) {
+    let range = |start: u32, end: u32| format!("{:#010x}..{:#010x}", start, end);
+    let memory = sim.processor().memory().size();
+    let sp = sim.read_reg(IntRegister::SP);
+
+    println!("{}  memory ({} KiB)", range(0, memory), memory / 1024);
+    for segment in sim.segments() {
+        println!("{}  segment", range(segment.start, segment.end));
+    }
+    for section in sim.sections() {
+        let end = section.address + section.size;
+        println!("{}  {}", range(section.address, end), section.name);
+    }
+
+    // newlib's sbrk grows the heap from `end` towards the stack
+    let heap = ["_end", "end"]
+        .iter()
+        .find_map(|name| sim.symbols().get(name))
+        .map(|sym| sym.address)
+        .or_else(|| sim.segments().iter().map(|s| s.end).max());
+    if let Some(heap) = heap.filter(|&heap| heap < sp) {
+        println!("{}  heap and stack (sp = {:#010x})", range(heap, sp), sp);
+    }
+
+    let devices = sim.processor().devices();
+    for (base, size, device) in devices.iter() {
+        let end = base as u64 + size as u64;
+        println!("{:#010x}..{:#010x}  {}", base, end, device.name());
+    }
+    if let Some(base) = sim.processor().interrupt_base() {
+        let end = base + interrupt::SIZE;
+        println!("{}  irq", range(base, end));
+    }
+}
+
+/// Describes the first line where the guest's standard output `actual`
+/// differs from the contents of `path`, if it does.
+fn diff_output(path: &Path, expected: &[u8], actual: &[u8]) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let expected = String::from_utf8_lossy(expected);
+    let actual = String::from_utf8_lossy(actual);
+    let mut lines = expected.split('\n').zip(actual.split('\n')).enumerate();
+    let (line, expected, actual) = match lines.find(|(_, (expected, actual))| expected != actual) {
+        Some((line, (expected, actual))) => {
+            (line, format!("{:?}", expected), format!("{:?}", actual))
+        }
+        None => {
+            let (expected, actual) = (expected.split('\n'), actual.split('\n'));
+            let line = expected.clone().count().min(actual.clone().count());
+            let end = "end of output".to_string();
+            let next = |mut lines: std::str::Split<'_, char>| {
+                lines
+                    .nth(line)
+                    .map_or(end.clone(), |text| format!("{:?}", text))
+            };
+            (line, next(expected), next(actual))
+        }
+    };
+    Some(format!(
+        "output differs from {} at line {}:\n  expected: {}\n  actual:   {}",
+        path.display(),
+        line + 1,
+        expected,
+        actual
+    ))
+}
+
+/// Memory around the address `err` accessed if it is in memory, or else the
+/// top of the stack, if there is any.
+fn fault_dump<W: Write>(sim: &Simulator<W>, err: &ProcessorError) -> Option<String> {
+    let memory = sim.processor().memory();
+    let (title, start) = match *err {
+        ProcessorError::IllegalAccess { address, .. }
+        | ProcessorError::MisalignedLoad { address, .. }
+        | ProcessorError::MisalignedStore { address, .. }
+        | ProcessorError::CodeWrite { address, .. }
+            if memory.contains(address, 1) =>
+        {
+            (
+                format!("Memory around {:#010x}", address),
+                (address & !15).saturating_sub(16),
+            )
+        }
+        _ => {
+            let sp = sim.read_reg(IntRegister::SP);
+            (format!("Stack at sp={:#010x}", sp), sp)
+        }
+    };
+    let dump = memory
+        .hexdump(start..start.saturating_add(64))
+        .with_symbols(sim.symbols())
+        .to_string();
+    match dump.is_empty() {
+        true => None,
+        false => Some(format!("{}:\n{}", title, dump)),
+    }
+}
+
+fn print_explanation<W: Write>(
+    sim: &Simulator<W>,
+    pc: u32,
+    inst: &Instruction,
+    before: &IntRegisterSet,
+) {
+    let after = sim.processor().registers();
+    println!(
+        "[PC={:08X}] {:<24} {}",
+        pc,
+        inst.display_at(pc).with_symbols(sim.symbols()).to_string(),
+        explain(pc, inst, before, after)
+    );
+}
+
+/// Runs a program to completion, also returning its test signature if it
+/// has one. Executed instructions are added to `coverage` when
+/// `--isa-coverage` is given.
+fn execute(
+    opts: &RunOpts,
+    program: &Path,
+    coverage: &Arc<Mutex<IsaCoverage>>,
+) -> (Report, Option<Vec<u32>>) {
+    let stdout = io::stdout();
+    let memory = opts.memory * 1048576;
+    let logger = match opts.log || opts.verbose >= 2 {
+        true => Some(stdout.lock()),
+        false => None,
+    };
+
+    let mut sim = match &opts.resume {
+        Some(snapshot) => Simulator::resume_from(snapshot, logger).unwrap(),
+        None => {
+            let buffer = fs::read(program).unwrap();
+            let offset = opts.load_offset.map_or(0, |LoadOffset(offset)| offset);
+            let library = |name: &str| find_library(opts, program, name);
+            let mut sim =
+                Simulator::from_linked_bytes(&buffer, offset, memory, opts.pc, logger, library)
+                    .unwrap();
+            if let Some(poison) = opts.poison {
+                sim.reset(&reset_config(poison, opts.seed));
+            }
+            // The entry stack holds words in the guest's byte order
+            sim.processor_mut().set_endianness(opts.endian);
+            if !opts.args.is_empty() || !opts.env.is_empty() {
+                let mut args = vec![program.display().to_string()];
+                args.extend(opts.args.iter().cloned());
+                sim.set_args(&args, &opts.env).unwrap();
+            }
+            sim
+        }
+    };
+    sim.set_pseudo(opts.pseudo);
+
+    if opts.verbose >= 1 {
+        print_load_info(&sim, program);
+    }
+
+    if let Some(isa) = &opts.isa {
+        if let Err(err) = sim.set_isa(isa.clone()) {
+            eprintln!("error: {}", err);
+            process::exit(2);
+        }
+    }
+    sim.set_deterministic(opts.seed);
+    sim.set_latency_jitter(opts.latency_jitter);
+    sim.processor_mut().set_check_division(opts.check_division);
+    sim.processor_mut()
+        .set_log_device_events(opts.device_events);
+    sim.set_env_calls(opts.env_calls);
+    sim.set_ebreak_mode(opts.ebreak);
+    sim.processor_mut()
+        .set_strict_alignment(opts.strict_alignment);
+    sim.processor_mut().set_smc_policy(opts.smc);
+    sim.processor_mut().set_endianness(opts.endian);
+
+    let input_log = match (&opts.record, &opts.replay) {
+        (Some(_), _) => Some(InputLog::recorder()),
+        (_, Some(path)) => match File::open(path).and_then(|f| InputLog::load(BufReader::new(f))) {
+            Ok(log) => Some(log),
+            Err(err) => {
+                eprintln!("error: {}: {}", path.display(), err);
+                process::exit(2);
+            }
+        },
+        _ => None,
+    };
+    sim.set_input_log(input_log.clone());
+
+    let rtc = Rtc::new(sim.entropy()).with_source(opts.time_source);
+    sim.map_device(RTC_BASE, 8, Box::new(rtc)).unwrap();
+    let rng = Rng(sim.entropy());
+    sim.map_device(RNG_BASE, 4, Box::new(rng)).unwrap();
+    let console = Console::new();
+    sim.map_console(CONSOLE_BASE, console.clone()).unwrap();
+    let stdout = opts
+        .expect_stdout
+        .as_ref()
+        .map(|_| console.capture(Stream::Stdout));
+
+    if opts.print_map {
+        print_map(&sim);
+    }
+
+    if opts.isa_coverage.is_some() {
+        let coverage = coverage.clone();
+        sim.add_pre_hook(move |_, _, inst| {
+            coverage.lock().unwrap().record(inst);
+            HookAction::Continue
+        });
+    }
+
+    let snapshot = program.with_extension("snap");
+    let kept = opts.checkpoints.unwrap_or(4);
+    if opts.checkpoint_interval == Some(0) || kept == 0 {
+        eprintln!("error: --checkpoint-interval and --checkpoints must not be zero");
+        process::exit(2);
+    }
+    if opts.snapshot_every == Some(0) {
+        eprintln!("error: --snapshot-every must not be zero");
+        process::exit(2);
+    }
+    // Both would read standard input, which the console already holds
+    if opts.console.is_some() && opts.ebreak == EbreakMode::Break {
+        eprintln!("error: --console cannot be used with --ebreak break");
+        process::exit(2);
+    }
+    let mut checkpoints = opts
+        .checkpoint_interval
+        .map(|interval| Checkpoints::new(interval, kept));
+    let run_options = RunOptions {
+        max_cycles: opts.max_cycles,
+        deadline: opts
+            .timeout
+            .map(|secs| Instant::now() + Duration::from_secs(secs)),
+        ..RunOptions::default()
+    };
+
+    let start = Instant::now();
+    let mut instructions = 0u64;
+    let mut mix = BTreeMap::new();
+    let mut stack = CallStack::new();
+    let mut stack_usage = StackUsage::new(sim.pc(), sim.read_reg(IntRegister::SP));
+    let mut heap = match opts.check_heap {
+        true => {
+            let heap = HeapChecker::new(sim.symbols());
+            if heap.is_none() {
+                eprintln!("warning: --check-heap found no malloc in the symbol table");
+            }
+            heap
+        }
+        false => None,
+    };
+    let printf = match opts.decode_printf {
+        true => {
+            let printf = PrintfDecoder::new(sim.symbols());
+            if printf.is_none() {
+                eprintln!("warning: --decode-printf found no printf or puts in the symbol table");
+            }
+            printf
+        }
+        false => None,
+    };
+    let lines = match &opts.lcov {
+        Some(_) => {
+            let lines = fs::read(program)
+                .map_err(|err| err.to_string())
+                .and_then(|buffer| {
+                    LineTable::from_bytes(&buffer).map_err(|err| format!("{:?}", err))
+                });
+            match lines {
+                Ok(lines) if lines.is_empty() => {
+                    eprintln!("warning: --lcov found no line information in the program");
+                    Some(lines)
+                }
+                Ok(lines) => Some(lines),
+                Err(err) => {
+                    eprintln!("error: {}: {}", program.display(), err);
+                    process::exit(2);
+                }
+            }
+        }
+        None => None,
+    };
+    let mut line_coverage = LineCoverage::new();
+    let canary = opts.stack_limit.as_ref().map(|limit| {
+        let limit = roi_address(&sim, "--stack-limit", limit);
+        match StackCanary::place(limit, sim.processor_mut().memory_mut()) {
+            Some(canary) => canary,
+            None => {
+                eprintln!(
+                    "error: --stack-limit: no room for a canary below {:#010x}",
+                    limit
+                );
+                process::exit(2);
+            }
+        }
+    });
+    let mut callgraph = CallGraph::new(sim.pc());
+    let mut folded = FoldedStacks::new();
+    let mut cfg = ControlFlowGraph::new();
+    let mut functions = FunctionProfile::new();
+    let mut pc_samples = PcSamples::new();
+    if opts.heatmap_bucket == 0 {
+        eprintln!("error: --heatmap-bucket must not be zero");
+        process::exit(2);
+    }
+    let mut heatmap = Heatmap::new(opts.heatmap_bucket);
+    let mut watched = opts.watch.iter().map(|w| w.read(&sim)).collect::<Vec<_>>();
+    let mut reference = opts
+        .compare
+        .as_ref()
+        .map(|path| Reference::open(path, sim.pc()).unwrap());
+
+    let roi_start = opts
+        .roi_start
+        .as_ref()
+        .map(|roi| roi_address(&sim, "--roi-start", roi));
+    let roi_end = opts
+        .roi_end
+        .as_ref()
+        .map(|roi| roi_address(&sim, "--roi-end", roi));
+    let mut region = Region::new();
+
+    let assertions = opts.assertions.as_ref().map(|path| {
+        let text = fs::read_to_string(path).unwrap();
+        match Assertions::parse(&text, sim.symbols()) {
+            Ok(assertions) => assertions,
+            Err(err) => {
+                eprintln!("error: {}: {}", path.display(), err);
+                process::exit(2);
+            }
+        }
+    });
+
+    // The terminal stays in raw mode until the console is dropped after the
+    // loop. Checkers run in this order, so stepping shows the final state.
+    let console_input = opts
+        .console
+        .map(|mode| ConsoleInput::start(mode, opts.forward_ctrl_c));
+    let mut checkers: Vec<Box<dyn Checker<_>>> =
+        vec![Box::new(ConsoleIo::new(console.clone(), console_input))];
+    if let Some(assertions) = assertions {
+        checkers.push(Box::new(Assert::new(assertions)));
+    }
+    if opts.progress {
+        checkers.push(Box::new(Progress::new(start)));
+    }
+    if !opts.inject.is_empty() {
+        checkers.push(Box::new(Inject(opts.inject.clone())));
+    }
+    if let Some(printf) = printf {
+        checkers.push(Box::new(Printf(printf)));
+    }
+    if opts.check_returns {
+        checkers.push(Box::new(Shadow(ShadowStack::new())));
+    }
+    if let Some(canary) = canary {
+        checkers.push(Box::new(Canary::new(canary)));
+    }
+    if let Some(every) = opts.snapshot_every {
+        let path = snapshot.clone();
+        checkers.push(Box::new(Snapshots { every, path }));
+    }
+    if opts.interactive || opts.ebreak == EbreakMode::Break {
+        let stdin = io::stdin();
+        let stepper = Stepper::new(stdin.lock(), opts.interactive, opts.numeric_registers);
+        checkers.push(Box::new(stepper));
+    }
+
+    let outcome = loop {
+        if Some(sim.pc()) == roi_end {
+            region.leave(instructions, sim.cycles(), &mix);
+        }
+        if Some(sim.pc()) == roi_start {
+            region.enter(instructions, sim.cycles(), &mix);
+        }
+
+        let stop = checkers
+            .iter_mut()
+            .find_map(|checker| checker.before_step(&mut sim, instructions));
+        if let Some(outcome) = stop {
+            break outcome;
+        }
+
+        if let Some(checkpoints) = &mut checkpoints {
+            checkpoints.update(&sim);
+        }
+
+        let before = *sim.processor().registers();
+        let cycles = sim.cycles();
+        let step = sim.step_with(&run_options);
+        let (retired, breakpoint) = match step.exit {
+            Some(ExitEvent::CycleLimit) => break Outcome::CycleLimit,
+            Some(ExitEvent::Timeout) => break Outcome::Timeout,
+            Some(ExitEvent::Fault { err, .. }) => break Outcome::Fault(err),
+            Some(ExitEvent::Ebreak) if opts.ebreak == EbreakMode::Break => {
+                instructions += 1;
+                *mix.entry(Category::System.name()).or_insert(0) += 1;
+                let retired = step.retired.unwrap();
+                match sim.symbols().lookup(retired.pc) {
+                    Some(sym) => println!("Breakpoint at {:#010x} <{}>", retired.pc, sym),
+                    None => println!("Breakpoint at {:#010x}", retired.pc),
+                }
+                (retired, true)
+            }
+            Some(ExitEvent::EcallExit) | Some(ExitEvent::Ebreak) => {
+                instructions += 1;
+                let RetiredInstruction { pc, inst, .. } = step.retired.unwrap();
+                if opts.explain {
+                    print_explanation(&sim, pc, &inst, &before);
+                }
+                if opts.cfg.is_some() || opts.block_coverage.is_some() {
+                    cfg.record_exit(pc, &inst);
+                }
+                if opts.hot_functions.is_some() {
+                    functions.record(pc, (sim.cycles() - cycles) as u64);
+                }
+                if opts.callgraph.is_some() {
+                    callgraph.update(&stack);
+                }
+                if lines.is_some() {
+                    line_coverage.record(pc);
+                }
+                *mix.entry(Category::System.name()).or_insert(0) += 1;
+                break Outcome::Exited;
+            }
+            _ => {
+                // The instruction retired, and the run may stop after it
+                let retired = step.retired.unwrap();
+                instructions += 1;
+                for diagnostic in sim.processor_mut().take_diagnostics() {
+                    eprintln!("warning: {}", diagnostic);
+                }
+                for event in sim.processor_mut().take_device_events() {
+                    println!("{}", event);
+                }
+                if opts.hot_functions.is_some() {
+                    functions.record(retired.pc, (sim.cycles() - cycles) as u64);
+                }
+                if opts.explain {
+                    print_explanation(&sim, retired.pc, &retired.inst, &before);
+                }
+                let category = retired.inst.category();
+                *mix.entry(category.name()).or_insert(0u64) += 1;
+                if let Some(reference) = &mut reference {
+                    if !reference.check(Commit::from_retired(&retired, sim.processor())) {
+                        match opts.numeric_registers {
+                            true => eprint!("{:#}", sim.processor().registers()),
+                            false => eprint!("{}", sim.processor().registers()),
+                        }
+                        break Outcome::Diverged;
+                    }
+                }
+                for (watch, old) in opts.watch.iter().zip(&mut watched) {
+                    let new = watch.read(&sim);
+                    if let (Some(old), Some(new)) = (*old, new) {
+                        if old != new {
+                            println!(
+                                "[PC={:08X}] {}: {:#010x} -> {:#010x}",
+                                retired.pc, watch, old, new
+                            );
+                        }
+                    }
+                    *old = new;
+                }
+                if opts.cfg.is_some() || opts.block_coverage.is_some() {
+                    cfg.record(retired.pc, &retired.inst, sim.pc());
+                }
+                if opts.heatmap.is_some() {
+                    heatmap.record(&retired.inst, &before);
+                }
+                if opts.folded_stacks.is_some() || opts.stack_usage || opts.callgraph.is_some() {
+                    stack.update(retired.pc, &retired.inst, sim.pc());
+                }
+                if opts.callgraph.is_some() {
+                    callgraph.update(&stack);
+                }
+                if opts.pc_samples.is_some() && instructions.is_multiple_of(opts.sample_every) {
+                    pc_samples.sample(retired.pc);
+                }
+                if opts.folded_stacks.is_some() && instructions.is_multiple_of(opts.sample_every) {
+                    folded.sample(&stack, sim.pc());
+                }
+                if opts.stack_usage {
+                    stack_usage.update(&stack, sim.read_reg(IntRegister::SP));
+                }
+                if let Some(heap) = &mut heap {
+                    heap.check(retired.pc, &retired.inst, &before);
+                }
+                if lines.is_some() {
+                    line_coverage.record(retired.pc);
+                }
+                (retired, false)
+            }
+        };
+
+        for marker in sim.take_markers() {
+            if opts.verbose >= 1 {
+                println!("Marker: {}", marker);
+            }
+            match marker {
+                Marker::RoiBegin { .. } => region.enter(instructions, sim.cycles(), &mix),
+                Marker::RoiEnd { .. } => region.leave(instructions, sim.cycles(), &mix),
+                _ => {}
+            }
+        }
+
+        let retired = Retired {
+            pc: retired.pc,
+            inst: &retired.inst,
+            before: &before,
+            breakpoint,
+            last: matches!(
+                step.exit,
+                Some(ExitEvent::Hang { .. }) | Some(ExitEvent::HostExit(_))
+            ),
+        };
+        let stop = checkers
+            .iter_mut()
+            .find_map(|checker| checker.after_step(&mut sim, &retired));
+        if let Some(outcome) = stop {
+            break outcome;
+        }
+        match step.exit {
+            Some(ExitEvent::Hang { .. }) => break Outcome::Hang,
+            Some(ExitEvent::HostExit(value)) => break Outcome::HostExit(value),
+            _ => {}
+        }
+    };
+
+    let violation = checkers.iter().find_map(|checker| checker.violation());
+    drop(checkers);
+
+    if opts.progress {
+        // Clears the status line
+        eprint!("\r\x1b[2K");
+    }
+
+    if let Some(path) = &opts.heatmap {
+        if let Err(err) = crate::heatmap::write(&heatmap, path) {
+            eprintln!("error: {}: {}", path.display(), err);
+            process::exit(2);
+        }
+    }
+
+    if let Some(path) = &opts.cfg {
+        let file = BufWriter::new(File::create(path).unwrap());
+        cfg.write_dot(sim.symbols(), opts.cfg_counts, file).unwrap();
+    }
+
+    if let Some(path) = &opts.block_coverage {
+        if let Err(err) = crate::block_coverage::write(&cfg, sim.symbols(), path) {
+            eprintln!("error: {}: {}", path.display(), err);
+            process::exit(2);
+        }
+    }
+
+    if let Some(path) = &opts.callgraph {
+        let file = BufWriter::new(File::create(path).unwrap());
+        callgraph.write_callgrind(sim.symbols(), file).unwrap();
+    }
+
+    if let (Some(path), Some(lines)) = (&opts.lcov, &lines) {
+        let file = BufWriter::new(File::create(path).unwrap());
+        line_coverage.write_lcov(lines, file).unwrap();
+    }
+
+    if let Some(path) = &opts.pc_samples {
+        let file = BufWriter::new(File::create(path).unwrap());
+        pc_samples.write(sim.symbols(), file).unwrap();
+    }
+
+    if let Some(path) = &opts.folded_stacks {
+        let file = BufWriter::new(File::create(path).unwrap());
+        folded.write(sim.symbols(), file).unwrap();
+    }
+
+    if let (Some(path), Some(log)) = (&opts.record, &input_log) {
+        let file = BufWriter::new(File::create(path).unwrap());
+        log.save(file).unwrap();
+    }
+    if let Some(log) = input_log.filter(|log| log.is_replaying() && log.diverged()) {
+        eprintln!(
+            "warning: the run diverged from the replay log after {} inputs",
+            log.len()
+        );
+    }
+
+    if opts.dump_regs {
+        match opts.numeric_registers {
+            true => print!("{:#}", sim.processor().registers()),
+            false => print!("{}", sim.processor().registers()),
+        }
+        println!("{:>4}={:#010X}", "pc", sim.pc());
+    }
+
+    if opts.stack_usage {
+        stack_usage.write(sim.symbols(), io::stdout()).unwrap();
+    }
+
+    if let Some(heap) = &heap {
+        heap.write(sim.symbols(), io::stdout()).unwrap();
+    }
+
+    if let Some(top) = opts.hot_functions {
+        functions.write(sim.symbols(), top, io::stdout()).unwrap();
+    }
+
+    region.leave(instructions, sim.cycles(), &mix);
+
+    let mut report = Report {
+        program: program.to_path_buf(),
+        outcome,
+        pc: sim.pc(),
+        a0: sim.read_reg(IntRegister::A0),
+        instructions,
+        cycles: sim.cycles(),
+        mix,
+        roi: region.marked,
+        state_hash: sim.state_hash(),
+        elapsed: start.elapsed(),
+        error: match outcome {
+            Outcome::Fault(err) => Some(err.describe(sim.symbols())),
+            Outcome::Assertion | Outcome::StackOverflow => violation,
+            _ => None,
+        },
+        dump: match outcome {
+            Outcome::Fault(err) => fault_dump(&sim, &err),
+            _ => None,
+        },
+        unmet: None,
+    };
+    if region.marked {
+        report.instructions = region.instructions;
+        report.cycles = region.cycles;
+        report.mix = region.mix;
+        report.elapsed = region.elapsed;
+    }
+    report.unmet = report.check(
+        opts,
+        stdout.as_ref().map(|capture| capture.contents()).as_deref(),
+    );
+    let signature = sim.signature();
+
+    if let (Outcome::Fault(_) | Outcome::Diverged, Some(checkpoints)) = (outcome, &mut checkpoints)
+    {
+        if let Some(cycles) = checkpoints.rollback_last(&mut sim) {
+            sim.save_snapshot(&snapshot).unwrap();
+            eprintln!(
+                "Saved the checkpoint from cycle {} to {}; retry from there with --resume.",
+                cycles,
+                snapshot.display()
+            );
+        }
+    }
+    (report, signature)
+}
@@ -0,0 +1,508 @@
+use clap::Clap;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use yars_lib::device::Console;
+use yars_lib::instruction::Instruction;
+use yars_lib::register::IntRegister;
+use yars_lib::simulator::{EbreakMode, ExitEvent, RunOptions, Simulator};
+
+use crate::watch::parse_address;
+
+/// Instructions `run` executes when the request gives no limit.
+const RUN_LIMIT: usize = 10_000_000;
+/// Most bytes `read_memory` returns, or instructions `disassemble` decodes,
+/// at once.
+const READ_LIMIT: u32 = 4096;
+/// Largest request body accepted, in bytes.
+const BODY_LIMIT: usize = 1 << 20;
+/// Most bytes read for the request line and headers.
+const HEADER_LIMIT: usize = 64 << 10;
+/// How long a client may take to send its request before it is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Where the console is mapped, as in `yars run`.
+const CONSOLE_BASE: u32 = 0xFFFF_0020;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SIMULATOR_ERROR: i64 = -32000;
+
+const WEB_UI: &str = include_str!("../web/index.html");
+
+#[derive(Clap)]
+pub struct ServeOpts {
+    #[clap(long, value_name = "address", default_value = "127.0.0.1:7878")]
+    #[clap(about = "Listens for HTTP requests on <address>")]
+    listen: String,
+
+    #[clap(short, long, value_name = "size", default_value = "32")]
+    #[clap(about = "Allocate <size> MiB for target memory")]
+    memory: u32,
+
+    #[clap(long)]
+    #[clap(about = "Also serves a web debugger at /")]
+    web: bool,
+
+    #[clap(long, value_name = "dir")]
+    #[clap(about = "Lets the load method open programs under <dir>")]
+    programs: Option<PathBuf>,
+
+    #[clap(about = "Path to a RISC-V program to load before serving")]
+    program: Option<PathBuf>,
+}
+
+pub fn run(opts: ServeOpts) {
+    let canonical = |path: &Path| match path.canonicalize() {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("error: {}: {}", path.display(), err);
+            process::exit(2);
+        }
+    };
+    let mut session = Session {
+        memory: opts.memory * 1048576,
+        sim: None,
+        breakpoints: BTreeSet::new(),
+        console: Console::new(),
+        program: opts.program.as_deref().map(canonical),
+        programs: opts.programs.as_deref().map(canonical),
+    };
+    if let Some(program) = &opts.program {
+        if let Err(err) = session.load(program, None) {
+            eprintln!("error: {}: {}", program.display(), err);
+            process::exit(2);
+        }
+    }
+
+    let listener = match TcpListener::bind(&opts.listen) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("error: {}: {}", opts.listen, err);
+            process::exit(2);
+        }
+    };
+    match opts.web {
+        true => eprintln!("Open http://{}/ in a browser", opts.listen),
+        false => eprintln!("Listening on http://{}", opts.listen),
+    }
+
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| serve(&mut session, stream, opts.web));
+        if let Err(err) = result {
+            eprintln!("warning: {}", err);
+        }
+    }
+}
+
+/// The simulator driven by the requests, shared by every client.
+struct Session {
+    memory: u32,
+    sim: Option<Simulator<io::Sink>>,
+    breakpoints: BTreeSet<u32>,
+    console: Console,
+    /// The program given on the command line, which `load` may reopen.
+    program: Option<PathBuf>,
+    /// The directory whose programs `load` may open.
+    programs: Option<PathBuf>,
+}
+
+/// A JSON-RPC error code and message.
+type RpcError = (i64, String);
+
+impl Session {
+    fn load(&mut self, program: &Path, pc: Option<u32>) -> Result<(), String> {
+        let mut sim =
+            Simulator::new(program, self.memory, pc, None).map_err(|e| format!("{:?}", e))?;
+        let console = Console::new();
+        sim.map_device(CONSOLE_BASE, Console::SIZE, Box::new(console.clone()))
+            .map_err(|e| format!("{:?}", e))?;
+        // Running again after an ebreak continues past it
+        sim.set_ebreak_mode(EbreakMode::Break);
+        self.sim = Some(sim);
+        self.breakpoints.clear();
+        self.console = console;
+        Ok(())
+    }
+
+    /// Answers a JSON-RPC 2.0 request, or a batch of them.
+    fn handle(&mut self, body: &[u8]) -> Value {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Array(batch)) => batch.iter().map(|r| self.handle_one(r)).collect(),
+            Ok(request) => self.handle_one(&request),
+            Err(err) => response(Value::Null, Err((PARSE_ERROR, err.to_string()))),
+        }
+    }
+
+    fn handle_one(&mut self, request: &Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = match request.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            None => {
+                let error = (INVALID_REQUEST, "expected a method".to_string());
+                return response(id, Err(error));
+            }
+        };
+        let empty = Map::new();
+        let params = request
+            .get("params")
+            .and_then(Value::as_object)
+            .unwrap_or(&empty);
+        response(id, self.call(method, params))
+    }
+
+    /// Resolves a program `load` was asked to open, refusing any outside
+    /// the ones given on the command line.
+    fn allowed(&self, program: &Path) -> Result<PathBuf, RpcError> {
+        let denied = || {
+            (
+                INVALID_PARAMS,
+                format!("{} is not servable", program.display()),
+            )
+        };
+        let program = program.canonicalize().map_err(|_| denied())?;
+        let listed = self.program.as_ref() == Some(&program);
+        let inside = self
+            .programs
+            .as_ref()
+            .is_some_and(|dir| program.starts_with(dir));
+        match listed || inside {
+            true => Ok(program),
+            false => Err(denied()),
+        }
+    }
+
+    fn call(&mut self, method: &str, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        if method == "load" {
+            let program = self.allowed(Path::new(string(params, "program")?))?;
+            let pc = optional(params, "pc", |v| address(None, v))?;
+            self.load(&program, pc).map_err(|e| (SIMULATOR_ERROR, e))?;
+            return Ok(self.state());
+        }
+
+        match method {
+            "set_breakpoint" | "clear_breakpoint" => {
+                let sim = self.sim()?;
+                let address = address(Some(sim), field(params, "address")?)?;
+                match method {
+                    "set_breakpoint" => self.breakpoints.insert(address),
+                    _ => self.breakpoints.remove(&address),
+                };
+                return Ok(json!({ "breakpoints": self.breakpoints }));
+            }
+            "breakpoints" => return Ok(json!({ "breakpoints": self.breakpoints })),
+            "read_console" => {
+                let output = String::from_utf8_lossy(&self.console.take_output()).into_owned();
+                let errors = String::from_utf8_lossy(&self.console.take_errors()).into_owned();
+                return Ok(json!({ "output": output, "errors": errors }));
+            }
+            "write_console" => {
+                self.console.push_input(string(params, "input")?.as_bytes());
+                return Ok(json!({}));
+            }
+            _ => {}
+        }
+
+        let breakpoints = &self.breakpoints;
+        let sim = self.sim.as_mut().ok_or_else(no_program)?;
+        match method {
+            "state" => {}
+            "step" => {
+                let count = optional(params, "count", number)?.unwrap_or(1);
+                if let Some(stop) = step(sim, count as usize, |_| false) {
+                    return Ok(stopped(sim, stop));
+                }
+            }
+            "run" => {
+                let limit = optional(params, "limit", number)?;
+                let limit = limit.map_or(RUN_LIMIT, |limit| limit as usize);
+                let stop = step(sim, limit, |pc| breakpoints.contains(&pc));
+                return Ok(stopped(sim, stop.unwrap_or_else(|| json!("limit"))));
+            }
+            "read_register" => {
+                let reg = register(field(params, "name")?)?;
+                return Ok(json!({ "value": sim.read_reg(reg) }));
+            }
+            "write_register" => {
+                let reg = register(field(params, "name")?)?;
+                sim.write_reg(reg, number(field(params, "value")?)?);
+            }
+            "read_memory" => {
+                let address = address(Some(sim), field(params, "address")?)?;
+                let length = number(field(params, "length")?)?;
+                if length > READ_LIMIT {
+                    let message = format!("length must not exceed {}", READ_LIMIT);
+                    return Err((INVALID_PARAMS, message));
+                }
+                let mut bytes = vec![0u8; length as usize];
+                sim.read_mem(address, &mut bytes)
+                    .map_err(|e| (SIMULATOR_ERROR, e.to_string()))?;
+                return Ok(json!({ "address": address, "bytes": bytes }));
+            }
+            "write_memory" => {
+                let address = address(Some(sim), field(params, "address")?)?;
+                let bytes = field(params, "bytes")?
+                    .as_array()
+                    .and_then(|bytes| bytes.iter().map(byte).collect::<Option<Vec<_>>>())
+                    .ok_or_else(|| (INVALID_PARAMS, "expected an array of bytes".to_string()))?;
+                sim.write_mem(address, &bytes)
+                    .map_err(|e| (SIMULATOR_ERROR, e.to_string()))?;
+            }
+            "disassemble" => {
+                let address = address(Some(sim), field(params, "address")?)?;
+                let count = number(field(params, "count")?)?;
+                if count > READ_LIMIT {
+                    let message = format!("count must not exceed {}", READ_LIMIT);
+                    return Err((INVALID_PARAMS, message));
+                }
+                return Ok(json!({ "instructions": disassemble(sim, address, count) }));
+            }
+            _ => return Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+        Ok(self.state())
+    }
+
+    fn sim(&self) -> Result<&Simulator<io::Sink>, RpcError> {
+        self.sim.as_ref().ok_or_else(no_program)
+    }
+
+    /// The PC, cycle count and registers.
+    fn state(&self) -> Value {
+        let sim = match &self.sim {
+            Some(sim) => sim,
+            None => return json!({ "loaded": false }),
+        };
+        let registers = (0..32)
+            .map(|reg| IntRegister::try_from(reg).unwrap())
+            .map(|reg| (reg.to_string(), json!(sim.read_reg(reg))))
+            .collect::<Map<_, _>>();
+        json!({
+            "loaded": true,
+            "pc": sim.pc(),
+            "cycles": sim.cycles(),
+            "registers": registers,
+        })
+    }
+}
+
+/// Executes up to `count` instructions, stopping before one for which
+/// `breakpoint` holds unless it is the first. Returns why it stopped early,
+/// by the same rules as `yars run`.
+fn step<F: Fn(u32) -> bool>(
+    sim: &mut Simulator<io::Sink>,
+    count: usize,
+    breakpoint: F,
+) -> Option<Value> {
+    let options = RunOptions {
+        max_instret: Some(sim.processor().instret().saturating_add(count)),
+        ..RunOptions::default()
+    };
+    let mut first = true;
+    let exit = loop {
+        if !first && breakpoint(sim.pc()) {
+            return Some(json!("breakpoint"));
+        }
+        first = false;
+        if let Some(exit) = sim.step_with(&options).exit {
+            break exit;
+        }
+    };
+    let stop = match exit {
+        ExitEvent::CycleLimit => return None,
+        ExitEvent::EcallExit => json!("ecall"),
+        ExitEvent::Ebreak => json!("ebreak"),
+        ExitEvent::HostExit(value) => json!({ "tohost": value }),
+        ExitEvent::Hang { .. } => json!("hang"),
+        ExitEvent::Fault { err, .. } => {
+            let trap = sim.trap().map(|trap| {
+                json!({
+                    "cause": trap.cause.to_string(),
+                    "mcause": trap.cause.code(),
+                    "mtval": trap.tval,
+                    "mepc": trap.epc,
+                    "privilege": trap.privilege.to_string(),
+                })
+            });
+            let fault = err.describe(sim.symbols());
+            json!({ "fault": fault, "trap": trap })
+        }
+        // Serving sets no deadline or interval callback
+        ExitEvent::Timeout | ExitEvent::Paused => json!("limit"),
+    };
+    Some(stop)
+}
+
+/// Decodes `count` words from `address`, skipping those outside memory.
+/// Words that are not instructions have no `text`.
+fn disassemble(sim: &Simulator<io::Sink>, address: u32, count: u32) -> Vec<Value> {
+    let symbols = sim.symbols();
+    (0..count)
+        .map(|i| address.wrapping_add(4 * i))
+        .filter_map(|address| {
+            let mut bytes = [0u8; 4];
+            sim.read_mem(address, &mut bytes).ok()?;
+            let raw = u32::from_le_bytes(bytes);
+            let text = Instruction::try_from(raw)
+                .ok()
+                .map(|inst| inst.display_at(address).with_symbols(symbols).to_string());
+            let label = symbols
+                .lookup(address)
+                .filter(|symbol| symbol.offset == 0)
+                .map(|symbol| symbol.to_string());
+            Some(json!({ "address": address, "raw": raw, "text": text, "label": label }))
+        })
+        .collect()
+}
+
+fn stopped(sim: &Simulator<io::Sink>, stop: Value) -> Value {
+    json!({ "stop": stop, "pc": sim.pc(), "cycles": sim.cycles() })
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    }
+}
+
+fn no_program() -> RpcError {
+    (SIMULATOR_ERROR, "no program loaded".to_string())
+}
+
+fn field<'a>(params: &'a Map<String, Value>, name: &str) -> Result<&'a Value, RpcError> {
+    params
+        .get(name)
+        .ok_or_else(|| (INVALID_PARAMS, format!("missing {}", name)))
+}
+
+fn optional<T, F>(params: &Map<String, Value>, name: &str, parse: F) -> Result<Option<T>, RpcError>
+where
+    F: Fn(&Value) -> Result<T, RpcError>,
+{
+    params.get(name).map(parse).transpose()
+}
+
+fn string<'a>(params: &'a Map<String, Value>, name: &str) -> Result<&'a str, RpcError> {
+    field(params, name)?
+        .as_str()
+        .ok_or_else(|| (INVALID_PARAMS, format!("{} must be a string", name)))
+}
+
+fn number(value: &Value) -> Result<u32, RpcError> {
+    value
+        .as_u64()
+        .and_then(|value| u32::try_from(value).ok())
+        .ok_or_else(|| (INVALID_PARAMS, format!("invalid number {}", value)))
+}
+
+fn byte(value: &Value) -> Option<u8> {
+    value.as_u64().and_then(|value| u8::try_from(value).ok())
+}
+
+fn register(value: &Value) -> Result<IntRegister, RpcError> {
+    value
+        .as_str()
+        .and_then(|name| name.parse().ok())
+        .ok_or_else(|| (INVALID_PARAMS, format!("unknown register {}", value)))
+}
+
+/// Accepts a number, or a string holding an address or a symbol of `sim`.
+fn address(sim: Option<&Simulator<io::Sink>>, value: &Value) -> Result<u32, RpcError> {
+    let symbol = |name: &str| Some(sim?.symbols().get(name)?.address);
+    match value {
+        Value::String(address) => parse_address(address).or_else(|| symbol(address)),
+        value => number(value).ok(),
+    }
+    .ok_or_else(|| (INVALID_PARAMS, format!("invalid address {}", value)))
+}
+
+/// Reads one HTTP request from `stream` and answers it. JSON-RPC requests
+/// are POSTed in the body as `application/json`, which browsers only send
+/// cross-origin after a preflight this server never allows. With `web`, GET
+/// requests return the bundled debugger page.
+fn serve(session: &mut Session, stream: TcpStream, web: bool) -> io::Result<()> {
+    // Clients are served one at a time, so an idle one must not block the rest
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let limit = (HEADER_LIMIT + BODY_LIMIT) as u64;
+    let mut reader = BufReader::new(stream.try_clone()?).take(limit);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let method = request.split_whitespace().next().unwrap_or("").to_string();
+
+    let mut length = 0;
+    let mut json = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("content-type") {
+                let media = value.split(';').next().unwrap_or("").trim();
+                json = media.eq_ignore_ascii_case("application/json");
+            }
+        }
+    }
+
+    match method.as_str() {
+        "POST" if !json => respond(
+            stream,
+            "415 Unsupported Media Type",
+            "text/plain",
+            b"POST application/json\n",
+        ),
+        "POST" if length > BODY_LIMIT => respond(
+            stream,
+            "413 Payload Too Large",
+            "text/plain",
+            b"Requests are limited to 1 MiB\n",
+        ),
+        "POST" => {
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body)?;
+            let reply = session.handle(&body).to_string();
+            respond(stream, "200 OK", "application/json", reply.as_bytes())
+        }
+        "GET" if web => respond(
+            stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            WEB_UI.as_bytes(),
+        ),
+        _ => respond(
+            stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"POST JSON-RPC requests\n",
+        ),
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
@@ -0,0 +1,113 @@
+use clap::Clap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use yars_lib::coverage::IsaCoverage;
+use yars_lib::register::IntRegister;
+use yars_lib::simulator::{ExitEvent, HookAction, Simulator};
+
+/// Test families of riscv-tests covering the extensions yars implements.
+const SUITES: &[&str] = &["rv32ui-", "rv32um-"];
+
+#[derive(Clap)]
+pub struct TestSuiteOpts {
+    #[clap(short, long, value_name = "size", default_value = "2049")]
+    #[clap(about = "Allocate <size> MiB for target memory")]
+    memory: u32,
+
+    #[clap(long, value_name = "count", default_value = "1000000")]
+    #[clap(about = "Fails a test after <count> instructions")]
+    max_cycles: usize,
+
+    #[clap(long, value_name = "file")]
+    #[clap(about = "Writes which instructions and operand cases the tests executed to <file>")]
+    isa_coverage: Option<PathBuf>,
+
+    #[clap(about = "Directory holding the compiled riscv-tests ISA tests")]
+    dir: PathBuf,
+}
+
+pub fn run(opts: TestSuiteOpts) {
+    let mut tests = fs::read_dir(&opts.dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_none())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            SUITES.iter().any(|suite| name.starts_with(suite))
+        })
+        .collect::<Vec<_>>();
+    tests.sort();
+
+    if tests.is_empty() {
+        eprintln!("error: no rv32ui/rv32um tests in {}", opts.dir.display());
+        process::exit(2);
+    }
+
+    let width = tests
+        .iter()
+        .map(|test| test.file_name().unwrap().len())
+        .max()
+        .unwrap_or(0);
+
+    let coverage = Arc::new(Mutex::new(IsaCoverage::new()));
+    let mut failed = 0;
+    for test in &tests {
+        let result = execute(&opts, test, &coverage);
+        if result != "pass" {
+            failed += 1;
+        }
+        let name = test.file_name().unwrap().to_string_lossy();
+        println!("{:<w$}  {}", name, result, w = width);
+    }
+
+    println!("\n{} passed, {} failed.", tests.len() - failed, failed);
+    if let Some(path) = &opts.isa_coverage {
+        let file = BufWriter::new(File::create(path).unwrap());
+        coverage.lock().unwrap().write(file).unwrap();
+    }
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Runs a single test. Tests report their result either by writing to
+/// `tohost` or, without a trap handler, through the exit `ecall`, in both
+/// cases as 1 on success or `(test << 1) | 1` on failure.
+fn execute(opts: &TestSuiteOpts, test: &Path, coverage: &Arc<Mutex<IsaCoverage>>) -> String {
+    let mut sim = match Simulator::<io::Sink>::new(test, opts.memory * 1048576, None, None) {
+        Ok(sim) => sim,
+        Err(err) => return format!("error ({:?})", err),
+    };
+
+    if opts.isa_coverage.is_some() {
+        let coverage = coverage.clone();
+        sim.add_pre_hook(move |_, _, inst| {
+            coverage.lock().unwrap().record(inst);
+            HookAction::Continue
+        });
+    }
+
+    let code = match sim.run(Some(opts.max_cycles)) {
+        ExitEvent::HostExit(value) => value,
+        ExitEvent::EcallExit => match sim.read_reg(IntRegister::A0) {
+            0 => 1,
+            value => value,
+        },
+        ExitEvent::Ebreak => return "fail (ebreak)".to_string(),
+        ExitEvent::Fault { err, .. } => return format!("fault ({})", err.describe(sim.symbols())),
+        ExitEvent::CycleLimit => return "cycle limit".to_string(),
+        ExitEvent::Timeout => return "timeout".to_string(),
+        ExitEvent::Hang { .. } => return "hang".to_string(),
+        ExitEvent::Paused => return "paused".to_string(),
+    };
+
+    match code {
+        1 => "pass".to_string(),
+        code => format!("fail (test {})", code >> 1),
+    }
+}
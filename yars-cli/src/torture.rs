@@ -0,0 +1,66 @@
+use clap::Clap;
+use std::process;
+
+use yars_lib::entropy::Entropy;
+use yars_lib::instruction::Instruction;
+use yars_lib::torture::{self, CODE_BASE};
+
+#[derive(Clap)]
+pub struct TortureOpts {
+    #[clap(short = 'n', long, value_name = "count", default_value = "1000")]
+    #[clap(about = "Generates and checks <count> programs")]
+    count: u64,
+
+    #[clap(short, long, value_name = "count", default_value = "200")]
+    #[clap(about = "Generates <count> random instructions per program")]
+    length: usize,
+
+    #[clap(long, value_name = "seed", default_value = "0")]
+    #[clap(about = "Seed of the first program, incremented for each one after it")]
+    seed: u64,
+
+    #[clap(long)]
+    #[clap(about = "Prints the programs as assembly instead of checking them")]
+    emit: bool,
+}
+
+pub fn run(opts: TortureOpts) {
+    let mut failed = 0;
+    for seed in opts.seed..opts.seed + opts.count {
+        let mut entropy = Entropy::seeded(seed);
+        let program = torture::generate(|| entropy.next_u32(), opts.length);
+
+        if opts.emit {
+            println!("# seed {}", seed);
+            print_program(&program);
+            continue;
+        }
+
+        if let Err(mismatch) = torture::check(&program) {
+            failed += 1;
+            println!("seed {}: {}", seed, mismatch);
+        }
+    }
+
+    if !opts.emit {
+        println!("\n{} passed, {} failed.", opts.count - failed, failed);
+        if failed > 0 {
+            println!("Rerun with --emit --count 1 --seed <seed> to print a program.");
+            process::exit(1);
+        }
+    }
+}
+
+/// Prints a program as `.word` directives, so any assembler can build it
+/// to run it elsewhere, with the disassembly as comments.
+fn print_program(program: &[Instruction]) {
+    for (index, inst) in program.iter().enumerate() {
+        let pc = CODE_BASE + 4 * index as u32;
+        println!(
+            "    .word {:#010x}  # {:08x}: {}",
+            u32::from(*inst),
+            pc,
+            inst.display_at(pc)
+        );
+    }
+}
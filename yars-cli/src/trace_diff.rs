@@ -0,0 +1,90 @@
+use clap::Clap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use yars_lib::trace::reader;
+use yars_lib::trace::Commit;
+
+#[derive(Clap)]
+pub struct TraceDiffOpts {
+    #[clap(short = 'n', long, value_name = "count", default_value = "10")]
+    #[clap(about = "Stops after <count> divergences")]
+    max_diffs: usize,
+
+    #[clap(about = "Trace written by yars --log or by Spike")]
+    a: PathBuf,
+
+    #[clap(about = "Trace to compare against")]
+    b: PathBuf,
+}
+
+pub fn run(opts: TraceDiffOpts) {
+    let (a, b) = (read(&opts.a), read(&opts.b));
+    let (a, b) = align(&a, &b);
+
+    let mut diffs = 0;
+    for (index, (x, y)) in a.iter().zip(b).enumerate() {
+        if x.matches(y) {
+            continue;
+        }
+
+        diffs += 1;
+        println!("#{}", index);
+        println!("  a: {}", x);
+        println!("  b: {}", y);
+
+        if x.pc != y.pc {
+            println!("Control flow diverged, stopping.");
+            break;
+        }
+        if diffs == opts.max_diffs {
+            break;
+        }
+    }
+
+    if diffs == 0 && a.len() != b.len() {
+        println!(
+            "Traces match up to the end of the shorter one ({} and {} instructions).",
+            a.len(),
+            b.len()
+        );
+        process::exit(1);
+    }
+
+    match diffs {
+        0 => println!("Traces match ({} instructions).", a.len()),
+        _ => process::exit(1),
+    }
+}
+
+/// Reads every instruction of a trace, skipping lines of other kinds.
+fn read(path: &Path) -> Vec<Commit> {
+    let commits = File::open(path)
+        .and_then(|file| reader::commits(BufReader::new(file)).collect::<io::Result<_>>());
+    match commits {
+        Ok(commits) => commits,
+        Err(err) => {
+            eprintln!("error: {}: {}", path.display(), err);
+            process::exit(2);
+        }
+    }
+}
+
+/// Drops the instructions one trace executed before the other one starts,
+/// such as a boot ROM.
+fn align<'a>(a: &'a [Commit], b: &'a [Commit]) -> (&'a [Commit], &'a [Commit]) {
+    let start = |trace: &[Commit], pc: Option<u32>| {
+        pc.and_then(|pc| trace.iter().position(|commit| commit.pc == pc))
+    };
+
+    match (
+        start(b, a.first().map(|c| c.pc)),
+        start(a, b.first().map(|c| c.pc)),
+    ) {
+        (Some(skip), _) => (a, &b[skip..]),
+        (None, Some(skip)) => (&a[skip..], b),
+        (None, None) => (a, b),
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use yars_lib::register::IntRegister;
+use yars_lib::simulator::Simulator;
+
+/// A register, or a memory location of 1, 2 or 4 bytes written as
+/// `address[:size]`, whose changes are reported while the program runs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Watch {
+    Register(IntRegister),
+    Memory { address: u32, size: usize },
+}
+
+impl Watch {
+    pub fn read<W: Write>(&self, sim: &Simulator<W>) -> Option<u32> {
+        match *self {
+            Watch::Register(reg) => Some(sim.read_reg(reg)),
+            Watch::Memory { address, size } => {
+                let mut bytes = [0u8; 4];
+                sim.read_mem(address, &mut bytes[..size]).ok()?;
+                let endianness = sim.processor().endianness();
+                Some(endianness.swap(u32::from_le_bytes(bytes), size as u32))
+            }
+        }
+    }
+}
+
+impl FromStr for Watch {
+    type Err = String;
+
+    fn from_str(watch: &str) -> Result<Self, Self::Err> {
+        if let Ok(reg) = watch.parse::<IntRegister>() {
+            return Ok(Watch::Register(reg));
+        }
+
+        let (address, size) = match watch.split_once(':') {
+            Some((address, size)) => (address, size.parse().map_err(|_| "invalid size")?),
+            None => (watch, 4),
+        };
+        let address = parse_address(address).ok_or("expected a register or an address")?;
+
+        match size {
+            1 | 2 | 4 => Ok(Watch::Memory { address, size }),
+            _ => Err("size must be 1, 2 or 4".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watch::Register(reg) => write!(f, "{}", reg),
+            Watch::Memory { address, .. } => write!(f, "[{:#010x}]", address),
+        }
+    }
+}
+
+pub fn parse_address(address: &str) -> Option<u32> {
+    match address.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => address.parse().ok(),
+    }
+}
@@ -0,0 +1,279 @@
+//! Runs the `yars` binary on small programs to check the flags of `yars run`.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const BASE: u32 = 0x1000;
+
+/// Writes a minimal statically linked RV32 executable holding `code` at
+/// `BASE`, with `symbols` as absolute symbols, and returns its path.
+fn program(name: &str, code: &[u32], symbols: &[(&str, u32)]) -> PathBuf {
+    let size = (code.len() * 4) as u32;
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; 16];
+    for (name, address) in symbols {
+        for word in &[strtab.len() as u32, *address, 4] {
+            symtab.extend_from_slice(&word.to_le_bytes());
+        }
+        // Global object, absolute
+        symtab.extend_from_slice(&[0x11, 0]);
+        symtab.extend_from_slice(&0xFFF1u16.to_le_bytes());
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+    let shstrtab = b"\0.symtab\0.strtab\0.shstrtab\0";
+    let symtab_offset = 84 + size;
+    let strtab_offset = symtab_offset + symtab.len() as u32;
+    let shstrtab_offset = strtab_offset + strtab.len() as u32;
+    let sections_offset = (shstrtab_offset + shstrtab.len() as u32 + 3) & !3;
+
+    let mut elf = vec![0x7F, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    for half in &[2u16, 243] {
+        elf.extend_from_slice(&half.to_le_bytes());
+    }
+    for word in &[1u32, BASE, 52, sections_offset, 0] {
+        elf.extend_from_slice(&word.to_le_bytes());
+    }
+    for half in &[52u16, 32, 1, 40, 4, 3] {
+        elf.extend_from_slice(&half.to_le_bytes());
+    }
+    for word in &[1u32, 84, BASE, BASE, size, size, 7, 4] {
+        elf.extend_from_slice(&word.to_le_bytes());
+    }
+    for word in code {
+        elf.extend_from_slice(&word.to_le_bytes());
+    }
+    elf.extend_from_slice(&symtab);
+    elf.extend_from_slice(&strtab);
+    elf.extend_from_slice(shstrtab);
+    elf.resize(sections_offset as usize, 0);
+
+    // Null, .symtab, .strtab and .shstrtab section headers
+    let sections: [[u32; 10]; 4] = [
+        [0; 10],
+        [1, 2, 0, 0, symtab_offset, symtab.len() as u32, 2, 1, 4, 16],
+        [9, 3, 0, 0, strtab_offset, strtab.len() as u32, 0, 0, 1, 0],
+        [
+            17,
+            3,
+            0,
+            0,
+            shstrtab_offset,
+            shstrtab.len() as u32,
+            0,
+            0,
+            1,
+            0,
+        ],
+    ];
+    for section in &sections {
+        for word in section {
+            elf.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("yars-cli-{}-{}.elf", std::process::id(), name));
+    std::fs::write(&path, elf).unwrap();
+    path
+}
+
+fn yars(args: &[&str], program: &Path, input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yars"))
+        .arg("run")
+        .args(args)
+        .arg(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Stores `value` to `tohost` after writing 0x11223344 to the signature,
+/// then spins on `j 0` if that does not stop it.
+fn tohost(name: &str, value: u32) -> PathBuf {
+    // lui t0, 1; li t1, 0x11223344; sw t1, 32(t0); li t1, <value>;
+    // sw t1, 28(t0); j 0; tohost: .word 0; signature: .word 0
+    let li = 0x0000_0313 | value << 20;
+    let code = [
+        0x000012B7, 0x11223337, 0x34430313, 0x0262A023, li, 0x0062AE23, 0x0000006F, 0, 0,
+    ];
+    let symbols = [
+        ("tohost", BASE + 0x1C),
+        ("begin_signature", BASE + 0x20),
+        ("end_signature", BASE + 0x24),
+    ];
+    program(name, &code, &symbols)
+}
+
+#[test]
+fn stop_at_limits() {
+    // addi a0, a0, 1; j -4
+    let elf = program("limits", &[0x00150513, 0xFFDFF06F], &[]);
+
+    let output = yars(&["--max-cycles", "100"], &elf, b"");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).starts_with("Cycle limit reached"));
+
+    // Jitter makes the cycles run out before 100 instructions retire
+    let args = [
+        "--max-cycles",
+        "100",
+        "--seed",
+        "1",
+        "--latency-jitter",
+        "4",
+    ];
+    let output = yars(&args, &elf, b"");
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = yars(&["--timeout", "1"], &elf, b"");
+    assert_eq!(output.status.code(), Some(124));
+    assert!(stderr(&output).starts_with("Timeout reached"));
+
+    // j 0
+    let output = yars(&[], &program("hang", &[0x0000006F], &[]), b"");
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).starts_with("Program hung at 0x00001000"));
+}
+
+#[test]
+fn exit_through_tohost() {
+    let pass = tohost("pass", 1);
+    let output = yars(&[], &pass, b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("Program finished"));
+
+    let output = yars(&["--endian", "big"], &pass, b"");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = yars(&[], &tohost("fail", 3), b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "Program failed (tohost=0x3).\n");
+}
+
+#[test]
+fn write_signature_in_data_order() {
+    let elf = tohost("signature", 1);
+    for endian in &["little", "big"] {
+        let path = elf.with_extension(format!("{}.sig", endian));
+        let args = ["--endian", endian, "--signature", path.to_str().unwrap()];
+        assert!(yars(&args, &elf, b"").status.success());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "11223344\n");
+    }
+}
+
+#[test]
+fn resume_from_snapshot() {
+    // li a0, 0; li a1, 100; addi a0, a0, 1; bne a0, a1, -4; ecall
+    let code = [0x00000513, 0x06400593, 0x00150513, 0xFEB51EE3, 0x00000073];
+    let elf = program("snapshot", &code, &[]);
+
+    let output = yars(&["--snapshot-every", "0"], &elf, b"");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        "error: --snapshot-every must not be zero\n"
+    );
+
+    let args = [
+        "--snapshot-every",
+        "50",
+        "--seed",
+        "1",
+        "--latency-jitter",
+        "3",
+    ];
+    assert!(yars(&args, &elf, b"").status.success());
+    let snapshot = elf.with_extension("snap");
+    let args = ["--resume", snapshot.to_str().unwrap(), "--dump-regs"];
+    let output = yars(&args, &elf, b"");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("a0=0x00000064"));
+}
+
+#[test]
+fn continue_from_ebreak() {
+    // li a0, 1; ebreak; li a0, 7; ecall
+    let elf = program(
+        "ebreak",
+        &[0x00100513, 0x00100073, 0x00700513, 0x00000073],
+        &[],
+    );
+
+    let output = yars(&["--dump-regs"], &elf, b"");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("a0=0x00000001"));
+
+    let output = yars(&["--ebreak", "break", "--console", "line"], &elf, b"");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        "error: --console cannot be used with --ebreak break\n"
+    );
+
+    let output = yars(&["--ebreak", "break"], &elf, b"c\n");
+    assert!(output.status.success());
+    let shown = stdout(&output);
+    assert!(shown.starts_with("Breakpoint at 0x00001004\n"));
+    // Stepping shows the registers at the breakpoint before continuing
+    assert!(shown.contains("a0=0x00000001"));
+
+    let output = yars(&["--ebreak", "break", "--dump-regs"], &elf, b"c\n");
+    assert!(stdout(&output).contains("a0=0x00000007"));
+}
+
+#[test]
+fn replay_console_input() {
+    // lui a1, 0xFFFF0; lw a0, 0x24(a1); beqz a0, -4; lw a3, 0x20(a1); ecall
+    let code = [0xFFFF05B7, 0x0245A503, 0xFE050EE3, 0x0205A683, 0x00000073];
+    let elf = program("console", &code, &[]);
+    let log = elf.with_extension("log");
+    let log = log.to_str().unwrap();
+
+    let args = ["--console", "line", "--record", log, "--dump-regs"];
+    let output = yars(&args, &elf, b"x\n");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("a3=0x00000078"));
+    let recorded = std::fs::read_to_string(log).unwrap();
+    assert!(recorded.ends_with(" console 780a\n"));
+
+    // The logged input arrives although standard input is empty
+    let args = ["--console", "line", "--replay", log, "--dump-regs"];
+    let output = yars(&args, &elf, b"");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("a3=0x00000078"));
+    assert!(!stderr(&output).contains("diverged"));
+}
+
+#[test]
+fn inject_after_retired_instructions() {
+    // li a0, 0; li a1, 0; addi a0, a0, 1; ecall
+    let code = [0x00000513, 0x00000593, 0x00150513, 0x00000073];
+    let elf = program("inject", &code, &[]);
+
+    // Jitter makes the cycles run ahead of the retired instructions
+    let args = [
+        "--inject",
+        "a0:3@2",
+        "--seed",
+        "1",
+        "--latency-jitter",
+        "3",
+        "--dump-regs",
+    ];
+    let output = yars(&args, &elf, b"");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("a0=0x00000009"));
+}
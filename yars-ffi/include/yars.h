@@ -0,0 +1,74 @@
+#ifndef YARS_H
+#define YARS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define YARS_OK           0
+#define YARS_EXITED       1  /* program executed ecall/ebreak or wrote tohost */
+#define YARS_HANG         2  /* program jumped to itself with no interrupt pending */
+#define YARS_PAUSED       3  /* the interval callback asked to pause */
+#define YARS_ERR_INVALID -1  /* invalid argument */
+#define YARS_ERR_FETCH   -2  /* illegal or misaligned instruction fetch */
+#define YARS_ERR_OPCODE  -3  /* invalid opcode */
+#define YARS_ERR_ACCESS  -4  /* illegal or misaligned memory access */
+#define YARS_ERR_ABORT   -5  /* execution aborted by a hook or the interval callback */
+
+/* Return values of yars_interval_fn. */
+#define YARS_CONTINUE     0
+#define YARS_PAUSE        1  /* yars_simulator_run returns YARS_PAUSED, and resumes when called again */
+#define YARS_CANCEL       2  /* yars_simulator_run returns YARS_ERR_ABORT */
+
+typedef struct yars_simulator yars_simulator;
+
+typedef uint32_t (*yars_mmio_read_fn)(void *ctx, uint32_t offset, uint32_t size);
+typedef void (*yars_mmio_write_fn)(void *ctx, uint32_t offset, uint32_t size, uint32_t value);
+typedef int (*yars_interval_fn)(void *ctx);
+
+/* Creation and destruction. Both constructors return NULL on failure. */
+yars_simulator *yars_simulator_new(const char *program, uint32_t memsize);
+yars_simulator *yars_simulator_new_from_bytes(const uint8_t *data, size_t len, uint32_t memsize);
+void yars_simulator_free(yars_simulator *sim);
+
+/* Loads an additional ELF image and jumps to its entry point. */
+int yars_simulator_load(yars_simulator *sim, const char *program);
+
+/* Execution. */
+int yars_simulator_step(yars_simulator *sim);
+int yars_simulator_run(yars_simulator *sim);
+uint64_t yars_simulator_cycles(const yars_simulator *sim);
+/* Calls back every `instructions` executed by yars_simulator_run, so a GUI
+ * can stay responsive or stop the guest. */
+int yars_simulator_set_interval(yars_simulator *sim, uint64_t instructions,
+                                yars_interval_fn callback, void *ctx);
+
+/* Architectural state. */
+uint32_t yars_simulator_get_pc(const yars_simulator *sim);
+int yars_simulator_set_pc(yars_simulator *sim, uint32_t pc);
+int yars_simulator_get_register(const yars_simulator *sim, uint8_t index, uint32_t *value);
+int yars_simulator_set_register(yars_simulator *sim, uint8_t index, uint32_t value);
+int yars_simulator_read_memory(const yars_simulator *sim, uint32_t address, uint8_t *buffer, size_t len);
+int yars_simulator_write_memory(yars_simulator *sim, uint32_t address, const uint8_t *buffer, size_t len);
+/* Hash of the PC, registers and non-zero memory pages. */
+uint64_t yars_simulator_state_hash(const yars_simulator *sim);
+
+/* Memory-mapped I/O. Accesses inside [base, base + size) call back into C. */
+int yars_simulator_map_mmio(yars_simulator *sim, uint32_t base, uint32_t size,
+                            yars_mmio_read_fn read, yars_mmio_write_fn write, void *ctx);
+
+/* External interrupts (lines 1-31), observed by the guest through the
+ * interrupt controller mapped at base: pending (+0), enable (+4) and
+ * claim/complete (+8). */
+int yars_simulator_map_irq(yars_simulator *sim, uint32_t base);
+int yars_simulator_raise_irq(yars_simulator *sim, uint32_t line);
+int yars_simulator_clear_irq(yars_simulator *sim, uint32_t line);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* YARS_H */
@@ -0,0 +1,393 @@
+//! C interface to the simulator. See `include/yars.h` for the matching
+//! declarations. Every function taking a `yars_simulator` pointer expects one
+//! obtained from `yars_simulator_new*` and not yet freed.
+
+#![allow(non_camel_case_types)]
+
+use std::convert::TryFrom;
+use std::ffi::{c_void, CStr};
+use std::io::Stdout;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+
+use yars_lib::device::Device;
+use yars_lib::processor::Processor;
+use yars_lib::processor::ProcessorError;
+use yars_lib::register::IntRegister;
+use yars_lib::simulator::{ExitEvent, IntervalAction, Simulator};
+
+pub struct yars_simulator(Simulator<Stdout>);
+
+pub type yars_mmio_read_fn = extern "C" fn(ctx: *mut c_void, offset: u32, size: u32) -> u32;
+pub type yars_mmio_write_fn = extern "C" fn(ctx: *mut c_void, offset: u32, size: u32, value: u32);
+pub type yars_interval_fn = extern "C" fn(ctx: *mut c_void) -> c_int;
+
+pub const YARS_OK: c_int = 0;
+pub const YARS_EXITED: c_int = 1;
+pub const YARS_HANG: c_int = 2;
+pub const YARS_PAUSED: c_int = 3;
+pub const YARS_ERR_INVALID: c_int = -1;
+pub const YARS_ERR_FETCH: c_int = -2;
+pub const YARS_ERR_OPCODE: c_int = -3;
+pub const YARS_ERR_ACCESS: c_int = -4;
+pub const YARS_ERR_ABORT: c_int = -5;
+
+pub const YARS_CONTINUE: c_int = 0;
+pub const YARS_PAUSE: c_int = 1;
+pub const YARS_CANCEL: c_int = 2;
+
+#[derive(Clone)]
+struct CallbackDevice {
+    read: yars_mmio_read_fn,
+    write: yars_mmio_write_fn,
+    ctx: *mut c_void,
+}
+
+// The C side owns `ctx` and is responsible for synchronizing it if the
+// simulator is moved to another thread.
+unsafe impl Send for CallbackDevice {}
+
+impl Device for CallbackDevice {
+    fn read(&mut self, offset: u32, size: u32) -> u32 {
+        (self.read)(self.ctx, offset, size)
+    }
+
+    fn write(&mut self, offset: u32, size: u32, value: u32) {
+        (self.write)(self.ctx, offset, size, value)
+    }
+}
+
+#[derive(Clone)]
+struct IntervalCallback {
+    callback: yars_interval_fn,
+    ctx: *mut c_void,
+}
+
+// As for `CallbackDevice`, synchronizing `ctx` is up to the C side.
+unsafe impl Send for IntervalCallback {}
+
+fn status<T>(result: Result<T, ProcessorError>) -> c_int {
+    match result {
+        Ok(_) => YARS_OK,
+        Err(ProcessorError::Ecall) | Err(ProcessorError::Ebreak) => YARS_EXITED,
+        Err(ProcessorError::IllegalFetch { .. }) | Err(ProcessorError::MisalignedFetch { .. }) => {
+            YARS_ERR_FETCH
+        }
+        Err(ProcessorError::InvalidOpcode { .. }) => YARS_ERR_OPCODE,
+        Err(ProcessorError::IllegalAccess { .. })
+        | Err(ProcessorError::MisalignedLoad { .. })
+        | Err(ProcessorError::MisalignedStore { .. })
+        | Err(ProcessorError::CodeWrite { .. }) => YARS_ERR_ACCESS,
+        Err(ProcessorError::Abort) => YARS_ERR_ABORT,
+    }
+}
+
+/// # Safety
+///
+/// `program` must be a valid NUL-terminated path.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_new(
+    program: *const c_char,
+    memsize: u32,
+) -> *mut yars_simulator {
+    if program.is_null() {
+        return ptr::null_mut();
+    }
+
+    let program = match CStr::from_ptr(program).to_str() {
+        Ok(program) => program,
+        Err(_) => return ptr::null_mut(),
+    };
+
+    match Simulator::new(program, memsize, None, None) {
+        Ok(sim) => Box::into_raw(Box::new(yars_simulator(sim))),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `data` must point to `len` readable bytes holding an ELF image.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_new_from_bytes(
+    data: *const u8,
+    len: usize,
+    memsize: u32,
+) -> *mut yars_simulator {
+    if data.is_null() {
+        return ptr::null_mut();
+    }
+
+    let program = slice::from_raw_parts(data, len);
+    match Simulator::from_bytes(program, memsize, None, None) {
+        Ok(sim) => Box::into_raw(Box::new(yars_simulator(sim))),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be null or a live simulator; it must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_free(sim: *mut yars_simulator) {
+    if !sim.is_null() {
+        drop(Box::from_raw(sim));
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator and `program` a valid NUL-terminated path.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_load(
+    sim: *mut yars_simulator,
+    program: *const c_char,
+) -> c_int {
+    let sim = match sim.as_mut() {
+        Some(sim) if !program.is_null() => &mut sim.0,
+        _ => return YARS_ERR_INVALID,
+    };
+
+    match CStr::from_ptr(program).to_str() {
+        Ok(program) if sim.load_program(program).is_ok() => YARS_OK,
+        _ => YARS_ERR_INVALID,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_step(sim: *mut yars_simulator) -> c_int {
+    match sim.as_mut() {
+        Some(sim) => status(sim.0.step()),
+        None => YARS_ERR_INVALID,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_run(sim: *mut yars_simulator) -> c_int {
+    let sim = match sim.as_mut() {
+        Some(sim) => &mut sim.0,
+        None => return YARS_ERR_INVALID,
+    };
+
+    match sim.run(None) {
+        ExitEvent::EcallExit | ExitEvent::Ebreak | ExitEvent::HostExit(_) => YARS_EXITED,
+        ExitEvent::Fault { err, .. } => status::<()>(Err(err)),
+        ExitEvent::CycleLimit | ExitEvent::Timeout => YARS_OK,
+        ExitEvent::Hang { .. } => YARS_HANG,
+        ExitEvent::Paused => YARS_PAUSED,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator. `callback` is invoked with `ctx` after
+/// every `instructions` executed by `yars_simulator_run`, until the
+/// simulator is freed or another callback is set.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_set_interval(
+    sim: *mut yars_simulator,
+    instructions: u64,
+    callback: Option<yars_interval_fn>,
+    ctx: *mut c_void,
+) -> c_int {
+    let (sim, callback) = match (sim.as_mut(), callback) {
+        (Some(sim), Some(callback)) if instructions > 0 => (&mut sim.0, callback),
+        _ => return YARS_ERR_INVALID,
+    };
+
+    let interval = IntervalCallback { callback, ctx };
+    sim.set_interval(
+        instructions as usize,
+        move |_: &Processor| match (interval.callback)(interval.ctx) {
+            YARS_PAUSE => IntervalAction::Pause,
+            YARS_CANCEL => IntervalAction::Cancel,
+            _ => IntervalAction::Continue,
+        },
+    );
+    YARS_OK
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_cycles(sim: *const yars_simulator) -> u64 {
+    sim.as_ref().map_or(0, |sim| sim.0.cycles() as u64)
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_state_hash(sim: *const yars_simulator) -> u64 {
+    sim.as_ref().map_or(0, |sim| sim.0.state_hash())
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_get_pc(sim: *const yars_simulator) -> u32 {
+    sim.as_ref().map_or(0, |sim| sim.0.pc())
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_set_pc(sim: *mut yars_simulator, pc: u32) -> c_int {
+    match sim.as_mut() {
+        Some(sim) => {
+            sim.0.set_pc(pc);
+            YARS_OK
+        }
+        None => YARS_ERR_INVALID,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator and `value` a writable pointer.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_get_register(
+    sim: *const yars_simulator,
+    index: u8,
+    value: *mut u32,
+) -> c_int {
+    match (sim.as_ref(), IntRegister::try_from(index), value.as_mut()) {
+        (Some(sim), Ok(reg), Some(value)) => {
+            *value = sim.0.read_reg(reg);
+            YARS_OK
+        }
+        _ => YARS_ERR_INVALID,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_set_register(
+    sim: *mut yars_simulator,
+    index: u8,
+    value: u32,
+) -> c_int {
+    match (sim.as_mut(), IntRegister::try_from(index)) {
+        (Some(sim), Ok(reg)) => {
+            sim.0.write_reg(reg, value);
+            YARS_OK
+        }
+        _ => YARS_ERR_INVALID,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator and `buffer` must point to `len` writable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_read_memory(
+    sim: *const yars_simulator,
+    address: u32,
+    buffer: *mut u8,
+    len: usize,
+) -> c_int {
+    let sim = match sim.as_ref() {
+        Some(sim) if !buffer.is_null() => &sim.0,
+        _ => return YARS_ERR_INVALID,
+    };
+
+    let buffer = slice::from_raw_parts_mut(buffer, len);
+    match sim.read_mem(address, buffer) {
+        Ok(()) => YARS_OK,
+        Err(_) => YARS_ERR_ACCESS,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator and `buffer` must point to `len` readable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_write_memory(
+    sim: *mut yars_simulator,
+    address: u32,
+    buffer: *const u8,
+    len: usize,
+) -> c_int {
+    let sim = match sim.as_mut() {
+        Some(sim) if !buffer.is_null() => &mut sim.0,
+        _ => return YARS_ERR_INVALID,
+    };
+
+    let buffer = slice::from_raw_parts(buffer, len);
+    match sim.write_mem(address, buffer) {
+        Ok(()) => YARS_OK,
+        Err(_) => YARS_ERR_ACCESS,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator. The callbacks are invoked with `ctx` for
+/// every access inside `[base, base + size)` until the simulator is freed.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_map_mmio(
+    sim: *mut yars_simulator,
+    base: u32,
+    size: u32,
+    read: Option<yars_mmio_read_fn>,
+    write: Option<yars_mmio_write_fn>,
+    ctx: *mut c_void,
+) -> c_int {
+    let (sim, read, write) = match (sim.as_mut(), read, write) {
+        (Some(sim), Some(read), Some(write)) => (&mut sim.0, read, write),
+        _ => return YARS_ERR_INVALID,
+    };
+
+    let device = Box::new(CallbackDevice { read, write, ctx });
+    match sim.map_device(base, size, device) {
+        Ok(()) => YARS_OK,
+        Err(_) => YARS_ERR_INVALID,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_map_irq(sim: *mut yars_simulator, base: u32) -> c_int {
+    match sim.as_mut().map(|sim| sim.0.map_interrupt_controller(base)) {
+        Some(Ok(())) => YARS_OK,
+        _ => YARS_ERR_INVALID,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_raise_irq(sim: *mut yars_simulator, line: u32) -> c_int {
+    match sim.as_mut().map(|sim| sim.0.raise_irq(line)) {
+        Some(Ok(())) => YARS_OK,
+        _ => YARS_ERR_INVALID,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be a live simulator.
+#[no_mangle]
+pub unsafe extern "C" fn yars_simulator_clear_irq(sim: *mut yars_simulator, line: u32) -> c_int {
+    match sim.as_mut().map(|sim| sim.0.clear_irq(line)) {
+        Some(Ok(())) => YARS_OK,
+        _ => YARS_ERR_INVALID,
+    }
+}
@@ -0,0 +1,184 @@
+//! Generates the decode table of `src/decode.rs` from the instruction lists
+//! in `opcodes/`, checking that every instruction sets all 32 bits exactly
+//! once and that no two instructions share an encoding.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Operand fields, with their bits and how they are decoded into a field of
+/// the instruction, if they make one up on their own.
+const OPERANDS: &[(&str, u32, u32, Option<&str>)] = &[
+    ("rd", 11, 7, Some("rd: reg(inst, 7)?")),
+    ("rs1", 19, 15, Some("rs1: reg(inst, 15)?")),
+    ("rs2", 24, 20, Some("rs2: reg(inst, 20)?")),
+    ("imm12", 31, 20, Some("imm: imm_i(inst)")),
+    ("imm12hi", 31, 25, Some("imm: imm_s(inst)")),
+    ("imm12lo", 11, 7, None),
+    ("bimm12hi", 31, 25, Some("imm: imm_b(inst)")),
+    ("bimm12lo", 11, 7, None),
+    ("imm20", 31, 12, Some("imm: imm_u(inst)")),
+    ("jimm20", 31, 12, Some("imm: imm_j(inst)")),
+    ("shamt", 24, 20, Some("shamt: shamt(inst)")),
+    ("pred", 27, 24, Some("pred: fence_kind(inst, 24)?")),
+    ("succ", 23, 20, Some("succ: fence_kind(inst, 20)?")),
+];
+
+struct Entry {
+    mnemonic: String,
+    mask: u32,
+    bits: u32,
+    format: &'static str,
+    fields: Vec<&'static str>,
+}
+
+fn main() {
+    let dir = Path::new("opcodes");
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .expect("cannot read opcodes/")
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+
+    let mut entries = Vec::new();
+    for file in files {
+        println!("cargo:rerun-if-changed={}", file.display());
+        let text = fs::read_to_string(&file).unwrap();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if !line.is_empty() {
+                let at = format!("{}:{}", file.display(), number + 1);
+                entries.push(parse(line, &at));
+            }
+        }
+    }
+
+    for (i, a) in entries.iter().enumerate() {
+        for b in &entries[i + 1..] {
+            if (a.bits ^ b.bits) & a.mask & b.mask == 0 {
+                panic!("{} and {} share an encoding", a.mnemonic, b.mnemonic);
+            }
+        }
+    }
+
+    // Grouped by major opcode, so decoding only looks at one group
+    let opcode = |entry: &Entry| (entry.bits >> 2) as usize & 0x1F;
+    entries.sort_by_key(opcode);
+    let mut groups = [(0, 0); 32];
+    for (i, entry) in entries.iter().enumerate() {
+        let group = &mut groups[opcode(entry)];
+        if group.0 == group.1 {
+            *group = (i, i);
+        }
+        group.1 = i + 1;
+    }
+
+    let mut code = String::new();
+    writeln!(code, "static TABLE: [Encoding; {}] = [", entries.len()).unwrap();
+    for entry in &entries {
+        let variant = entry.mnemonic.replace('.', "").to_uppercase();
+        let decode = match entry.fields.is_empty() {
+            true => format!("|_| Ok(Instruction::{})", variant),
+            false => format!(
+                "|inst| Ok(Instruction::{} {{ {} }})",
+                variant,
+                entry.fields.join(", ")
+            ),
+        };
+        writeln!(
+            code,
+            "    Encoding {{ mnemonic: {:?}, mask: {:#010x}, bits: {:#010x}, \
+             format: InstructionFormat::{}, decode: {} }},",
+            entry.mnemonic, entry.mask, entry.bits, entry.format, decode
+        )
+        .unwrap();
+    }
+    writeln!(code, "];").unwrap();
+    writeln!(code, "static GROUPS: [(usize, usize); 32] = {:?};", groups).unwrap();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("decode.rs");
+    fs::write(out, code).unwrap();
+}
+
+/// Parses the instruction on `line`, read from `at`.
+fn parse(line: &str, at: &str) -> Entry {
+    let mut tokens = line.split_whitespace();
+    let mnemonic = tokens.next().unwrap().to_string();
+    let (mut covered, mut mask, mut bits) = (0u32, 0u32, 0u32);
+    let mut fields = Vec::new();
+    let mut operands = Vec::new();
+
+    let mut cover = |hi: u32, lo: u32, token: &str| {
+        if hi > 31 || lo > hi {
+            panic!("{}: {} is out of range", at, token);
+        }
+        let field = (u64::from(u32::MAX) >> (31 - (hi - lo)) << lo) as u32;
+        if covered & field != 0 {
+            panic!("{}: {} overlaps another field", at, token);
+        }
+        covered |= field;
+        field
+    };
+
+    for token in tokens {
+        match token.split_once('=') {
+            Some((range, value)) => {
+                let (hi, lo) = match range.split_once("..") {
+                    Some((hi, lo)) => (hi.parse().unwrap(), lo.parse().unwrap()),
+                    None => (range.parse().unwrap(), range.parse().unwrap()),
+                };
+                let value = match value.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => value.parse(),
+                }
+                .unwrap_or_else(|_| panic!("{}: bad value in {}", at, token));
+                let field = cover(hi, lo, token);
+                if u64::from(value) >> (hi - lo + 1) != 0 {
+                    panic!("{}: {} does not fit its bits", at, token);
+                }
+                mask |= field;
+                bits |= value << lo;
+            }
+            None => {
+                let name = token.trim_start_matches('_');
+                let &(_, hi, lo, field) = OPERANDS
+                    .iter()
+                    .find(|operand| operand.0 == name)
+                    .unwrap_or_else(|| panic!("{}: unknown field {}", at, token));
+                cover(hi, lo, token);
+                if let (false, Some(field)) = (token.starts_with('_'), field) {
+                    fields.push(field);
+                }
+                operands.push(name);
+            }
+        }
+    }
+
+    if covered != u32::MAX {
+        panic!(
+            "{}: bits {:#010x} of {} are not set",
+            at, !covered, mnemonic
+        );
+    }
+
+    let has = |name: &str| operands.contains(&name);
+    let format = match () {
+        _ if has("jimm20") => "J",
+        _ if has("imm20") => "U",
+        _ if has("bimm12hi") => "B",
+        _ if has("imm12hi") => "S",
+        _ if has("rs2") => "R",
+        _ => "I",
+    };
+
+    Entry {
+        mnemonic,
+        mask,
+        bits,
+        format,
+        fields,
+    }
+}
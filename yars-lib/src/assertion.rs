@@ -0,0 +1,478 @@
+//! Guest assertions: expressions over registers and memory that must hold
+//! whenever execution reaches an address, checked before the instruction
+//! there executes. They are read from a file with one assertion per line,
+//! an address or symbol followed by the expression:
+//!
+//! ```text
+//! # a0 holds the loop counter, and the buffer must stay zero-terminated
+//! loop        a0 < 10 && [buffer + 15]:1 == 0
+//! 0x00010080  sp & 15 == 0
+//! ```
+//!
+//! Expressions use registers by ABI name or number, `pc`, symbols for their
+//! address, numbers in decimal or hexadecimal and `[address]` for the word
+//! in memory there, or `[address]:1` and `[address]:2` for a byte or a
+//! halfword. Operators are as in Rust, from the tightest binding: unary `-`,
+//! `~` and `!`; `*`; `+` and `-`; `<<` and `>>`; `&`; `^`; `|`; the
+//! comparisons, which are signed; `&&`; and `||`. Values are 32 bits wide
+//! and wrap around.
+
+use crate::processor::Processor;
+use crate::register::IntRegister;
+use crate::symbol::SymbolTable;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Unary {
+    Negate,
+    Not,
+    LogicalNot,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Binary {
+    Multiply,
+    Add,
+    Subtract,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Xor,
+    Or,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    LogicalAnd,
+    LogicalOr,
+}
+
+impl Binary {
+    /// Operators of each precedence level, from the loosest binding.
+    const LEVELS: [&'static [(&'static str, Binary)]; 9] = [
+        &[("||", Binary::LogicalOr)],
+        &[("&&", Binary::LogicalAnd)],
+        &[
+            ("==", Binary::Equal),
+            ("!=", Binary::NotEqual),
+            ("<=", Binary::LessEqual),
+            (">=", Binary::GreaterEqual),
+            ("<", Binary::Less),
+            (">", Binary::Greater),
+        ],
+        &[("|", Binary::Or)],
+        &[("^", Binary::Xor)],
+        &[("&", Binary::And)],
+        &[("<<", Binary::ShiftLeft), (">>", Binary::ShiftRight)],
+        &[("+", Binary::Add), ("-", Binary::Subtract)],
+        &[("*", Binary::Multiply)],
+    ];
+
+    fn apply(self, a: u32, b: u32) -> u32 {
+        let (sa, sb) = (a as i32, b as i32);
+        match self {
+            Binary::Multiply => a.wrapping_mul(b),
+            Binary::Add => a.wrapping_add(b),
+            Binary::Subtract => a.wrapping_sub(b),
+            Binary::ShiftLeft => a.checked_shl(b).unwrap_or(0),
+            Binary::ShiftRight => a.checked_shr(b).unwrap_or(0),
+            Binary::And => a & b,
+            Binary::Xor => a ^ b,
+            Binary::Or => a | b,
+            Binary::Equal => (a == b) as u32,
+            Binary::NotEqual => (a != b) as u32,
+            Binary::Less => (sa < sb) as u32,
+            Binary::LessEqual => (sa <= sb) as u32,
+            Binary::Greater => (sa > sb) as u32,
+            Binary::GreaterEqual => (sa >= sb) as u32,
+            Binary::LogicalAnd => (a != 0 && b != 0) as u32,
+            Binary::LogicalOr => (a != 0 || b != 0) as u32,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Expr {
+    Number(u32),
+    Register(IntRegister),
+    Pc,
+    Memory { address: Box<Expr>, size: u32 },
+    Unary(Unary, Box<Expr>),
+    Binary(Binary, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the expression, or gives the address of a read outside
+    /// memory.
+    fn eval(&self, processor: &Processor) -> Result<u32, u32> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Register(reg) => Ok(processor.registers().read(*reg)),
+            Expr::Pc => Ok(processor.pc()),
+            Expr::Memory { address, size } => {
+                let address = address.eval(processor)?;
+                let memory = processor.memory();
+                let value = match (memory.contains(address, *size), size) {
+                    (false, _) => return Err(address),
+                    (true, 1) => u32::from(memory.read_byte(address)),
+                    (true, 2) => u32::from(memory.read_halfword(address)),
+                    (true, _) => memory.read_word(address),
+                };
+                Ok(processor.endianness().swap(value, *size))
+            }
+            Expr::Unary(op, a) => {
+                let a = a.eval(processor)?;
+                Ok(match op {
+                    Unary::Negate => a.wrapping_neg(),
+                    Unary::Not => !a,
+                    Unary::LogicalNot => (a == 0) as u32,
+                })
+            }
+            Expr::Binary(op, a, b) => Ok(op.apply(a.eval(processor)?, b.eval(processor)?)),
+        }
+    }
+
+    /// Adds the registers and memory the expression reads, with their
+    /// values, to `values`.
+    fn operands(&self, processor: &Processor, values: &mut Vec<(String, u32)>) {
+        let push = |values: &mut Vec<(String, u32)>, name: String, value| {
+            if !values.iter().any(|(known, _)| *known == name) {
+                values.push((name, value));
+            }
+        };
+        match self {
+            Expr::Number(_) => {}
+            Expr::Register(reg) => push(values, reg.to_string(), processor.registers().read(*reg)),
+            Expr::Pc => push(values, "pc".to_string(), processor.pc()),
+            Expr::Memory { address, .. } => {
+                address.operands(processor, values);
+                if let (Ok(at), Ok(value)) = (address.eval(processor), self.eval(processor)) {
+                    push(values, format!("[{:#010x}]", at), value);
+                }
+            }
+            Expr::Unary(_, a) => a.operands(processor, values),
+            Expr::Binary(_, a, b) => {
+                a.operands(processor, values);
+                b.operands(processor, values);
+            }
+        }
+    }
+}
+
+/// Parses expressions by precedence climbing over the characters left in
+/// `text`.
+struct Parser<'a> {
+    text: &'a str,
+    symbols: &'a SymbolTable,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        self.text = self.text.trim_start();
+    }
+
+    /// Consumes `token` if the text starts with it. An operator is not taken
+    /// for the start of a longer one, such as `<` for `<<`.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let rest = match self.text.strip_prefix(token) {
+            Some(rest) => rest,
+            None => return false,
+        };
+        let longer = ["||", "&&", "<<", ">>", "<=", ">=", "==", "!="]
+            .iter()
+            .any(|op| op.len() > token.len() && op.starts_with(token) && self.text.starts_with(op));
+        if longer {
+            return false;
+        }
+        self.text = rest;
+        true
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == Binary::LEVELS.len() {
+            return self.unary();
+        }
+        let mut expr = self.binary(level + 1)?;
+        'operators: loop {
+            for &(token, op) in Binary::LEVELS[level] {
+                if self.eat(token) {
+                    let rhs = self.binary(level + 1)?;
+                    expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            return Ok(expr);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match () {
+            _ if self.eat("-") => Unary::Negate,
+            _ if self.eat("~") => Unary::Not,
+            _ if self.eat("!") => Unary::LogicalNot,
+            _ => return self.primary(),
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            return match self.eat(")") {
+                true => Ok(expr),
+                false => Err("expected )".to_string()),
+            };
+        }
+        if self.eat("[") {
+            let address = Box::new(self.binary(0)?);
+            if !self.eat("]") {
+                return Err("expected ]".to_string());
+            }
+            let size = match () {
+                _ if self.eat(":1") => 1,
+                _ if self.eat(":2") => 2,
+                _ if self.eat(":4") => 4,
+                _ => 4,
+            };
+            return Ok(Expr::Memory { address, size });
+        }
+
+        self.skip_space();
+        let end = self
+            .text
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "_.$".contains(c)))
+            .unwrap_or(self.text.len());
+        let (word, rest) = self.text.split_at(end);
+        if word.is_empty() {
+            return Err(match self.text.chars().next() {
+                Some(c) => format!("unexpected {}", c),
+                None => "unexpected end of expression".to_string(),
+            });
+        }
+        self.text = rest;
+
+        let number = match word.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => word.parse().ok(),
+        };
+        match (number, word.parse::<IntRegister>()) {
+            (Some(value), _) => Ok(Expr::Number(value)),
+            (None, Ok(reg)) => Ok(Expr::Register(reg)),
+            _ if word == "pc" => Ok(Expr::Pc),
+            _ => match self.symbols.get(word) {
+                Some(symbol) => Ok(Expr::Number(symbol.address)),
+                None => Err(format!("unknown register or symbol {}", word)),
+            },
+        }
+    }
+}
+
+/// An expression that must hold whenever execution reaches `address`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Assertion {
+    pub address: u32,
+    /// Line of the assertion file it was read from.
+    pub line: usize,
+    /// The expression as written.
+    pub text: String,
+    expr: Expr,
+}
+
+/// An assertion that did not hold.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+    pub assertion: Assertion,
+    /// Registers and memory the expression read, with their values.
+    pub values: Vec<(String, u32)>,
+    /// Address of a read outside memory, if the expression made one.
+    pub outside: Option<u32>,
+}
+
+impl Violation {
+    /// Like the `Display` output, but naming the function of the address.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let assertion = &self.assertion;
+        let at = match symbols.lookup(assertion.address) {
+            Some(sym) => format!("{:#010x} <{}>", assertion.address, sym),
+            None => format!("{:#010x}", assertion.address),
+        };
+        let mut text = format!(
+            "assertion on line {} failed at {}: {}",
+            assertion.line, at, assertion.text
+        );
+        match self.outside {
+            Some(address) => text.push_str(&format!(" reads outside memory at {:#010x}", address)),
+            None if !self.values.is_empty() => {
+                let values = self
+                    .values
+                    .iter()
+                    .map(|(name, value)| format!("{}={:#010x}", name, value))
+                    .collect::<Vec<_>>();
+                text.push_str(&format!(" with {}", values.join(", ")));
+            }
+            None => {}
+        }
+        text
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolTable::new()))
+    }
+}
+
+/// Assertions grouped by the address they are checked at.
+#[derive(Clone, Debug, Default)]
+pub struct Assertions {
+    by_address: HashMap<u32, Vec<Assertion>>,
+}
+
+impl Assertions {
+    /// Parses an assertion file, resolving symbols in `symbols`.
+    pub fn parse(text: &str, symbols: &SymbolTable) -> Result<Self, String> {
+        let mut assertions = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let (location, text) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| error("expected <address> <expression>".to_string()))?;
+            let address = match location.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => location.parse().ok(),
+            };
+            let address = address
+                .or_else(|| symbols.get(location).map(|sym| sym.address))
+                .ok_or_else(|| error(format!("no symbol named {}", location)))?;
+
+            let text = text.trim();
+            let mut parser = Parser { text, symbols };
+            let expr = parser.binary(0).map_err(error)?;
+            parser.skip_space();
+            if !parser.text.is_empty() {
+                return Err(error(format!("unexpected {}", parser.text)));
+            }
+            assertions
+                .by_address
+                .entry(address)
+                .or_default()
+                .push(Assertion {
+                    address,
+                    line: number + 1,
+                    text: text.to_string(),
+                    expr,
+                });
+        }
+        Ok(assertions)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_address.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_address.is_empty()
+    }
+
+    /// Checks the assertions at the PC of `processor`, before the
+    /// instruction there executes, and returns the first that does not hold.
+    pub fn check(&self, processor: &Processor) -> Option<Violation> {
+        let assertions = self.by_address.get(&processor.pc())?;
+        assertions.iter().find_map(|assertion| {
+            let outside = match assertion.expr.eval(processor) {
+                Ok(0) => None,
+                Ok(_) => return None,
+                Err(address) => Some(address),
+            };
+            let mut values = Vec::new();
+            assertion.expr.operands(processor, &mut values);
+            Some(Violation {
+                assertion: assertion.clone(),
+                values,
+                outside,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::tests::{simulator, BASE};
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn evaluate_expressions() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Symbol {
+            name: "counter".to_string(),
+            address: 0x800,
+            size: 4,
+        });
+        let text = "
+            # comment
+            0x1000  1 + 2 * 3 == 7 && -1 < 0 && 0xF0 >> 4 == 15 && !(1 | 2 ^ 3 & 1 != 3)
+            4096    counter == 0x800 && [counter] == 0 && [counter]:1 == 0 && pc == 0x1000
+        ";
+        let assertions = Assertions::parse(text, &symbols).unwrap();
+        assert_eq!(assertions.len(), 2);
+
+        let sim = simulator("assert-eval", &[0x00000013]);
+        assert_eq!(assertions.check(sim.processor()), None);
+
+        for (text, message) in [
+            ("0x1000 a0 <", "line 1: unexpected end of expression"),
+            ("0x1000 (a0", "line 1: expected )"),
+            ("0x1000 a0 == 1 )", "line 1: unexpected )"),
+            ("main a0 == 1", "line 1: no symbol named main"),
+            ("0x1000 a8 == 1", "line 1: unknown register or symbol a8"),
+        ] {
+            assert_eq!(Assertions::parse(text, &symbols).unwrap_err(), message);
+        }
+    }
+
+    #[test]
+    fn report_violated_assertion() {
+        // li a0, 12; addi a0, a0, 1
+        let mut sim = simulator("assert", &[0x00C00513, 0x00150513]);
+        let text = format!(
+            "{:#x} a0 < 12 && [sp - 4] == 0\n{:#x} [0x80000000] == 0",
+            BASE + 4,
+            BASE + 8
+        );
+        let assertions = Assertions::parse(&text, &SymbolTable::new()).unwrap();
+
+        assert_eq!(assertions.check(sim.processor()), None);
+        sim.step().unwrap();
+        let violation = assertions.check(sim.processor()).unwrap();
+        assert_eq!(violation.assertion.line, 1);
+        assert_eq!(violation.outside, None);
+        let sp = sim.read_reg(IntRegister::SP);
+        assert_eq!(
+            violation.to_string(),
+            format!(
+                "assertion on line 1 failed at 0x00001004: a0 < 12 && [sp - 4] == 0 with \
+                 a0=0x0000000c, sp={:#010x}, [{:#010x}]=0x00000000",
+                sp,
+                sp - 4
+            )
+        );
+
+        sim.step().unwrap();
+        let violation = assertions.check(sim.processor()).unwrap();
+        assert_eq!(violation.outside, Some(0x8000_0000));
+        assert_eq!(
+            violation.to_string(),
+            "assertion on line 2 failed at 0x00001008: [0x80000000] == 0 reads outside \
+             memory at 0x80000000"
+        );
+    }
+}
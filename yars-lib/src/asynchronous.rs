@@ -0,0 +1,169 @@
+//! Wrappers for embedding simulations in async servers, such as online
+//! judges, without blocking executor threads. Requires the `tokio` feature.
+
+use crate::device::Device;
+use crate::simulator::{ExitEvent, Simulator};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Instructions executed between yields unless set with
+/// [`AsyncSimulator::yield_every`].
+pub const YIELD_EVERY: usize = 10_000;
+
+/// A simulator whose [`AsyncSimulator::run`] yields to the executor
+/// periodically.
+pub struct AsyncSimulator<W: Write> {
+    inner: Simulator<W>,
+    yield_every: usize,
+}
+
+impl<W: Write> AsyncSimulator<W> {
+    pub fn new(inner: Simulator<W>) -> Self {
+        Self {
+            inner,
+            yield_every: YIELD_EVERY,
+        }
+    }
+
+    /// Yields after every `instructions` executed, which must not be zero.
+    pub fn yield_every(mut self, instructions: usize) -> Self {
+        assert!(
+            instructions > 0,
+            "instructions between yields must not be zero"
+        );
+        self.yield_every = instructions;
+        self
+    }
+
+    pub fn get_ref(&self) -> &Simulator<W> {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut Simulator<W> {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> Simulator<W> {
+        self.inner
+    }
+
+    /// Like [`Simulator::run`], but yields to the executor between slices
+    /// of instructions.
+    pub async fn run(&mut self, limit: Option<usize>) -> ExitEvent {
+        let mut remaining = limit;
+        loop {
+            let slice = match remaining {
+                Some(remaining) => remaining.min(self.yield_every),
+                None => self.yield_every,
+            };
+            let start = self.inner.processor().instret();
+            match self.inner.run(Some(slice)) {
+                ExitEvent::CycleLimit => {}
+                exit => return exit,
+            }
+            if let Some(remaining) = &mut remaining {
+                let executed = self.inner.processor().instret() - start;
+                *remaining -= executed.min(*remaining);
+                if *remaining == 0 {
+                    return ExitEvent::CycleLimit;
+                }
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+/// Reading returns the next byte received, or 0 if there is none yet.
+/// Writing sends the low byte.
+pub const DATA: u32 = 0x0;
+/// Bit 0 is set when a byte can be read from `DATA` (read-only).
+pub const STATUS: u32 = 0x4;
+pub const SIZE: u32 = 0x8;
+
+/// Byte stream between the guest and async tasks, mapped as a device. The
+/// guest polls `STATUS` and moves bytes through `DATA`; the host side uses
+/// the channels returned by [`StreamDevice::new`]. Clones of the device
+/// share the same channels.
+#[derive(Clone)]
+pub struct StreamDevice {
+    input: Arc<Mutex<UnboundedReceiver<u8>>>,
+    peeked: Option<u8>,
+    output: UnboundedSender<u8>,
+}
+
+impl StreamDevice {
+    /// Returns the device, the sender of the bytes the guest reads and the
+    /// receiver of the bytes it writes.
+    pub fn new() -> (Self, UnboundedSender<u8>, UnboundedReceiver<u8>) {
+        let (input_tx, input_rx) = mpsc::unbounded_channel();
+        let (output_tx, output_rx) = mpsc::unbounded_channel();
+        let device = Self {
+            input: Arc::new(Mutex::new(input_rx)),
+            peeked: None,
+            output: output_tx,
+        };
+        (device, input_tx, output_rx)
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        if self.peeked.is_none() {
+            self.peeked = self.input.lock().unwrap().try_recv().ok();
+        }
+        self.peeked
+    }
+}
+
+impl Device for StreamDevice {
+    fn read(&mut self, offset: u32, _size: u32) -> u32 {
+        match offset {
+            DATA => {
+                self.peek();
+                self.peeked.take().unwrap_or(0) as u32
+            }
+            STATUS => self.peek().is_some() as u32,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: u32, value: u32) {
+        if offset == DATA {
+            // The host dropping its receiver only means nobody listens
+            let _ = self.output.send(value as u8);
+        }
+    }
+
+    fn name(&self) -> &str {
+        "stream"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::tests::simulator;
+    use tokio::runtime::Builder;
+
+    #[test]
+    fn echo_through_stream_device() {
+        // lui a1, 0x8
+        // loop: lw t0, 4(a1); beq t0, zero, loop
+        // lw a0, 0(a1); sw a0, 0(a1); ecall
+        let code = [
+            0x000085B7, 0x0045A283, 0xFE028EE3, 0x0005A503, 0x00A5A023, 0x00000073,
+        ];
+        let mut sim = simulator("stream", &code);
+        let (device, input, mut output) = StreamDevice::new();
+        sim.map_device(0x8000, SIZE, Box::new(device)).unwrap();
+        let mut sim = AsyncSimulator::new(sim).yield_every(16);
+
+        let runtime = Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            assert_eq!(sim.run(Some(100)).await, ExitEvent::CycleLimit);
+            assert_eq!(sim.get_ref().cycles(), 100);
+            input.send(b'y').unwrap();
+            assert_eq!(sim.run(None).await, ExitEvent::EcallExit);
+            assert_eq!(output.recv().await, Some(b'y'));
+        });
+    }
+}
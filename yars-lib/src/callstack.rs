@@ -0,0 +1,119 @@
+//! Shadow call stack reconstructed from the standard calling convention: a
+//! jump that links to `ra` or `t0` is a call, and a `jalr` through one of
+//! them that does not link is a return.
+
+use crate::instruction::Instruction;
+use crate::register::IntRegister;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Frame {
+    /// Address the call jumped to.
+    pub function: u32,
+    /// Address of the call instruction.
+    pub call_site: u32,
+    pub return_address: u32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CallEvent {
+    Call(Frame),
+    Return(Frame),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames from the outermost call to the innermost one.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Tracks an instruction executed at `pc` that moved the PC to `next`.
+    pub fn update(&mut self, pc: u32, inst: &Instruction, next: u32) -> Option<CallEvent> {
+        let (rd, rs1) = match *inst {
+            Instruction::JAL { rd, .. } => (rd, None),
+            Instruction::JALR { rd, rs1, .. } => (rd, Some(rs1)),
+            _ => return None,
+        };
+
+        if is_link(rd) {
+            let frame = Frame {
+                function: next,
+                call_site: pc,
+                return_address: pc.wrapping_add(4),
+            };
+            self.frames.push(frame);
+            return Some(CallEvent::Call(frame));
+        }
+
+        match rs1 {
+            Some(_) if is_return(inst) => {
+                // Frames skipped by a non-local return are discarded
+                let index = self
+                    .frames
+                    .iter()
+                    .rposition(|frame| frame.return_address == next)?;
+                let frame = self.frames[index];
+                self.frames.truncate(index);
+                Some(CallEvent::Return(frame))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Whether `inst` is a return: a `jalr` through `ra` or `t0` that does not
+/// link.
+pub fn is_return(inst: &Instruction) -> bool {
+    matches!(*inst, Instruction::JALR { rd: IntRegister::Zero, rs1, .. } if is_link(rs1))
+}
+
+fn is_link(reg: IntRegister) -> bool {
+    matches!(reg, IntRegister::RA | IntRegister::T0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_calls_and_returns() {
+        let call = Instruction::JAL {
+            rd: IntRegister::RA,
+            imm: 0x100,
+        };
+        let ret = Instruction::JALR {
+            rd: IntRegister::Zero,
+            rs1: IntRegister::RA,
+            imm: 0,
+        };
+        let jump = Instruction::JAL {
+            rd: IntRegister::Zero,
+            imm: 8,
+        };
+
+        let mut stack = CallStack::new();
+        stack.update(0x1000, &call, 0x1100);
+        stack.update(0x1104, &call, 0x1204);
+        assert_eq!(stack.update(0x1208, &jump, 0x1210), None);
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(stack.frames()[1].function, 0x1204);
+
+        // Returning straight to the outermost caller unwinds both frames
+        let event = stack.update(0x1210, &ret, 0x1004);
+        assert!(matches!(event, Some(CallEvent::Return(f)) if f.call_site == 0x1000));
+        assert_eq!(stack.depth(), 0);
+        assert_eq!(stack.update(0x1004, &ret, 0x2000), None);
+    }
+}
@@ -0,0 +1,159 @@
+//! Stack overflow detection for guest programs. A canary pattern is placed
+//! in memory just below the lowest address the stack may use, and checked
+//! on every call and every few thousand instructions, while the stack
+//! pointer is checked after every instruction. Either catches an overflow
+//! close to where it happens, instead of when the memory it corrupted is
+//! next used.
+
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+use crate::register::IntRegister;
+use crate::symbol::SymbolTable;
+use std::fmt;
+
+/// Bytes of the canary below the stack limit.
+pub const SIZE: u32 = 64;
+
+const PATTERN: u32 = 0xC0DE_CA5E;
+
+/// Instructions between checks of the canary when there are no calls.
+const PERIOD: u64 = 4096;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StackOverflow {
+    /// Instruction after which the overflow was detected.
+    pub pc: u32,
+    pub sp: u32,
+    pub limit: u32,
+    /// Lowest canary byte found overwritten, if the overflow was detected
+    /// from the canary rather than the stack pointer.
+    pub address: Option<u32>,
+}
+
+impl StackOverflow {
+    /// Like the `Display` output, but naming the function of the PC.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let function = match symbols.lookup(self.pc) {
+            Some(sym) => format!(" in {}", sym.symbol.name),
+            None => String::new(),
+        };
+        let cause = match self.address {
+            Some(address) => format!("canary overwritten at {:#010x}", address),
+            None => format!("sp = {:#010x}", self.sp),
+        };
+        format!(
+            "stack overflow{} at {:#010x} ({}, limit {:#010x})",
+            function, self.pc, cause, self.limit
+        )
+    }
+}
+
+impl fmt::Display for StackOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolTable::new()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StackCanary {
+    limit: u32,
+    since_check: u64,
+}
+
+impl StackCanary {
+    /// Places the canary in the [`SIZE`] bytes below `limit`, the lowest
+    /// address the stack may use. Returns `None` if they are not all in
+    /// memory.
+    pub fn place(limit: u32, memory: &mut Memory) -> Option<Self> {
+        let start = limit.checked_sub(SIZE).filter(|&start| start % 4 == 0)?;
+        if !memory.contains(start, SIZE) {
+            return None;
+        }
+        for address in (start..limit).step_by(4) {
+            memory.write_word(address, PATTERN);
+        }
+        Some(Self {
+            limit,
+            since_check: 0,
+        })
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// Checks the stack after `inst`, executed at `pc`, with `sp` and
+    /// `memory` as it left them.
+    pub fn check(
+        &mut self,
+        pc: u32,
+        inst: &Instruction,
+        sp: u32,
+        memory: &Memory,
+    ) -> Option<StackOverflow> {
+        let limit = self.limit;
+        let overflow = |address| StackOverflow {
+            pc,
+            sp,
+            limit,
+            address,
+        };
+        if sp < limit {
+            return Some(overflow(None));
+        }
+
+        self.since_check += 1;
+        let call = inst.is_jump() && inst.rd() == Some(IntRegister::RA);
+        if !call && self.since_check < PERIOD {
+            return None;
+        }
+        self.since_check = 0;
+        (limit - SIZE..limit)
+            .step_by(4)
+            .find(|&address| memory.read_word(address) != PATTERN)
+            .map(|address| {
+                let changed = memory.read_word(address) ^ PATTERN;
+                overflow(Some(address + changed.trailing_zeros() / 8))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::tests::simulator;
+    use crate::simulator::Simulator;
+
+    #[test]
+    fn detect_stack_overflow() {
+        // sw zero, -4(sp); jal ra, 4; addi sp, sp, -64; ecall
+        let code = [0xFE012E23, 0x004000EF, 0xFC010113, 0x00000073];
+        let mut sim = simulator("canary", &code);
+        let sp = sim.read_reg(IntRegister::SP);
+        let mut canary = StackCanary::place(sp, sim.processor_mut().memory_mut()).unwrap();
+        assert!(StackCanary::place(2, sim.processor_mut().memory_mut()).is_none());
+
+        let mut step = |sim: &mut Simulator<Vec<u8>>| {
+            let retired = sim.step().unwrap();
+            let processor = sim.processor();
+            let sp = processor.registers().read(IntRegister::SP);
+            canary.check(retired.pc, &retired.inst, sp, processor.memory())
+        };
+
+        // The store is only found at the next call
+        assert_eq!(step(&mut sim), None);
+        let overflow = step(&mut sim).unwrap();
+        assert_eq!((overflow.pc, overflow.address), (0x1004, Some(sp - 4)));
+        assert_eq!(
+            overflow.to_string(),
+            format!(
+                "stack overflow at 0x00001004 (canary overwritten at {:#010x}, limit {:#010x})",
+                sp - 4,
+                sp
+            )
+        );
+
+        let overflow = step(&mut sim).unwrap();
+        assert_eq!((overflow.sp, overflow.address), (sp - 64, None));
+    }
+}
@@ -0,0 +1,296 @@
+//! Control-flow graphs of executed code, rebuilt from the instructions a
+//! program retired and the jumps and branches it took.
+
+use crate::instruction::{Category, Instruction};
+use crate::symbol::SymbolTable;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+/// Basic blocks and edges of the code a program executed. Only executed
+/// instructions and edges appear in the graph, so a branch that was never
+/// taken has a single successor.
+#[derive(Clone, Debug, Default)]
+pub struct ControlFlowGraph {
+    entry: Option<u32>,
+    /// Executed instructions by address, with how many times they ran.
+    instructions: BTreeMap<u32, (Instruction, u64)>,
+    /// Control transfers from the address of a branch, jump or environment
+    /// call to the address executed after it.
+    transfers: BTreeMap<(u32, u32), u64>,
+}
+
+/// A straight-line run of instructions entered only at the top and left
+/// only at the bottom.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasicBlock {
+    pub start: u32,
+    pub instructions: Vec<Instruction>,
+    pub count: u64,
+}
+
+impl BasicBlock {
+    /// Address of the last instruction of the block.
+    pub fn last(&self) -> u32 {
+        self.start + 4 * (self.instructions.len() as u32 - 1)
+    }
+}
+
+/// How many times a conditional branch was taken and not taken.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Branch {
+    pub pc: u32,
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl ControlFlowGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `inst` at `pc` was executed and that execution continued
+    /// at `next`.
+    pub fn record(&mut self, pc: u32, inst: &Instruction, next: u32) {
+        self.record_exit(pc, inst);
+        if transfers_control(inst) {
+            *self.transfers.entry((pc, next)).or_insert(0) += 1;
+        }
+    }
+
+    /// Records that `inst` at `pc` was executed and stopped the program.
+    pub fn record_exit(&mut self, pc: u32, inst: &Instruction) {
+        self.entry.get_or_insert(pc);
+        self.instructions.entry(pc).or_insert((*inst, 0)).1 += 1;
+    }
+
+    /// Splits the executed instructions into basic blocks, in address order.
+    pub fn blocks(&self) -> Vec<BasicBlock> {
+        let mut leaders = self.entry.into_iter().collect::<BTreeSet<_>>();
+        for &(from, to) in self.transfers.keys() {
+            leaders.insert(to);
+            leaders.insert(from.wrapping_add(4));
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut previous = None;
+        for (&pc, &(inst, count)) in &self.instructions {
+            let extends = match (previous, blocks.last()) {
+                (Some(previous), Some(block)) => {
+                    pc == previous + 4 && !leaders.contains(&pc) && !ends_block(block)
+                }
+                _ => false,
+            };
+            match extends {
+                true => blocks.last_mut().unwrap().instructions.push(inst),
+                false => blocks.push(BasicBlock {
+                    start: pc,
+                    instructions: vec![inst],
+                    count,
+                }),
+            }
+            previous = Some(pc);
+        }
+        blocks
+    }
+
+    /// Edges between blocks as `(from, to, count)`, with blocks named by
+    /// their start address.
+    pub fn edges(&self) -> Vec<(u32, u32, u64)> {
+        let blocks = self.blocks();
+        let mut edges = Vec::new();
+        for (index, block) in blocks.iter().enumerate() {
+            let last = block.last();
+            match ends_block(block) {
+                true => edges.extend(
+                    self.transfers
+                        .range((last, 0)..=(last, u32::MAX))
+                        .map(|(&(_, to), &count)| (block.start, to, count)),
+                ),
+                false => {
+                    // Falls through into a block that starts with a jump target
+                    if let Some(next) = blocks.get(index + 1).filter(|b| b.start == last + 4) {
+                        let count = self.instructions[&last].1;
+                        edges.push((block.start, next.start, count));
+                    }
+                }
+            }
+        }
+        edges
+    }
+
+    /// Executed conditional branches, in address order.
+    pub fn branches(&self) -> Vec<Branch> {
+        self.instructions
+            .iter()
+            .filter(|(_, (inst, _))| inst.category() == Category::Branch)
+            .map(|(&pc, _)| {
+                let mut branch = Branch {
+                    pc,
+                    taken: 0,
+                    not_taken: 0,
+                };
+                for (&(_, to), &count) in self.transfers.range((pc, 0)..=(pc, u32::MAX)) {
+                    match to == pc.wrapping_add(4) {
+                        true => branch.not_taken += count,
+                        false => branch.taken += count,
+                    }
+                }
+                branch
+            })
+            .collect()
+    }
+
+    /// Writes one `kind,start,end,symbol,count,taken,not_taken` line per
+    /// executed basic block, then per conditional branch. Blocks cover
+    /// `start..end` and leave the last two columns empty; branches are
+    /// four bytes long. A branch never taken, or always taken, shows code
+    /// the program did not exercise.
+    pub fn write_coverage_csv<W: Write>(
+        &self,
+        symbols: &SymbolTable,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let symbol = |address| match symbols.lookup(address) {
+            Some(sym) => sym.to_string(),
+            None => String::new(),
+        };
+        writeln!(writer, "kind,start,end,symbol,count,taken,not_taken")?;
+        for block in self.blocks() {
+            writeln!(
+                writer,
+                "block,{:#010x},{:#010x},{},{},,",
+                block.start,
+                block.last() + 4,
+                symbol(block.start),
+                block.count
+            )?;
+        }
+        for branch in self.branches() {
+            writeln!(
+                writer,
+                "branch,{:#010x},{:#010x},{},{},{},{}",
+                branch.pc,
+                branch.pc + 4,
+                symbol(branch.pc),
+                branch.taken + branch.not_taken,
+                branch.taken,
+                branch.not_taken
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the graph in Graphviz DOT format, labelling blocks with their
+    /// disassembly and, if `counts` is set, blocks and edges with how many
+    /// times they were executed.
+    pub fn write_dot<W: Write>(
+        &self,
+        symbols: &SymbolTable,
+        counts: bool,
+        mut writer: W,
+    ) -> io::Result<()> {
+        writeln!(writer, "digraph cfg {{")?;
+        writeln!(writer, "    node [shape=box, fontname=monospace];")?;
+
+        for block in self.blocks() {
+            let mut label = match symbols.lookup(block.start) {
+                Some(sym) => format!("{}:\\l", sym),
+                None => format!("{:#x}:\\l", block.start),
+            };
+            for (index, inst) in block.instructions.iter().enumerate() {
+                let pc = block.start + 4 * index as u32;
+                let text = inst.display_at(pc).with_symbols(symbols).to_string();
+                label.push_str(&format!("  {}\\l", escape(&text)));
+            }
+            if counts {
+                label.push_str(&format!("executed {} times\\l", block.count));
+            }
+            writeln!(writer, "    b{:x} [label=\"{}\"];", block.start, label)?;
+        }
+
+        for (from, to, count) in self.edges() {
+            match counts {
+                true => writeln!(
+                    writer,
+                    "    b{:x} -> b{:x} [label=\"{}\"];",
+                    from, to, count
+                )?,
+                false => writeln!(writer, "    b{:x} -> b{:x};", from, to)?,
+            }
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+fn ends_block(block: &BasicBlock) -> bool {
+    transfers_control(block.instructions.last().unwrap())
+}
+
+/// Whether execution may continue somewhere other than the next address
+/// after `inst`, counting calls to the environment, which can stop it.
+fn transfers_control(inst: &Instruction) -> bool {
+    matches!(
+        inst.category(),
+        Category::Branch | Category::Jump | Category::System
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn split_loop_into_blocks() {
+        // 0x0: addi t0, zero, 2
+        // 0x4: addi t0, t0, -1
+        // 0x8: bne t0, zero, -4
+        // 0xc: ecall
+        let code = [0x00200293, 0xFFF28293, 0xFE029EE3, 0x00000073];
+        let trace = [(0x0, 0x4), (0x4, 0x8), (0x8, 0x4), (0x4, 0x8), (0x8, 0xC)];
+        let inst = |pc: u32| Instruction::try_from(code[pc as usize / 4]).unwrap();
+
+        let mut cfg = ControlFlowGraph::new();
+        for &(pc, next) in &trace {
+            cfg.record(pc, &inst(pc), next);
+        }
+        cfg.record_exit(0xC, &inst(0xC));
+
+        let blocks = cfg.blocks();
+        let starts = blocks
+            .iter()
+            .map(|b| (b.start, b.count))
+            .collect::<Vec<_>>();
+        assert_eq!(starts, [(0x0, 1), (0x4, 2), (0xC, 1)]);
+        assert_eq!(blocks[1].instructions.len(), 2);
+        assert_eq!(cfg.edges(), [(0x0, 0x4, 1), (0x4, 0x4, 1), (0x4, 0xC, 1)]);
+
+        let mut dot = Vec::new();
+        cfg.write_dot(&SymbolTable::new(), true, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("    b4 -> b4 [label=\"1\"];\n"));
+        assert!(dot.contains("executed 2 times"));
+
+        let branch = Branch {
+            pc: 0x8,
+            taken: 1,
+            not_taken: 1,
+        };
+        assert_eq!(cfg.branches(), [branch]);
+        let mut csv = Vec::new();
+        cfg.write_coverage_csv(&SymbolTable::new(), &mut csv)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "kind,start,end,symbol,count,taken,not_taken\n\
+             block,0x00000000,0x00000004,,1,,\n\
+             block,0x00000004,0x0000000c,,2,,\n\
+             block,0x0000000c,0x00000010,,1,,\n\
+             branch,0x00000008,0x0000000c,,2,1,1\n"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Checkpoints of the processor state taken periodically in memory, to
+//! roll a long run back to shortly before a failure instead of restarting
+//! it.
+
+use crate::processor::Processor;
+use crate::simulator::Simulator;
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// The processor state of a simulation after some number of cycles.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    pub cycles: usize,
+    processor: Processor,
+}
+
+/// The last `capacity` checkpoints of a simulation, taken every `interval`
+/// cycles.
+#[derive(Clone, Debug)]
+pub struct Checkpoints {
+    interval: usize,
+    capacity: usize,
+    next: usize,
+    taken: VecDeque<Checkpoint>,
+}
+
+impl Checkpoints {
+    /// Keeps up to `capacity` checkpoints, one every `interval` cycles. Both
+    /// must not be zero.
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        assert!(interval > 0, "checkpoint interval must not be zero");
+        assert!(capacity > 0, "checkpoint capacity must not be zero");
+        Self {
+            interval,
+            capacity,
+            next: 0,
+            taken: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Takes a checkpoint of `sim` if `interval` cycles have passed since
+    /// the last one, dropping the oldest one if there are too many.
+    pub fn update<W: Write>(&mut self, sim: &Simulator<W>) {
+        let cycles = sim.cycles();
+        if cycles < self.next {
+            return;
+        }
+
+        if self.taken.len() == self.capacity {
+            self.taken.pop_front();
+        }
+        self.taken.push_back(Checkpoint {
+            cycles,
+            processor: sim.processor().clone(),
+        });
+        self.next = cycles + self.interval;
+    }
+
+    /// Checkpoints kept, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Checkpoint> {
+        self.taken.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.taken.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taken.is_empty()
+    }
+
+    /// Restores `sim` to the checkpoint at `index`, counting from the oldest
+    /// one, and discards the checkpoints taken after it. Returns the cycle
+    /// count the simulation went back to, or `None` if there is no such
+    /// checkpoint.
+    pub fn rollback<W: Write>(&mut self, sim: &mut Simulator<W>, index: usize) -> Option<usize> {
+        let checkpoint = self.taken.get(index)?;
+        *sim.processor_mut() = checkpoint.processor.clone();
+        let cycles = checkpoint.cycles;
+        self.taken.truncate(index + 1);
+        self.next = cycles + self.interval;
+        Some(cycles)
+    }
+
+    /// Restores `sim` to the newest checkpoint. See [`Checkpoints::rollback`].
+    pub fn rollback_last<W: Write>(&mut self, sim: &mut Simulator<W>) -> Option<usize> {
+        let index = self.taken.len().checked_sub(1)?;
+        self.rollback(sim, index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::register::IntRegister;
+    use crate::simulator::tests::simulator;
+
+    #[test]
+    fn keep_last_checkpoints_and_roll_back() {
+        // addi a0, a0, 1; jal zero, -4
+        let mut sim = simulator("checkpoints", &[0x00150513, 0xFFDFF06F]);
+        let mut checkpoints = Checkpoints::new(10, 3);
+        for _ in 0..50 {
+            checkpoints.update(&sim);
+            sim.step().unwrap();
+        }
+
+        let cycles = checkpoints.iter().map(|c| c.cycles).collect::<Vec<_>>();
+        assert_eq!(cycles, [20, 30, 40]);
+
+        assert_eq!(checkpoints.rollback(&mut sim, 1), Some(30));
+        assert_eq!(sim.cycles(), 30);
+        assert_eq!(sim.read_reg(IntRegister::A0), 15);
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints.rollback(&mut sim, 2), None);
+
+        sim.step().unwrap();
+        checkpoints.update(&sim);
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints.rollback_last(&mut sim), Some(30));
+    }
+}
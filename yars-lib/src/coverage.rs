@@ -0,0 +1,184 @@
+//! Instruction set coverage of executed programs.
+
+use crate::instruction::Instruction;
+use crate::register::IntRegister;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+/// Operand patterns that are easy to get wrong and worth exercising.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Case {
+    /// The result is discarded by writing it to `zero`.
+    ZeroDestination,
+    NegativeImmediate,
+    ZeroShift,
+    /// Shift by 31, the largest amount on RV32.
+    MaxShift,
+    /// Branch or jump to a lower address.
+    BackwardTarget,
+}
+
+impl Case {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Case::ZeroDestination => "rd=zero",
+            Case::NegativeImmediate => "imm<0",
+            Case::ZeroShift => "shamt=0",
+            Case::MaxShift => "shamt=31",
+            Case::BackwardTarget => "backward",
+        }
+    }
+
+    /// Cases that apply to `inst`, each with whether `inst` exercises it.
+    pub fn of(inst: &Instruction) -> Vec<(Case, bool)> {
+        use Instruction::*;
+        let mut cases = Vec::new();
+
+        if let Some(rd) = inst.rd() {
+            cases.push((Case::ZeroDestination, rd == IntRegister::Zero));
+        }
+
+        let imm = match *inst {
+            LB { imm, .. }
+            | LH { imm, .. }
+            | LW { imm, .. }
+            | LBU { imm, .. }
+            | LHU { imm, .. } => Some(i32::from(imm)),
+            SB { imm, .. } | SH { imm, .. } | SW { imm, .. } | JALR { imm, .. } => Some(imm.into()),
+            ADDI { imm, .. } | SLTI { imm, .. } | SLTIU { imm, .. } => Some(imm.into()),
+            XORI { imm, .. } | ORI { imm, .. } | ANDI { imm, .. } => Some(imm.into()),
+            LUI { imm, .. } | AUIPC { imm, .. } => Some(imm),
+            _ => None,
+        };
+        if let Some(imm) = imm {
+            cases.push((Case::NegativeImmediate, imm < 0));
+        }
+
+        if let SLLI { shamt, .. } | SRLI { shamt, .. } | SRAI { shamt, .. } = *inst {
+            cases.push((Case::ZeroShift, shamt == 0));
+            cases.push((Case::MaxShift, shamt == 31));
+        }
+
+        let target = match *inst {
+            BEQ { imm, .. } | BNE { imm, .. } | BLT { imm, .. } | BGE { imm, .. } => {
+                Some(i32::from(imm))
+            }
+            BLTU { imm, .. } | BGEU { imm, .. } => Some(imm.into()),
+            JAL { imm, .. } => Some(imm),
+            _ => None,
+        };
+        if let Some(target) = target {
+            cases.push((Case::BackwardTarget, target < 0));
+        }
+
+        cases
+    }
+}
+
+/// Counts of the instructions a program executed, and the [`Case`]s of each
+/// one that were exercised.
+#[derive(Clone, Debug, Default)]
+pub struct IsaCoverage {
+    counts: BTreeMap<&'static str, u64>,
+    cases: BTreeSet<(&'static str, Case)>,
+}
+
+impl IsaCoverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, inst: &Instruction) {
+        let mnemonic = inst.mnemonic();
+        *self.counts.entry(mnemonic).or_insert(0) += 1;
+        for (case, hit) in Case::of(inst) {
+            if hit {
+                self.cases.insert((mnemonic, case));
+            }
+        }
+    }
+
+    /// Number of times `mnemonic` was executed.
+    pub fn count(&self, mnemonic: &str) -> u64 {
+        self.counts.get(mnemonic).copied().unwrap_or(0)
+    }
+
+    pub fn covers(&self, mnemonic: &str, case: Case) -> bool {
+        self.cases.iter().any(|&(m, c)| m == mnemonic && c == case)
+    }
+
+    /// Writes one line per instruction with its count and the cases that
+    /// were and were not exercised, preceded by a summary.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let examples = Instruction::examples().collect::<Vec<_>>();
+        let total_cases: usize = examples.iter().map(|inst| Case::of(inst).len()).sum();
+        let executed = examples
+            .iter()
+            .filter(|inst| self.count(inst.mnemonic()) > 0)
+            .count();
+
+        writeln!(
+            writer,
+            "Instructions: {}/{} executed, cases: {}/{} exercised",
+            executed,
+            examples.len(),
+            self.cases.len(),
+            total_cases
+        )?;
+
+        for inst in &examples {
+            let mnemonic = inst.mnemonic();
+            let (hit, missed): (Vec<_>, Vec<_>) = Case::of(inst)
+                .into_iter()
+                .map(|(case, _)| case)
+                .partition(|&case| self.covers(mnemonic, case));
+            let names = |cases: Vec<Case>| {
+                cases
+                    .iter()
+                    .map(|case| case.name())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+
+            write!(writer, "{:<10}{:>12}", mnemonic, self.count(mnemonic))?;
+            if !hit.is_empty() {
+                write!(writer, "  {}", names(hit))?;
+            }
+            if !missed.is_empty() {
+                write!(writer, "  (missing: {})", names(missed))?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn record_instructions_and_cases() {
+        let mut coverage = IsaCoverage::new();
+        // addi zero, zero, 0; srai a0, a0, 31; beq zero, zero, -4
+        for raw in &[0x00000013, 0x41F55513, 0xFE000EE3] {
+            coverage.record(&Instruction::try_from(*raw).unwrap());
+        }
+
+        assert_eq!(coverage.count("addi"), 1);
+        assert_eq!(coverage.count("add"), 0);
+        assert!(coverage.covers("addi", Case::ZeroDestination));
+        assert!(!coverage.covers("addi", Case::NegativeImmediate));
+        assert!(coverage.covers("srai", Case::MaxShift));
+        assert!(!coverage.covers("srai", Case::ZeroDestination));
+        assert!(coverage.covers("beq", Case::BackwardTarget));
+
+        let mut report = Vec::new();
+        coverage.write(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("Instructions: 3/49 executed, cases: 3/"));
+        assert!(report.contains("srai                 1  shamt=31  (missing: rd=zero shamt=0)\n"));
+        assert!(report.contains("sub                  0  (missing: rd=zero)\n"));
+    }
+}
@@ -0,0 +1,133 @@
+//! Table-driven decoding of instruction words. The table is generated at
+//! build time from the instruction lists in `opcodes/`, one per extension,
+//! where each instruction is given by its operand fields and the values of
+//! its other bits. Supporting another extension takes a new list and the
+//! [`Instruction`] variants it names.
+
+use crate::instruction::{FenceKind, Instruction, InstructionFormat};
+use crate::register::IntRegister;
+use std::convert::TryFrom;
+
+/// How an instruction is encoded.
+#[derive(Copy, Clone, Debug)]
+pub struct Encoding {
+    pub mnemonic: &'static str,
+    /// Bits that identify the instruction.
+    pub mask: u32,
+    /// Value of the bits in `mask`.
+    pub bits: u32,
+    pub format: InstructionFormat,
+    decode: fn(u32) -> Result<Instruction, ()>,
+}
+
+impl Encoding {
+    pub fn matches(&self, inst: u32) -> bool {
+        inst & self.mask == self.bits
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/decode.rs"));
+
+/// Every encoding the decoder knows, ordered by major opcode.
+pub fn encodings() -> &'static [Encoding] {
+    &TABLE
+}
+
+/// The encoding `inst` matches, if any.
+pub fn lookup(inst: u32) -> Option<&'static Encoding> {
+    let (start, end) = GROUPS[(inst >> 2) as usize & 0x1F];
+    TABLE[start..end]
+        .iter()
+        .find(|encoding| encoding.matches(inst))
+}
+
+/// Decodes `inst`, if it encodes an instruction the table knows.
+pub fn decode(inst: u32) -> Option<Instruction> {
+    let encoding = lookup(inst)?;
+    (encoding.decode)(inst).ok()
+}
+
+fn reg(inst: u32, shift: u32) -> Result<IntRegister, ()> {
+    IntRegister::try_from((inst >> shift) as u8 & 0x1F)
+}
+
+fn fence_kind(inst: u32, shift: u32) -> Result<FenceKind, ()> {
+    FenceKind::try_from((inst >> shift) as u8 & 0xF)
+}
+
+fn shamt(inst: u32) -> u8 {
+    (inst >> 20) as u8 & 0x1F
+}
+
+fn imm_i(inst: u32) -> i16 {
+    ((inst as i32) >> 20) as i16
+}
+
+fn imm_s(inst: u32) -> i16 {
+    let imm115 = (inst as i32) >> 25;
+    let imm40 = (inst >> 7) & 0b11111;
+    ((imm115 << 5) | imm40 as i32) as i16
+}
+
+fn imm_b(inst: u32) -> i16 {
+    let imm12 = (inst as i32) >> 31;
+    let imm105 = (inst >> 25) & 0b111111;
+    let imm41 = (inst >> 8) & 0b1111;
+    let imm11 = (inst >> 7) & 0b1;
+    ((imm12 << 12) | ((imm11 << 11) | (imm105 << 5) | (imm41 << 1)) as i32) as i16
+}
+
+fn imm_u(inst: u32) -> i32 {
+    (inst as i32) >> 12
+}
+
+fn imm_j(inst: u32) -> i32 {
+    let imm20 = (inst as i32) >> 31;
+    let imm101 = (inst >> 21) & 0b1111111111;
+    let imm11 = (inst >> 20) & 0b1;
+    let imm1912 = (inst >> 12) & 0b11111111;
+    (imm20 << 20) | ((imm1912 << 12) | (imm11 << 11) | (imm101 << 1)) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entropy::Entropy;
+
+    #[test]
+    fn encode_what_is_decoded() {
+        let mut entropy = Entropy::seeded(1);
+        for encoding in encodings() {
+            let opcode = encoding.bits as u8 & 0x7F;
+            assert_eq!(
+                Some(encoding.format),
+                InstructionFormat::from_opcode(opcode),
+                "{}",
+                encoding.mnemonic
+            );
+
+            let mut decoded = 0;
+            for _ in 0..1000 {
+                let word = entropy.next_u32() & !encoding.mask | encoding.bits;
+                let inst = match decode(word) {
+                    Some(inst) => inst,
+                    None => continue,
+                };
+                decoded += 1;
+                assert_eq!(inst.mnemonic(), encoding.mnemonic);
+                assert!(encoding.matches(u32::from(inst)), "{}", inst);
+                assert_eq!(decode(u32::from(inst)), Some(inst));
+            }
+            assert!(decoded > 0, "{} never decodes", encoding.mnemonic);
+        }
+    }
+
+    #[test]
+    fn decode_what_is_encoded() {
+        for inst in Instruction::examples() {
+            let encoding = lookup(u32::from(inst)).unwrap();
+            assert_eq!(encoding.mnemonic, inst.mnemonic());
+        }
+        assert_eq!(encodings().len(), Instruction::examples().count());
+    }
+}
@@ -0,0 +1,554 @@
+use crate::entropy::{Entropy, TimeSource, TICK_NS};
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Instructions retired and cycles elapsed when a device is accessed.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Counters {
+    pub instret: u64,
+    pub cycles: u64,
+}
+
+/// A memory-mapped device. Offsets are relative to the base address the
+/// device was mapped at and `size` is the access width in bytes (1, 2 or 4).
+pub trait Device: DeviceClone + Send {
+    fn read(&mut self, offset: u32, size: u32) -> u32;
+    fn write(&mut self, offset: u32, size: u32, value: u32);
+
+    /// Short name shown in memory maps.
+    fn name(&self) -> &str {
+        "device"
+    }
+
+    /// Called before every access with the processor counters, for devices
+    /// that follow simulated time.
+    fn sync(&mut self, _counters: Counters) {}
+
+    /// Describes what writing `value` at `offset` does, if it is worth
+    /// noting in device event logs, such as the byte a serial port sends.
+    fn describe_write(&self, _offset: u32, _value: u32) -> Option<String> {
+        None
+    }
+
+    /// State to keep in snapshots, handed back to [`Device::restore`] on the
+    /// device of the same name mapped at the same address on resume.
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore(&mut self, _state: &[u8]) {}
+}
+
+/// Access to a device by the guest, or a change of an interrupt line by the
+/// host.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceEventKind {
+    Read {
+        device: String,
+        offset: u32,
+        size: u32,
+        value: u32,
+    },
+    Write {
+        device: String,
+        offset: u32,
+        size: u32,
+        value: u32,
+        /// What the write does, as told by [`Device::describe_write`].
+        note: Option<String>,
+    },
+    Irq {
+        line: u32,
+        raised: bool,
+    },
+}
+
+/// Device event with the counters and PC when it happened.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceEvent {
+    pub counters: Counters,
+    pub pc: u32,
+    pub kind: DeviceEventKind,
+}
+
+impl fmt::Display for DeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[PC={:08X}] [cycle {}] ", self.pc, self.counters.cycles)?;
+        let width = |size: &u32| 2 + 2 * *size as usize;
+        match &self.kind {
+            DeviceEventKind::Read {
+                device,
+                offset,
+                size,
+                value,
+            } => write!(
+                f,
+                "{}+{:#x} -> {:#0w$x}",
+                device,
+                offset,
+                value,
+                w = width(size)
+            ),
+            DeviceEventKind::Write {
+                device,
+                offset,
+                size,
+                value,
+                note,
+            } => {
+                write!(
+                    f,
+                    "{}+{:#x} <- {:#0w$x}",
+                    device,
+                    offset,
+                    value,
+                    w = width(size)
+                )?;
+                match note {
+                    Some(note) => write!(f, " ({})", note),
+                    None => Ok(()),
+                }
+            }
+            DeviceEventKind::Irq { line, raised } => match raised {
+                true => write!(f, "irq {} raised", line),
+                false => write!(f, "irq {} cleared", line),
+            },
+        }
+    }
+}
+
+/// Allows cloning boxed devices; implemented for every `Clone` device.
+pub trait DeviceClone {
+    fn clone_box(&self) -> Box<dyn Device>;
+}
+
+impl<T: Device + Clone + 'static> DeviceClone for T {
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+/// Random number generator. Every read returns a fresh value.
+#[derive(Clone)]
+pub struct Rng(pub Entropy);
+
+impl Device for Rng {
+    fn read(&mut self, _offset: u32, _size: u32) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn write(&mut self, _offset: u32, _size: u32, _value: u32) {}
+
+    fn name(&self) -> &str {
+        "rng"
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.0.save()
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        self.0.restore(state);
+    }
+}
+
+/// Real-time clock counting nanoseconds since the Unix epoch. Reading the low
+/// word at offset 0 latches the high word returned at offset 4.
+#[derive(Clone)]
+pub struct Rtc {
+    entropy: Entropy,
+    source: TimeSource,
+    counters: Counters,
+    high: u32,
+}
+
+impl Rtc {
+    pub fn new(entropy: Entropy) -> Self {
+        Self {
+            entropy,
+            source: TimeSource::Host,
+            counters: Counters::default(),
+            high: 0,
+        }
+    }
+
+    /// Advances the clock with `source` instead of reading it from the
+    /// entropy source.
+    pub fn with_source(mut self, source: TimeSource) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+impl Device for Rtc {
+    fn read(&mut self, offset: u32, _size: u32) -> u32 {
+        match offset {
+            0 => {
+                let time = match self.source {
+                    TimeSource::Icount => self.counters.instret * TICK_NS,
+                    TimeSource::Cycles => self.counters.cycles * TICK_NS,
+                    TimeSource::Host => self.entropy.time_ns(),
+                };
+                self.high = (time >> 32) as u32;
+                time as u32
+            }
+            _ => self.high,
+        }
+    }
+
+    fn write(&mut self, _offset: u32, _size: u32, _value: u32) {}
+
+    fn name(&self) -> &str {
+        "rtc"
+    }
+
+    fn sync(&mut self, counters: Counters) {
+        self.counters = counters;
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let mut state = self.high.to_le_bytes().to_vec();
+        state.extend(self.entropy.save());
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        if state.len() >= 4 {
+            let (high, entropy) = state.split_at(4);
+            self.high = u32::from_le_bytes(high.try_into().unwrap());
+            self.entropy.restore(entropy);
+        }
+    }
+}
+
+/// Output stream of a guest program.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Everything a guest writes to a stream since the capture was attached,
+/// kept for the host to read after the run. Clones share the same buffer.
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Capture {
+    /// Returns the bytes captured so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.bytes.lock().unwrap().clone()
+    }
+
+    /// Returns the bytes captured so far and starts over.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.bytes.lock().unwrap())
+    }
+}
+
+/// Serial console. Writing `DATA` at offset 0 sends its low byte to the
+/// host's standard output, and writing `ERROR` at offset 8 to its standard
+/// error. Reading `DATA` returns the next byte of input, or 0 if there is
+/// none; bit 0 of `STATUS` at offset 4 tells whether there is. Clones share
+/// the same buffers, so the host keeps one to exchange bytes with the guest.
+#[derive(Clone, Default)]
+pub struct Console {
+    output: Arc<Mutex<Vec<u8>>>,
+    errors: Arc<Mutex<Vec<u8>>>,
+    input: Arc<Mutex<VecDeque<u8>>>,
+    captures: Arc<Mutex<Vec<(Stream, Capture)>>>,
+}
+
+impl Console {
+    pub const SIZE: u32 = 12;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the bytes written by the guest to its standard output since
+    /// the last call.
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+
+    /// Returns the bytes written by the guest to its standard error since
+    /// the last call.
+    pub fn take_errors(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.errors.lock().unwrap())
+    }
+
+    /// Attaches a capture of `stream`. It collects every byte the guest
+    /// writes from now on, whether or not the host also takes them with
+    /// [`Console::take_output`] to show them as they come, so output can be
+    /// checked once the program stops.
+    pub fn capture(&self, stream: Stream) -> Capture {
+        let capture = Capture::default();
+        self.captures
+            .lock()
+            .unwrap()
+            .push((stream, capture.clone()));
+        capture
+    }
+
+    /// Queues `bytes` for the guest to read.
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.input.lock().unwrap().extend(bytes);
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, offset: u32, _size: u32) -> u32 {
+        let mut input = self.input.lock().unwrap();
+        match offset {
+            0 => input.pop_front().unwrap_or(0) as u32,
+            4 => !input.is_empty() as u32,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: u32, value: u32) {
+        let (stream, buffer) = match offset {
+            0 => (Stream::Stdout, &self.output),
+            8 => (Stream::Stderr, &self.errors),
+            _ => return,
+        };
+        buffer.lock().unwrap().push(value as u8);
+        let captures = self.captures.lock().unwrap();
+        for (_, capture) in captures.iter().filter(|(other, _)| *other == stream) {
+            capture.bytes.lock().unwrap().push(value as u8);
+        }
+    }
+
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn describe_write(&self, offset: u32, value: u32) -> Option<String> {
+        match offset {
+            0 => Some(format!("stdout {:?}", value as u8 as char)),
+            8 => Some(format!("stderr {:?}", value as u8 as char)),
+            _ => None,
+        }
+    }
+
+    /// Keeps the input the guest has not read yet. Output is handed to the
+    /// host as it is written, so there is none to keep.
+    fn save(&self) -> Vec<u8> {
+        self.input.lock().unwrap().iter().copied().collect()
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        let mut input = self.input.lock().unwrap();
+        input.clear();
+        input.extend(state);
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DeviceError {
+    Overlapping,
+    InvalidRange,
+}
+
+struct Mapping {
+    base: u32,
+    size: u32,
+    device: Box<dyn Device>,
+}
+
+impl Clone for Mapping {
+    fn clone(&self) -> Self {
+        Self {
+            base: self.base,
+            size: self.size,
+            device: self.device.clone_box(),
+        }
+    }
+}
+
+impl Mapping {
+    fn contains(&self, address: u32, size: u32) -> bool {
+        address >= self.base && (address - self.base) as u64 + size as u64 <= self.size as u64
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct DeviceMap {
+    mappings: Vec<Mapping>,
+}
+
+impl DeviceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn map(
+        &mut self,
+        base: u32,
+        size: u32,
+        device: Box<dyn Device>,
+    ) -> Result<(), DeviceError> {
+        let end = base as u64 + size as u64;
+
+        if size == 0 || end > 1 << 32 {
+            return Err(DeviceError::InvalidRange);
+        }
+
+        let overlaps = self
+            .mappings
+            .iter()
+            .any(|m| (base as u64) < m.base as u64 + m.size as u64 && (m.base as u64) < end);
+
+        if overlaps {
+            return Err(DeviceError::Overlapping);
+        }
+
+        self.mappings.push(Mapping { base, size, device });
+        Ok(())
+    }
+
+    pub fn find(&mut self, address: u32, size: u32) -> Option<(&mut (dyn Device + 'static), u32)> {
+        self.mappings
+            .iter_mut()
+            .find(|m| m.contains(address, size))
+            .map(|m| (m.device.as_mut(), address - m.base))
+    }
+
+    pub fn ranges(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.mappings.iter().map(|m| (m.base, m.size))
+    }
+
+    /// Mapped devices with their base address and size.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32, &dyn Device)> + '_ {
+        self.mappings
+            .iter()
+            .map(|m| (m.base, m.size, m.device.as_ref() as &dyn Device))
+    }
+}
+
+impl fmt::Debug for DeviceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.ranges()
+                    .map(|(base, size)| base..base.wrapping_add(size)),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct Latch(u32);
+
+    impl Device for Latch {
+        fn read(&mut self, _offset: u32, _size: u32) -> u32 {
+            self.0
+        }
+
+        fn write(&mut self, _offset: u32, _size: u32, value: u32) {
+            self.0 = value;
+        }
+    }
+
+    #[test]
+    fn reject_overlapping_mappings() {
+        let mut devices = DeviceMap::new();
+        assert_eq!(devices.map(0x100, 0x10, Box::new(Latch(0))), Ok(()));
+        assert_eq!(
+            devices.map(0x10C, 0x10, Box::new(Latch(0))),
+            Err(DeviceError::Overlapping)
+        );
+        assert_eq!(devices.map(0x110, 0x10, Box::new(Latch(0))), Ok(()));
+    }
+
+    #[test]
+    fn find_device_by_address() {
+        let mut devices = DeviceMap::new();
+        devices.map(0x100, 0x10, Box::new(Latch(7))).unwrap();
+
+        let (device, offset) = devices.find(0x104, 4).unwrap();
+        assert_eq!((device.read(offset, 4), offset), (7, 4));
+        assert!(devices.find(0x10E, 4).is_none());
+    }
+
+    #[test]
+    fn exchange_bytes_through_console() {
+        let console = Console::new();
+        let mut device = console.clone();
+        assert_eq!(device.read(4, 4), 0);
+        console.push_input(b"ok");
+        assert_eq!((device.read(4, 4), device.read(0, 1)), (1, b'o' as u32));
+        assert_eq!(device.read(0, 1), b'k' as u32);
+        assert_eq!((device.read(4, 4), device.read(0, 1)), (0, 0));
+
+        device.write(0, 1, 0x100 | b'!' as u32);
+        assert_eq!(console.take_output(), b"!");
+        assert!(console.take_output().is_empty());
+    }
+
+    #[test]
+    fn capture_guest_streams() {
+        let console = Console::new();
+        let mut device = console.clone();
+        device.write(0, 1, b'a' as u32);
+        let stdout = console.capture(Stream::Stdout);
+        let stderr = console.capture(Stream::Stderr);
+        for &byte in b"ok\n" {
+            device.write(0, 1, byte as u32);
+        }
+        device.write(8, 1, b'!' as u32);
+
+        // Taking the output as it comes leaves the captures untouched
+        assert_eq!(console.take_output(), b"aok\n");
+        assert_eq!(console.take_errors(), b"!");
+        assert_eq!(stdout.contents(), b"ok\n");
+        assert_eq!(stderr.take(), b"!");
+        assert!(stderr.contents().is_empty());
+    }
+
+    #[test]
+    fn log_device_events() {
+        // lui a1, 0x8; li a0, 'A'; sb a0, 0(a1); lw a2, 4(a1); ecall
+        let code = [0x000085B7, 0x04100513, 0x00A58023, 0x0045A603, 0x00000073];
+        let mut sim = crate::simulator::tests::simulator("events", &code);
+        sim.map_device(0x8000, Console::SIZE, Box::new(Console::new()))
+            .unwrap();
+        sim.processor_mut().set_log_device_events(true);
+        sim.raise_irq(3).unwrap();
+        sim.run(None);
+
+        let events = sim.processor_mut().take_device_events();
+        let events: Vec<_> = events.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            events,
+            [
+                "[PC=00001000] [cycle 0] irq 3 raised",
+                "[PC=00001008] [cycle 2] console+0x0 <- 0x41 (stdout 'A')",
+                "[PC=0000100C] [cycle 3] console+0x4 -> 0x00000000",
+            ]
+        );
+        assert!(sim.processor_mut().take_device_events().is_empty());
+    }
+
+    #[test]
+    fn advance_rtc_per_instruction() {
+        // nop; nop; lui a1, 0x8; lw a0, 0(a1); ecall
+        let code = [0x00000013, 0x00000013, 0x000085B7, 0x0005A503, 0x00000073];
+        let mut sim = crate::simulator::tests::simulator("rtc-icount", &code);
+        let rtc = Rtc::new(Entropy::seeded(0)).with_source(TimeSource::Icount);
+        sim.map_device(0x8000, 8, Box::new(rtc)).unwrap();
+        sim.run(None);
+        assert_eq!(
+            sim.read_reg(crate::register::IntRegister::A0),
+            3 * TICK_NS as u32
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use crate::instruction::Instruction;
+use crate::processor::{Processor, ProcessorError};
+use crate::register::{IntRegister, IntRegisterSet};
+use crate::simulator::Simulator;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::ops::Range;
+
+/// Architectural differences between two machines, used to localize where
+/// two runs diverged. Each entry holds the value on the left-hand side
+/// followed by the one on the right-hand side.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StateDiff {
+    pub pc: Option<(u32, u32)>,
+    pub registers: Vec<(IntRegister, u32, u32)>,
+    pub memory: Vec<Range<u32>>,
+}
+
+impl StateDiff {
+    pub fn between<A: Write, B: Write>(a: &Simulator<A>, b: &Simulator<B>) -> Self {
+        Self::between_processors(a.processor(), b.processor())
+    }
+
+    pub fn between_processors(a: &Processor, b: &Processor) -> Self {
+        let pc = match (a.pc(), b.pc()) {
+            (a, b) if a != b => Some((a, b)),
+            _ => None,
+        };
+
+        let registers = (0..32)
+            .map(|reg| IntRegister::try_from(reg).unwrap())
+            .map(|reg| (reg, a.registers().read(reg), b.registers().read(reg)))
+            .filter(|(_, a, b)| a != b)
+            .collect();
+
+        let (a, b) = (a.memory().as_bytes(), b.memory().as_bytes());
+        let size = a.len().max(b.len());
+        let mut memory = Vec::new();
+        let mut start = None;
+
+        for addr in 0..=size {
+            let differs = addr < size && a.get(addr) != b.get(addr);
+            match (start, differs) {
+                (None, true) => start = Some(addr),
+                (Some(begin), false) => {
+                    memory.push(begin as u32..addr as u32);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            pc,
+            registers,
+            memory,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pc.is_none() && self.registers.is_empty() && self.memory.is_empty()
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((a, b)) = self.pc {
+            writeln!(f, "{:>6}: {:#010x} != {:#010x}", "pc", a, b)?;
+        }
+
+        for (reg, a, b) in &self.registers {
+            writeln!(f, "{:>6}: {:#010x} != {:#010x}", reg.to_string(), a, b)?;
+        }
+
+        for range in &self.memory {
+            writeln!(
+                f,
+                "{:>6}: {:#010x}..{:#010x} ({} bytes)",
+                "mem",
+                range.start,
+                range.end,
+                range.end - range.start
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// First instruction after which two simulations of the same program, run
+/// in lock-step, no longer agree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    /// Instructions both sides executed before the diverging one.
+    pub step: u64,
+    /// Address of the diverging instruction on the left-hand side.
+    pub pc: u32,
+    pub left: Result<Instruction, ProcessorError>,
+    pub right: Result<Instruction, ProcessorError>,
+    pub diff: StateDiff,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |result: &Result<Instruction, ProcessorError>| match result {
+            Ok(inst) => inst.display_at(self.pc).to_string(),
+            Err(err) => err.to_string(),
+        };
+        writeln!(
+            f,
+            "diverged after {} instructions at pc {:#010x}",
+            self.step, self.pc
+        )?;
+        writeln!(f, "{:>6}: {}", "left", side(&self.left))?;
+        writeln!(f, "{:>6}: {}", "right", side(&self.right))?;
+        write!(f, "{}", self.diff)
+    }
+}
+
+/// Steps `a` and `b` together, e.g. the same program with and without an
+/// extension or a timing model, until both stop the same way, `limit`
+/// instructions have been executed or they diverge.
+///
+/// The sides diverge when one instruction succeeds and the other fails, or
+/// when they leave different PCs, registers or stored bytes behind. The rest
+/// of memory is only compared when reporting a divergence.
+pub fn run_lockstep<A: Write, B: Write>(
+    a: &mut Simulator<A>,
+    b: &mut Simulator<B>,
+    limit: Option<u64>,
+) -> Option<Divergence> {
+    let mut step = 0;
+    while !matches!(limit, Some(limit) if step >= limit) {
+        let (pc, before) = (a.pc(), *a.processor().registers());
+        let left = a.step().map(|retired| retired.inst);
+        let right = b.step().map(|retired| retired.inst);
+
+        let agree = match (&left, &right) {
+            (Ok(inst), Ok(_)) => {
+                a.processor().pc() == b.processor().pc()
+                    && a.processor().registers() == b.processor().registers()
+                    && same_store(a, b, inst, &before)
+            }
+            (Err(left), Err(right)) => left == right,
+            _ => false,
+        };
+        if !agree {
+            return Some(Divergence {
+                step,
+                pc,
+                left,
+                right,
+                diff: StateDiff::between(a, b),
+            });
+        }
+        if left.is_err() {
+            return None;
+        }
+        step += 1;
+    }
+    None
+}
+
+/// Whether the bytes `inst` stored, if it is a store, are the same on both
+/// sides, given the registers before it ran.
+fn same_store<A: Write, B: Write>(
+    a: &Simulator<A>,
+    b: &Simulator<B>,
+    inst: &Instruction,
+    registers: &IntRegisterSet,
+) -> bool {
+    let (rs1, imm, size) = match *inst {
+        Instruction::SB { rs1, imm, .. } => (rs1, imm, 1),
+        Instruction::SH { rs1, imm, .. } => (rs1, imm, 2),
+        Instruction::SW { rs1, imm, .. } => (rs1, imm, 4),
+        _ => return true,
+    };
+    let address = registers.read(rs1).wrapping_add(imm as u32);
+    let (mut left, mut right) = ([0u8; 4], [0u8; 4]);
+    let left = a.read_mem(address, &mut left[..size]).map(|_| left);
+    let right = b.read_mem(address, &mut right[..size]).map(|_| right);
+    left == right
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::tests::simulator;
+
+    #[test]
+    fn diff_registers_and_memory() {
+        let code = [0x00100513, 0x00000073];
+        let a = simulator("diff-a", &code);
+        let mut b = simulator("diff-b", &code);
+        assert!(StateDiff::between(&a, &b).is_empty());
+
+        b.write_reg(IntRegister::A0, 7);
+        b.write_mem(0x2000, &[1, 2, 0, 4]).unwrap();
+        let diff = StateDiff::between(&a, &b);
+
+        assert_eq!(diff.registers, vec![(IntRegister::A0, 0, 7)]);
+        assert_eq!(diff.memory, vec![0x2000..0x2002, 0x2003..0x2004]);
+        assert_eq!(
+            diff.to_string(),
+            "    a0: 0x00000000 != 0x00000007\n   \
+               mem: 0x00002000..0x00002002 (2 bytes)\n   \
+               mem: 0x00002003..0x00002004 (1 bytes)\n"
+        );
+    }
+
+    #[test]
+    fn find_first_divergence_in_lockstep() {
+        // addi a0, zero, 3; mul a0, a0, a0; sw a0, 0(zero); ecall
+        let code = [0x00300513, 0x02A50533, 0x00A02023, 0x00000073];
+        let (mut a, mut b) = (
+            simulator("lockstep-a", &code),
+            simulator("lockstep-b", &code),
+        );
+        assert_eq!(run_lockstep(&mut a, &mut b, None), None);
+
+        let (mut a, mut b) = (
+            simulator("lockstep-a", &code),
+            simulator("lockstep-b", &code),
+        );
+        b.set_isa("rv32i".parse().unwrap()).unwrap();
+        let divergence = run_lockstep(&mut a, &mut b, None).unwrap();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(divergence.pc, 0x1004);
+        assert!(divergence.left.is_ok());
+        assert!(matches!(
+            divergence.right,
+            Err(ProcessorError::InvalidOpcode { .. })
+        ));
+        assert_eq!(divergence.diff.registers, [(IntRegister::A0, 9, 3)]);
+    }
+}
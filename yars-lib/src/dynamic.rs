@@ -0,0 +1,177 @@
+//! Loading of dynamically linked programs: a PIE or dynamically linked
+//! executable together with the shared objects it needs (`DT_NEEDED`),
+//! found by name. The executable is placed `offset` bytes away from where it
+//! was linked, as any program, and each shared object at the next page after
+//! the objects loaded before it. Every dynamic relocation, including those
+//! of the PLT, is applied eagerly at load time, so no dynamic linker runs in
+//! the guest. Initializers of shared objects are not run.
+
+use crate::memory::{Memory, Program, ProgramError};
+use goblin::elf::header::{EM_RISCV, ET_DYN};
+use goblin::elf::reloc::*;
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::{Sym, STB_LOCAL, STB_WEAK};
+use goblin::elf::Elf;
+use std::collections::{HashSet, VecDeque};
+
+/// Alignment of the address each shared object is loaded at.
+const ALIGN: u32 = 4096;
+
+/// A loaded executable or shared object and the offset it was loaded at.
+struct Module<'a> {
+    binary: Elf<'a>,
+    bias: u32,
+}
+
+impl Module<'_> {
+    fn name(&self, sym: &Sym) -> &str {
+        match self.binary.dynstrtab.get(sym.st_name) {
+            Some(Ok(name)) => name,
+            _ => "",
+        }
+    }
+
+    /// The dynamic symbol `name`, if the module defines it for other
+    /// modules to use.
+    fn export(&self, name: &str) -> Option<Sym> {
+        self.binary.dynsyms.iter().find(|sym| {
+            sym.st_shndx != SHN_UNDEF as usize
+                && sym.st_bind() != STB_LOCAL
+                && self.name(sym) == name
+        })
+    }
+}
+
+/// Loads the executable in `buffer` and the shared objects it needs, which
+/// `library` returns given their names, and links them.
+pub(crate) fn load<F>(
+    memory: &mut Memory,
+    binary: Elf,
+    buffer: &[u8],
+    offset: u32,
+    mut library: F,
+) -> Result<Program, ProgramError>
+where
+    F: FnMut(&str) -> Option<Vec<u8>>,
+{
+    // Shared objects are found breadth-first, which is also the order
+    // symbols are looked up in
+    let mut buffers = Vec::new();
+    let mut pending = binary
+        .libraries
+        .iter()
+        .map(|&name| name.to_string())
+        .collect::<VecDeque<_>>();
+    let mut seen = pending.iter().cloned().collect::<HashSet<_>>();
+    while let Some(name) = pending.pop_front() {
+        let bytes = library(&name).ok_or_else(|| ProgramError::MissingLibrary(name.clone()))?;
+        let shared = Elf::parse(&bytes).map_err(ProgramError::Goblin)?;
+        for &needed in &shared.libraries {
+            if seen.insert(needed.to_string()) {
+                pending.push_back(needed.to_string());
+            }
+        }
+        buffers.push(bytes);
+    }
+
+    let mut program = memory.load_image(&binary, buffer, offset)?;
+    let mut modules = vec![Module {
+        binary,
+        bias: offset,
+    }];
+    for bytes in &buffers {
+        let binary = Elf::parse(bytes).map_err(ProgramError::Goblin)?;
+        if binary.header.e_machine != EM_RISCV || binary.header.e_type != ET_DYN || binary.is_64 {
+            return Err(ProgramError::UnsupportedBinary);
+        }
+        let end = program.segments.iter().map(|segment| segment.end).max();
+        let bias = end.unwrap_or(offset).wrapping_add(ALIGN - 1) & !(ALIGN - 1);
+        let image = memory.load_image(&binary, bytes, bias)?;
+        program.segments.extend(image.segments);
+        program.code.extend(image.code);
+        program.sections.extend(image.sections);
+        for symbol in image.symbols.iter() {
+            program.symbols.insert(symbol.clone());
+        }
+        modules.push(Module { binary, bias });
+    }
+
+    for (index, module) in modules.iter().enumerate() {
+        link(memory, &modules, index, module)?;
+    }
+    Ok(program)
+}
+
+/// Applies the dynamic relocations of `module`, the one at `index` in
+/// `modules`, resolving symbols against all of them.
+fn link(
+    memory: &mut Memory,
+    modules: &[Module],
+    index: usize,
+    module: &Module,
+) -> Result<(), ProgramError> {
+    let binary = &module.binary;
+    for reloc in binary.dynrelas.iter().chain(binary.pltrelocs.iter()) {
+        let place = module.bias.wrapping_add(reloc.r_offset as u32);
+        let addend = reloc.r_addend.unwrap_or(0) as u32;
+        if !memory.contains(place, 4) {
+            return Err(ProgramError::OutOfMemory);
+        }
+
+        // Looks the symbol up in every module, or only in the others for
+        // copies, whose destination is the definition in the executable
+        let resolve = |skip: Option<usize>| -> Result<Option<(u32, Sym)>, ProgramError> {
+            let sym = match binary.dynsyms.get(reloc.r_sym) {
+                Some(sym) if reloc.r_sym != 0 => sym,
+                _ => return Ok(None),
+            };
+            if sym.st_bind() == STB_LOCAL && sym.st_shndx != SHN_UNDEF as usize {
+                return Ok(None);
+            }
+            let name = module.name(&sym);
+            let found = modules
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| Some(other) != skip)
+                .find_map(|(_, other)| {
+                    let sym = other.export(name)?;
+                    Some((other.bias.wrapping_add(sym.st_value as u32), sym))
+                });
+            match found {
+                Some((address, sym)) => Ok(Some((address, sym))),
+                None if sym.st_bind() == STB_WEAK => Ok(None),
+                None => Err(ProgramError::UndefinedSymbol(name.to_string())),
+            }
+        };
+        let symbol = |skip| -> Result<u32, ProgramError> {
+            Ok(match resolve(skip)? {
+                Some((address, _)) => address,
+                None => match binary.dynsyms.get(reloc.r_sym) {
+                    Some(sym) if sym.st_shndx != SHN_UNDEF as usize => {
+                        module.bias.wrapping_add(sym.st_value as u32)
+                    }
+                    _ => 0,
+                },
+            })
+        };
+
+        match reloc.r_type {
+            R_RISCV_NONE => {}
+            R_RISCV_RELATIVE => memory.write_word(place, module.bias.wrapping_add(addend)),
+            R_RISCV_32 => memory.write_word(place, symbol(None)?.wrapping_add(addend)),
+            R_RISCV_JUMP_SLOT => memory.write_word(place, symbol(None)?),
+            R_RISCV_COPY => {
+                if let Some((source, sym)) = resolve(Some(index))? {
+                    let size = sym.st_size as u32;
+                    if !memory.contains(source, size) || !memory.contains(place, size) {
+                        return Err(ProgramError::OutOfMemory);
+                    }
+                    let bytes = memory.as_bytes_mut();
+                    bytes.copy_within(source as usize..(source + size) as usize, place as usize);
+                }
+            }
+            kind => return Err(ProgramError::UnsupportedRelocation(kind)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+//! Source of the inputs a guest can observe but not control, such as the
+//! wall-clock time and random numbers. A seeded source derives all of them
+//! from the seed, so repeated runs are bit-identical. Inputs can also be
+//! recorded to, and replayed from, an [`InputLog`].
+
+use crate::replay::InputLog;
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Nanoseconds a deterministic clock advances on every read.
+pub const CLOCK_STEP: u64 = 1000;
+
+/// Nanoseconds of guest time per instruction or cycle, as in a 100 MHz core.
+pub const TICK_NS: u64 = 10;
+
+/// What makes the guest-visible time advance.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimeSource {
+    /// Every instruction retired advances the time by [`TICK_NS`].
+    Icount,
+    /// Every cycle elapsed advances the time by [`TICK_NS`].
+    Cycles,
+    /// The time is read from an [`Entropy`] source: the host clock, or the
+    /// deterministic clock of a seeded source.
+    Host,
+}
+
+impl fmt::Display for TimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeSource::Icount => write!(f, "icount"),
+            TimeSource::Cycles => write!(f, "cycles"),
+            TimeSource::Host => write!(f, "host"),
+        }
+    }
+}
+
+impl FromStr for TimeSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source {
+            "icount" => Ok(TimeSource::Icount),
+            "cycles" => Ok(TimeSource::Cycles),
+            "host" => Ok(TimeSource::Host),
+            _ => Err("expected icount, cycles or host".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entropy {
+    state: u64,
+    clock: Option<u64>,
+    log: Option<InputLog>,
+}
+
+impl Entropy {
+    pub fn host() -> Self {
+        let seed = host_time() ^ ((std::process::id() as u64) << 32);
+        Self {
+            state: splitmix64(seed),
+            clock: None,
+            log: None,
+        }
+    }
+
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            state: splitmix64(seed),
+            clock: Some(0),
+            log: None,
+        }
+    }
+
+    /// Records every input to `log` or, if it is being replayed, returns
+    /// the inputs it holds.
+    pub fn with_log(mut self, log: InputLog) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.clock.is_some()
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        match self.log.clone() {
+            Some(log) => log.random(|| self.generate()),
+            None => self.generate(),
+        }
+    }
+
+    /// Nanoseconds since the Unix epoch. A deterministic clock starts at the
+    /// epoch and advances by [`CLOCK_STEP`] on every call.
+    pub fn time_ns(&mut self) -> u64 {
+        match self.log.clone() {
+            Some(log) => log.time(|| self.clock()),
+            None => self.clock(),
+        }
+    }
+
+    /// Generator and clock state, for device snapshots.
+    pub(crate) fn save(&self) -> Vec<u8> {
+        let mut state = self.state.to_le_bytes().to_vec();
+        if let Some(clock) = self.clock {
+            state.extend_from_slice(&clock.to_le_bytes());
+        }
+        state
+    }
+
+    /// Restores the state from [`Entropy::save`], leaving the entropy as it
+    /// is if the state is malformed.
+    pub(crate) fn restore(&mut self, state: &[u8]) {
+        let word = |i: usize| {
+            state
+                .get(i..i + 8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        };
+        match (state.len(), word(0), word(8)) {
+            (8, Some(generator), _) => {
+                self.state = generator;
+                self.clock = None;
+            }
+            (16, Some(generator), clock) => {
+                self.state = generator;
+                self.clock = clock;
+            }
+            _ => {}
+        }
+    }
+
+    fn generate(&mut self) -> u32 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32
+    }
+
+    fn clock(&mut self) -> u64 {
+        match &mut self.clock {
+            Some(clock) => {
+                *clock += CLOCK_STEP;
+                *clock
+            }
+            None => host_time(),
+        }
+    }
+}
+
+fn host_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    match z ^ (z >> 31) {
+        0 => 1,
+        z => z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_sources_are_reproducible() {
+        let (mut a, mut b) = (Entropy::seeded(42), Entropy::seeded(42));
+        let a = (0..4).map(|_| a.next_u32()).collect::<Vec<_>>();
+        let b = (0..4).map(|_| b.next_u32()).collect::<Vec<_>>();
+        assert_eq!(a, b);
+        assert_ne!(a[0], Entropy::seeded(7).next_u32());
+
+        let mut clock = Entropy::seeded(42);
+        assert_eq!(
+            (clock.time_ns(), clock.time_ns()),
+            (CLOCK_STEP, 2 * CLOCK_STEP)
+        );
+    }
+}
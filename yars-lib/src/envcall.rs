@@ -0,0 +1,201 @@
+//! The yars environment call, enabled with [`Simulator::set_env_calls`]. An
+//! `ecall` with `a7` set to [`ECALL`] does not stop the program: it performs
+//! the function selected by `a0` and returns its result in `a0`, so guests
+//! can ask about the simulator running them and mark where tracing should
+//! start and stop, or the region of interest measured. Functions that do not exist return -1.
+//!
+//! [`Simulator::set_env_calls`]: crate::simulator::Simulator::set_env_calls
+
+use crate::processor::{Processor, ProcessorError};
+use crate::register::IntRegister;
+use std::fmt;
+
+/// Value of `a7` selecting the yars environment call ("YARS" in ASCII).
+pub const ECALL: u32 = 0x5941_5253;
+
+/// Returns the size of memory in bytes.
+pub const MEMORY_SIZE: u32 = 0;
+/// Copies the ISA string, NUL-terminated and truncated to fit, to the
+/// buffer at `a1` of `a2` bytes, and returns its length.
+pub const ISA: u32 = 1;
+/// Returns the number of harts.
+pub const HARTS: u32 = 2;
+/// Returns the version of the simulator as `major << 16 | minor << 8 | patch`.
+pub const VERSION: u32 = 3;
+/// Starts logging instructions and returns 0.
+pub const TRACE_START: u32 = 4;
+/// Stops logging instructions and returns 0.
+pub const TRACE_STOP: u32 = 5;
+/// Marks the start of the region of interest and returns 0.
+pub const ROI_BEGIN: u32 = 6;
+/// Marks the end of the region of interest and returns 0.
+pub const ROI_END: u32 = 7;
+
+/// Point in the program marked through the environment call.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Marker {
+    TraceStart { pc: u32 },
+    TraceStop { pc: u32 },
+    RoiBegin { pc: u32 },
+    RoiEnd { pc: u32 },
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Marker::TraceStart { pc } => write!(f, "trace start at {:#010x}", pc),
+            Marker::TraceStop { pc } => write!(f, "trace stop at {:#010x}", pc),
+            Marker::RoiBegin { pc } => write!(f, "region of interest begins at {:#010x}", pc),
+            Marker::RoiEnd { pc } => write!(f, "region of interest ends at {:#010x}", pc),
+        }
+    }
+}
+
+/// State of the environment call in a simulator.
+#[derive(Clone, Debug)]
+pub(crate) struct Environment {
+    pub enabled: bool,
+    /// Whether executed instructions are logged.
+    pub tracing: bool,
+    pub markers: Vec<Marker>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            tracing: true,
+            markers: Vec::new(),
+        }
+    }
+
+    /// Performs the environment call if `result` stopped at one and they
+    /// are enabled, moving past it. Other results are given back as is.
+    pub fn call(
+        &mut self,
+        result: Result<(), ProcessorError>,
+        processor: &mut Processor,
+    ) -> Result<(), ProcessorError> {
+        match result {
+            Err(ProcessorError::Ecall) if self.enabled => {}
+            result => return result,
+        }
+        let marker = match handle(processor)? {
+            Some(marker) => marker,
+            None => return Ok(()),
+        };
+        match marker {
+            Marker::TraceStart { .. } => self.tracing = true,
+            Marker::TraceStop { .. } => self.tracing = false,
+            Marker::RoiBegin { .. } | Marker::RoiEnd { .. } => {}
+        }
+        self.markers.push(marker);
+        Ok(())
+    }
+}
+
+/// Performs the environment call `processor` stopped at and moves past it,
+/// returning the marker it set, if any. Other `ecall`s give back
+/// [`ProcessorError::Ecall`].
+fn handle(processor: &mut Processor) -> Result<Option<Marker>, ProcessorError> {
+    let registers = processor.registers();
+    if registers.read(IntRegister::A7) != ECALL {
+        return Err(ProcessorError::Ecall);
+    }
+
+    let pc = processor.pc();
+    let (result, marker) = match registers.read(IntRegister::A0) {
+        MEMORY_SIZE => (processor.memory().size(), None),
+        ISA => {
+            let isa = processor.isa().to_string();
+            let buffer = registers.read(IntRegister::A1);
+            let size = registers.read(IntRegister::A2);
+            let copied = isa.len().min(size.saturating_sub(1) as usize);
+            if size > 0 {
+                if !processor.memory().contains(buffer, copied as u32 + 1) {
+                    let address = buffer;
+                    return Err(ProcessorError::IllegalAccess { pc, address });
+                }
+                let start = buffer as usize;
+                let bytes = &mut processor.memory_mut().as_bytes_mut()[start..=start + copied];
+                bytes[..copied].copy_from_slice(&isa.as_bytes()[..copied]);
+                bytes[copied] = 0;
+            }
+            (isa.len() as u32, None)
+        }
+        HARTS => (1, None),
+        VERSION => (version(), None),
+        TRACE_START => (0, Some(Marker::TraceStart { pc })),
+        TRACE_STOP => (0, Some(Marker::TraceStop { pc })),
+        ROI_BEGIN => (0, Some(Marker::RoiBegin { pc })),
+        ROI_END => (0, Some(Marker::RoiEnd { pc })),
+        _ => (u32::MAX, None),
+    };
+    processor.registers_mut().write(IntRegister::A0, result);
+    processor.set_pc(pc.wrapping_add(4));
+    Ok(marker)
+}
+
+fn version() -> u32 {
+    let part = |part: &str| part.parse::<u32>().unwrap();
+    part(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+        | part(env!("CARGO_PKG_VERSION_MINOR")) << 8
+        | part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::tests::simulator;
+    use crate::simulator::ExitEvent;
+
+    #[test]
+    fn answer_environment_calls() {
+        let code = [
+            0x594158B7, // lui a7, 0x59415
+            0x25388893, // addi a7, a7, 0x253
+            0x00000513, // li a0, 0
+            0x00000073, // ecall
+            0x00050413, // mv s0, a0
+            0x00100513, // li a0, 1
+            0x000015B7, // lui a1, 0x1
+            0x80058593, // addi a1, a1, -0x800
+            0x00400613, // li a2, 4
+            0x00000073, // ecall
+            0x00050493, // mv s1, a0
+            0x00400513, // li a0, 4
+            0x00000073, // ecall
+            0x00500513, // li a0, 5
+            0x00000073, // ecall
+            0x06300513, // li a0, 99
+            0x00000073, // ecall
+            0x00050913, // mv s2, a0
+            0x05D00893, // li a7, 93
+            0x00000073, // ecall
+        ];
+        let mut sim = simulator("envcall", &code);
+        sim.set_env_calls(true);
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.read_reg(IntRegister::S0), 0x10000);
+
+        let isa = sim.processor().isa().to_string();
+        let mut buffer = [0u8; 4];
+        sim.read_mem(0x800, &mut buffer).unwrap();
+        assert_eq!(sim.read_reg(IntRegister::S1), isa.len() as u32);
+        assert_eq!(&buffer, b"rv3\0");
+
+        assert_eq!(
+            sim.take_markers(),
+            vec![
+                Marker::TraceStart { pc: 0x1030 },
+                Marker::TraceStop { pc: 0x1038 }
+            ]
+        );
+        assert_eq!(sim.read_reg(IntRegister::S2), u32::MAX);
+
+        // Without env calls, the first ecall ends the program
+        let mut sim = simulator("envcall-off", &code);
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.pc(), 0x100C);
+    }
+}
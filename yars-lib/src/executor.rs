@@ -0,0 +1,317 @@
+//! Instruction semantics, written once over a [`Domain`] of values.
+//!
+//! The [`Processor`](crate::processor::Processor) is the concrete domain,
+//! whose values are plain [`Xlen`](crate::xlen::Xlen) integers. Tools such
+//! as symbolic or concolic executors can implement [`Domain`] with values
+//! that also carry an expression, and reuse [`execute`] as it is.
+
+use crate::instruction::Instruction;
+use crate::processor::ProcessorError;
+use crate::register::IntRegister;
+
+/// Operations producing a value from two others, named after the register
+/// form of the instruction that performs them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    /// Shifts use the low 5 (or 6, on RV64) bits of the right operand.
+    Sll,
+    Srl,
+    Sra,
+    /// Gives 1 if the left operand is less than the right one, else 0.
+    Slt,
+    Sltu,
+    Xor,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+/// Branch conditions, named after the branch instruction testing them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Condition {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+/// Values the executor computes with and the state it reads and writes.
+///
+/// Writes to `zero` never reach the domain, and reads of it are replaced
+/// by the constant 0.
+pub trait Domain {
+    type Value: Clone;
+
+    /// Address of the instruction being executed.
+    fn pc(&self) -> Self::Value;
+    /// Sign-extends a 32-bit constant.
+    fn constant(&mut self, value: i32) -> Self::Value;
+    fn read(&mut self, reg: IntRegister) -> Self::Value;
+    fn write(&mut self, reg: IntRegister, value: Self::Value);
+    fn compute(&mut self, op: Op, lhs: Self::Value, rhs: Self::Value) -> Self::Value;
+    /// Decides whether a branch is taken. A concolic domain would decide
+    /// on the concrete values and record the condition as a constraint.
+    fn test(&mut self, condition: Condition, lhs: Self::Value, rhs: Self::Value) -> bool;
+    /// Loads `size` bytes, sign-extending them if `signed` is set.
+    fn load(
+        &mut self,
+        address: Self::Value,
+        size: u32,
+        signed: bool,
+    ) -> Result<Self::Value, ProcessorError>;
+    fn store(
+        &mut self,
+        address: Self::Value,
+        size: u32,
+        value: Self::Value,
+    ) -> Result<(), ProcessorError>;
+}
+
+/// Executes `inst` in `domain`. Returns the target of a taken branch or
+/// jump, or `None` if execution continues with the next instruction.
+///
+/// # Panics
+///
+/// Panics on [`Instruction::CUSTOM`], which only the extension that
+/// decoded it can execute.
+pub fn execute<D: Domain>(
+    domain: &mut D,
+    inst: Instruction,
+) -> Result<Option<D::Value>, ProcessorError> {
+    use Instruction::*;
+    let offset = |domain: &mut D, imm: i32| {
+        let (pc, imm) = (domain.pc(), domain.constant(imm));
+        domain.compute(Op::Add, pc, imm)
+    };
+
+    match inst {
+        LUI { rd, imm } => {
+            let val = domain.constant(imm << 12);
+            write(domain, rd, val);
+        }
+        AUIPC { rd, imm } => {
+            let val = offset(domain, imm << 12);
+            write(domain, rd, val);
+        }
+        JAL { rd, imm } => {
+            let target = offset(domain, imm);
+            let link = offset(domain, 4);
+            write(domain, rd, link);
+            return Ok(Some(target));
+        }
+        JALR { rd, rs1, imm } => {
+            let target = address(domain, rs1, imm);
+            let mask = domain.constant(!1);
+            let target = domain.compute(Op::And, target, mask);
+            let link = offset(domain, 4);
+            write(domain, rd, link);
+            return Ok(Some(target));
+        }
+        BEQ { rs1, rs2, imm } => return branch(domain, Condition::Eq, rs1, rs2, imm),
+        BNE { rs1, rs2, imm } => return branch(domain, Condition::Ne, rs1, rs2, imm),
+        BLT { rs1, rs2, imm } => return branch(domain, Condition::Lt, rs1, rs2, imm),
+        BGE { rs1, rs2, imm } => return branch(domain, Condition::Ge, rs1, rs2, imm),
+        BLTU { rs1, rs2, imm } => return branch(domain, Condition::Ltu, rs1, rs2, imm),
+        BGEU { rs1, rs2, imm } => return branch(domain, Condition::Geu, rs1, rs2, imm),
+        LB { rd, rs1, imm } => load(domain, rd, rs1, imm, 1, true)?,
+        LH { rd, rs1, imm } => load(domain, rd, rs1, imm, 2, true)?,
+        LW { rd, rs1, imm } => load(domain, rd, rs1, imm, 4, true)?,
+        LBU { rd, rs1, imm } => load(domain, rd, rs1, imm, 1, false)?,
+        LHU { rd, rs1, imm } => load(domain, rd, rs1, imm, 2, false)?,
+        SB { rs1, rs2, imm } => store(domain, rs1, rs2, imm, 1)?,
+        SH { rs1, rs2, imm } => store(domain, rs1, rs2, imm, 2)?,
+        SW { rs1, rs2, imm } => store(domain, rs1, rs2, imm, 4)?,
+        ADDI { rd, rs1, imm } => op_imm(domain, Op::Add, rd, rs1, imm as i32),
+        SLTI { rd, rs1, imm } => op_imm(domain, Op::Slt, rd, rs1, imm as i32),
+        SLTIU { rd, rs1, imm } => op_imm(domain, Op::Sltu, rd, rs1, imm as i32),
+        XORI { rd, rs1, imm } => op_imm(domain, Op::Xor, rd, rs1, imm as i32),
+        ORI { rd, rs1, imm } => op_imm(domain, Op::Or, rd, rs1, imm as i32),
+        ANDI { rd, rs1, imm } => op_imm(domain, Op::And, rd, rs1, imm as i32),
+        SLLI { rd, rs1, shamt } => op_imm(domain, Op::Sll, rd, rs1, shamt as i32),
+        SRLI { rd, rs1, shamt } => op_imm(domain, Op::Srl, rd, rs1, shamt as i32),
+        SRAI { rd, rs1, shamt } => op_imm(domain, Op::Sra, rd, rs1, shamt as i32),
+        ADD { rd, rs1, rs2 } => op(domain, Op::Add, rd, rs1, rs2),
+        SUB { rd, rs1, rs2 } => op(domain, Op::Sub, rd, rs1, rs2),
+        SLL { rd, rs1, rs2 } => op(domain, Op::Sll, rd, rs1, rs2),
+        SLT { rd, rs1, rs2 } => op(domain, Op::Slt, rd, rs1, rs2),
+        SLTU { rd, rs1, rs2 } => op(domain, Op::Sltu, rd, rs1, rs2),
+        XOR { rd, rs1, rs2 } => op(domain, Op::Xor, rd, rs1, rs2),
+        SRL { rd, rs1, rs2 } => op(domain, Op::Srl, rd, rs1, rs2),
+        SRA { rd, rs1, rs2 } => op(domain, Op::Sra, rd, rs1, rs2),
+        OR { rd, rs1, rs2 } => op(domain, Op::Or, rd, rs1, rs2),
+        AND { rd, rs1, rs2 } => op(domain, Op::And, rd, rs1, rs2),
+        MUL { rd, rs1, rs2 } => op(domain, Op::Mul, rd, rs1, rs2),
+        MULH { rd, rs1, rs2 } => op(domain, Op::Mulh, rd, rs1, rs2),
+        MULHSU { rd, rs1, rs2 } => op(domain, Op::Mulhsu, rd, rs1, rs2),
+        MULHU { rd, rs1, rs2 } => op(domain, Op::Mulhu, rd, rs1, rs2),
+        DIV { rd, rs1, rs2 } => op(domain, Op::Div, rd, rs1, rs2),
+        DIVU { rd, rs1, rs2 } => op(domain, Op::Divu, rd, rs1, rs2),
+        REM { rd, rs1, rs2 } => op(domain, Op::Rem, rd, rs1, rs2),
+        REMU { rd, rs1, rs2 } => op(domain, Op::Remu, rd, rs1, rs2),
+        FENCE { .. } | FENCETSO => {}
+        ECALL => return Err(ProcessorError::Ecall),
+        EBREAK => return Err(ProcessorError::Ebreak),
+        CUSTOM { raw } => panic!("custom instruction {:#010x} has no semantics", raw),
+    }
+    Ok(None)
+}
+
+fn read<D: Domain>(domain: &mut D, reg: IntRegister) -> D::Value {
+    match reg {
+        IntRegister::Zero => domain.constant(0),
+        _ => domain.read(reg),
+    }
+}
+
+fn write<D: Domain>(domain: &mut D, reg: IntRegister, value: D::Value) {
+    if reg != IntRegister::Zero {
+        domain.write(reg, value);
+    }
+}
+
+fn imm<D: Domain>(domain: &mut D, imm: i16) -> D::Value {
+    domain.constant(imm as i32)
+}
+
+fn op<D: Domain>(domain: &mut D, op: Op, rd: IntRegister, rs1: IntRegister, rs2: IntRegister) {
+    let (lhs, rhs) = (read(domain, rs1), read(domain, rs2));
+    let val = domain.compute(op, lhs, rhs);
+    write(domain, rd, val);
+}
+
+fn op_imm<D: Domain>(domain: &mut D, op: Op, rd: IntRegister, rs1: IntRegister, imm: i32) {
+    let (lhs, rhs) = (read(domain, rs1), domain.constant(imm));
+    let val = domain.compute(op, lhs, rhs);
+    write(domain, rd, val);
+}
+
+fn address<D: Domain>(domain: &mut D, rs1: IntRegister, offset: i16) -> D::Value {
+    let (base, offset) = (read(domain, rs1), imm(domain, offset));
+    domain.compute(Op::Add, base, offset)
+}
+
+fn load<D: Domain>(
+    domain: &mut D,
+    rd: IntRegister,
+    rs1: IntRegister,
+    offset: i16,
+    size: u32,
+    signed: bool,
+) -> Result<(), ProcessorError> {
+    let address = address(domain, rs1, offset);
+    let val = domain.load(address, size, signed)?;
+    write(domain, rd, val);
+    Ok(())
+}
+
+fn store<D: Domain>(
+    domain: &mut D,
+    rs1: IntRegister,
+    rs2: IntRegister,
+    offset: i16,
+    size: u32,
+) -> Result<(), ProcessorError> {
+    let address = address(domain, rs1, offset);
+    let val = read(domain, rs2);
+    domain.store(address, size, val)
+}
+
+fn branch<D: Domain>(
+    domain: &mut D,
+    condition: Condition,
+    rs1: IntRegister,
+    rs2: IntRegister,
+    offset: i16,
+) -> Result<Option<D::Value>, ProcessorError> {
+    let (lhs, rhs) = (read(domain, rs1), read(domain, rs2));
+    match domain.test(condition, lhs, rhs) {
+        true => {
+            let (pc, offset) = (domain.pc(), imm(domain, offset));
+            Ok(Some(domain.compute(Op::Add, pc, offset)))
+        }
+        false => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    /// Builds expressions over the initial register values instead of
+    /// computing them, and takes every branch.
+    #[derive(Default)]
+    struct Symbolic {
+        registers: HashMap<u8, String>,
+        constraints: Vec<String>,
+    }
+
+    impl Domain for Symbolic {
+        type Value = String;
+
+        fn pc(&self) -> String {
+            "pc".to_string()
+        }
+
+        fn constant(&mut self, value: i32) -> String {
+            value.to_string()
+        }
+
+        fn read(&mut self, reg: IntRegister) -> String {
+            let name = reg.to_string();
+            self.registers.entry(reg as u8).or_insert(name).clone()
+        }
+
+        fn write(&mut self, reg: IntRegister, value: String) {
+            self.registers.insert(reg as u8, value);
+        }
+
+        fn compute(&mut self, op: Op, lhs: String, rhs: String) -> String {
+            format!("({:?} {} {})", op, lhs, rhs)
+        }
+
+        fn test(&mut self, condition: Condition, lhs: String, rhs: String) -> bool {
+            self.constraints
+                .push(format!("({:?} {} {})", condition, lhs, rhs));
+            true
+        }
+
+        fn load(&mut self, address: String, size: u32, _: bool) -> Result<String, ProcessorError> {
+            Ok(format!("(load{} {})", size, address))
+        }
+
+        fn store(&mut self, _: String, _: u32, _: String) -> Result<(), ProcessorError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn execute_in_symbolic_domain() {
+        let mut domain = Symbolic::default();
+        // lw a0, 0(sp); addi a0, a0, 1; blt a0, zero, 8
+        for &raw in &[0x00012503, 0x00150513] {
+            let inst = Instruction::try_from(raw).unwrap();
+            assert_eq!(execute(&mut domain, inst), Ok(None));
+        }
+        let target = execute(&mut domain, Instruction::try_from(0x00054463).unwrap());
+
+        assert_eq!(target, Ok(Some("(Add pc 8)".to_string())));
+        assert_eq!(
+            domain.registers[&(IntRegister::A0 as u8)],
+            "(Add (load4 (Add sp 0)) 1)"
+        );
+        assert_eq!(domain.constraints, ["(Lt (Add (load4 (Add sp 0)) 1) 0)"]);
+    }
+}
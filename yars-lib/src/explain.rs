@@ -0,0 +1,223 @@
+//! Plain-English descriptions of executed instructions, for people reading
+//! assembly for the first time.
+
+use crate::instruction::Instruction;
+use crate::register::{IntRegister, IntRegisterSet};
+
+/// Describes what `inst`, executed at `pc`, did given the registers before
+/// and after it ran, e.g. `a0 ← a0 + 4 = 9 (addi)`.
+pub fn explain(
+    pc: u32,
+    inst: &Instruction,
+    before: &IntRegisterSet,
+    after: &IntRegisterSet,
+) -> String {
+    use Instruction::*;
+    let x = |reg: IntRegister| before.read(reg);
+    let target = |offset: i32| pc.wrapping_add(offset as u32);
+    let set = |rd: IntRegister, expression: String| match rd {
+        IntRegister::Zero => format!("{} is discarded, rd is zero", expression),
+        rd => format!("{} ← {} = {}", rd, expression, value(after.read(rd))),
+    };
+    let address = |rs1: IntRegister, imm: i16| x(rs1).wrapping_add(imm as i32 as u32);
+
+    let description = match *inst {
+        LUI { rd, imm } => set(rd, format!("{:#x} << 12", imm & 0xFFFFF)),
+        AUIPC { rd, imm } => set(rd, format!("pc + ({:#x} << 12)", imm & 0xFFFFF)),
+        JAL { rd, imm } => jump(rd, target(imm), after),
+        JALR { rd, rs1, imm } => {
+            let destination = address(rs1, imm) & !1;
+            format!("{} + {} → {}", rs1, imm, jump(rd, destination, after))
+        }
+
+        BEQ { rs1, rs2, imm } => branch(
+            x,
+            rs1,
+            rs2,
+            x(rs1) == x(rs2),
+            ("==", "!="),
+            target(imm.into()),
+        ),
+        BNE { rs1, rs2, imm } => branch(
+            x,
+            rs1,
+            rs2,
+            x(rs1) != x(rs2),
+            ("!=", "=="),
+            target(imm.into()),
+        ),
+        BLT { rs1, rs2, imm } => {
+            let taken = (x(rs1) as i32) < (x(rs2) as i32);
+            branch(x, rs1, rs2, taken, ("<", ">="), target(imm.into()))
+        }
+        BGE { rs1, rs2, imm } => {
+            let taken = (x(rs1) as i32) >= (x(rs2) as i32);
+            branch(x, rs1, rs2, taken, (">=", "<"), target(imm.into()))
+        }
+        BLTU { rs1, rs2, imm } => {
+            let taken = x(rs1) < x(rs2);
+            branch(x, rs1, rs2, taken, ("<", ">="), target(imm.into())) + ", unsigned"
+        }
+        BGEU { rs1, rs2, imm } => {
+            let taken = x(rs1) >= x(rs2);
+            branch(x, rs1, rs2, taken, (">=", "<"), target(imm.into())) + ", unsigned"
+        }
+
+        LB { rd, rs1, imm } => set(rd, load(address(rs1, imm), "byte", true)),
+        LH { rd, rs1, imm } => set(rd, load(address(rs1, imm), "halfword", true)),
+        LW { rd, rs1, imm } => set(rd, load(address(rs1, imm), "word", false)),
+        LBU { rd, rs1, imm } => set(rd, load(address(rs1, imm), "byte", false)),
+        LHU { rd, rs1, imm } => set(rd, load(address(rs1, imm), "halfword", false)),
+        SB { rs1, rs2, imm } => store(address(rs1, imm), rs2, x(rs2) & 0xFF, "byte"),
+        SH { rs1, rs2, imm } => store(address(rs1, imm), rs2, x(rs2) & 0xFFFF, "halfword"),
+        SW { rs1, rs2, imm } => store(address(rs1, imm), rs2, x(rs2), "word"),
+
+        ADDI { rd, rs1, imm } if imm < 0 => set(rd, format!("{} - {}", rs1, -i32::from(imm))),
+        ADDI { rd, rs1, imm } => set(rd, format!("{} + {}", rs1, imm)),
+        SLTI { rd, rs1, imm } => set(rd, format!("1 if {} < {} else 0", rs1, imm)),
+        SLTIU { rd, rs1, imm } => set(
+            rd,
+            format!("1 if {} < {} else 0, unsigned", rs1, imm as i32 as u32),
+        ),
+        XORI { rd, rs1, imm } => set(rd, format!("{} ^ {:#x}", rs1, imm as i32 as u32)),
+        ORI { rd, rs1, imm } => set(rd, format!("{} | {:#x}", rs1, imm as i32 as u32)),
+        ANDI { rd, rs1, imm } => set(rd, format!("{} & {:#x}", rs1, imm as i32 as u32)),
+        SLLI { rd, rs1, shamt } => set(rd, format!("{} << {}", rs1, shamt)),
+        SRLI { rd, rs1, shamt } => set(rd, format!("{} >> {}", rs1, shamt)),
+        SRAI { rd, rs1, shamt } => set(rd, format!("{} >> {}, keeping the sign", rs1, shamt)),
+
+        ADD { rd, rs1, rs2 } => set(rd, format!("{} + {}", rs1, rs2)),
+        SUB { rd, rs1, rs2 } => set(rd, format!("{} - {}", rs1, rs2)),
+        SLL { rd, rs1, rs2 } => set(rd, format!("{} << {}", rs1, rs2)),
+        SLT { rd, rs1, rs2 } => set(rd, format!("1 if {} < {} else 0", rs1, rs2)),
+        SLTU { rd, rs1, rs2 } => set(rd, format!("1 if {} < {} else 0, unsigned", rs1, rs2)),
+        XOR { rd, rs1, rs2 } => set(rd, format!("{} ^ {}", rs1, rs2)),
+        SRL { rd, rs1, rs2 } => set(rd, format!("{} >> {}", rs1, rs2)),
+        SRA { rd, rs1, rs2 } => set(rd, format!("{} >> {}, keeping the sign", rs1, rs2)),
+        OR { rd, rs1, rs2 } => set(rd, format!("{} | {}", rs1, rs2)),
+        AND { rd, rs1, rs2 } => set(rd, format!("{} & {}", rs1, rs2)),
+
+        MUL { rd, rs1, rs2 } => set(rd, format!("{} * {}", rs1, rs2)),
+        MULH { rd, rs1, rs2 } => set(rd, format!("upper half of {} * {}", rs1, rs2)),
+        MULHSU { rd, rs1, rs2 } => set(
+            rd,
+            format!("upper half of {} * {}, {} unsigned", rs1, rs2, rs2),
+        ),
+        MULHU { rd, rs1, rs2 } => set(rd, format!("upper half of {} * {}, unsigned", rs1, rs2)),
+        DIV { rd, rs1, rs2 } => set(rd, format!("{} / {}", rs1, rs2)),
+        DIVU { rd, rs1, rs2 } => set(rd, format!("{} / {}, unsigned", rs1, rs2)),
+        REM { rd, rs1, rs2 } => set(rd, format!("{} % {}", rs1, rs2)),
+        REMU { rd, rs1, rs2 } => set(rd, format!("{} % {}, unsigned", rs1, rs2)),
+
+        FENCE { .. } | FENCETSO => "orders memory accesses, nothing to do here".to_string(),
+        ECALL => format!("calls the environment with a7 = {}", x(IntRegister::A7)),
+        EBREAK => "stops at a breakpoint".to_string(),
+        CUSTOM { raw } => format!("runs custom instruction {:#010x}", raw),
+    };
+
+    format!("{} ({})", description, inst.mnemonic())
+}
+
+fn jump(rd: IntRegister, target: u32, after: &IntRegisterSet) -> String {
+    match rd {
+        IntRegister::Zero => format!("jump to {:#x}", target),
+        rd => format!(
+            "jump to {:#x}, {} ← return address {:#x}",
+            target,
+            rd,
+            after.read(rd)
+        ),
+    }
+}
+
+fn branch<F: Fn(IntRegister) -> u32>(
+    x: F,
+    rs1: IntRegister,
+    rs2: IntRegister,
+    taken: bool,
+    (holds, fails): (&str, &str),
+    target: u32,
+) -> String {
+    let (a, b) = (value(x(rs1)), value(x(rs2)));
+    match taken {
+        true => format!(
+            "branch to {:#x} taken because {} {} {} ({} {} {})",
+            target, rs1, holds, rs2, a, holds, b
+        ),
+        false => format!(
+            "branch not taken because {} {} {} ({} {} {})",
+            rs1, fails, rs2, a, fails, b
+        ),
+    }
+}
+
+fn load(address: u32, size: &str, signed: bool) -> String {
+    match signed {
+        true => format!("{} at {:#x}, sign-extended", size, address),
+        false => format!("{} at {:#x}", size, address),
+    }
+}
+
+fn store(address: u32, rs2: IntRegister, stored: u32, size: &str) -> String {
+    format!("{} at {:#x} ← {} = {}", size, address, rs2, value(stored))
+}
+
+/// Small values read best in decimal, addresses and bit patterns in hex.
+fn value(value: u32) -> String {
+    match value as i32 {
+        -4096..=4096 => format!("{}", value as i32),
+        _ => format!("{:#x}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn describe(
+        raw: u32,
+        pc: u32,
+        set: &[(IntRegister, u32)],
+        result: Option<(IntRegister, u32)>,
+    ) -> String {
+        let mut before = IntRegisterSet::new();
+        for &(reg, value) in set {
+            before.write(reg, value);
+        }
+        let mut after = before;
+        if let Some((reg, value)) = result {
+            after.write(reg, value);
+        }
+        explain(pc, &Instruction::try_from(raw).unwrap(), &before, &after)
+    }
+
+    #[test]
+    fn explain_instructions() {
+        use IntRegister::*;
+        // addi a0, a0, 4
+        assert_eq!(
+            describe(0x00450513, 0, &[(A0, 5)], Some((A0, 9))),
+            "a0 ← a0 + 4 = 9 (addi)"
+        );
+        // beq t0, t1, 8
+        assert_eq!(
+            describe(0x00628463, 0x100, &[(T0, 1), (T1, 2)], None),
+            "branch not taken because t0 != t1 (1 != 2) (beq)"
+        );
+        assert_eq!(
+            describe(0x00628463, 0x100, &[(T0, 2), (T1, 2)], None),
+            "branch to 0x108 taken because t0 == t1 (2 == 2) (beq)"
+        );
+        // sw a0, -4(sp)
+        assert_eq!(
+            describe(0xFEA12E23, 0, &[(SP, 0x8000), (A0, 7)], None),
+            "word at 0x7ffc ← a0 = 7 (sw)"
+        );
+        // add zero, a0, a1
+        assert_eq!(
+            describe(0x00B50033, 0, &[], None),
+            "a0 + a1 is discarded, rd is zero (add)"
+        );
+    }
+}
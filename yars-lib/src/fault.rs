@@ -0,0 +1,275 @@
+//! Single-bit fault injection, to study how programs behave when a
+//! register, a memory byte or a fetched instruction word is corrupted.
+
+use crate::entropy::Entropy;
+use crate::register::IntRegister;
+use crate::simulator::{ExitEvent, Simulator};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Where a [`Fault`] flips a bit.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Location {
+    Register(IntRegister),
+    /// The byte at an address.
+    Memory(u32),
+    /// The next instruction word fetched. Memory is left untouched, so the
+    /// fault does not persist if the instruction runs again.
+    Instruction,
+}
+
+/// A bit flip injected after `instret` instructions have been retired.
+///
+/// Faults are written `<location>:<bit>@<instret>`, where the location is a
+/// register name, a memory address or `inst`, e.g. `a0:3@100`,
+/// `0x11000:7@50` or `inst:12@20`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Fault {
+    pub instret: u64,
+    pub location: Location,
+    pub bit: u32,
+}
+
+impl Fault {
+    /// Picks a fault after a random number of instructions below `instret`,
+    /// equally likely in a register, in memory within `memory` or in an
+    /// instruction word.
+    pub fn random(entropy: &mut Entropy, instret: u64, memory: &[std::ops::Range<u32>]) -> Self {
+        let due = match instret {
+            0 => 0,
+            _ => ((entropy.next_u32() as u64) << 32 | entropy.next_u32() as u64) % instret,
+        };
+        let size = memory.iter().map(|r| (r.end - r.start) as u64).sum::<u64>();
+
+        let (location, bits) = match entropy.next_u32() % 3 {
+            0 if size > 0 => {
+                let mut offset = entropy.next_u32() as u64 % size;
+                let range = memory
+                    .iter()
+                    .find(|r| match offset < (r.end - r.start) as u64 {
+                        true => true,
+                        false => {
+                            offset -= (r.end - r.start) as u64;
+                            false
+                        }
+                    })
+                    .unwrap();
+                (Location::Memory(range.start + offset as u32), 8)
+            }
+            1 => {
+                let reg = IntRegister::try_from(1 + (entropy.next_u32() % 31) as u8).unwrap();
+                (Location::Register(reg), 32)
+            }
+            _ => (Location::Instruction, 32),
+        };
+
+        Self {
+            instret: due,
+            location,
+            bit: entropy.next_u32() % bits,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Location::Register(reg) => write!(f, "{}", reg)?,
+            Location::Memory(address) => write!(f, "{:#x}", address)?,
+            Location::Instruction => write!(f, "inst")?,
+        }
+        write!(f, ":{}@{}", self.bit, self.instret)
+    }
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(fault: &str) -> Result<Self, Self::Err> {
+        let (fault, instret) = fault
+            .split_once('@')
+            .ok_or("expected <location>:<bit>@<instret>")?;
+        let (location, bit) = fault.split_once(':').ok_or("expected <location>:<bit>")?;
+        let instret = instret.parse().map_err(|_| "invalid instruction count")?;
+        let bit = bit.parse::<u32>().map_err(|_| "invalid bit")?;
+
+        let (location, bits) = match location {
+            "inst" => (Location::Instruction, 32),
+            _ => match location.parse::<IntRegister>() {
+                Ok(reg) => (Location::Register(reg), 32),
+                Err(_) => {
+                    let address = match location.strip_prefix("0x") {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => location.parse(),
+                    };
+                    let address = address.map_err(|_| "expected a register, an address or inst")?;
+                    (Location::Memory(address), 8)
+                }
+            },
+        };
+
+        match bit < bits {
+            true => Ok(Self {
+                instret,
+                location,
+                bit,
+            }),
+            false => Err(format!("bit must be below {}", bits)),
+        }
+    }
+}
+
+/// How a run with a fault ended, compared with the run without it.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Outcome {
+    /// The program ended exactly as without the fault.
+    Masked,
+    /// The program ended normally but with a different result.
+    WrongResult,
+    /// The program faulted.
+    Crash,
+    /// The program ran far longer than without the fault, or got stuck.
+    Hang,
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 4] = [
+        Outcome::Masked,
+        Outcome::WrongResult,
+        Outcome::Crash,
+        Outcome::Hang,
+    ];
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Masked => write!(f, "masked"),
+            Outcome::WrongResult => write!(f, "wrong result"),
+            Outcome::Crash => write!(f, "crash"),
+            Outcome::Hang => write!(f, "hang"),
+        }
+    }
+}
+
+/// What a run produced: how it stopped, its exit code in `a0` and its
+/// signature, if it has one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RunResult {
+    pub exit: ExitEvent,
+    pub a0: u32,
+    pub signature: Option<Vec<u32>>,
+}
+
+impl RunResult {
+    fn of<W: Write>(sim: &Simulator<W>, exit: ExitEvent) -> Self {
+        Self {
+            exit,
+            a0: sim.read_reg(IntRegister::A0),
+            signature: sim.signature(),
+        }
+    }
+}
+
+/// Runs `sim` for at most `limit` instructions, injecting `fault` when it
+/// is due. A fault due after the program stopped is never injected.
+pub fn run_with_fault<W: Write>(sim: &mut Simulator<W>, fault: &Fault, limit: usize) -> ExitEvent {
+    let due = fault.instret.min(limit as u64) as usize;
+    match sim.run(Some(due)) {
+        ExitEvent::CycleLimit if due < limit => {}
+        exit => return exit,
+    }
+    if let Err(err) = sim.inject(fault) {
+        let pc = sim.pc();
+        return ExitEvent::Fault { err, pc };
+    }
+    sim.run(Some(limit - due))
+}
+
+/// Runs a program once without faults, then once per fault from the same
+/// initial state, classifying each run by comparing it with the first.
+pub struct Campaign<W: Write> {
+    initial: Simulator<W>,
+    golden: RunResult,
+    instret: u64,
+    limit: usize,
+}
+
+impl<W: Write + Clone> Campaign<W> {
+    /// Runs `sim` without faults. Faulty runs are stopped as hangs after
+    /// twice as many instructions as it took, plus some slack.
+    pub fn new(sim: Simulator<W>) -> Self {
+        let mut golden = sim.clone();
+        let start = golden.processor().instret();
+        let exit = golden.run(None);
+        let instret = (golden.processor().instret() - start) as u64;
+        Self {
+            golden: RunResult::of(&golden, exit),
+            initial: sim,
+            instret,
+            limit: 2 * instret as usize + 1000,
+        }
+    }
+
+    /// Result of the run without faults.
+    pub fn golden(&self) -> &RunResult {
+        &self.golden
+    }
+
+    /// Instructions retired by the run without faults.
+    pub fn instret(&self) -> u64 {
+        self.instret
+    }
+
+    /// Picks a fault due while the program runs, in a register, in loaded
+    /// memory or in a fetched instruction.
+    pub fn random_fault(&self, entropy: &mut Entropy) -> Fault {
+        Fault::random(entropy, self.instret, self.initial.segments())
+    }
+
+    pub fn run(&self, fault: &Fault) -> Outcome {
+        let mut sim = self.initial.clone();
+        let exit = run_with_fault(&mut sim, fault, self.limit);
+        let result = RunResult::of(&sim, exit);
+        match result.exit {
+            _ if result == self.golden => Outcome::Masked,
+            ExitEvent::CycleLimit | ExitEvent::Hang { .. } => Outcome::Hang,
+            ExitEvent::Fault { .. } => Outcome::Crash,
+            _ => Outcome::WrongResult,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::tests::simulator;
+
+    #[test]
+    fn parse_and_display_faults() {
+        for &fault in &["a0:3@100", "0x11000:7@50", "inst:12@0"] {
+            assert_eq!(fault.parse::<Fault>().unwrap().to_string(), fault);
+        }
+        assert!("a0:32@1".parse::<Fault>().is_err());
+        assert!("0x100:8@1".parse::<Fault>().is_err());
+        assert!("a0:1".parse::<Fault>().is_err());
+    }
+
+    #[test]
+    fn classify_faulty_runs() {
+        // addi a0, zero, 5; addi a1, zero, 0; addi a7, zero, 93; ecall
+        let code = [0x00500513, 0x00000593, 0x05D00893, 0x00000073];
+        let campaign = Campaign::new(simulator("fault-campaign", &code));
+        assert_eq!(campaign.instret(), 4);
+        assert_eq!(campaign.golden().a0, 5);
+
+        let outcome = |fault: &str| campaign.run(&fault.parse().unwrap());
+        assert_eq!(outcome("a1:0@2"), Outcome::Masked);
+        assert_eq!(outcome("a0:1@1"), Outcome::WrongResult);
+        // Turns the ecall into an invalid opcode
+        assert_eq!(outcome("inst:0@3"), Outcome::Crash);
+        assert_eq!(outcome("a0:0@10"), Outcome::Masked);
+    }
+}
@@ -0,0 +1,424 @@
+//! Heap checking for guest programs, by following calls to the C allocator
+//! found in the symbol table: `malloc`, `calloc`, `realloc` and `free`, or
+//! their newlib `_r` variants. Blocks are tracked from the values these
+//! calls take and return, to report double and invalid frees, accesses to
+//! freed blocks and blocks never freed.
+
+use crate::instruction::Instruction;
+use crate::register::{IntRegister, IntRegisterSet};
+use crate::symbol::SymbolTable;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Function {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+}
+
+const FUNCTIONS: [(&str, Function); 4] = [
+    ("malloc", Function::Malloc),
+    ("calloc", Function::Calloc),
+    ("realloc", Function::Realloc),
+    ("free", Function::Free),
+];
+
+/// A block returned by the allocator.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Block {
+    pub address: u32,
+    pub size: u32,
+    /// Address of the call that allocated the block.
+    pub site: u32,
+}
+
+impl Block {
+    fn contains(&self, address: u32) -> bool {
+        address >= self.address && address - self.address < self.size.max(1)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HeapError {
+    /// A block was freed again at `site` after being freed at `freed_at`.
+    DoubleFree {
+        block: Block,
+        site: u32,
+        freed_at: u32,
+    },
+    /// An address the allocator never returned was freed at `site`.
+    InvalidFree { address: u32, site: u32 },
+    /// The instruction at `pc` accessed `address` inside a block freed at
+    /// `freed_at`.
+    UseAfterFree {
+        pc: u32,
+        address: u32,
+        block: Block,
+        freed_at: u32,
+    },
+}
+
+impl HeapError {
+    /// Like the `Display` output, but naming the functions of the addresses.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let at = |address: u32| match symbols.lookup(address) {
+            Some(sym) => format!("{:#010x} <{}>", address, sym),
+            None => format!("{:#010x}", address),
+        };
+        match *self {
+            HeapError::DoubleFree {
+                block,
+                site,
+                freed_at,
+            } => format!(
+                "double free of {:#010x} at {} (allocated at {}, freed at {})",
+                block.address,
+                at(site),
+                at(block.site),
+                at(freed_at)
+            ),
+            HeapError::InvalidFree { address, site } => {
+                format!("invalid free of {:#010x} at {}", address, at(site))
+            }
+            HeapError::UseAfterFree {
+                pc,
+                address,
+                block,
+                freed_at,
+            } => format!(
+                "access to {:#010x} at {}, {} bytes into a block of {} allocated at {} \
+                 and freed at {}",
+                address,
+                at(pc),
+                address - block.address,
+                block.size,
+                at(block.site),
+                at(freed_at)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolTable::new()))
+    }
+}
+
+/// An allocator call waiting to return.
+#[derive(Copy, Clone, Debug)]
+struct Call {
+    function: Function,
+    site: u32,
+    return_address: u32,
+    sp: u32,
+    size: u32,
+    old: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HeapChecker {
+    /// Allocator entry points, with the index of their first argument
+    /// register past the newlib reentrancy pointer.
+    functions: HashMap<u32, (Function, u8)>,
+    call: Option<Call>,
+    live: BTreeMap<u32, Block>,
+    /// Freed blocks, with the address of the call that freed them.
+    freed: BTreeMap<u32, (Block, u32)>,
+    errors: Vec<HeapError>,
+    /// Instructions already reported for accessing freed blocks.
+    reported: BTreeSet<u32>,
+}
+
+impl HeapChecker {
+    /// Follows the allocator functions in `symbols`, or returns `None` if
+    /// there is no `malloc` or `_malloc_r`.
+    pub fn new(symbols: &SymbolTable) -> Option<Self> {
+        let mut functions = HashMap::new();
+        for &(name, function) in &FUNCTIONS {
+            if let Some(sym) = symbols.get(name) {
+                functions.insert(sym.address, (function, 0));
+            }
+            if let Some(sym) = symbols.get(&format!("_{}_r", name)) {
+                functions.entry(sym.address).or_insert((function, 1));
+            }
+        }
+        match functions.values().any(|&(f, _)| f == Function::Malloc) {
+            true => Some(Self {
+                functions,
+                ..Self::default()
+            }),
+            false => None,
+        }
+    }
+
+    /// Tracks `inst`, executed at `pc` with `registers` as they were
+    /// before it.
+    pub fn check(&mut self, pc: u32, inst: &Instruction, registers: &IntRegisterSet) {
+        let arg = |skip: u8, n: u8| {
+            let reg = IntRegister::A0 as u8 + skip + n;
+            registers.read(IntRegister::try_from(reg).unwrap())
+        };
+
+        if let Some(call) = self.call {
+            // The allocator is free to touch its own metadata in freed blocks
+            if pc != call.return_address || registers.read(IntRegister::SP) != call.sp {
+                return;
+            }
+            self.call = None;
+            self.returned(call, registers.read(IntRegister::A0));
+        }
+
+        if let Some(&(function, skip)) = self.functions.get(&pc) {
+            let return_address = registers.read(IntRegister::RA);
+            let site = return_address.wrapping_sub(4);
+            let (size, old) = match function {
+                Function::Malloc => (arg(skip, 0), 0),
+                Function::Calloc => (arg(skip, 0).wrapping_mul(arg(skip, 1)), 0),
+                Function::Realloc => (arg(skip, 1), arg(skip, 0)),
+                Function::Free => (0, arg(skip, 0)),
+            };
+            if function == Function::Free {
+                self.free(old, site);
+            }
+            self.call = Some(Call {
+                function,
+                site,
+                return_address,
+                sp: registers.read(IntRegister::SP),
+                size,
+                old,
+            });
+            return;
+        }
+
+        if let Some(address) = access(inst, registers) {
+            self.access(pc, address);
+        }
+    }
+
+    fn returned(&mut self, call: Call, address: u32) {
+        match call.function {
+            Function::Free => {}
+            // realloc(p, 0) may free the block and return null
+            Function::Realloc if address == 0 && call.size == 0 => self.free(call.old, call.site),
+            // A failed realloc leaves the block alone
+            Function::Realloc if address == 0 => {}
+            Function::Realloc if call.old != 0 && address != call.old => {
+                self.free(call.old, call.site);
+                self.allocate(address, call.size, call.site);
+            }
+            Function::Realloc if call.old != 0 => {
+                if let Some(block) = self.live.get_mut(&address) {
+                    block.size = call.size;
+                }
+            }
+            _ if address != 0 => self.allocate(address, call.size, call.site),
+            _ => {}
+        }
+    }
+
+    fn allocate(&mut self, address: u32, size: u32, site: u32) {
+        let end = address.saturating_add(size);
+        let reused = self
+            .freed
+            .iter()
+            .filter(|(_, (block, _))| {
+                block.address < end && address < block.address.saturating_add(block.size)
+            })
+            .map(|(&start, _)| start)
+            .collect::<Vec<_>>();
+        for start in reused {
+            self.freed.remove(&start);
+        }
+        self.live.insert(
+            address,
+            Block {
+                address,
+                size,
+                site,
+            },
+        );
+    }
+
+    fn free(&mut self, address: u32, site: u32) {
+        if address == 0 {
+            return;
+        }
+        if let Some(block) = self.live.remove(&address) {
+            self.freed.insert(address, (block, site));
+        } else if let Some(&(block, freed_at)) = self.freed.get(&address) {
+            self.errors.push(HeapError::DoubleFree {
+                block,
+                site,
+                freed_at,
+            });
+        } else {
+            self.errors.push(HeapError::InvalidFree { address, site });
+        }
+    }
+
+    fn access(&mut self, pc: u32, address: u32) {
+        let freed = self.freed.range(..=address).next_back();
+        if let Some((_, &(block, freed_at))) = freed {
+            if block.contains(address) && self.reported.insert(pc) {
+                self.errors.push(HeapError::UseAfterFree {
+                    pc,
+                    address,
+                    block,
+                    freed_at,
+                });
+            }
+        }
+    }
+
+    /// Errors found so far, in the order they happened.
+    pub fn errors(&self) -> &[HeapError] {
+        &self.errors
+    }
+
+    /// Blocks allocated and not freed, in address order.
+    pub fn leaks(&self) -> impl Iterator<Item = &Block> + '_ {
+        self.live.values()
+    }
+
+    /// Writes the errors found and the blocks leaked.
+    pub fn write<W: Write>(&self, symbols: &SymbolTable, mut writer: W) -> io::Result<()> {
+        for error in &self.errors {
+            writeln!(writer, "Heap error: {}", error.describe(symbols))?;
+        }
+        let leaked = self.leaks().map(|block| block.size as u64).sum::<u64>();
+        writeln!(
+            writer,
+            "Heap: {} errors, {} bytes leaked in {} blocks",
+            self.errors.len(),
+            leaked,
+            self.live.len()
+        )?;
+        for block in self.leaks() {
+            let site = match symbols.lookup(block.site) {
+                Some(sym) => format!("{:#010x} <{}>", block.site, sym),
+                None => format!("{:#010x}", block.site),
+            };
+            writeln!(
+                writer,
+                "  {} bytes at {:#010x} allocated at {}",
+                block.size, block.address, site
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Address accessed by `inst` if it is a load or store, given the registers
+/// before it.
+fn access(inst: &Instruction, registers: &IntRegisterSet) -> Option<u32> {
+    use Instruction::*;
+    match *inst {
+        LB { rs1, imm, .. }
+        | LH { rs1, imm, .. }
+        | LW { rs1, imm, .. }
+        | LBU { rs1, imm, .. }
+        | LHU { rs1, imm, .. }
+        | SB { rs1, imm, .. }
+        | SH { rs1, imm, .. }
+        | SW { rs1, imm, .. } => Some(registers.read(rs1).wrapping_add(imm as u32)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+
+    const MALLOC: u32 = 0x100;
+    const FREE: u32 = 0x200;
+    const NOP: Instruction = Instruction::ADDI {
+        rd: IntRegister::Zero,
+        rs1: IntRegister::Zero,
+        imm: 0,
+    };
+
+    /// Calls `function` from `site` with `arg` and returns `result` from it.
+    fn call(heap: &mut HeapChecker, function: u32, site: u32, arg: u32, result: u32) {
+        let mut registers = IntRegisterSet::new();
+        registers.write(IntRegister::SP, 0x8000);
+        registers.write(IntRegister::RA, site + 4);
+        registers.write(IntRegister::A0, arg);
+        heap.check(function, &NOP, &registers);
+        registers.write(IntRegister::A0, result);
+        heap.check(site + 4, &NOP, &registers);
+    }
+
+    #[test]
+    fn report_heap_misuse_and_leaks() {
+        let mut symbols = SymbolTable::new();
+        for &(name, address) in &[("malloc", MALLOC), ("free", FREE)] {
+            symbols.insert(Symbol {
+                name: name.to_string(),
+                address,
+                size: 0x100,
+            });
+        }
+        assert!(HeapChecker::new(&SymbolTable::new()).is_none());
+        let mut heap = HeapChecker::new(&symbols).unwrap();
+
+        call(&mut heap, MALLOC, 0x1000, 16, 0x5000);
+        call(&mut heap, MALLOC, 0x1010, 8, 0x6000);
+        call(&mut heap, FREE, 0x1020, 0x5000, 0);
+
+        let mut registers = IntRegisterSet::new();
+        registers.write(IntRegister::A1, 0x5000);
+        let load = Instruction::LW {
+            rd: IntRegister::A0,
+            rs1: IntRegister::A1,
+            imm: 12,
+        };
+        heap.check(0x1030, &load, &registers);
+        heap.check(0x1030, &load, &registers);
+
+        call(&mut heap, FREE, 0x1040, 0x5000, 0);
+        call(&mut heap, FREE, 0x1050, 0x7000, 0);
+
+        let block = Block {
+            address: 0x5000,
+            size: 16,
+            site: 0x1000,
+        };
+        assert_eq!(
+            heap.errors(),
+            [
+                HeapError::UseAfterFree {
+                    pc: 0x1030,
+                    address: 0x500C,
+                    block,
+                    freed_at: 0x1020,
+                },
+                HeapError::DoubleFree {
+                    block,
+                    site: 0x1040,
+                    freed_at: 0x1020,
+                },
+                HeapError::InvalidFree {
+                    address: 0x7000,
+                    site: 0x1050,
+                },
+            ]
+        );
+        let leaks = heap
+            .leaks()
+            .map(|b| (b.address, b.size))
+            .collect::<Vec<_>>();
+        assert_eq!(leaks, [(0x6000, 8)]);
+
+        // Allocating over a freed block forgets it
+        call(&mut heap, MALLOC, 0x1060, 4, 0x5008);
+        heap.check(0x1070, &load, &registers);
+        assert_eq!(heap.errors().len(), 3);
+    }
+}
@@ -0,0 +1,123 @@
+//! Counts of memory accesses per address range, to show where a program
+//! reads and writes memory.
+
+use crate::instruction::Instruction;
+use crate::register::IntRegisterSet;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Loads and stores made to one bucket of a [`Heatmap`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Accesses {
+    pub loads: u64,
+    pub stores: u64,
+}
+
+impl Accesses {
+    pub fn total(&self) -> u64 {
+        self.loads + self.stores
+    }
+}
+
+/// Memory accesses counted per bucket of `bucket_size` bytes, e.g. per page.
+#[derive(Clone, Debug)]
+pub struct Heatmap {
+    bucket_size: u32,
+    buckets: BTreeMap<u32, Accesses>,
+}
+
+impl Heatmap {
+    /// Creates a heatmap with buckets of `bucket_size` bytes, which must not
+    /// be zero.
+    pub fn new(bucket_size: u32) -> Self {
+        assert!(bucket_size > 0, "bucket size must not be zero");
+        Self {
+            bucket_size,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    pub fn bucket_size(&self) -> u32 {
+        self.bucket_size
+    }
+
+    /// Records the access made by `inst`, if it is a load or a store, given
+    /// the registers before it was executed.
+    pub fn record(&mut self, inst: &Instruction, registers: &IntRegisterSet) {
+        use Instruction::*;
+        let address = |rs1, imm: i16| registers.read(rs1).wrapping_add(imm as u32);
+        match *inst {
+            LB { rs1, imm, .. }
+            | LH { rs1, imm, .. }
+            | LW { rs1, imm, .. }
+            | LBU { rs1, imm, .. }
+            | LHU { rs1, imm, .. } => self.bucket(address(rs1, imm)).loads += 1,
+            SB { rs1, imm, .. } | SH { rs1, imm, .. } | SW { rs1, imm, .. } => {
+                self.bucket(address(rs1, imm)).stores += 1
+            }
+            _ => {}
+        }
+    }
+
+    fn bucket(&mut self, address: u32) -> &mut Accesses {
+        let start = address - address % self.bucket_size;
+        self.buckets.entry(start).or_default()
+    }
+
+    /// Accessed buckets by start address, in address order.
+    pub fn buckets(&self) -> impl Iterator<Item = (u32, Accesses)> + '_ {
+        self.buckets
+            .iter()
+            .map(|(&start, &accesses)| (start, accesses))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Writes one `address,loads,stores` line per accessed bucket.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "address,loads,stores")?;
+        for (start, accesses) in self.buckets() {
+            writeln!(
+                writer,
+                "{:#010x},{},{}",
+                start, accesses.loads, accesses.stores
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::register::IntRegister;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn count_accesses_per_bucket() {
+        let mut registers = IntRegisterSet::new();
+        registers.write(IntRegister::SP, 0x2000);
+
+        let mut heatmap = Heatmap::new(0x1000);
+        // lw a0, 0(sp); sw a0, -4(sp); sw a0, -8(sp); addi a0, a0, 1
+        for &raw in &[0x00012503, 0xFEA12E23, 0xFEA12C23, 0x00150513] {
+            heatmap.record(&Instruction::try_from(raw).unwrap(), &registers);
+        }
+
+        let buckets = heatmap.buckets().collect::<Vec<_>>();
+        let accesses = |loads, stores| Accesses { loads, stores };
+        assert_eq!(
+            buckets,
+            [(0x1000, accesses(0, 2)), (0x2000, accesses(1, 0))]
+        );
+
+        let mut csv = Vec::new();
+        heatmap.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "address,loads,stores\n0x00001000,0,2\n0x00002000,1,0\n"
+        );
+    }
+}
@@ -1,6 +1,12 @@
+use crate::decode;
 use crate::register::IntRegister;
+use crate::symbol::SymbolTable;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::fmt;
+use std::io::{self, Write};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum InstructionFormat {
@@ -60,6 +66,7 @@ impl InstructionFormat {
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum FenceKind {
     R = 0b10,
     W = 0b01,
@@ -89,8 +96,80 @@ impl TryFrom<u8> for FenceKind {
     }
 }
 
+/// Instruction groups, as listed in the unprivileged specification.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Category {
+    Load,
+    Store,
+    Shift,
+    Arithmetic,
+    Logical,
+    Compare,
+    Branch,
+    Jump,
+    Sync,
+    System,
+    Multiply,
+    Divide,
+}
+
+impl Category {
+    pub const ALL: [Category; 12] = [
+        Category::Load,
+        Category::Store,
+        Category::Shift,
+        Category::Arithmetic,
+        Category::Logical,
+        Category::Compare,
+        Category::Branch,
+        Category::Jump,
+        Category::Sync,
+        Category::System,
+        Category::Multiply,
+        Category::Divide,
+    ];
+
+    /// Every value returned by [`Category::group`], in report order.
+    pub const GROUPS: [&'static str; 7] = [
+        "alu", "load", "store", "branch", "jump", "mul-div", "system",
+    ];
+
+    /// Coarser grouping of categories used to compare instruction mixes,
+    /// e.g. between compilers.
+    pub fn group(&self) -> &'static str {
+        match self {
+            Category::Shift | Category::Arithmetic | Category::Logical | Category::Compare => "alu",
+            Category::Load => "load",
+            Category::Store => "store",
+            Category::Branch => "branch",
+            Category::Jump => "jump",
+            Category::Multiply | Category::Divide => "mul-div",
+            Category::Sync | Category::System => "system",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Load => "load",
+            Category::Store => "store",
+            Category::Shift => "shift",
+            Category::Arithmetic => "arithmetic",
+            Category::Logical => "logical",
+            Category::Compare => "compare",
+            Category::Branch => "branch",
+            Category::Jump => "jump",
+            Category::Sync => "sync",
+            Category::System => "system",
+            Category::Multiply => "multiply",
+            Category::Divide => "divide",
+        }
+    }
+}
+
 #[rustfmt::skip]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Instruction {
     /* --- RV32I --- */
  
@@ -165,12 +244,250 @@ pub enum Instruction {
     DIVU { rd: IntRegister, rs1: IntRegister, rs2: IntRegister },
     REM { rd: IntRegister, rs1: IntRegister, rs2: IntRegister },
     REMU { rd: IntRegister, rs1: IntRegister, rs2: IntRegister },
+
+    /* --- Non-standard --- */
+
+    /// A word the decoder does not know, executed by a
+    /// [`plugin::Extension`](crate::plugin::Extension).
+    CUSTOM { raw: u32 },
 }
 
-impl fmt::Display for Instruction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Instruction {
+    /// Letter of the standard extension defining this instruction.
+    pub fn extension(&self) -> char {
+        use Instruction::*;
+        match self {
+            MUL { .. } | MULH { .. } | MULHSU { .. } | MULHU { .. } => 'm',
+            DIV { .. } | DIVU { .. } | REM { .. } | REMU { .. } => 'm',
+            CUSTOM { .. } => 'x',
+            _ => 'i',
+        }
+    }
+
+    #[rustfmt::skip]
+    pub fn category(&self) -> Category {
+        use Instruction::*;
+        match self {
+            LB { .. } | LH { .. } | LW { .. } | LBU { .. } | LHU { .. } => Category::Load,
+            SB { .. } | SH { .. } | SW { .. } => Category::Store,
+            SLLI { .. } | SRLI { .. } | SRAI { .. } => Category::Shift,
+            SLL { .. } | SRL { .. } | SRA { .. } => Category::Shift,
+            LUI { .. } | AUIPC { .. } => Category::Arithmetic,
+            ADDI { .. } | ADD { .. } | SUB { .. } => Category::Arithmetic,
+            ORI { .. } | XORI { .. } | ANDI { .. } => Category::Logical,
+            OR { .. } | XOR { .. } | AND { .. } => Category::Logical,
+            SLTI { .. } | SLTIU { .. } | SLT { .. } | SLTU { .. } => Category::Compare,
+            BEQ { .. } | BNE { .. } | BLT { .. } | BGE { .. } => Category::Branch,
+            BLTU { .. } | BGEU { .. } => Category::Branch,
+            JAL { .. } | JALR { .. } => Category::Jump,
+            FENCE { .. } | FENCETSO => Category::Sync,
+            ECALL | EBREAK => Category::System,
+            CUSTOM { .. } => Category::System,
+            MUL { .. } | MULH { .. } | MULHSU { .. } | MULHU { .. } => Category::Multiply,
+            DIV { .. } | DIVU { .. } | REM { .. } | REMU { .. } => Category::Divide,
+        }
+    }
+
+    #[rustfmt::skip]
+    pub fn mnemonic(&self) -> &'static str {
+        use Instruction::*;
+        match self {
+            LUI { .. } => "lui", AUIPC { .. } => "auipc", JAL { .. } => "jal", JALR { .. } => "jalr",
+            BEQ { .. } => "beq", BNE { .. } => "bne", BLT { .. } => "blt", BGE { .. } => "bge",
+            BLTU { .. } => "bltu", BGEU { .. } => "bgeu",
+            LB { .. } => "lb", LH { .. } => "lh", LW { .. } => "lw", LBU { .. } => "lbu", LHU { .. } => "lhu",
+            SB { .. } => "sb", SH { .. } => "sh", SW { .. } => "sw",
+            ADDI { .. } => "addi", SLTI { .. } => "slti", SLTIU { .. } => "sltiu",
+            XORI { .. } => "xori", ORI { .. } => "ori", ANDI { .. } => "andi",
+            SLLI { .. } => "slli", SRLI { .. } => "srli", SRAI { .. } => "srai",
+            ADD { .. } => "add", SUB { .. } => "sub", SLL { .. } => "sll", SLT { .. } => "slt",
+            SLTU { .. } => "sltu", XOR { .. } => "xor", SRL { .. } => "srl", SRA { .. } => "sra",
+            OR { .. } => "or", AND { .. } => "and",
+            FENCE { .. } => "fence", FENCETSO => "fence.tso", ECALL => "ecall", EBREAK => "ebreak",
+            MUL { .. } => "mul", MULH { .. } => "mulh", MULHSU { .. } => "mulhsu", MULHU { .. } => "mulhu",
+            DIV { .. } => "div", DIVU { .. } => "divu", REM { .. } => "rem", REMU { .. } => "remu",
+            CUSTOM { .. } => "custom",
+        }
+    }
+
+    /// Destination register, if the instruction has one, including `zero`.
+    #[rustfmt::skip]
+    pub fn rd(&self) -> Option<IntRegister> {
+        use Instruction::*;
+        match *self {
+            LUI { rd, .. } | AUIPC { rd, .. } | JAL { rd, .. } | JALR { rd, .. } => Some(rd),
+            LB { rd, .. } | LH { rd, .. } | LW { rd, .. } | LBU { rd, .. } | LHU { rd, .. } => Some(rd),
+            SLLI { rd, .. } | SRLI { rd, .. } | SRAI { rd, .. } => Some(rd),
+            SLL { rd, .. } | SRL { rd, .. } | SRA { rd, .. } => Some(rd),
+            ADDI { rd, .. } | ADD { rd, .. } | SUB { rd, .. } => Some(rd),
+            ORI { rd, .. } | XORI { rd, .. } | ANDI { rd, .. } => Some(rd),
+            OR { rd, .. } | XOR { rd, .. } | AND { rd, .. } => Some(rd),
+            SLTI { rd, .. } | SLTIU { rd, .. } | SLT { rd, .. } | SLTU { rd, .. } => Some(rd),
+            MUL { rd, .. } | MULH { rd, .. } | MULHSU { rd, .. } | MULHU { rd, .. } => Some(rd),
+            DIV { rd, .. } | DIVU { rd, .. } | REM { rd, .. } | REMU { rd, .. } => Some(rd),
+            _ => None,
+        }
+    }
+
+    /// First source register, if the instruction has one.
+    #[rustfmt::skip]
+    pub fn rs1(&self) -> Option<IntRegister> {
+        use Instruction::*;
+        match *self {
+            JALR { rs1, .. } => Some(rs1),
+            LB { rs1, .. } | LH { rs1, .. } | LW { rs1, .. } | LBU { rs1, .. } | LHU { rs1, .. } => Some(rs1),
+            SB { rs1, .. } | SH { rs1, .. } | SW { rs1, .. } => Some(rs1),
+            SLLI { rs1, .. } | SRLI { rs1, .. } | SRAI { rs1, .. } => Some(rs1),
+            SLL { rs1, .. } | SRL { rs1, .. } | SRA { rs1, .. } => Some(rs1),
+            ADDI { rs1, .. } | ADD { rs1, .. } | SUB { rs1, .. } => Some(rs1),
+            ORI { rs1, .. } | XORI { rs1, .. } | ANDI { rs1, .. } => Some(rs1),
+            OR { rs1, .. } | XOR { rs1, .. } | AND { rs1, .. } => Some(rs1),
+            SLTI { rs1, .. } | SLTIU { rs1, .. } | SLT { rs1, .. } | SLTU { rs1, .. } => Some(rs1),
+            BEQ { rs1, .. } | BNE { rs1, .. } | BLT { rs1, .. } | BGE { rs1, .. } => Some(rs1),
+            BLTU { rs1, .. } | BGEU { rs1, .. } => Some(rs1),
+            MUL { rs1, .. } | MULH { rs1, .. } | MULHSU { rs1, .. } | MULHU { rs1, .. } => Some(rs1),
+            DIV { rs1, .. } | DIVU { rs1, .. } | REM { rs1, .. } | REMU { rs1, .. } => Some(rs1),
+            _ => None,
+        }
+    }
+
+    /// Second source register, if the instruction has one.
+    #[rustfmt::skip]
+    pub fn rs2(&self) -> Option<IntRegister> {
+        use Instruction::*;
+        match *self {
+            SB { rs2, .. } | SH { rs2, .. } | SW { rs2, .. } => Some(rs2),
+            SLL { rs2, .. } | SRL { rs2, .. } | SRA { rs2, .. } => Some(rs2),
+            ADD { rs2, .. } | SUB { rs2, .. } => Some(rs2),
+            OR { rs2, .. } | XOR { rs2, .. } | AND { rs2, .. } => Some(rs2),
+            SLT { rs2, .. } | SLTU { rs2, .. } => Some(rs2),
+            BEQ { rs2, .. } | BNE { rs2, .. } | BLT { rs2, .. } | BGE { rs2, .. } => Some(rs2),
+            BLTU { rs2, .. } | BGEU { rs2, .. } => Some(rs2),
+            MUL { rs2, .. } | MULH { rs2, .. } | MULHSU { rs2, .. } | MULHU { rs2, .. } => Some(rs2),
+            DIV { rs2, .. } | DIVU { rs2, .. } | REM { rs2, .. } | REMU { rs2, .. } => Some(rs2),
+            _ => None,
+        }
+    }
+
+    /// Immediate operand, if the instruction has one, as written in
+    /// assembly: the shift amount for immediate shifts, the upper 20 bits
+    /// for `lui` and `auipc`, and the byte offset for branches and jumps.
+    #[rustfmt::skip]
+    pub fn imm(&self) -> Option<i32> {
+        use Instruction::*;
+        match *self {
+            LUI { imm, .. } | AUIPC { imm, .. } | JAL { imm, .. } => Some(imm),
+            JALR { imm, .. } => Some(i32::from(imm)),
+            LB { imm, .. } | LH { imm, .. } | LW { imm, .. } | LBU { imm, .. } | LHU { imm, .. } => Some(i32::from(imm)),
+            SB { imm, .. } | SH { imm, .. } | SW { imm, .. } => Some(i32::from(imm)),
+            SLLI { shamt, .. } | SRLI { shamt, .. } | SRAI { shamt, .. } => Some(i32::from(shamt)),
+            ADDI { imm, .. } | ORI { imm, .. } | XORI { imm, .. } | ANDI { imm, .. } => Some(i32::from(imm)),
+            SLTI { imm, .. } | SLTIU { imm, .. } => Some(i32::from(imm)),
+            BEQ { imm, .. } | BNE { imm, .. } | BLT { imm, .. } | BGE { imm, .. } => Some(i32::from(imm)),
+            BLTU { imm, .. } | BGEU { imm, .. } => Some(i32::from(imm)),
+            _ => None,
+        }
+    }
+
+    pub fn is_load(&self) -> bool {
+        self.category() == Category::Load
+    }
+
+    pub fn is_store(&self) -> bool {
+        self.category() == Category::Store
+    }
+
+    /// Whether this is a conditional branch.
+    pub fn is_branch(&self) -> bool {
+        self.category() == Category::Branch
+    }
+
+    /// Whether this is an unconditional jump, `jal` or `jalr`.
+    pub fn is_jump(&self) -> bool {
+        self.category() == Category::Jump
+    }
+
+    /// Number of bytes a load or store accesses.
+    pub fn mem_access_width(&self) -> Option<u32> {
         use Instruction::*;
         match self {
+            LB { .. } | LBU { .. } | SB { .. } => Some(1),
+            LH { .. } | LHU { .. } | SH { .. } => Some(2),
+            LW { .. } | SW { .. } => Some(4),
+            _ => None,
+        }
+    }
+
+    pub fn display_pseudo(&self) -> DisplayPseudo {
+        DisplayPseudo(*self)
+    }
+
+    pub fn display_at(&self, pc: u32) -> DisplayAt<'static> {
+        DisplayAt {
+            inst: *self,
+            pc,
+            symbols: None,
+        }
+    }
+
+    #[rustfmt::skip]
+    fn fmt_pseudo(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        pc: Option<u32>,
+        symbols: Option<&SymbolTable>,
+    ) -> Option<fmt::Result> {
+        use Instruction::*;
+        use IntRegister::{Zero, RA};
+        let t = |offset| Target {
+            offset,
+            pc,
+            symbols,
+        };
+        let result = match *self {
+            ADDI { rd: Zero, rs1: Zero, imm: 0 } => write!(f, "nop"),
+            ADDI { rd, rs1: Zero, imm } => write!(f, "li      {}, {}", rd, imm),
+            ADDI { rd, rs1, imm: 0 } => write!(f, "mv      {}, {}", rd, rs1),
+            XORI { rd, rs1, imm: -1 } => write!(f, "not     {}, {}", rd, rs1),
+            SUB { rd, rs1: Zero, rs2 } => write!(f, "neg     {}, {}", rd, rs2),
+            SLTIU { rd, rs1, imm: 1 } => write!(f, "seqz    {}, {}", rd, rs1),
+            SLTU { rd, rs1: Zero, rs2 } => write!(f, "snez    {}, {}", rd, rs2),
+            BEQ { rs1, rs2: Zero, imm } => write!(f, "beqz    {}, {}", rs1, t(imm.into())),
+            BNE { rs1, rs2: Zero, imm } => write!(f, "bnez    {}, {}", rs1, t(imm.into())),
+            BLT { rs1, rs2: Zero, imm } => write!(f, "bltz    {}, {}", rs1, t(imm.into())),
+            BGE { rs1, rs2: Zero, imm } => write!(f, "bgez    {}, {}", rs1, t(imm.into())),
+            BLT { rs1: Zero, rs2, imm } => write!(f, "bgtz    {}, {}", rs2, t(imm.into())),
+            BGE { rs1: Zero, rs2, imm } => write!(f, "blez    {}, {}", rs2, t(imm.into())),
+            JAL { rd: Zero, imm } => write!(f, "j       {}", t(imm)),
+            JAL { rd: RA, imm } => write!(f, "jal     {}", t(imm)),
+            JALR { rd: Zero, rs1: RA, imm: 0 } => write!(f, "ret"),
+            JALR { rd: Zero, rs1, imm: 0 } => write!(f, "jr      {}", rs1),
+            JALR { rd: RA, rs1, imm: 0 } => write!(f, "jalr    {}", rs1),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    fn fmt_at(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        pc: Option<u32>,
+        symbols: Option<&SymbolTable>,
+    ) -> fmt::Result {
+        use Instruction::*;
+        let t = |offset| Target {
+            offset,
+            pc,
+            symbols,
+        };
+
+        if f.alternate() {
+            if let Some(result) = self.fmt_pseudo(f, pc, symbols) {
+                return result;
+            }
+        }
+
+        match *self {
             LUI { rd, imm } => write!(f, "lui     {}, {}", rd, imm),
             LB { rd, rs1, imm } => write!(f, "lb      {}, {}({})", rd, imm, rs1),
             LH { rd, rs1, imm } => write!(f, "lh      {}, {}({})", rd, imm, rs1),
@@ -199,19 +516,20 @@ impl fmt::Display for Instruction {
             SLTIU { rd, rs1, imm } => write!(f, "sltiu   {}, {}, {}", rd, rs1, imm),
             SLT { rd, rs1, rs2 } => write!(f, "slt     {}, {}, {}", rd, rs1, rs2),
             SLTU { rd, rs1, rs2 } => write!(f, "sltu    {}, {}, {}", rd, rs1, rs2),
-            BEQ { rs1, rs2, imm } => write!(f, "beq     {}, {}, pc{:+}", rs1, rs2, imm),
-            BNE { rs1, rs2, imm } => write!(f, "bne     {}, {}, pc{:+}", rs1, rs2, imm),
-            BLT { rs1, rs2, imm } => write!(f, "blt     {}, {}, pc{:+}", rs1, rs2, imm),
-            BGE { rs1, rs2, imm } => write!(f, "bge     {}, {}, pc{:+}", rs1, rs2, imm),
-            BLTU { rs1, rs2, imm } => write!(f, "bltu    {}, {}, pc{:+}", rs1, rs2, imm),
-            BGEU { rs1, rs2, imm } => write!(f, "bgeu    {}, {}, pc{:+}", rs1, rs2, imm),
-            JAL { rd, imm } => write!(f, "jal     {}, pc{:+}", rd, imm),
+            BEQ { rs1, rs2, imm } => write!(f, "beq     {}, {}, {}", rs1, rs2, t(imm.into())),
+            BNE { rs1, rs2, imm } => write!(f, "bne     {}, {}, {}", rs1, rs2, t(imm.into())),
+            BLT { rs1, rs2, imm } => write!(f, "blt     {}, {}, {}", rs1, rs2, t(imm.into())),
+            BGE { rs1, rs2, imm } => write!(f, "bge     {}, {}, {}", rs1, rs2, t(imm.into())),
+            BLTU { rs1, rs2, imm } => write!(f, "bltu    {}, {}, {}", rs1, rs2, t(imm.into())),
+            BGEU { rs1, rs2, imm } => write!(f, "bgeu    {}, {}, {}", rs1, rs2, t(imm.into())),
+            JAL { rd, imm } => write!(f, "jal     {}, {}", rd, t(imm)),
             AUIPC { rd, imm } => write!(f, "auipc   {}, {}", rd, imm),
             JALR { rd, rs1, imm } => write!(f, "jalr    {}, {}({})", rd, imm, rs1),
             FENCE { pred, succ } => write!(f, "fence   {}, {}", pred, succ),
             FENCETSO => write!(f, "fence.tso"),
             ECALL => write!(f, "ecall"),
             EBREAK => write!(f, "ebreak"),
+            CUSTOM { raw } => write!(f, "custom  {:#010x}", raw),
             MUL { rd, rs1, rs2 } => write!(f, "mul     {}, {}, {}", rd, rs1, rs2),
             MULH { rd, rs1, rs2 } => write!(f, "mulh    {}, {}, {}", rd, rs1, rs2),
             MULHSU { rd, rs1, rs2 } => write!(f, "mulhsu  {}, {}, {}", rd, rs1, rs2),
@@ -224,188 +542,487 @@ impl fmt::Display for Instruction {
     }
 }
 
+/// Wrapper that formats an instruction using its canonical pseudo-instruction
+/// form when one exists. Equivalent to formatting with `{:#}`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DisplayPseudo(Instruction);
+
+impl fmt::Display for DisplayPseudo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+/// Wrapper that formats an instruction located at `pc`, resolving branch and
+/// jump offsets into absolute target addresses (and symbols, when given).
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayAt<'a> {
+    inst: Instruction,
+    pc: u32,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl DisplayAt<'_> {
+    pub fn with_symbols(self, symbols: &SymbolTable) -> DisplayAt<'_> {
+        DisplayAt {
+            inst: self.inst,
+            pc: self.pc,
+            symbols: Some(symbols),
+        }
+    }
+}
+
+impl fmt::Display for DisplayAt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inst.fmt_at(f, Some(self.pc), self.symbols)
+    }
+}
+
+struct Target<'a> {
+    offset: i32,
+    pc: Option<u32>,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl fmt::Display for Target<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pc = match self.pc {
+            Some(pc) => pc,
+            None => return write!(f, "pc{:+}", self.offset),
+        };
+
+        let addr = pc.wrapping_add(self.offset as u32);
+        write!(f, "{:#010x}", addr)?;
+
+        match self.symbols.and_then(|symbols| symbols.lookup(addr)) {
+            Some(symbol) => write!(f, " <{}>", symbol),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_at(f, None, None)
+    }
+}
+
 impl TryFrom<u32> for Instruction {
     type Error = ();
 
     fn try_from(inst: u32) -> Result<Self, Self::Error> {
-        let opcode = (inst & 0x7F) as u8;
-        let format = InstructionFormat::from_opcode(opcode).ok_or(())?;
-
-        match format {
-            InstructionFormat::R => {
-                let rd = IntRegister::try_from(((inst >> 7) & 0b11111) as u8)?;
-                let rs1 = IntRegister::try_from(((inst >> 15) & 0b11111) as u8)?;
-                let rs2 = IntRegister::try_from(((inst >> 20) & 0b11111) as u8)?;
-                let funct3 = ((inst >> 12) & 0b111) as u8;
-                let funct7 = ((inst >> 25) & 0b1111111) as u8;
-                let fn3_opcode = (funct3 << 5) | (opcode >> 2);
-
-                match fn3_opcode {
-                    0b000_01100 => match funct7 {
-                        0b0000000 => Ok(Instruction::ADD { rd, rs1, rs2 }),
-                        0b0000001 => Ok(Instruction::MUL { rd, rs1, rs2 }),
-                        0b0100000 => Ok(Instruction::SUB { rd, rs1, rs2 }),
-                        _ => Err(()),
-                    },
-                    0b001_01100 => match funct7 {
-                        0b0000000 => Ok(Instruction::SLL { rd, rs1, rs2 }),
-                        0b0000001 => Ok(Instruction::MULH { rd, rs1, rs2 }),
-                        _ => Err(()),
-                    },
-                    0b010_01100 => match funct7 {
-                        0b0000000 => Ok(Instruction::SLT { rd, rs1, rs2 }),
-                        0b0000001 => Ok(Instruction::MULHSU { rd, rs1, rs2 }),
-                        _ => Err(()),
-                    },
-                    0b011_01100 => match funct7 {
-                        0b0000000 => Ok(Instruction::SLTU { rd, rs1, rs2 }),
-                        0b0000001 => Ok(Instruction::MULHU { rd, rs1, rs2 }),
-                        _ => Err(()),
-                    },
-                    0b100_01100 => match funct7 {
-                        0b0000000 => Ok(Instruction::XOR { rd, rs1, rs2 }),
-                        0b0000001 => Ok(Instruction::DIV { rd, rs1, rs2 }),
-                        _ => Err(()),
-                    },
-                    0b101_01100 => match funct7 {
-                        0b0000000 => Ok(Instruction::SRL { rd, rs1, rs2 }),
-                        0b0000001 => Ok(Instruction::DIVU { rd, rs1, rs2 }),
-                        0b0100000 => Ok(Instruction::SRA { rd, rs1, rs2 }),
-                        _ => Err(()),
-                    },
-                    0b110_01100 => match funct7 {
-                        0b0000000 => Ok(Instruction::OR { rd, rs1, rs2 }),
-                        0b0000001 => Ok(Instruction::REM { rd, rs1, rs2 }),
-                        _ => Err(()),
-                    },
-                    0b111_01100 => match funct7 {
-                        0b0000000 => Ok(Instruction::AND { rd, rs1, rs2 }),
-                        0b0000001 => Ok(Instruction::REMU { rd, rs1, rs2 }),
-                        _ => Err(()),
-                    },
-                    _ => Err(()),
-                }
-            }
-            InstructionFormat::R4 => Err(()),
-            InstructionFormat::I => {
-                let rd = IntRegister::try_from(((inst >> 7) & 0b11111) as u8)?;
-                let rs1 = IntRegister::try_from(((inst >> 15) & 0b11111) as u8)?;
-                let funct3 = ((inst >> 12) & 0b111) as u8;
-                let imm = ((inst as i32) >> 20) as i16;
-                let fn3_opcode = (funct3 << 5) | (opcode >> 2);
-
-                match fn3_opcode {
-                    0b000_11001 => Ok(Instruction::JALR { rd, rs1, imm }),
-                    0b000_00000 => Ok(Instruction::LB { rd, rs1, imm }),
-                    0b001_00000 => Ok(Instruction::LH { rd, rs1, imm }),
-                    0b010_00000 => Ok(Instruction::LW { rd, rs1, imm }),
-                    0b100_00000 => Ok(Instruction::LBU { rd, rs1, imm }),
-                    0b101_00000 => Ok(Instruction::LHU { rd, rs1, imm }),
-                    0b000_00100 => Ok(Instruction::ADDI { rd, rs1, imm }),
-                    0b001_00100 => {
-                        let shamt = (imm & 0b11111) as u8;
-                        match imm >> 5 {
-                            0b0000000 => Ok(Instruction::SLLI { rd, rs1, shamt }),
-                            _ => Err(()),
-                        }
-                    }
-                    0b010_00100 => Ok(Instruction::SLTI { rd, rs1, imm }),
-                    0b011_00100 => Ok(Instruction::SLTIU { rd, rs1, imm }),
-                    0b100_00100 => Ok(Instruction::XORI { rd, rs1, imm }),
-                    0b101_00100 => {
-                        let shamt = (imm & 0b11111) as u8;
-                        match imm >> 5 {
-                            0b0000000 => Ok(Instruction::SRLI { rd, rs1, shamt }),
-                            0b0100000 => Ok(Instruction::SRAI { rd, rs1, shamt }),
-                            _ => Err(()),
-                        }
-                    }
-                    0b110_00100 => Ok(Instruction::ORI { rd, rs1, imm }),
-                    0b111_00100 => Ok(Instruction::ANDI { rd, rs1, imm }),
-                    0b000_00011 => {
-                        let fm = ((imm >> 8) & 0b1111) as u8;
-                        let pred = ((imm >> 4) & 0b1111) as u8;
-                        let succ = (imm & 0b1111) as u8;
-                        let pred = FenceKind::try_from(pred)?;
-                        let succ = FenceKind::try_from(succ)?;
-
-                        match (fm, pred, succ) {
-                            (0b1000, FenceKind::RW, FenceKind::RW) => Ok(Instruction::FENCETSO),
-                            (0b0000, pred, succ) => Ok(Instruction::FENCE { pred, succ }),
-                            _ => Err(()),
-                        }
-                    }
-                    0b000_11100 => match imm {
-                        0 => Ok(Instruction::ECALL),
-                        1 => Ok(Instruction::EBREAK),
-                        _ => Err(()),
-                    },
-                    _ => Err(()),
-                }
+        decode::decode(inst).ok_or(())
+    }
+}
+
+impl From<Instruction> for u32 {
+    #[rustfmt::skip]
+    fn from(inst: Instruction) -> Self {
+        use Instruction::*;
+        match inst {
+            LUI { rd, imm } => encode_u(imm, rd, 0b0110111),
+            AUIPC { rd, imm } => encode_u(imm, rd, 0b0010111),
+            JAL { rd, imm } => encode_j(imm, rd, 0b1101111),
+            JALR { rd, rs1, imm } => encode_i(imm, rs1, 0b000, rd, 0b1100111),
+
+            BEQ { rs1, rs2, imm } => encode_b(imm, rs2, rs1, 0b000, 0b1100011),
+            BNE { rs1, rs2, imm } => encode_b(imm, rs2, rs1, 0b001, 0b1100011),
+            BLT { rs1, rs2, imm } => encode_b(imm, rs2, rs1, 0b100, 0b1100011),
+            BGE { rs1, rs2, imm } => encode_b(imm, rs2, rs1, 0b101, 0b1100011),
+            BLTU { rs1, rs2, imm } => encode_b(imm, rs2, rs1, 0b110, 0b1100011),
+            BGEU { rs1, rs2, imm } => encode_b(imm, rs2, rs1, 0b111, 0b1100011),
+
+            LB { rd, rs1, imm } => encode_i(imm, rs1, 0b000, rd, 0b0000011),
+            LH { rd, rs1, imm } => encode_i(imm, rs1, 0b001, rd, 0b0000011),
+            LW { rd, rs1, imm } => encode_i(imm, rs1, 0b010, rd, 0b0000011),
+            LBU { rd, rs1, imm } => encode_i(imm, rs1, 0b100, rd, 0b0000011),
+            LHU { rd, rs1, imm } => encode_i(imm, rs1, 0b101, rd, 0b0000011),
+
+            SB { rs1, rs2, imm } => encode_s(imm, rs2, rs1, 0b000, 0b0100011),
+            SH { rs1, rs2, imm } => encode_s(imm, rs2, rs1, 0b001, 0b0100011),
+            SW { rs1, rs2, imm } => encode_s(imm, rs2, rs1, 0b010, 0b0100011),
+
+            ADDI { rd, rs1, imm } => encode_i(imm, rs1, 0b000, rd, 0b0010011),
+            SLTI { rd, rs1, imm } => encode_i(imm, rs1, 0b010, rd, 0b0010011),
+            SLTIU { rd, rs1, imm } => encode_i(imm, rs1, 0b011, rd, 0b0010011),
+            XORI { rd, rs1, imm } => encode_i(imm, rs1, 0b100, rd, 0b0010011),
+            ORI { rd, rs1, imm } => encode_i(imm, rs1, 0b110, rd, 0b0010011),
+            ANDI { rd, rs1, imm } => encode_i(imm, rs1, 0b111, rd, 0b0010011),
+            SLLI { rd, rs1, shamt } => encode_i(shamt as i16, rs1, 0b001, rd, 0b0010011),
+            SRLI { rd, rs1, shamt } => encode_i(shamt as i16, rs1, 0b101, rd, 0b0010011),
+            SRAI { rd, rs1, shamt } => encode_i(0x400 | shamt as i16, rs1, 0b101, rd, 0b0010011),
+
+            ADD { rd, rs1, rs2 } => encode_r(0b0000000, rs2, rs1, 0b000, rd, 0b0110011),
+            SUB { rd, rs1, rs2 } => encode_r(0b0100000, rs2, rs1, 0b000, rd, 0b0110011),
+            SLL { rd, rs1, rs2 } => encode_r(0b0000000, rs2, rs1, 0b001, rd, 0b0110011),
+            SLT { rd, rs1, rs2 } => encode_r(0b0000000, rs2, rs1, 0b010, rd, 0b0110011),
+            SLTU { rd, rs1, rs2 } => encode_r(0b0000000, rs2, rs1, 0b011, rd, 0b0110011),
+            XOR { rd, rs1, rs2 } => encode_r(0b0000000, rs2, rs1, 0b100, rd, 0b0110011),
+            SRL { rd, rs1, rs2 } => encode_r(0b0000000, rs2, rs1, 0b101, rd, 0b0110011),
+            SRA { rd, rs1, rs2 } => encode_r(0b0100000, rs2, rs1, 0b101, rd, 0b0110011),
+            OR { rd, rs1, rs2 } => encode_r(0b0000000, rs2, rs1, 0b110, rd, 0b0110011),
+            AND { rd, rs1, rs2 } => encode_r(0b0000000, rs2, rs1, 0b111, rd, 0b0110011),
+
+            FENCE { pred, succ } => {
+                let imm = ((pred as i16) << 4) | succ as i16;
+                encode_i(imm, IntRegister::Zero, 0b000, IntRegister::Zero, 0b0001111)
             }
-            InstructionFormat::S => {
-                let funct3 = ((inst >> 12) & 0b111) as u8;
-                let rs1 = IntRegister::try_from(((inst >> 15) & 0b11111) as u8)?;
-                let rs2 = IntRegister::try_from(((inst >> 20) & 0b11111) as u8)?;
-                let imm115 = (inst >> 25) & 0b1111111;
-                let imm40 = (inst >> 7) & 0b11111;
-                let imm = (imm115 << 5) | imm40;
-                let imm = ((imm as i16) << 4) >> 4;
-                let fn3_opcode = (funct3 << 5) | (opcode >> 2);
-
-                match fn3_opcode {
-                    0b000_01000 => Ok(Instruction::SB { rs1, rs2, imm }),
-                    0b001_01000 => Ok(Instruction::SH { rs1, rs2, imm }),
-                    0b010_01000 => Ok(Instruction::SW { rs1, rs2, imm }),
-                    _ => Err(()),
-                }
+            FENCETSO => encode_i(0x833, IntRegister::Zero, 0b000, IntRegister::Zero, 0b0001111),
+            ECALL => encode_i(0, IntRegister::Zero, 0b000, IntRegister::Zero, 0b1110011),
+            EBREAK => encode_i(1, IntRegister::Zero, 0b000, IntRegister::Zero, 0b1110011),
+            CUSTOM { raw } => raw,
+
+            MUL { rd, rs1, rs2 } => encode_r(0b0000001, rs2, rs1, 0b000, rd, 0b0110011),
+            MULH { rd, rs1, rs2 } => encode_r(0b0000001, rs2, rs1, 0b001, rd, 0b0110011),
+            MULHSU { rd, rs1, rs2 } => encode_r(0b0000001, rs2, rs1, 0b010, rd, 0b0110011),
+            MULHU { rd, rs1, rs2 } => encode_r(0b0000001, rs2, rs1, 0b011, rd, 0b0110011),
+            DIV { rd, rs1, rs2 } => encode_r(0b0000001, rs2, rs1, 0b100, rd, 0b0110011),
+            DIVU { rd, rs1, rs2 } => encode_r(0b0000001, rs2, rs1, 0b101, rd, 0b0110011),
+            REM { rd, rs1, rs2 } => encode_r(0b0000001, rs2, rs1, 0b110, rd, 0b0110011),
+            REMU { rd, rs1, rs2 } => encode_r(0b0000001, rs2, rs1, 0b111, rd, 0b0110011),
+        }
+    }
+}
+
+fn encode_r(
+    funct7: u32,
+    rs2: IntRegister,
+    rs1: IntRegister,
+    funct3: u32,
+    rd: IntRegister,
+    opcode: u32,
+) -> u32 {
+    funct7 << 25
+        | (rs2 as u32) << 20
+        | (rs1 as u32) << 15
+        | funct3 << 12
+        | (rd as u32) << 7
+        | opcode
+}
+
+fn encode_i(imm: i16, rs1: IntRegister, funct3: u32, rd: IntRegister, opcode: u32) -> u32 {
+    (imm as u32 & 0xFFF) << 20 | (rs1 as u32) << 15 | funct3 << 12 | (rd as u32) << 7 | opcode
+}
+
+fn encode_s(imm: i16, rs2: IntRegister, rs1: IntRegister, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    let (imm115, imm40) = ((imm >> 5) & 0x7F, imm & 0x1F);
+    imm115 << 25 | (rs2 as u32) << 20 | (rs1 as u32) << 15 | funct3 << 12 | imm40 << 7 | opcode
+}
+
+fn encode_b(imm: i16, rs2: IntRegister, rs1: IntRegister, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    let (imm12, imm11) = ((imm >> 12) & 1, (imm >> 11) & 1);
+    let (imm105, imm41) = ((imm >> 5) & 0x3F, (imm >> 1) & 0xF);
+    let (rs2, rs1) = (rs2 as u32, rs1 as u32);
+    imm12 << 31
+        | imm105 << 25
+        | rs2 << 20
+        | rs1 << 15
+        | funct3 << 12
+        | imm41 << 8
+        | imm11 << 7
+        | opcode
+}
+
+fn encode_u(imm: i32, rd: IntRegister, opcode: u32) -> u32 {
+    (imm as u32) << 12 | (rd as u32) << 7 | opcode
+}
+
+fn encode_j(imm: i32, rd: IntRegister, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    let (imm20, imm101) = ((imm >> 20) & 1, (imm >> 1) & 0x3FF);
+    let (imm11, imm1912) = ((imm >> 11) & 1, (imm >> 12) & 0xFF);
+    imm20 << 31 | imm101 << 21 | imm11 << 20 | imm1912 << 12 | (rd as u32) << 7 | opcode
+}
+
+impl Instruction {
+    /// Number of distinct instructions [`Instruction::random`] chooses from.
+    const KINDS: u32 = 49;
+
+    /// Builds a valid instruction from the words returned by `next`, each
+    /// used as a source of random bits. Every instruction, with any operands,
+    /// can be produced, which makes it suitable for fuzzing and for random
+    /// program generation.
+    #[rustfmt::skip]
+    pub fn random<F: FnMut() -> u32>(mut next: F) -> Self {
+        use Instruction::*;
+        let kind = next() % Self::KINDS;
+        let mut reg = || IntRegister::try_from((next() & 0x1F) as u8).unwrap();
+        let (rd, rs1, rs2) = (reg(), reg(), reg());
+        let imm = sign_extend(next(), 12) as i16;
+        let shamt = (next() & 0x1F) as u8;
+        let offset = (sign_extend(next(), 12) << 1) as i16;
+        let upper = sign_extend(next(), 20);
+        let target = sign_extend(next(), 20) << 1;
+        let kinds = [FenceKind::R, FenceKind::W, FenceKind::RW];
+        let pred = kinds[(next() % 3) as usize];
+        let succ = kinds[(next() % 3) as usize];
+
+        match kind {
+            0 => LUI { rd, imm: upper },
+            1 => AUIPC { rd, imm: upper },
+            2 => JAL { rd, imm: target },
+            3 => JALR { rd, rs1, imm },
+            4 => BEQ { rs1, rs2, imm: offset },
+            5 => BNE { rs1, rs2, imm: offset },
+            6 => BLT { rs1, rs2, imm: offset },
+            7 => BGE { rs1, rs2, imm: offset },
+            8 => BLTU { rs1, rs2, imm: offset },
+            9 => BGEU { rs1, rs2, imm: offset },
+            10 => LB { rd, rs1, imm },
+            11 => LH { rd, rs1, imm },
+            12 => LW { rd, rs1, imm },
+            13 => LBU { rd, rs1, imm },
+            14 => LHU { rd, rs1, imm },
+            15 => SB { rs1, rs2, imm },
+            16 => SH { rs1, rs2, imm },
+            17 => SW { rs1, rs2, imm },
+            18 => ADDI { rd, rs1, imm },
+            19 => SLTI { rd, rs1, imm },
+            20 => SLTIU { rd, rs1, imm },
+            21 => XORI { rd, rs1, imm },
+            22 => ORI { rd, rs1, imm },
+            23 => ANDI { rd, rs1, imm },
+            24 => SLLI { rd, rs1, shamt },
+            25 => SRLI { rd, rs1, shamt },
+            26 => SRAI { rd, rs1, shamt },
+            27 => ADD { rd, rs1, rs2 },
+            28 => SUB { rd, rs1, rs2 },
+            29 => SLL { rd, rs1, rs2 },
+            30 => SLT { rd, rs1, rs2 },
+            31 => SLTU { rd, rs1, rs2 },
+            32 => XOR { rd, rs1, rs2 },
+            33 => SRL { rd, rs1, rs2 },
+            34 => SRA { rd, rs1, rs2 },
+            35 => OR { rd, rs1, rs2 },
+            36 => AND { rd, rs1, rs2 },
+            37 => FENCE { pred, succ },
+            38 => FENCETSO,
+            39 => ECALL,
+            40 => EBREAK,
+            41 => MUL { rd, rs1, rs2 },
+            42 => MULH { rd, rs1, rs2 },
+            43 => MULHSU { rd, rs1, rs2 },
+            44 => MULHU { rd, rs1, rs2 },
+            45 => DIV { rd, rs1, rs2 },
+            46 => DIVU { rd, rs1, rs2 },
+            47 => REM { rd, rs1, rs2 },
+            _ => REMU { rd, rs1, rs2 },
+        }
+    }
+
+    /// One instance of every instruction, with all operands zero.
+    pub fn examples() -> impl Iterator<Item = Self> {
+        (0..Self::KINDS).map(|kind| {
+            let mut kind = Some(kind);
+            Self::random(|| kind.take().unwrap_or(0))
+        })
+    }
+
+    /// Every instruction with each of its operand fields swept in turn, the
+    /// others left zero. Registers take all 32 values, immediates zero, every
+    /// single bit, all ones and the extremes of their range, and fences every
+    /// ordering, so every bit the decoder extracts is set on its own at least
+    /// once.
+    pub fn corpus() -> impl Iterator<Item = Self> {
+        // Fields in the order `random` draws them after the kind
+        const FIELDS: usize = 10;
+        let registers: Vec<u32> = (0..32).collect();
+        let immediate = |bits: u32| {
+            let mut values = vec![0, (1 << bits) - 1, (1 << (bits - 1)) - 1, 1 << (bits - 1)];
+            values.extend((0..bits).map(|bit| 1 << bit));
+            values
+        };
+        let sweeps = [
+            registers.clone(),
+            registers.clone(),
+            registers.clone(),
+            immediate(12),
+            registers,
+            immediate(12),
+            immediate(20),
+            immediate(20),
+            vec![0, 1, 2],
+            vec![0, 1, 2],
+        ];
+
+        let mut seen = HashSet::new();
+        (0..Self::KINDS)
+            .flat_map(move |kind| {
+                let sweeps = sweeps.clone();
+                (0..FIELDS).flat_map(move |field| {
+                    sweeps[field].clone().into_iter().map(move |value| {
+                        let mut draws = vec![0; FIELDS + 1];
+                        draws[0] = kind;
+                        draws[field + 1] = value;
+                        let mut draws = draws.into_iter();
+                        Self::random(|| draws.next().unwrap_or(0))
+                    })
+                })
+            })
+            .filter(move |inst| seen.insert(u32::from(*inst)))
+    }
+}
+
+/// Writes the encoding and disassembly of every instruction in
+/// [`Instruction::corpus`], one per line, decoding each word back so the
+/// output changes whenever the decoder or the encoder does.
+pub fn write_corpus<W: Write>(mut writer: W) -> io::Result<()> {
+    for inst in Instruction::corpus() {
+        let word = u32::from(inst);
+        match Instruction::try_from(word) {
+            Ok(decoded) => writeln!(writer, "{:08x}  {}", word, decoded)?,
+            Err(()) => writeln!(writer, "{:08x}  <invalid>", word)?,
+        }
+    }
+    Ok(())
+}
+
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entropy::Entropy;
+
+    #[test]
+    fn encode_decoded_instructions() {
+        let mut entropy = Entropy::seeded(1);
+        for _ in 0..100_000 {
+            let inst = Instruction::random(|| entropy.next_u32());
+            assert_eq!(Instruction::try_from(u32::from(inst)), Ok(inst));
+
+            let word = entropy.next_u32();
+            if let Ok(inst) = Instruction::try_from(word) {
+                assert_eq!(Instruction::try_from(u32::from(inst)), Ok(inst));
             }
-            InstructionFormat::B => {
-                let funct3 = ((inst >> 12) & 0b111) as u8;
-                let rs1 = IntRegister::try_from(((inst >> 15) & 0b11111) as u8)?;
-                let rs2 = IntRegister::try_from(((inst >> 20) & 0b11111) as u8)?;
-                let imm12 = (inst >> 31) & 0b1;
-                let imm105 = (inst >> 25) & 0b111111;
-                let imm41 = (inst >> 8) & 0b1111;
-                let imm11 = (inst >> 7) & 0b1;
-                let imm = (imm12 << 12) | (imm11 << 11) | (imm105 << 5) | (imm41 << 1);
-                let imm = ((imm as i16) << 3) >> 3;
-                let fn3_opcode = (funct3 << 5) | (opcode >> 2);
-
-                match fn3_opcode {
-                    0b000_11000 => Ok(Instruction::BEQ { rs1, rs2, imm }),
-                    0b001_11000 => Ok(Instruction::BNE { rs1, rs2, imm }),
-                    0b100_11000 => Ok(Instruction::BLT { rs1, rs2, imm }),
-                    0b101_11000 => Ok(Instruction::BGE { rs1, rs2, imm }),
-                    0b110_11000 => Ok(Instruction::BLTU { rs1, rs2, imm }),
-                    0b111_11000 => Ok(Instruction::BGEU { rs1, rs2, imm }),
-                    _ => Err(()),
-                }
+        }
+
+        // sw a0, -4(sp); beq a0, a1, -8; jal ra, -2048
+        assert_eq!(
+            u32::from(Instruction::try_from(0xFEA12E23).unwrap()),
+            0xFEA12E23
+        );
+        assert_eq!(
+            u32::from(Instruction::try_from(0xFEB50CE3).unwrap()),
+            0xFEB50CE3
+        );
+        assert_eq!(
+            u32::from(Instruction::try_from(0x801FF0EF).unwrap()),
+            0x801FF0EF
+        );
+    }
+
+    #[test]
+    fn match_golden_corpus() {
+        let mut corpus = Vec::new();
+        write_corpus(&mut corpus).unwrap();
+        let corpus = String::from_utf8(corpus).unwrap();
+        let golden = include_str!("../testdata/decoder.golden");
+        for (line, (actual, expected)) in corpus.lines().zip(golden.lines()).enumerate() {
+            assert_eq!(
+                actual,
+                expected,
+                "line {} of testdata/decoder.golden differs; if the change is intended, \
+                 regenerate it with `yars decoder-corpus`",
+                line + 1
+            );
+        }
+        assert_eq!(corpus.lines().count(), golden.lines().count());
+    }
+
+    #[test]
+    fn group_every_category() {
+        for inst in Instruction::examples() {
+            assert!(Category::ALL.contains(&inst.category()));
+            assert!(Category::GROUPS.contains(&inst.category().group()));
+        }
+    }
+
+    #[test]
+    fn access_operands() {
+        let field = |raw: u32, shift: u32| IntRegister::try_from((raw >> shift) as u8 & 0x1F).ok();
+        for inst in Instruction::examples() {
+            let raw = u32::from(inst);
+            if inst.rd().is_some() {
+                assert_eq!(inst.rd(), field(raw, 7), "{}", inst);
             }
-            InstructionFormat::U => {
-                let rd = IntRegister::try_from(((inst >> 7) & 0b11111) as u8)?;
-                let imm = (inst as i32) >> 12;
-
-                match opcode >> 2 {
-                    0b01101 => Ok(Instruction::LUI { rd, imm }),
-                    0b00101 => Ok(Instruction::AUIPC { rd, imm }),
-                    _ => Err(()),
-                }
+            if inst.rs1().is_some() {
+                assert_eq!(inst.rs1(), field(raw, 15), "{}", inst);
             }
-            InstructionFormat::J => {
-                let rd = IntRegister::try_from(((inst >> 7) & 0b11111) as u8)?;
-                let imm20 = (inst >> 31) & 0b1;
-                let imm101 = (inst >> 21) & 0b1111111111;
-                let imm11 = (inst >> 20) & 0b1;
-                let imm1912 = (inst >> 12) & 0b11111111;
-                let imm = (imm20 << 20) | (imm1912 << 12) | (imm11 << 11) | (imm101 << 1);
-                let imm = ((imm as i32) << 11) >> 11;
-
-                match opcode >> 2 {
-                    0b11011 => Ok(Instruction::JAL { rd, imm }),
-                    _ => Err(()),
-                }
+            if inst.rs2().is_some() {
+                assert_eq!(inst.rs2(), field(raw, 20), "{}", inst);
             }
+            assert_eq!(
+                inst.mem_access_width().is_some(),
+                inst.is_load() || inst.is_store()
+            );
         }
+
+        // lhu a0, -2(sp)
+        let lhu = Instruction::try_from(0xFFE15503).unwrap();
+        assert_eq!(lhu.rd(), Some(IntRegister::A0));
+        assert_eq!(lhu.rs1(), Some(IntRegister::SP));
+        assert_eq!(lhu.rs2(), None);
+        assert_eq!(lhu.imm(), Some(-2));
+        assert_eq!(lhu.mem_access_width(), Some(2));
+        assert!(lhu.is_load() && !lhu.is_store());
+
+        // bne a0, a1, -8
+        let bne = Instruction::try_from(0xFEB51CE3).unwrap();
+        assert_eq!(bne.rd(), None);
+        assert_eq!(bne.rs2(), Some(IntRegister::A1));
+        assert_eq!(bne.imm(), Some(-8));
+        assert!(bne.is_branch() && !bne.is_jump());
+        assert_eq!(bne.mem_access_width(), None);
+
+        // srai a0, a0, 3
+        let srai = Instruction::try_from(0x40355513).unwrap();
+        assert_eq!(srai.imm(), Some(3));
+        assert_eq!(Instruction::ECALL.imm(), None);
+    }
+
+    #[test]
+    fn display_pseudo_instructions() {
+        let li = Instruction::try_from(0x00500513).unwrap();
+        let mv = Instruction::try_from(0x00058513).unwrap();
+        let ret = Instruction::try_from(0x00008067).unwrap();
+        let nop = Instruction::try_from(0x00000013).unwrap();
+        assert_eq!(format!("{}", li.display_pseudo()), "li      a0, 5");
+        assert_eq!(format!("{:#}", mv), "mv      a0, a1");
+        assert_eq!(format!("{:#}", ret), "ret");
+        assert_eq!(format!("{:#}", nop), "nop");
+    }
+
+    #[test]
+    fn display_raw_without_alternate_flag() {
+        let li = Instruction::try_from(0x00500513).unwrap();
+        let add = Instruction::try_from(0x00b50533).unwrap();
+        assert_eq!(format!("{}", li), "addi    a0, zero, 5");
+        assert_eq!(format!("{:#}", add), "add     a0, a0, a1");
+    }
+
+    #[test]
+    fn display_absolute_branch_targets() {
+        use crate::symbol::Symbol;
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Symbol {
+            name: String::from("loop"),
+            address: 0x100,
+            size: 0,
+        });
+
+        let bnez = Instruction::try_from(0xfe051ee3).unwrap();
+        let at = bnez.display_at(0x108);
+        assert_eq!(format!("{}", bnez), "bne     a0, zero, pc-4");
+        assert_eq!(format!("{}", at), "bne     a0, zero, 0x00000104");
+        assert_eq!(
+            format!("{:#}", at.with_symbols(&symbols)),
+            "bnez    a0, 0x00000104 <loop+0x4>"
+        );
     }
 }
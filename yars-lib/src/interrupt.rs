@@ -0,0 +1,121 @@
+//! External interrupt lines raised by the host, and the memory-mapped
+//! controller the guest uses to observe and acknowledge them. Line 0 is
+//! reserved to mean "no interrupt", as in the PLIC.
+
+use crate::device::{Device, DeviceError};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+pub const LINES: u32 = 32;
+
+/// Pending lines (read-only).
+pub const PENDING: u32 = 0x0;
+/// Enabled lines (read-write).
+pub const ENABLE: u32 = 0x4;
+/// Reading claims the lowest enabled pending line (0 if none), writing a
+/// line completes it and clears its pending bit.
+pub const CLAIM: u32 = 0x8;
+pub const SIZE: u32 = 0xC;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InterruptController {
+    pending: u32,
+    enabled: u32,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn raise(&mut self, line: u32) -> Result<(), DeviceError> {
+        self.pending |= Self::mask(line)?;
+        Ok(())
+    }
+
+    pub fn clear(&mut self, line: u32) -> Result<(), DeviceError> {
+        self.pending &= !Self::mask(line)?;
+        Ok(())
+    }
+
+    pub fn pending(&self) -> u32 {
+        self.pending
+    }
+
+    pub fn claimable(&self) -> u32 {
+        match self.pending & self.enabled {
+            0 => 0,
+            lines => lines.trailing_zeros(),
+        }
+    }
+
+    fn mask(line: u32) -> Result<u32, DeviceError> {
+        match line {
+            1..=31 => Ok(1 << line),
+            _ => Err(DeviceError::InvalidRange),
+        }
+    }
+}
+
+impl Device for InterruptController {
+    fn read(&mut self, offset: u32, _size: u32) -> u32 {
+        match offset {
+            PENDING => self.pending,
+            ENABLE => self.enabled,
+            CLAIM => self.claimable(),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: u32, value: u32) {
+        match offset {
+            ENABLE => self.enabled = value & !1,
+            CLAIM => self.clear(value).unwrap_or(()),
+            _ => {}
+        }
+    }
+
+    fn name(&self) -> &str {
+        "irq"
+    }
+
+    fn save(&self) -> Vec<u8> {
+        [self.pending, self.enabled]
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        if let [pending, enabled] = state
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>()[..]
+        {
+            self.pending = pending & !1;
+            self.enabled = enabled & !1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claim_enabled_lines() {
+        let mut irq = InterruptController::new();
+        irq.raise(5).unwrap();
+        irq.raise(3).unwrap();
+        assert_eq!(irq.raise(0), Err(DeviceError::InvalidRange));
+        assert_eq!(irq.read(CLAIM, 4), 0);
+
+        irq.write(ENABLE, 4, 1 << 5);
+        assert_eq!(irq.read(CLAIM, 4), 5);
+        irq.write(CLAIM, 4, 5);
+        assert_eq!(irq.read(PENDING, 4), 1 << 3);
+        assert_eq!(irq.read(CLAIM, 4), 0);
+    }
+}
@@ -0,0 +1,199 @@
+//! ISA strings such as `rv32im` or `rv32imac_zicsr`, following the naming
+//! rules of the RISC-V unprivileged specification.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Single-letter extensions implemented by the simulator.
+pub const SUPPORTED: &str = "im";
+
+/// Canonical order of single-letter extensions in ISA strings.
+const ORDER: &str = "iemafdqlcbkjtpvnh";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IsaError {
+    InvalidFormat,
+    UnsupportedXlen(u32),
+    UnsupportedExtensions(Vec<String>),
+}
+
+impl fmt::Display for IsaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsaError::InvalidFormat => write!(f, "invalid ISA string"),
+            IsaError::UnsupportedXlen(xlen) => write!(f, "RV{} is not supported", xlen),
+            IsaError::UnsupportedExtensions(exts) => {
+                write!(f, "unsupported extensions: {}", exts.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Isa {
+    xlen: u32,
+    letters: u32,
+    others: Vec<String>,
+}
+
+impl Default for Isa {
+    fn default() -> Self {
+        "rv32im".parse().unwrap()
+    }
+}
+
+impl Isa {
+    pub fn xlen(&self) -> u32 {
+        self.xlen
+    }
+
+    pub fn has(&self, extension: char) -> bool {
+        match extension {
+            'a'..='z' => self.letters & 1 << (extension as u32 - 'a' as u32) != 0,
+            _ => false,
+        }
+    }
+
+    /// Single-letter extensions, in canonical order.
+    pub fn letters(&self) -> impl Iterator<Item = char> + '_ {
+        let rest = ('a'..='z').filter(|&ext| !ORDER.contains(ext));
+        ORDER.chars().chain(rest).filter(move |&ext| self.has(ext))
+    }
+
+    /// Multi-letter extensions (e.g. `zicsr`), in the order they were given.
+    pub fn others(&self) -> &[String] {
+        &self.others
+    }
+
+    /// Checks that the simulator implements every extension of this ISA for
+    /// a hart with the given XLEN.
+    pub fn validate(&self, xlen: u32) -> Result<(), IsaError> {
+        if self.xlen != xlen {
+            return Err(IsaError::UnsupportedXlen(self.xlen));
+        }
+
+        let unsupported = self
+            .letters()
+            .filter(|&ext| !SUPPORTED.contains(ext))
+            .map(|ext| ext.to_string())
+            .chain(self.others.iter().cloned())
+            .collect::<Vec<_>>();
+
+        match unsupported.is_empty() {
+            true => Ok(()),
+            false => Err(IsaError::UnsupportedExtensions(unsupported)),
+        }
+    }
+
+    fn add(&mut self, extension: char) {
+        self.letters |= 1 << (extension as u32 - 'a' as u32);
+    }
+}
+
+impl FromStr for Isa {
+    type Err = IsaError;
+
+    fn from_str(isa: &str) -> Result<Self, Self::Err> {
+        let isa = isa.to_ascii_lowercase();
+        let (xlen, rest) = match isa.get(..4) {
+            Some("rv32") => (32, &isa[4..]),
+            Some("rv64") => (64, &isa[4..]),
+            _ => return Err(IsaError::InvalidFormat),
+        };
+
+        let mut parts = rest.split('_');
+        let letters = parts.next().unwrap_or("");
+        let mut isa = Isa {
+            xlen,
+            letters: 0,
+            others: Vec::new(),
+        };
+
+        let mut chars = letters.char_indices().peekable();
+        match chars.next() {
+            Some((_, 'i')) => isa.add('i'),
+            Some((_, 'e')) => isa.add('e'),
+            Some((_, 'g')) => "imafd".chars().for_each(|ext| isa.add(ext)),
+            _ => return Err(IsaError::InvalidFormat),
+        }
+
+        let mut others = Vec::new();
+        while let Some((index, ext)) = chars.next() {
+            match ext {
+                // Version numbers such as `m2p0` are accepted and ignored
+                '0'..='9' => {}
+                'p' if matches!(chars.peek(), Some((_, c)) if c.is_ascii_digit()) => {}
+                'z' | 's' | 'x' => {
+                    others.push(&letters[index..]);
+                    break;
+                }
+                'a'..='z' => isa.add(ext),
+                _ => return Err(IsaError::InvalidFormat),
+            }
+        }
+
+        for other in others.into_iter().chain(parts) {
+            let name = other.trim_end_matches(|c: char| c.is_ascii_digit() || c == 'p');
+            if !name.chars().all(|c| c.is_ascii_lowercase()) {
+                return Err(IsaError::InvalidFormat);
+            }
+
+            match name.len() {
+                0 => return Err(IsaError::InvalidFormat),
+                1 => isa.add(name.chars().next().unwrap()),
+                _ => isa.others.push(name.to_string()),
+            }
+        }
+
+        if letters.starts_with('g') {
+            isa.others
+                .extend(vec!["zicsr".to_string(), "zifencei".to_string()]);
+        }
+
+        Ok(isa)
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rv{}", self.xlen)?;
+        for ext in self.letters() {
+            write!(f, "{}", ext)?;
+        }
+        for ext in &self.others {
+            write!(f, "_{}", ext)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_isa_strings() {
+        let isa = "RV32IMAC_Zicsr".parse::<Isa>().unwrap();
+        assert_eq!(isa.to_string(), "rv32imac_zicsr");
+        assert!(isa.has('m') && !isa.has('f'));
+        assert_eq!(
+            isa.validate(32),
+            Err(IsaError::UnsupportedExtensions(vec![
+                "a".to_string(),
+                "c".to_string(),
+                "zicsr".to_string()
+            ]))
+        );
+
+        assert_eq!("rv32i2p0m".parse::<Isa>().unwrap().validate(32), Ok(()));
+        assert_eq!(
+            Isa::default().validate(64),
+            Err(IsaError::UnsupportedXlen(32))
+        );
+        assert_eq!("rv64g".parse::<Isa>().unwrap().others().len(), 2);
+        assert_eq!("x86".parse::<Isa>(), Err(IsaError::InvalidFormat));
+    }
+}
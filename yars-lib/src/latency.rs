@@ -0,0 +1,120 @@
+//! Random perturbation of instruction and memory latencies. Every
+//! instruction normally takes one cycle; with jitter, each also takes a
+//! random number of extra cycles up to a bound, and loads and stores up to
+//! another bound on top, so timing-sensitive guest code such as lock
+//! backoffs and polling loops can be tested under varied timing. Guests see
+//! the effect through the cycle counter and, with [`TimeSource::Cycles`],
+//! through the clock.
+//!
+//! [`TimeSource::Cycles`]: crate::entropy::TimeSource::Cycles
+
+use crate::entropy::Entropy;
+use crate::instruction::Instruction;
+use std::fmt;
+use std::str::FromStr;
+
+/// Most extra cycles an instruction takes.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LatencyJitter {
+    /// Extra cycles any instruction may take.
+    pub instruction: u32,
+    /// Extra cycles loads and stores may take on top.
+    pub memory: u32,
+}
+
+impl fmt::Display for LatencyJitter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.instruction, self.memory)
+    }
+}
+
+/// Parses `<instruction>[,<memory>]`, with no extra memory latency if it is
+/// left out.
+impl FromStr for LatencyJitter {
+    type Err = String;
+
+    fn from_str(jitter: &str) -> Result<Self, Self::Err> {
+        let (instruction, memory) = jitter.split_once(',').unwrap_or((jitter, "0"));
+        match (instruction.parse(), memory.parse()) {
+            (Ok(instruction), Ok(memory)) => Ok(Self {
+                instruction,
+                memory,
+            }),
+            _ => Err("expected <instruction>[,<memory>] cycles".to_string()),
+        }
+    }
+}
+
+/// Jitter with the source of its random draws.
+#[derive(Clone, Debug)]
+pub(crate) struct Jitter {
+    bounds: LatencyJitter,
+    entropy: Entropy,
+}
+
+impl Jitter {
+    /// Draws from `seed` if given, kept apart from the random numbers the
+    /// guest reads with the same seed, or else from the host.
+    pub fn new(bounds: LatencyJitter, seed: Option<u64>) -> Self {
+        let entropy = match seed {
+            Some(seed) => Entropy::seeded(seed ^ 0x6A69_7474_6572), // "jitter"
+            None => Entropy::host(),
+        };
+        Self { bounds, entropy }
+    }
+
+    /// Draws the extra cycles `inst` takes.
+    pub fn extra(&mut self, inst: &Instruction) -> usize {
+        let (bounds, entropy) = (self.bounds, &mut self.entropy);
+        let mut draw = |bound: u32| match bound {
+            0 => 0,
+            _ => (u64::from(entropy.next_u32()) % (u64::from(bound) + 1)) as usize,
+        };
+        let memory = match inst.mem_access_width() {
+            Some(_) => draw(bounds.memory),
+            None => 0,
+        };
+        draw(bounds.instruction) + memory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::tests::simulator;
+    use crate::simulator::ExitEvent;
+
+    #[test]
+    fn perturb_latencies() {
+        assert_eq!(
+            "2,30".parse(),
+            Ok(LatencyJitter {
+                instruction: 2,
+                memory: 30
+            })
+        );
+        assert_eq!("4".parse::<LatencyJitter>().unwrap().memory, 0);
+        assert!("4,".parse::<LatencyJitter>().is_err());
+
+        // li a0, 10; lw a1, 0(zero); addi a0, a0, -1; bnez a0, -8; ecall
+        let code = [0x00A00513, 0x00002583, 0xFFF50513, 0xFE051CE3, 0x00000073];
+        let jitter = LatencyJitter {
+            instruction: 2,
+            memory: 30,
+        };
+        let cycles = |seed| {
+            let mut sim = simulator("jitter", &code);
+            sim.set_deterministic(Some(seed));
+            sim.set_latency_jitter(Some(jitter));
+            assert_eq!(sim.run(None), ExitEvent::EcallExit);
+            assert_eq!(sim.processor().instret(), 32);
+            sim.cycles()
+        };
+
+        // 31 instructions retired before the ecall, 10 of them loads
+        let (first, again, other) = (cycles(1), cycles(1), cycles(2));
+        assert!((32..=32 + 31 * 2 + 10 * 30).contains(&first));
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+    }
+}
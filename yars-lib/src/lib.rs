@@ -1,5 +1,43 @@
+pub mod assertion;
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+pub mod callstack;
+pub mod canary;
+pub mod cfg;
+pub mod checkpoint;
+pub mod coverage;
+pub mod decode;
+pub mod device;
+pub mod diff;
+pub mod dynamic;
+pub mod entropy;
+pub mod envcall;
+pub mod executor;
+pub mod explain;
+pub mod fault;
+pub mod heap;
+pub mod heatmap;
 pub mod instruction;
+pub mod interrupt;
+pub mod isa;
+pub mod latency;
+pub mod lines;
 pub mod memory;
+pub mod plugin;
+pub mod printf;
 pub mod processor;
+pub mod profile;
 pub mod register;
+pub mod relocation;
+pub mod replay;
+pub mod reset;
+pub mod shadow;
+pub mod signature;
 pub mod simulator;
+pub mod snapshot;
+pub mod stack;
+pub mod symbol;
+pub mod torture;
+pub mod trace;
+pub mod trap;
+pub mod xlen;
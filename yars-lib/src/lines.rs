@@ -0,0 +1,234 @@
+//! Source line information read from the DWARF line tables of a program,
+//! and line coverage of executed programs in LCOV format.
+
+use crate::memory::ProgramError;
+use gimli::{AttributeValue, Dwarf, EndianSlice, LittleEndian, SectionId};
+use goblin::elf::Elf;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::path::Path;
+
+type Reader<'a> = EndianSlice<'a, LittleEndian>;
+
+/// Addresses `start..end` generated for `line` of the file at `file`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Range {
+    start: u32,
+    end: u32,
+    file: usize,
+    line: u32,
+}
+
+/// Maps addresses to source files and lines.
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    files: Vec<String>,
+    ranges: Vec<Range>,
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the line tables of the ELF program in `buffer`. Programs built
+    /// without debug information give an empty table.
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, ProgramError> {
+        let binary = Elf::parse(buffer).map_err(ProgramError::Goblin)?;
+        let section = |id: SectionId| -> Result<Reader, gimli::Error> {
+            let data = binary
+                .section_headers
+                .iter()
+                .find(|sh| matches!(binary.shdr_strtab.get(sh.sh_name), Some(Ok(name)) if name == id.name()))
+                .and_then(|sh| {
+                    let start = sh.sh_offset as usize;
+                    buffer.get(start..start + sh.sh_size as usize)
+                })
+                .unwrap_or(&[]);
+            Ok(EndianSlice::new(data, LittleEndian))
+        };
+        let dwarf = Dwarf::load(section).map_err(ProgramError::Dwarf)?;
+        Self::from_dwarf(&dwarf).map_err(ProgramError::Dwarf)
+    }
+
+    fn from_dwarf(dwarf: &Dwarf<Reader>) -> Result<Self, gimli::Error> {
+        let mut table = Self::new();
+        let mut indices = HashMap::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let program = match unit.line_program.clone() {
+                Some(program) => program,
+                None => continue,
+            };
+            let comp_dir = unit.comp_dir.map(|dir| dir.to_string_lossy().into_owned());
+
+            // Each row covers the addresses up to the next row in its sequence
+            let mut previous: Option<(u32, usize, u32)> = None;
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let address = row.address() as u32;
+                if let Some((start, file, line)) = previous.take() {
+                    if address > start && line != 0 {
+                        table.ranges.push(Range {
+                            start,
+                            end: address,
+                            file,
+                            line,
+                        });
+                    }
+                }
+                if row.end_sequence() {
+                    continue;
+                }
+                let file = match row.file(header) {
+                    Some(file) => file,
+                    None => continue,
+                };
+                let mut path = comp_dir
+                    .as_ref()
+                    .map(Path::new)
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf();
+                if let Some(dir) = file.directory(header) {
+                    path.push(string(dwarf, &unit, dir)?);
+                }
+                path.push(string(dwarf, &unit, file.path_name())?);
+                let path = path.display().to_string();
+                let index = *indices.entry(path.clone()).or_insert_with(|| {
+                    table.files.push(path);
+                    table.files.len() - 1
+                });
+                let line = row.line().map_or(0, |line| line.get() as u32);
+                previous = Some((address, index, line));
+            }
+        }
+        table.ranges.sort_by_key(|range| range.start);
+        Ok(table)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// File and line the instruction at `address` was generated for.
+    pub fn lookup(&self, address: u32) -> Option<(&str, u32)> {
+        let index = match self
+            .ranges
+            .binary_search_by_key(&address, |range| range.start)
+        {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let range = &self.ranges[index];
+        match address < range.end {
+            true => Some((&self.files[range.file], range.line)),
+            false => None,
+        }
+    }
+}
+
+fn string(
+    dwarf: &Dwarf<Reader>,
+    unit: &gimli::Unit<Reader>,
+    value: AttributeValue<Reader>,
+) -> Result<String, gimli::Error> {
+    Ok(dwarf
+        .attr_string(unit, value)?
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// Counts of the instructions executed at each address, reported per source
+/// line.
+#[derive(Clone, Debug, Default)]
+pub struct LineCoverage {
+    counts: HashMap<u32, u64>,
+}
+
+impl LineCoverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, pc: u32) {
+        *self.counts.entry(pc).or_insert(0) += 1;
+    }
+
+    /// Writes the coverage of every line in `lines` as an LCOV tracefile,
+    /// which `genhtml` renders as HTML. The count of a line is the largest
+    /// among the instructions generated for it, so a line runs as many
+    /// times as its most executed instruction.
+    pub fn write_lcov<W: Write>(&self, lines: &LineTable, mut writer: W) -> io::Result<()> {
+        let mut files = BTreeMap::new();
+        for range in &lines.ranges {
+            let hits = (range.start..range.end)
+                .step_by(2)
+                .filter_map(|address| self.counts.get(&address))
+                .max()
+                .copied()
+                .unwrap_or(0);
+            let count = files
+                .entry(&lines.files[range.file])
+                .or_insert_with(BTreeMap::new)
+                .entry(range.line)
+                .or_insert(0);
+            *count = hits.max(*count);
+        }
+
+        writeln!(writer, "TN:")?;
+        for (file, counts) in files {
+            writeln!(writer, "SF:{}", file)?;
+            for (line, count) in &counts {
+                writeln!(writer, "DA:{},{}", line, count)?;
+            }
+            writeln!(writer, "LF:{}", counts.len())?;
+            let hit = counts.values().filter(|&&count| count > 0).count();
+            writeln!(writer, "LH:{}", hit)?;
+            writeln!(writer, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_line_coverage() {
+        let range = |start, end, file, line| Range {
+            start,
+            end,
+            file,
+            line,
+        };
+        let lines = LineTable {
+            files: vec!["/src/main.c".to_string(), "/src/util.c".to_string()],
+            ranges: vec![
+                range(0x1000, 0x1008, 0, 3),
+                range(0x1008, 0x100C, 0, 4),
+                range(0x100C, 0x1010, 0, 3),
+                range(0x2000, 0x2004, 1, 7),
+            ],
+        };
+        assert_eq!(lines.lookup(0x1004), Some(("/src/main.c", 3)));
+        assert_eq!(lines.lookup(0x2002), Some(("/src/util.c", 7)));
+        assert_eq!(lines.lookup(0x1010), None);
+        assert_eq!(lines.lookup(0x0FFC), None);
+
+        let mut coverage = LineCoverage::new();
+        for &pc in &[0x1000, 0x1004, 0x100C, 0x100C, 0x100C] {
+            coverage.record(pc);
+        }
+        let mut lcov = Vec::new();
+        coverage.write_lcov(&lines, &mut lcov).unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "TN:\n\
+             SF:/src/main.c\nDA:3,3\nDA:4,0\nLF:2\nLH:1\nend_of_record\n\
+             SF:/src/util.c\nDA:7,0\nLF:1\nLH:0\nend_of_record\n"
+        );
+    }
+}
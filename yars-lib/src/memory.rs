@@ -1,15 +1,53 @@
-use goblin::elf::header::{EM_RISCV, ET_EXEC};
-use goblin::elf::program_header::PT_LOAD;
+use crate::dynamic;
+use crate::relocation;
+use crate::symbol::SymbolTable;
+use goblin::elf::header::{EM_RISCV, ET_DYN, ET_EXEC, ET_REL};
+use goblin::elf::program_header::{PF_X, PT_LOAD};
+use goblin::elf::section_header::SHF_ALLOC;
 use goblin::elf::Elf;
 use goblin::error::Error;
+#[cfg(feature = "serde")]
+use serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::TryInto;
+use std::fmt;
+use std::ops::Range;
 use std::path::Path;
 
+/// Granularity of sparse memory encodings: only pages holding at least one
+/// non-zero byte are serialized.
+pub const PAGE_SIZE: usize = 4096;
+
 #[derive(Debug)]
 pub enum ProgramError {
     OutOfMemory,
     UnsupportedBinary,
     Goblin(Error),
+    Dwarf(gimli::Error),
+    /// A relocation refers to a symbol no loaded object defines.
+    UndefinedSymbol(String),
+    /// A shared object the program needs could not be found.
+    MissingLibrary(String),
+    /// A relocation of a type the loader does not apply.
+    UnsupportedRelocation(u32),
+}
+
+/// An allocated ELF section, such as `.text` or `.bss`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub entry: u32,
+    pub symbols: SymbolTable,
+    /// Address ranges the loadable segments were placed at.
+    pub segments: Vec<Range<u32>>,
+    /// Address ranges of the segments holding code.
+    pub code: Vec<Range<u32>>,
+    pub sections: Vec<Section>,
 }
 
 #[derive(Clone, Debug)]
@@ -28,19 +66,117 @@ impl Memory {
         self.memory.len() as u32
     }
 
-    pub fn load_program<P: AsRef<Path>>(&mut self, program: P) -> Result<u32, ProgramError> {
+    pub fn contains(&self, address: u32, size: u32) -> bool {
+        address as usize + size as usize <= self.memory.len()
+    }
+
+    /// Pages holding at least one non-zero byte, with their addresses.
+    pub fn pages(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.memory
+            .chunks(PAGE_SIZE)
+            .enumerate()
+            .filter(|(_, page)| page.iter().any(|&byte| byte != 0))
+            .map(|(index, page)| ((index * PAGE_SIZE) as u32, page))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub(crate) fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// Formats the bytes in `range` like `xxd`, clamped to the memory size.
+    pub fn hexdump(&self, range: Range<u32>) -> Hexdump<'_, 'static> {
+        let end = range.end.min(self.size());
+        Hexdump {
+            memory: self,
+            start: range.start.min(end),
+            end,
+            symbols: None,
+        }
+    }
+
+    pub fn load_program<P: AsRef<Path>>(&mut self, program: P) -> Result<Program, ProgramError> {
         let buffer = std::fs::read(program)
             .map_err(Error::IO)
             .map_err(ProgramError::Goblin)?;
-        let binary = Elf::parse(&buffer).map_err(ProgramError::Goblin)?;
+        self.load_program_bytes(&buffer)
+    }
 
-        if binary.header.e_machine != EM_RISCV || binary.header.e_type != ET_EXEC || binary.is_64 {
+    pub fn load_program_bytes(&mut self, buffer: &[u8]) -> Result<Program, ProgramError> {
+        self.load_program_bytes_at(buffer, 0)
+    }
+
+    /// Like [`Memory::load_program_bytes`], but moves the segments, entry
+    /// point, symbols and sections `offset` bytes away from the addresses
+    /// the program was linked at, wrapping around, so an image linked at
+    /// `0x80000000` runs from address 0 with an offset of `0x80000000`.
+    /// Relocatable objects, which are not linked at any address, are placed
+    /// at `offset`; see [`relocation`](crate::relocation).
+    pub fn load_program_bytes_at(
+        &mut self,
+        buffer: &[u8],
+        offset: u32,
+    ) -> Result<Program, ProgramError> {
+        self.load_linked_program(buffer, offset, |_| None)
+    }
+
+    /// Like [`Memory::load_program_bytes_at`], but also loads the shared
+    /// objects a dynamically linked program needs, whose contents `library`
+    /// returns given their names; see [`dynamic`](crate::dynamic).
+    pub fn load_linked_program<F>(
+        &mut self,
+        buffer: &[u8],
+        offset: u32,
+        library: F,
+    ) -> Result<Program, ProgramError>
+    where
+        F: FnMut(&str) -> Option<Vec<u8>>,
+    {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("load_program", size = buffer.len()).entered();
+
+        let binary = Elf::parse(buffer).map_err(ProgramError::Goblin)?;
+
+        if binary.header.e_machine != EM_RISCV || binary.is_64 {
             return Err(ProgramError::UnsupportedBinary);
         }
+        let program = match binary.header.e_type {
+            ET_EXEC | ET_DYN if binary.dynamic.is_some() => {
+                dynamic::load(self, binary, buffer, offset, library)?
+            }
+            ET_EXEC => self.load_image(&binary, buffer, offset)?,
+            ET_REL => relocation::load_object(self, &binary, buffer, offset)?,
+            _ => return Err(ProgramError::UnsupportedBinary),
+        };
 
-        for ph in binary.program_headers {
+        #[cfg(feature = "tracing")]
+        tracing::info!(entry = program.entry, offset, "loaded program");
+
+        Ok(program)
+    }
+
+    /// Copies the loadable segments of `binary` to memory `offset` bytes
+    /// away from their addresses, and moves its entry point, symbols and
+    /// sections with them.
+    pub(crate) fn load_image(
+        &mut self,
+        binary: &Elf,
+        buffer: &[u8],
+        offset: u32,
+    ) -> Result<Program, ProgramError> {
+        let mut segments = Vec::new();
+        let mut code = Vec::new();
+        for ph in &binary.program_headers {
             if ph.p_type == PT_LOAD {
-                let vm_range = ph.vm_range();
+                let start = (ph.p_vaddr as u32).wrapping_add(offset) as usize;
+                let vm_range = start..start + ph.p_memsz as usize;
+                segments.push(vm_range.start as u32..vm_range.end as u32);
+                if ph.p_flags & PF_X != 0 {
+                    code.push(vm_range.start as u32..vm_range.end as u32);
+                }
                 let file_range = ph.file_range();
 
                 if vm_range.end >= self.memory.len() {
@@ -56,10 +192,40 @@ impl Memory {
                 }
 
                 self.memory[ph_range].copy_from_slice(&buffer[file_range]);
+
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    start = vm_range.start,
+                    end = vm_range.end,
+                    file_size = ph_size,
+                    "loaded segment"
+                );
             }
         }
 
-        Ok(binary.entry as u32)
+        let sections = binary
+            .section_headers
+            .iter()
+            .filter(|sh| sh.sh_flags & SHF_ALLOC as u64 != 0 && sh.sh_size != 0)
+            .map(|sh| Section {
+                name: match binary.shdr_strtab.get(sh.sh_name) {
+                    Some(Ok(name)) => name.to_string(),
+                    _ => String::new(),
+                },
+                address: (sh.sh_addr as u32).wrapping_add(offset),
+                size: sh.sh_size as u32,
+            })
+            .collect();
+
+        let mut symbols = SymbolTable::from_elf(binary);
+        symbols.relocate(offset);
+        Ok(Program {
+            entry: (binary.entry as u32).wrapping_add(offset),
+            symbols,
+            segments,
+            code,
+            sections,
+        })
     }
 
     pub fn read_byte(&self, address: u32) -> u8 {
@@ -95,6 +261,110 @@ impl Memory {
     }
 }
 
+/// Wrapper that formats a range of memory 16 bytes per line, with the
+/// address, the bytes in hex and as ASCII, and the symbols that start on
+/// the line when given. Lines are aligned to 16 bytes; bytes outside the
+/// range are left blank.
+#[derive(Copy, Clone)]
+pub struct Hexdump<'a, 's> {
+    memory: &'a Memory,
+    start: u32,
+    end: u32,
+    symbols: Option<&'s SymbolTable>,
+}
+
+impl<'a> Hexdump<'a, '_> {
+    pub fn with_symbols(self, symbols: &SymbolTable) -> Hexdump<'a, '_> {
+        Hexdump {
+            memory: self.memory,
+            start: self.start,
+            end: self.end,
+            symbols: Some(symbols),
+        }
+    }
+}
+
+impl fmt::Display for Hexdump<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = self.start & !15;
+        while line < self.end {
+            let byte = |address: u32| match (self.start..self.end).contains(&address) {
+                true => Some(self.memory.read_byte(address)),
+                false => None,
+            };
+            write!(f, "{:08x} ", line)?;
+            for address in line..line + 16 {
+                let space = if address % 8 == 0 { "  " } else { " " };
+                match byte(address) {
+                    Some(byte) => write!(f, "{}{:02x}", space, byte)?,
+                    None => write!(f, "{}  ", space)?,
+                }
+            }
+            let ascii = (line..line + 16)
+                .map(|address| match byte(address) {
+                    Some(byte) if byte.is_ascii_graphic() || byte == b' ' => byte as char,
+                    Some(_) => '.',
+                    None => ' ',
+                })
+                .collect::<String>();
+            write!(f, "  |{}|", ascii)?;
+            if let Some(symbols) = self.symbols {
+                for sym in symbols.iter() {
+                    if (line..line + 16).contains(&sym.address) {
+                        write!(f, " <{}@{:#x}>", sym.name, sym.address)?;
+                    }
+                }
+            }
+            writeln!(f)?;
+            line = match line.checked_add(16) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Memory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let pages = self.pages().collect::<Vec<_>>();
+
+        let mut state = serializer.serialize_struct("Memory", 2)?;
+        state.serialize_field("size", &self.size())?;
+        state.serialize_field("pages", &pages)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Memory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "Memory")]
+        struct SparseMemory {
+            size: u32,
+            pages: Vec<(u32, Vec<u8>)>,
+        }
+
+        let sparse = SparseMemory::deserialize(deserializer)?;
+        let mut memory = Memory::new(sparse.size);
+
+        for (address, bytes) in sparse.pages {
+            let start = address as usize;
+            let end = start + bytes.len();
+
+            if end > memory.memory.len() {
+                return Err(de::Error::custom("memory page out of bounds"));
+            }
+
+            memory.memory[start..end].copy_from_slice(&bytes);
+        }
+
+        Ok(memory)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -119,6 +389,43 @@ mod tests {
         assert_eq!(mem.read_word(0), 0x00FF0FF0);
     }
 
+    #[test]
+    fn hexdump_with_symbols() {
+        let mut mem = Memory::new(0x40);
+        for (offset, &byte) in b"Hello, yars!\n".iter().enumerate() {
+            mem.write_byte(0x14 + offset as u32, byte);
+        }
+        let mut symbols = SymbolTable::new();
+        symbols.insert(crate::symbol::Symbol {
+            name: "greeting".to_string(),
+            address: 0x14,
+            size: 13,
+        });
+
+        assert_eq!(
+            mem.hexdump(0x14..0x21).with_symbols(&symbols).to_string(),
+            "00000010               48 65 6c 6c  6f 2c 20 79 61 72 73 21  \
+             |    Hello, yars!| <greeting@0x14>\n\
+             00000020   0a                                                |.               |\n"
+        );
+        assert_eq!(mem.hexdump(0x3C..0x100).to_string().lines().count(), 1);
+        assert_eq!(mem.hexdump(0x100..0x200).to_string(), "");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serialize_only_non_zero_pages() {
+        let mut mem = Memory::new(4 * PAGE_SIZE as u32);
+        mem.write_word(PAGE_SIZE as u32 + 8, 0xDEADBEEF);
+
+        let json = serde_json::to_value(&mem).unwrap();
+        assert_eq!(json["pages"].as_array().unwrap().len(), 1);
+
+        let restored: Memory = serde_json::from_value(json).unwrap();
+        assert_eq!(restored.size(), mem.size());
+        assert_eq!(restored.read_word(PAGE_SIZE as u32 + 8), 0xDEADBEEF);
+    }
+
     #[test]
     #[should_panic]
     fn panic_on_read_out_of_bounds() {
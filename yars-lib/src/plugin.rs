@@ -0,0 +1,228 @@
+//! Registry through which crates outside yars add instruction-set
+//! extensions, devices and tracers to a [`Simulator`].
+//!
+//! A [`Plugin`] registers what it provides into a [`Registry`], and the
+//! registry installs everything collected from all plugins with
+//! [`Registry::install`].
+
+use crate::device::{Device, DeviceError};
+use crate::instruction::Instruction;
+use crate::processor::{Processor, ProcessorError};
+use crate::simulator::{HookAction, Simulator};
+use std::fmt;
+use std::io::Write;
+
+/// Executes instruction words the built-in decoder does not know, such as
+/// those in the custom opcode spaces.
+pub trait Extension: ExtensionClone + Send {
+    fn name(&self) -> &str;
+
+    /// Executes `raw` at the current PC if this extension implements it, or
+    /// returns `None`. Unless the extension moves the PC itself, execution
+    /// continues with the next instruction.
+    fn execute(
+        &mut self,
+        processor: &mut Processor,
+        raw: u32,
+    ) -> Option<Result<(), ProcessorError>>;
+}
+
+/// Allows cloning boxed extensions; implemented for every `Clone` one.
+pub trait ExtensionClone {
+    fn clone_box(&self) -> Box<dyn Extension>;
+}
+
+impl<T: Extension + Clone + 'static> ExtensionClone for T {
+    fn clone_box(&self) -> Box<dyn Extension> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Extension> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Observes every instruction after it is executed.
+pub trait Tracer: TracerClone + Send {
+    fn trace(&mut self, processor: &Processor, pc: u32, inst: &Instruction);
+}
+
+/// Allows cloning boxed tracers; implemented for every `Clone` one.
+pub trait TracerClone {
+    fn clone_box(&self) -> Box<dyn Tracer>;
+}
+
+impl<T: Tracer + Clone + 'static> TracerClone for T {
+    fn clone_box(&self) -> Box<dyn Tracer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Tracer> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// A bundle of extensions, devices and tracers provided by one crate.
+pub trait Plugin {
+    fn name(&self) -> &str;
+    fn register(&self, registry: &mut Registry);
+}
+
+/// Extensions, devices and tracers collected from plugins, waiting to be
+/// installed into a simulator.
+#[derive(Default)]
+pub struct Registry {
+    plugins: Vec<String>,
+    extensions: Vec<Box<dyn Extension>>,
+    devices: Vec<(u32, u32, Box<dyn Device>)>,
+    tracers: Vec<Box<dyn Tracer>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets `plugin` register what it provides.
+    pub fn register(&mut self, plugin: &dyn Plugin) -> &mut Self {
+        self.plugins.push(plugin.name().to_string());
+        plugin.register(self);
+        self
+    }
+
+    pub fn add_extension(&mut self, extension: Box<dyn Extension>) {
+        self.extensions.push(extension);
+    }
+
+    /// Maps `device` at `base` when the registry is installed.
+    pub fn add_device(&mut self, base: u32, size: u32, device: Box<dyn Device>) {
+        self.devices.push((base, size, device));
+    }
+
+    pub fn add_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracers.push(tracer);
+    }
+
+    /// Names of the registered plugins, in registration order.
+    pub fn plugins(&self) -> &[String] {
+        &self.plugins
+    }
+
+    /// Adds everything registered to `sim`. Extensions are tried in
+    /// registration order, and tracers run after the post-execution hooks
+    /// already added.
+    pub fn install<W: Write>(self, sim: &mut Simulator<W>) -> Result<(), DeviceError> {
+        for (base, size, device) in self.devices {
+            sim.map_device(base, size, device)?;
+        }
+        for extension in self.extensions {
+            sim.add_extension(extension);
+        }
+        for mut tracer in self.tracers {
+            sim.add_post_hook(move |processor, pc, inst| {
+                tracer.trace(processor, pc, inst);
+                HookAction::Continue
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("plugins", &self.plugins)
+            .field("extensions", &self.extensions.len())
+            .field("devices", &self.devices.len())
+            .field("tracers", &self.tracers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::register::IntRegister;
+    use crate::simulator::tests::simulator;
+    use std::sync::{Arc, Mutex};
+
+    /// Implements `custom-0` word 0x0000000B as `a0 = a0 * 2`.
+    #[derive(Clone)]
+    struct Double;
+
+    impl Extension for Double {
+        fn name(&self) -> &str {
+            "double"
+        }
+
+        fn execute(
+            &mut self,
+            processor: &mut Processor,
+            raw: u32,
+        ) -> Option<Result<(), ProcessorError>> {
+            if raw != 0x0000000B {
+                return None;
+            }
+            let registers = processor.registers_mut();
+            registers.write(IntRegister::A0, registers.read(IntRegister::A0) * 2);
+            Some(Ok(()))
+        }
+    }
+
+    #[derive(Clone)]
+    struct Mnemonics(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Tracer for Mnemonics {
+        fn trace(&mut self, _: &Processor, _: u32, inst: &Instruction) {
+            self.0.lock().unwrap().push(inst.mnemonic());
+        }
+    }
+
+    struct Example(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Plugin for Example {
+        fn name(&self) -> &str {
+            "example"
+        }
+
+        fn register(&self, registry: &mut Registry) {
+            registry.add_extension(Box::new(Double));
+            registry.add_tracer(Box::new(Mnemonics(self.0.clone())));
+        }
+    }
+
+    #[test]
+    fn install_plugin_extension_and_tracer() {
+        // addi a0, zero, 3; custom-0; ecall
+        let code = [0x00300513, 0x0000000B, 0x00000073];
+        let mnemonics = Arc::new(Mutex::new(Vec::new()));
+        let mut sim = simulator("plugin", &code);
+
+        let mut registry = Registry::new();
+        registry.register(&Example(mnemonics.clone()));
+        assert_eq!(registry.plugins(), ["example"]);
+        registry.install(&mut sim).unwrap();
+
+        assert!(sim.step().is_ok());
+        let retired = sim.step().unwrap();
+        assert_eq!(retired.inst, Instruction::CUSTOM { raw: 0x0000000B });
+        assert_eq!(sim.read_reg(IntRegister::A0), 6);
+        assert_eq!(sim.pc(), 0x1008);
+        assert_eq!(sim.cycles(), 2);
+        assert_eq!(*mnemonics.lock().unwrap(), ["addi", "custom"]);
+
+        let mut plain = simulator("plugin-plain", &code);
+        plain.step().unwrap();
+        assert!(matches!(
+            plain.step(),
+            Err(ProcessorError::InvalidOpcode {
+                raw: 0x0000000B,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,500 @@
+//! Host-side decoding of the text guest programs print with the C library,
+//! by following calls to `printf` and `puts` found in the symbol table, or
+//! their newlib `_r` variants. Their arguments are read as the functions
+//! are entered and formatted on the host, so bare-metal programs built
+//! without any I/O mechanism still show their output.
+
+use crate::memory::Memory;
+use crate::register::{IntRegister, IntRegisterSet};
+use crate::symbol::SymbolTable;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::iter::Peekable;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Function {
+    Printf,
+    Puts,
+    Putchar,
+}
+
+const FUNCTIONS: [(&str, Function); 4] = [
+    ("printf", Function::Printf),
+    ("iprintf", Function::Printf),
+    ("puts", Function::Puts),
+    ("putchar", Function::Putchar),
+];
+
+/// Longest string read from the guest.
+const MAX_STRING: u32 = 4096;
+
+#[derive(Clone, Debug, Default)]
+pub struct PrintfDecoder {
+    /// Entry points, with the index of their first argument register past
+    /// the newlib reentrancy pointer.
+    functions: HashMap<u32, (Function, u8)>,
+    /// Return address and stack pointer of the call being decoded, during
+    /// which other calls are left alone as they are part of it.
+    call: Option<(u32, u32)>,
+}
+
+impl PrintfDecoder {
+    /// Follows the functions in `symbols`, or returns `None` if there are
+    /// none.
+    pub fn new(symbols: &SymbolTable) -> Option<Self> {
+        let mut functions = HashMap::new();
+        for &(name, function) in &FUNCTIONS {
+            if let Some(sym) = symbols.get(name) {
+                functions.insert(sym.address, (function, 0));
+            }
+            if let Some(sym) = symbols.get(&format!("_{}_r", name)) {
+                functions.entry(sym.address).or_insert((function, 1));
+            }
+        }
+        match functions.is_empty() {
+            true => None,
+            false => Some(Self {
+                functions,
+                call: None,
+            }),
+        }
+    }
+
+    /// Returns the text printed by the call entered at `pc`, if it is one,
+    /// with `registers` as they were before its first instruction.
+    pub fn check(
+        &mut self,
+        pc: u32,
+        registers: &IntRegisterSet,
+        memory: &Memory,
+    ) -> Option<Vec<u8>> {
+        let sp = registers.read(IntRegister::SP);
+        if let Some((return_address, call_sp)) = self.call {
+            match pc == return_address && sp == call_sp {
+                true => self.call = None,
+                false => return None,
+            }
+        }
+
+        let &(function, skip) = self.functions.get(&pc)?;
+        self.call = Some((registers.read(IntRegister::RA), sp));
+        let mut args = Arguments {
+            registers,
+            memory,
+            sp,
+            next: skip,
+        };
+        let mut text = Vec::new();
+        match function {
+            Function::Printf => {
+                let format = read_string(memory, args.word());
+                format_into(&mut text, &format, &mut args);
+            }
+            Function::Puts => {
+                text = read_string(memory, args.word());
+                text.push(b'\n');
+            }
+            Function::Putchar => text.push(args.word() as u8),
+        }
+        Some(text)
+    }
+}
+
+/// Arguments of a variadic call, following the ilp32 calling convention:
+/// in `a0` to `a7` and then on the stack, with 64-bit values in an aligned
+/// pair of words.
+struct Arguments<'a> {
+    registers: &'a IntRegisterSet,
+    memory: &'a Memory,
+    sp: u32,
+    next: u8,
+}
+
+impl Arguments<'_> {
+    fn word(&mut self) -> u32 {
+        let n = self.next;
+        self.next += 1;
+        match n {
+            0..=7 => {
+                let reg = IntRegister::try_from(IntRegister::A0 as u8 + n).unwrap();
+                self.registers.read(reg)
+            }
+            _ => {
+                let address = self.sp.wrapping_add(4 * (n as u32 - 8));
+                match self.memory.contains(address, 4) {
+                    true => self.memory.read_word(address),
+                    false => 0,
+                }
+            }
+        }
+    }
+
+    fn pair(&mut self) -> u64 {
+        self.next = (self.next + 1) & !1;
+        let low = self.word();
+        (u64::from(self.word()) << 32) | u64::from(low)
+    }
+}
+
+fn read_string(memory: &Memory, address: u32) -> Vec<u8> {
+    if address == 0 {
+        return b"(null)".to_vec();
+    }
+    (address..address.saturating_add(MAX_STRING))
+        .take_while(|&address| memory.contains(address, 1))
+        .map(|address| memory.read_byte(address))
+        .take_while(|&byte| byte != 0)
+        .collect()
+}
+
+/// Conversion flags, width and precision.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pads `body`, preceded by `prefix` (a sign or `0x`), to the width.
+    fn pad(&self, out: &mut Vec<u8>, prefix: &str, body: &[u8], numeric: bool) {
+        let fill = self.width.saturating_sub(prefix.len() + body.len());
+        let zero = self.zero && numeric && !self.left;
+        if !self.left && !zero {
+            out.resize(out.len() + fill, b' ');
+        }
+        out.extend_from_slice(prefix.as_bytes());
+        if zero {
+            out.resize(out.len() + fill, b'0');
+        }
+        out.extend_from_slice(body);
+        if self.left {
+            out.resize(out.len() + fill, b' ');
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        }
+    }
+}
+
+/// Formats `format` as C's `printf` would, taking the values from `args`.
+fn format_into(out: &mut Vec<u8>, format: &[u8], args: &mut Arguments) {
+    let mut bytes = format.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            out.push(byte);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&flag) = bytes.peek() {
+            match flag {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                _ => break,
+            }
+            bytes.next();
+        }
+
+        if let Some(width) = number(&mut bytes, args) {
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+        }
+        if bytes.next_if_eq(&b'.').is_some() {
+            spec.precision = number(&mut bytes, args)
+                .or(Some(0))
+                .filter(|&precision| precision >= 0)
+                .map(|precision| precision as usize);
+        }
+
+        let mut long = 0;
+        while let Some(&length) = bytes.peek() {
+            match length {
+                b'l' => long += 1,
+                b'h' | b'z' | b'j' | b't' => {}
+                _ => break,
+            }
+            bytes.next();
+        }
+
+        let conversion = match bytes.next() {
+            Some(conversion) => conversion,
+            None => break,
+        };
+        let integer = |args: &mut Arguments| match long >= 2 {
+            true => args.pair(),
+            false => u64::from(args.word()),
+        };
+        match conversion {
+            b'd' | b'i' => {
+                let value = match long >= 2 {
+                    true => integer(args) as i64,
+                    false => i64::from(args.word() as i32),
+                };
+                let digits = digits(value.unsigned_abs(), 10, false, spec.precision);
+                spec.pad(out, spec.sign(value < 0), &digits, spec.precision.is_none());
+            }
+            b'u' | b'o' | b'x' | b'X' | b'p' => {
+                let (radix, prefix) = match conversion {
+                    b'u' => (10, ""),
+                    b'o' => (8, ""),
+                    b'X' => (16, "0X"),
+                    _ => (16, "0x"),
+                };
+                let value = match conversion {
+                    b'p' => u64::from(args.word()),
+                    _ => integer(args),
+                };
+                let digits = digits(value, radix, conversion == b'X', spec.precision);
+                let prefix = match (conversion == b'p' || spec.alternate) && value != 0 {
+                    true => prefix,
+                    false => "",
+                };
+                spec.pad(out, prefix, &digits, spec.precision.is_none());
+            }
+            b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                let value = f64::from_bits(args.pair());
+                let body = float(value.abs(), conversion, &spec);
+                let negative = value.is_sign_negative() && !value.is_nan();
+                spec.pad(out, spec.sign(negative), body.as_bytes(), value.is_finite());
+            }
+            b'c' => spec.pad(out, "", &[args.word() as u8], false),
+            b's' => {
+                let mut string = read_string(args.memory, args.word());
+                if let Some(precision) = spec.precision {
+                    string.truncate(precision);
+                }
+                spec.pad(out, "", &string, false);
+            }
+            b'%' => out.push(b'%'),
+            // The count of bytes written is not stored back
+            b'n' => {
+                args.word();
+            }
+            other => out.extend_from_slice(&[b'%', other]),
+        }
+    }
+}
+
+/// Reads a width or precision, either given in `format` or as an argument
+/// for `*`.
+fn number<I: Iterator<Item = u8>>(format: &mut Peekable<I>, args: &mut Arguments) -> Option<i32> {
+    if format.next_if_eq(&b'*').is_some() {
+        return Some(args.word() as i32);
+    }
+    let mut value = None;
+    while let Some(digit) = format.next_if(u8::is_ascii_digit) {
+        value = Some(value.unwrap_or(0) * 10 + (digit - b'0') as i32);
+    }
+    value
+}
+
+/// Digits of `value`, with at least `precision` of them.
+fn digits(value: u64, radix: u32, upper: bool, precision: Option<usize>) -> Vec<u8> {
+    let mut digits = match radix {
+        8 => format!("{:o}", value),
+        16 if upper => format!("{:X}", value),
+        16 => format!("{:x}", value),
+        _ => value.to_string(),
+    };
+    if precision == Some(0) && value == 0 {
+        digits.clear();
+    }
+    let precision = precision.unwrap_or(1);
+    if digits.len() < precision {
+        digits.insert_str(0, &"0".repeat(precision - digits.len()));
+    }
+    digits.into_bytes()
+}
+
+/// Formats the magnitude of a floating-point value.
+fn float(value: f64, conversion: u8, spec: &Spec) -> String {
+    let upper = conversion.is_ascii_uppercase();
+    let text = match () {
+        _ if value.is_nan() => "nan".to_string(),
+        _ if value.is_infinite() => "inf".to_string(),
+        _ => {
+            let precision = spec.precision.unwrap_or(6);
+            match conversion.to_ascii_lowercase() {
+                b'f' => format!("{:.*}", precision, value),
+                b'e' => exponential(value, precision),
+                _ => {
+                    let precision = precision.max(1);
+                    // The exponent after rounding to the precision
+                    let exponent = format!("{:.*e}", precision - 1, value)
+                        .split_once('e')
+                        .and_then(|(_, exponent)| exponent.parse().ok())
+                        .unwrap_or(0);
+                    let text = match exponent < -4 || exponent >= precision as i32 {
+                        true => exponential(value, precision - 1),
+                        false => {
+                            format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+                        }
+                    };
+                    match spec.alternate {
+                        true => text,
+                        false => trim_zeros(&text),
+                    }
+                }
+            }
+        }
+    };
+    match upper {
+        true => text.to_uppercase(),
+        false => text,
+    }
+}
+
+/// Formats `value` as `d.ddde+xx`.
+fn exponential(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let sign = match exponent < 0 {
+        true => '-',
+        false => '+',
+    };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// Removes the trailing zeros of the fraction, and the point if it is left
+/// alone.
+fn trim_zeros(text: &str) -> String {
+    let (mantissa, exponent) = match text.find('e') {
+        Some(at) => text.split_at(at),
+        None => (text, ""),
+    };
+    let mantissa = match mantissa.contains('.') {
+        true => mantissa.trim_end_matches('0').trim_end_matches('.'),
+        false => mantissa,
+    };
+    format!("{}{}", mantissa, exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+
+    /// Formats `format` with `words` as the arguments after it, starting
+    /// in `a1`, and `stack` as the ones passed on the stack at `sp`.
+    fn printf(format: &str, words: &[u32], stack: &[u32]) -> String {
+        let mut memory = Memory::new(0x1000);
+        for (i, byte) in format.bytes().enumerate() {
+            memory.write_byte(0x100 + i as u32, byte);
+        }
+        memory.write_word(0x200, u32::from_le_bytes(*b"hi\0\0"));
+        for (i, &word) in stack.iter().enumerate() {
+            memory.write_word(0x800 + 4 * i as u32, word);
+        }
+
+        let mut registers = IntRegisterSet::new();
+        registers.write(IntRegister::SP, 0x800);
+        registers.write(IntRegister::A0, 0x100);
+        for (i, &word) in words.iter().enumerate() {
+            registers.write(IntRegister::try_from(11 + i as u8).unwrap(), word);
+        }
+        let mut args = Arguments {
+            registers: &registers,
+            memory: &memory,
+            sp: 0x800,
+            next: 1,
+        };
+        let mut out = Vec::new();
+        format_into(&mut out, format.as_bytes(), &mut args);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn format_like_c() {
+        assert_eq!(
+            printf("%d%%|%5d|%-5d|%05d", &[-42i32 as u32, 7, 7, 7], &[]),
+            "-42%|    7|7    |00007"
+        );
+        assert_eq!(
+            printf("%x %#X %o %u %.3d", &[255, 255, 8, u32::MAX, 5], &[]),
+            "ff 0XFF 10 4294967295 005"
+        );
+        assert_eq!(
+            printf(
+                "[%s] [%4.1s] %c %p",
+                &[0x200, 0x200, b'!' as u32, 0x10],
+                &[]
+            ),
+            "[hi] [   h] ! 0x10"
+        );
+        assert_eq!(printf("%*d|%+d", &[4, 3, 3], &[]), "   3|+3");
+
+        // 64-bit values start at an even register, and move on to the stack
+        let pi = 3.25f64.to_bits();
+        let (low, high) = (pi as u32, (pi >> 32) as u32);
+        assert_eq!(
+            printf("%.2f %lld", &[0, low, high, 1, 2], &[]),
+            "3.25 8589934593"
+        );
+        assert_eq!(
+            printf("%d %d %d %d %d %d %e", &[1, 2, 3, 4, 5, 6], &[low, high]),
+            "1 2 3 4 5 6 3.250000e+00"
+        );
+        assert_eq!(
+            printf(
+                "%g %d %d %d %G",
+                &[0, low, high, 1, 2, 3],
+                &[0x88E368F1, 0x3EE4F8B5]
+            ),
+            "3.25 1 2 3 1E-05"
+        );
+    }
+
+    #[test]
+    fn decode_calls_by_symbol() {
+        let mut symbols = SymbolTable::new();
+        for &(name, address) in &[("puts", 0x100), ("_printf_r", 0x200)] {
+            symbols.insert(Symbol {
+                name: name.to_string(),
+                address,
+                size: 0x100,
+            });
+        }
+        assert!(PrintfDecoder::new(&SymbolTable::new()).is_none());
+        let mut decoder = PrintfDecoder::new(&symbols).unwrap();
+
+        let mut memory = Memory::new(0x1000);
+        for (i, &byte) in b"%s!\0ok\0".iter().enumerate() {
+            memory.write_byte(0x800 + i as u32, byte);
+        }
+        let mut registers = IntRegisterSet::new();
+        registers.write(IntRegister::SP, 0xF00);
+        registers.write(IntRegister::RA, 0x40);
+        registers.write(IntRegister::A0, 0x804);
+        assert_eq!(
+            decoder.check(0x100, &registers, &memory),
+            Some(b"ok\n".to_vec())
+        );
+
+        // Calls made until puts returns are part of it
+        assert_eq!(decoder.check(0x200, &registers, &memory), None);
+        assert_eq!(decoder.check(0x40, &registers, &memory), None);
+
+        // _printf_r takes the reentrancy pointer first
+        registers.write(IntRegister::A1, 0x800);
+        registers.write(IntRegister::A2, 0x804);
+        assert_eq!(
+            decoder.check(0x200, &registers, &memory),
+            Some(b"ok!".to_vec())
+        );
+    }
+}
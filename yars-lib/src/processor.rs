@@ -1,38 +1,341 @@
+use crate::device::{Counters, Device, DeviceError, DeviceEvent, DeviceEventKind, DeviceMap};
+use crate::executor::{self, Condition, Domain, Op};
 use crate::instruction::Instruction;
+use crate::interrupt::{self, InterruptController};
+use crate::isa::{Isa, IsaError};
 use crate::memory::Memory;
 use crate::register::{IntRegister, IntRegisterSet};
+use crate::reset::ResetConfig;
+use crate::symbol::SymbolTable;
+use crate::xlen::Xlen;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ProcessorError {
+    Abort,
     Ebreak,
     Ecall,
-    IllegalAccess,
-    IllegalFetch,
-    InvalidOpcode,
-    MisalignedFetch,
+    /// Access to `address` outside of memory and devices by the
+    /// instruction at `pc`.
+    IllegalAccess {
+        pc: u32,
+        address: u32,
+    },
+    /// Fetch from outside of memory.
+    IllegalFetch {
+        pc: u32,
+    },
+    /// `raw` is not a valid instruction, or not one of the current ISA.
+    InvalidOpcode {
+        pc: u32,
+        raw: u32,
+    },
+    MisalignedFetch {
+        pc: u32,
+    },
+    /// Load from an address that is not a multiple of its size, with
+    /// strict alignment enabled.
+    MisalignedLoad {
+        pc: u32,
+        address: u32,
+    },
+    /// Store to an address that is not a multiple of its size, with strict
+    /// alignment enabled.
+    MisalignedStore {
+        pc: u32,
+        address: u32,
+    },
+    /// Store to `address` in code, with [`SmcPolicy::Trap`].
+    CodeWrite {
+        pc: u32,
+        address: u32,
+    },
 }
 
-#[derive(Debug)]
-pub struct Processor {
-    pc: u32,
+impl ProcessorError {
+    /// Address of the instruction that caused the error, if known.
+    pub fn pc(&self) -> Option<u32> {
+        use ProcessorError::*;
+        match *self {
+            Abort | Ebreak | Ecall => None,
+            IllegalAccess { pc, .. }
+            | IllegalFetch { pc }
+            | InvalidOpcode { pc, .. }
+            | MisalignedFetch { pc }
+            | MisalignedLoad { pc, .. }
+            | MisalignedStore { pc, .. }
+            | CodeWrite { pc, .. } => Some(pc),
+        }
+    }
+
+    /// Describes the error followed by the function it happened in, e.g.
+    /// `invalid opcode 0x0000a00b at pc 0x000111f4 in <frobnicate+0x24>`.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        match self.pc().and_then(|pc| symbols.lookup(pc)) {
+            Some(sym) => format!("{} in <{}>", self, sym),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ProcessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ProcessorError::*;
+        match self {
+            Abort => write!(f, "execution aborted"),
+            Ebreak => write!(f, "breakpoint"),
+            Ecall => write!(f, "environment call"),
+            IllegalAccess { pc, address } => {
+                write!(f, "illegal access to {:#010x} at pc {:#010x}", address, pc)
+            }
+            IllegalFetch { pc } => write!(f, "illegal instruction fetch at pc {:#010x}", pc),
+            InvalidOpcode { pc, raw } => {
+                write!(f, "invalid opcode {:#010x} at pc {:#010x}", raw, pc)
+            }
+            MisalignedFetch { pc } => write!(f, "misaligned instruction fetch at pc {:#010x}", pc),
+            MisalignedLoad { pc, address } => {
+                write!(
+                    f,
+                    "misaligned load from {:#010x} at pc {:#010x}",
+                    address, pc
+                )
+            }
+            MisalignedStore { pc, address } => {
+                write!(
+                    f,
+                    "misaligned store to {:#010x} at pc {:#010x}",
+                    address, pc
+                )
+            }
+            CodeWrite { pc, address } => {
+                write!(f, "store to code at {:#010x} at pc {:#010x}", address, pc)
+            }
+        }
+    }
+}
+
+/// What a store into code does. Instructions are decoded from memory every
+/// time they are fetched, so code a program modifies always runs as
+/// written, without any cache to invalidate.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SmcPolicy {
+    /// Stores into code are performed, as self-modifying programs and JIT
+    /// compilers expect.
+    #[default]
+    Allow,
+    /// Stores into code fail with [`ProcessorError::CodeWrite`], to catch
+    /// stray pointers overwriting the program.
+    Trap,
+}
+
+impl fmt::Display for SmcPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmcPolicy::Allow => write!(f, "allow"),
+            SmcPolicy::Trap => write!(f, "trap"),
+        }
+    }
+}
+
+impl FromStr for SmcPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "allow" => Ok(SmcPolicy::Allow),
+            "trap" => Ok(SmcPolicy::Trap),
+            _ => Err("expected allow or trap".to_string()),
+        }
+    }
+}
+
+/// Byte order of the loads and stores to memory. Instructions are always
+/// fetched little-endian, and device registers are accessed as whole
+/// values, as the specification leaves them to the platform.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Converts a value of `size` bytes between this byte order and the
+    /// little-endian order [`Memory`] reads and writes in, either way.
+    pub fn swap(self, value: u32, size: u32) -> u32 {
+        match (self, size) {
+            (Endianness::Big, 2) => u32::from((value as u16).swap_bytes()),
+            (Endianness::Big, 4) => value.swap_bytes(),
+            _ => value,
+        }
+    }
+}
+
+impl fmt::Display for Endianness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endianness::Little => write!(f, "little"),
+            Endianness::Big => write!(f, "big"),
+        }
+    }
+}
+
+impl FromStr for Endianness {
+    type Err = String;
+
+    fn from_str(order: &str) -> Result<Self, Self::Err> {
+        match order {
+            "little" => Ok(Endianness::Little),
+            "big" => Ok(Endianness::Big),
+            _ => Err("expected little or big".to_string()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RetiredInstruction {
+    pub pc: u32,
+    pub raw: u32,
+    pub inst: Instruction,
+}
+
+/// A register written by an instruction, with its value before and after.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RegisterWrite<X: Xlen = u32> {
+    pub reg: IntRegister,
+    pub old: X,
+    pub new: X,
+}
+
+/// An operation with a result defined by the specification that is still
+/// likely to be a mistake, reported when division checking is enabled.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Diagnostic {
+    /// Division or remainder by zero, which gives all ones or the dividend.
+    DivideByZero { pc: u32, inst: Instruction },
+    /// Signed division of the most negative value by -1, which overflows
+    /// and gives the dividend, or zero for the remainder.
+    DivideOverflow { pc: u32, inst: Instruction },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::DivideByZero { pc, inst } => {
+                write!(
+                    f,
+                    "division by zero at {:#010x} ({})",
+                    pc,
+                    inst.display_at(*pc)
+                )
+            }
+            Diagnostic::DivideOverflow { pc, inst } => {
+                write!(
+                    f,
+                    "division overflow at {:#010x} ({})",
+                    pc,
+                    inst.display_at(*pc)
+                )
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Processor<X: Xlen = u32> {
+    pc: X,
     cycles: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    instret: usize,
     memory: Memory,
-    registers: IntRegisterSet,
+    registers: IntRegisterSet<X>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    devices: DeviceMap,
+    interrupts: InterruptController,
+    interrupt_base: Option<u32>,
+    isa: Isa,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_write: Option<RegisterWrite<X>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    check_division: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    strict_alignment: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    smc_policy: SmcPolicy,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    endianness: Endianness,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    code: Vec<Range<u32>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    diagnostics: Vec<Diagnostic>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    log_device_events: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    device_events: Vec<DeviceEvent>,
 }
 
-impl Processor {
+impl<X: Xlen> Processor<X> {
     pub fn new(memory: Memory) -> Self {
-        let pc = 0;
+        let pc = X::default();
         let cycles = 0;
-        let mut registers = IntRegisterSet::new();
-        registers.write(IntRegister::SP, memory.size() - 4);
+        let registers = IntRegisterSet::new();
+
+        let mut processor = Self {
+            pc,
+            cycles,
+            instret: 0,
+            memory,
+            registers,
+            devices: DeviceMap::new(),
+            interrupts: InterruptController::new(),
+            interrupt_base: None,
+            isa: Isa::default(),
+            last_write: None,
+            check_division: false,
+            strict_alignment: false,
+            smc_policy: SmcPolicy::default(),
+            endianness: Endianness::default(),
+            code: Vec::new(),
+            diagnostics: Vec::new(),
+            log_device_events: false,
+            device_events: Vec::new(),
+        };
+        ResetConfig::default().apply(&mut processor, &SymbolTable::new());
+        processor
+    }
 
+    pub(crate) fn restore(
+        pc: X,
+        cycles: usize,
+        instret: usize,
+        memory: Memory,
+        registers: IntRegisterSet<X>,
+    ) -> Self {
         Self {
             pc,
             cycles,
+            instret,
             memory,
             registers,
+            devices: DeviceMap::new(),
+            interrupts: InterruptController::new(),
+            interrupt_base: None,
+            isa: Isa::default(),
+            last_write: None,
+            check_division: false,
+            strict_alignment: false,
+            smc_policy: SmcPolicy::default(),
+            endianness: Endianness::default(),
+            code: Vec::new(),
+            diagnostics: Vec::new(),
+            log_device_events: false,
+            device_events: Vec::new(),
         }
     }
 
@@ -40,15 +343,40 @@ impl Processor {
         self.cycles
     }
 
+    /// Instructions retired so far.
+    pub fn instret(&self) -> usize {
+        self.instret
+    }
+
+    /// Accounts for cycles spent outside of [`Processor::execute`].
+    pub fn add_cycles(&mut self, cycles: usize) {
+        self.cycles += cycles;
+    }
+
+    /// Counts an instruction executed outside of [`Processor::execute`],
+    /// such as one of an extension, as retired in one cycle.
+    pub fn retire(&mut self) {
+        self.instret += 1;
+        self.cycles += 1;
+    }
+
     pub fn reset_cycles(&mut self) {
         self.cycles = 0;
+        self.instret = 0;
+    }
+
+    fn counters(&self) -> Counters {
+        Counters {
+            instret: self.instret as u64,
+            cycles: self.cycles as u64,
+        }
     }
 
-    pub fn pc(&self) -> u32 {
+    pub fn pc(&self) -> X {
         self.pc
     }
 
-    pub fn set_pc(&mut self, pc: u32) {
+    pub fn set_pc(&mut self, pc: X) {
         self.pc = pc;
     }
 
@@ -56,451 +384,722 @@ impl Processor {
         &self.memory
     }
 
-    pub fn registers(&self) -> &IntRegisterSet {
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn registers(&self) -> &IntRegisterSet<X> {
         &self.registers
     }
 
+    pub fn registers_mut(&mut self) -> &mut IntRegisterSet<X> {
+        &mut self.registers
+    }
+
+    /// The register written by the last executed instruction, if any.
+    /// Writes to `zero` are not reported.
+    pub fn last_write(&self) -> Option<RegisterWrite<X>> {
+        self.last_write
+    }
+
+    pub fn devices(&self) -> &DeviceMap {
+        &self.devices
+    }
+
+    pub fn map_device(
+        &mut self,
+        base: u32,
+        size: u32,
+        device: Box<dyn Device>,
+    ) -> Result<(), DeviceError> {
+        if let Some(irq) = self.interrupt_base {
+            if overlaps(base, size, irq, interrupt::SIZE) {
+                return Err(DeviceError::Overlapping);
+            }
+        }
+        self.devices.map(base, size, device)
+    }
+
+    pub fn isa(&self) -> &Isa {
+        &self.isa
+    }
+
+    /// Restricts execution to the instructions of `isa`. Fails if the ISA
+    /// includes extensions that are not implemented.
+    pub fn set_isa(&mut self, isa: Isa) -> Result<(), IsaError> {
+        isa.validate(X::BITS)?;
+        self.isa = isa;
+        Ok(())
+    }
+
+    /// Reports divisions by zero and signed division overflows as
+    /// [`Diagnostic`]s. They still give the results the specification
+    /// mandates.
+    pub fn set_check_division(&mut self, check: bool) {
+        self.check_division = check;
+    }
+
+    /// Makes misaligned loads and stores fail with
+    /// [`ProcessorError::MisalignedLoad`] and
+    /// [`ProcessorError::MisalignedStore`], as on cores that do not support
+    /// them, instead of performing the access.
+    pub fn set_strict_alignment(&mut self, strict: bool) {
+        self.strict_alignment = strict;
+    }
+
+    /// Sets what stores into the address ranges given to
+    /// [`Processor::set_code`] do.
+    pub fn set_smc_policy(&mut self, policy: SmcPolicy) {
+        self.smc_policy = policy;
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Sets the byte order of loads and stores, as the `MBE`, `SBE` and
+    /// `UBE` bits of `mstatus` would, for all privilege levels at once.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// Sets the address ranges holding code, usually the executable
+    /// segments of the program.
+    pub fn set_code(&mut self, code: Vec<Range<u32>>) {
+        self.code = code;
+    }
+
+    /// Returns the diagnostics reported since the last call.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Records every access to a device or the interrupt controller as a
+    /// [`DeviceEvent`], as well as the interrupt lines the host raises or
+    /// clears through the [`Simulator`].
+    ///
+    /// [`Simulator`]: crate::simulator::Simulator
+    pub fn set_log_device_events(&mut self, log: bool) {
+        self.log_device_events = log;
+    }
+
+    /// Returns the device events recorded since the last call.
+    pub fn take_device_events(&mut self) -> Vec<DeviceEvent> {
+        std::mem::take(&mut self.device_events)
+    }
+
+    pub(crate) fn log_device_event(&mut self, kind: DeviceEventKind) {
+        if self.log_device_events {
+            self.device_events.push(DeviceEvent {
+                counters: self.counters(),
+                pc: self.pc.as_u32(),
+                kind,
+            });
+        }
+    }
+
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    pub fn interrupts_mut(&mut self) -> &mut InterruptController {
+        &mut self.interrupts
+    }
+
+    /// Makes the interrupt controller registers visible to the guest at
+    /// `base`. See [`crate::interrupt`] for the register layout.
+    pub fn map_interrupt_controller(&mut self, base: u32) -> Result<(), DeviceError> {
+        if base.checked_add(interrupt::SIZE).is_none() {
+            return Err(DeviceError::InvalidRange);
+        }
+
+        let mut ranges = self.devices.ranges();
+        if ranges.any(|(start, size)| overlaps(base, interrupt::SIZE, start, size)) {
+            return Err(DeviceError::Overlapping);
+        }
+
+        self.interrupt_base = Some(base);
+        Ok(())
+    }
+
+    /// Base address of the interrupt controller registers, if mapped.
+    pub fn interrupt_base(&self) -> Option<u32> {
+        self.interrupt_base
+    }
+
+    fn interrupt_offset(&self, addr: u32, size: u32) -> Option<u32> {
+        let base = self.interrupt_base?;
+        match addr.checked_sub(base) {
+            Some(offset) if offset as u64 + size as u64 <= interrupt::SIZE as u64 => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// Translates an effective address to a physical one. Memory and devices
+    /// live in a 32-bit physical address space regardless of XLEN.
+    fn translate(&self, addr: X) -> Result<u32, ProcessorError> {
+        u32::try_from(addr.to_u64()).map_err(|_| self.illegal_access(addr.as_u32()))
+    }
+
+    fn illegal_access(&self, address: u32) -> ProcessorError {
+        let pc = self.pc.as_u32();
+        ProcessorError::IllegalAccess { pc, address }
+    }
+
+    fn load(&mut self, addr: X, size: u32) -> Result<u32, ProcessorError> {
+        let addr = self.translate(addr)?;
+        if self.strict_alignment && addr % size != 0 {
+            let pc = self.pc.as_u32();
+            return Err(ProcessorError::MisalignedLoad { pc, address: addr });
+        }
+
+        if let Some(offset) = self.interrupt_offset(addr, size) {
+            let value = self.interrupts.read(offset, size);
+            self.log_device_event(DeviceEventKind::Read {
+                device: self.interrupts.name().to_string(),
+                offset,
+                size,
+                value,
+            });
+            return Ok(value);
+        }
+
+        let counters = self.counters();
+        if let Some((device, offset)) = self.devices.find(addr, size) {
+            device.sync(counters);
+            let value = device.read(offset, size);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(address = addr, size, value, "device read");
+            let device = match self.log_device_events {
+                true => device.name().to_string(),
+                false => return Ok(value),
+            };
+            self.log_device_event(DeviceEventKind::Read {
+                device,
+                offset,
+                size,
+                value,
+            });
+            return Ok(value);
+        }
+
+        if !self.memory.contains(addr, size) {
+            return Err(self.illegal_access(addr));
+        }
+
+        let value = match size {
+            1 => self.memory.read_byte(addr) as u32,
+            2 => self.memory.read_halfword(addr) as u32,
+            _ => self.memory.read_word(addr),
+        };
+        Ok(self.endianness.swap(value, size))
+    }
+
+    fn store(&mut self, addr: X, size: u32, val: X) -> Result<(), ProcessorError> {
+        let addr = self.translate(addr)?;
+        if self.strict_alignment && addr % size != 0 {
+            let pc = self.pc.as_u32();
+            return Err(ProcessorError::MisalignedStore { pc, address: addr });
+        }
+
+        let val = val.as_u32();
+        if let Some(offset) = self.interrupt_offset(addr, size) {
+            self.interrupts.write(offset, size, val);
+            self.log_device_event(DeviceEventKind::Write {
+                device: self.interrupts.name().to_string(),
+                offset,
+                size,
+                value: val,
+                note: None,
+            });
+            return Ok(());
+        }
+
+        let counters = self.counters();
+        if let Some((device, offset)) = self.devices.find(addr, size) {
+            device.sync(counters);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(address = addr, size, value = val, "device write");
+            device.write(offset, size, val);
+            let (device, note) = match self.log_device_events {
+                true => (
+                    device.name().to_string(),
+                    device.describe_write(offset, val),
+                ),
+                false => return Ok(()),
+            };
+            self.log_device_event(DeviceEventKind::Write {
+                device,
+                offset,
+                size,
+                value: val,
+                note,
+            });
+            return Ok(());
+        }
+
+        if !self.memory.contains(addr, size) {
+            return Err(self.illegal_access(addr));
+        }
+
+        if self.smc_policy == SmcPolicy::Trap
+            && self
+                .code
+                .iter()
+                .any(|code| overlaps(addr, size, code.start, code.end - code.start))
+        {
+            let pc = self.pc.as_u32();
+            return Err(ProcessorError::CodeWrite { pc, address: addr });
+        }
+
+        let val = self.endianness.swap(val, size);
+        match size {
+            1 => self.memory.write_byte(addr, val as u8),
+            2 => self.memory.write_halfword(addr, val as u16),
+            _ => self.memory.write_word(addr, val),
+        }
+
+        Ok(())
+    }
+
     pub fn fetch(&self) -> Result<Instruction, ProcessorError> {
-        if self.pc >= self.memory.size() {
-            return Err(ProcessorError::IllegalFetch);
+        let pc = self.pc.as_u32();
+        if self.pc.to_u64() >= self.memory.size() as u64 {
+            return Err(ProcessorError::IllegalFetch { pc });
         }
 
-        if self.pc & 0b11 != 0b00 {
-            return Err(ProcessorError::MisalignedFetch);
+        if pc & 0b11 != 0b00 {
+            return Err(ProcessorError::MisalignedFetch { pc });
         }
 
-        let opcode = self.memory.read_word(self.pc);
-        Instruction::try_from(opcode).map_err(|_| ProcessorError::InvalidOpcode)
+        let raw = self.memory.read_word(pc);
+        Instruction::try_from(raw).map_err(|_| ProcessorError::InvalidOpcode { pc, raw })
     }
 
+    /// Fetches and executes the instruction at the current PC.
+    pub fn step(&mut self) -> Result<Instruction, ProcessorError> {
+        let inst = self.fetch()?;
+        self.execute(inst)?;
+        Ok(inst)
+    }
+
+    /// Executes `inst` as if it was fetched from the current PC and moves the
+    /// PC to the next instruction. On error the PC is left untouched.
     pub fn execute(&mut self, inst: Instruction) -> Result<(), ProcessorError> {
+        if !self.isa.has(inst.extension()) {
+            let (pc, raw) = (self.pc.as_u32(), u32::from(inst));
+            return Err(ProcessorError::InvalidOpcode { pc, raw });
+        }
+
+        let rd = destination(&inst);
+        let old = rd.map(|rd| self.registers.read(rd));
+
+        #[cfg(feature = "tracing")]
+        let pc = self.pc;
+        let mut next = self.pc.wrapping_add(X::from_u32(4));
+        let result = self.execute_at(inst, &mut next);
+        if result.is_ok() {
+            self.pc = next;
+        }
+
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(()) => tracing::trace!(pc = pc.to_u64(), %inst, "retired"),
+            Err(err) => tracing::debug!(%err, "trap"),
+        }
+
+        self.last_write = match (result, rd, old) {
+            (Ok(()), Some(reg), Some(old)) => Some(RegisterWrite {
+                reg,
+                old,
+                new: self.registers.read(reg),
+            }),
+            _ => None,
+        };
+        result
+    }
+
+    fn execute_at(&mut self, inst: Instruction, next: &mut X) -> Result<(), ProcessorError> {
         use Instruction::*;
-        match inst {
-            LUI { rd, imm } => {
-                self.registers.write(rd, (imm as u32) << 12);
-                self.cycles += 1;
-                Ok(())
-            }
-            LB { rd, rs1, imm } => {
-                let addr = self.registers.read(rs1).wrapping_add(imm as i32 as u32);
+        if let DIV { rs1, rs2, .. } | REM { rs1, rs2, .. } = inst {
+            self.check_division(inst, rs1, rs2, true);
+        } else if let DIVU { rs1, rs2, .. } | REMU { rs1, rs2, .. } = inst {
+            self.check_division(inst, rs1, rs2, false);
+        }
 
-                if addr >= self.memory.size() {
-                    return Err(ProcessorError::IllegalAccess);
-                }
+        let result = executor::execute(self, inst);
+        if let Ok(_) | Err(ProcessorError::Ecall) | Err(ProcessorError::Ebreak) = result {
+            self.instret += 1;
+            self.cycles += 1;
+        }
+        if let Some(target) = result? {
+            *next = target;
+        }
+        Ok(())
+    }
 
-                let val = self.memory.read_byte(addr) as i32 as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            LH { rd, rs1, imm } => {
-                let addr = self.registers.read(rs1).wrapping_add(imm as i32 as u32);
+    fn check_division(
+        &mut self,
+        inst: Instruction,
+        rs1: IntRegister,
+        rs2: IntRegister,
+        signed: bool,
+    ) {
+        if !self.check_division {
+            return;
+        }
 
-                if addr >= self.memory.size() {
-                    return Err(ProcessorError::IllegalAccess);
-                }
+        let (dividend, divisor) = (self.registers.read(rs1), self.registers.read(rs2));
 
-                let val = self.memory.read_halfword(addr) as i32 as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            LW { rd, rs1, imm } => {
-                let addr = self.registers.read(rs1).wrapping_add(imm as i32 as u32);
+        let pc = self.pc.as_u32();
+        let min = X::from_u32(1).sll(X::BITS - 1);
+        if divisor == X::default() {
+            self.diagnostics.push(Diagnostic::DivideByZero { pc, inst });
+        } else if signed && dividend == min && divisor == X::from_i32(-1) {
+            self.diagnostics
+                .push(Diagnostic::DivideOverflow { pc, inst });
+        }
+    }
+}
 
-                if addr >= self.memory.size() {
-                    return Err(ProcessorError::IllegalAccess);
-                }
+impl<X: Xlen> Domain for Processor<X> {
+    type Value = X;
 
-                let val = self.memory.read_word(addr);
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            LBU { rd, rs1, imm } => {
-                let addr = self.registers.read(rs1).wrapping_add(imm as i32 as u32);
+    fn pc(&self) -> X {
+        self.pc
+    }
 
-                if addr >= self.memory.size() {
-                    return Err(ProcessorError::IllegalAccess);
-                }
+    fn constant(&mut self, value: i32) -> X {
+        X::from_i32(value)
+    }
 
-                let val = self.memory.read_byte(addr) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            LHU { rd, rs1, imm } => {
-                let addr = self.registers.read(rs1).wrapping_add(imm as i32 as u32);
+    fn read(&mut self, reg: IntRegister) -> X {
+        self.registers.read(reg)
+    }
 
-                if addr >= self.memory.size() {
-                    return Err(ProcessorError::IllegalAccess);
-                }
+    fn write(&mut self, reg: IntRegister, value: X) {
+        self.registers.write(reg, value);
+    }
 
-                let val = self.memory.read_halfword(addr) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SB { rs1, rs2, imm } => {
-                let addr = self.registers.read(rs1).wrapping_add(imm as i32 as u32);
+    fn compute(&mut self, op: Op, lhs: X, rhs: X) -> X {
+        let shamt = rhs.as_u32() & (X::BITS - 1);
+        match op {
+            Op::Add => lhs.wrapping_add(rhs),
+            Op::Sub => lhs.wrapping_sub(rhs),
+            Op::Sll => lhs.sll(shamt),
+            Op::Srl => lhs.srl(shamt),
+            Op::Sra => lhs.sra(shamt),
+            Op::Slt => X::from_u32(lhs.slt(rhs) as u32),
+            Op::Sltu => X::from_u32((lhs < rhs) as u32),
+            Op::Xor => lhs ^ rhs,
+            Op::Or => lhs | rhs,
+            Op::And => lhs & rhs,
+            Op::Mul => lhs.mul(rhs),
+            Op::Mulh => lhs.mulh(rhs),
+            Op::Mulhsu => lhs.mulhsu(rhs),
+            Op::Mulhu => lhs.mulhu(rhs),
+            Op::Div => lhs.div(rhs),
+            Op::Divu => lhs.divu(rhs),
+            Op::Rem => lhs.rem(rhs),
+            Op::Remu => lhs.remu(rhs),
+        }
+    }
 
-                if addr >= self.memory.size() {
-                    return Err(ProcessorError::IllegalAccess);
-                }
+    fn test(&mut self, condition: Condition, lhs: X, rhs: X) -> bool {
+        match condition {
+            Condition::Eq => lhs == rhs,
+            Condition::Ne => lhs != rhs,
+            Condition::Lt => lhs.slt(rhs),
+            Condition::Ge => !lhs.slt(rhs),
+            Condition::Ltu => lhs < rhs,
+            Condition::Geu => lhs >= rhs,
+        }
+    }
 
-                let val = self.registers.read(rs2) as u8;
-                self.memory.write_byte(addr, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SH { rs1, rs2, imm } => {
-                let addr = self.registers.read(rs1).wrapping_add(imm as i32 as u32);
+    fn load(&mut self, address: X, size: u32, signed: bool) -> Result<X, ProcessorError> {
+        let val = Processor::load(self, address, size)?;
+        match (signed, size) {
+            (true, 1) => Ok(X::from_i32(val as u8 as i8 as i32)),
+            (true, 2) => Ok(X::from_i32(val as u16 as i16 as i32)),
+            (true, _) => Ok(X::from_i32(val as i32)),
+            (false, _) => Ok(X::from_u32(val)),
+        }
+    }
 
-                if addr >= self.memory.size() {
-                    return Err(ProcessorError::IllegalAccess);
-                }
+    fn store(&mut self, address: X, size: u32, value: X) -> Result<(), ProcessorError> {
+        Processor::store(self, address, size, value)
+    }
+}
 
-                let val = self.registers.read(rs2) as u16;
-                self.memory.write_halfword(addr, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SW { rs1, rs2, imm } => {
-                let addr = self.registers.read(rs1).wrapping_add(imm as i32 as u32);
+/// The register `inst` writes its result to, other than `zero`.
+fn destination(inst: &Instruction) -> Option<IntRegister> {
+    inst.rd().filter(|&rd| rd != IntRegister::Zero)
+}
 
-                if addr >= self.memory.size() {
-                    return Err(ProcessorError::IllegalAccess);
-                }
+fn overlaps(a: u32, a_size: u32, b: u32, b_size: u32) -> bool {
+    (a as u64) < b as u64 + b_size as u64 && (b as u64) < a as u64 + a_size as u64
+}
 
-                let val = self.registers.read(rs2);
-                self.memory.write_word(addr, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SLLI { rd, rs1, shamt } => {
-                let v1 = self.registers.read(rs1);
-                let val = v1 << shamt;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SRLI { rd, rs1, shamt } => {
-                let v1 = self.registers.read(rs1);
-                let val = v1 >> shamt;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SRAI { rd, rs1, shamt } => {
-                let v1 = self.registers.read(rs1);
-                let val = (v1 >> shamt) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SLL { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2) & 0b11111;
-                let val = v1 << v2;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SRL { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2) & 0b11111;
-                let val = v1 >> v2;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SRA { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) & 0b11111;
-                let val = (v1 >> v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            ADDI { rd, rs1, imm } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = imm as i32;
-                let val = v1.wrapping_add(v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            ADD { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) as i32;
-                let val = v1.wrapping_add(v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SUB { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) as i32;
-                let val = v1.wrapping_sub(v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            ORI { rd, rs1, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = imm as i32 as u32;
-                let val = v1 | v2;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            XORI { rd, rs1, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = imm as i32 as u32;
-                let val = v1 ^ v2;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            ANDI { rd, rs1, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = imm as i32 as u32;
-                let val = v1 & v2;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            OR { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let val = v1 | v2;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            XOR { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let val = v1 ^ v2;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            AND { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let val = v1 & v2;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SLTI { rd, rs1, imm } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = imm as i32;
-                let val = (v1 < v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SLTIU { rd, rs1, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = imm as i32 as u32;
-                let val = (v1 < v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SLT { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) as i32;
-                let val = (v1 < v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            SLTU { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let val = (v1 < v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            BEQ { rs1, rs2, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let v3 = imm as i32 as u32;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                if v1 == v2 {
-                    self.pc = self.pc.wrapping_add(v3);
-                }
+    #[test]
+    fn advance_pc_and_jump_to_self() {
+        // addi a0, a0, 1; beq zero, zero, 0
+        let mut memory = Memory::new(0x100);
+        memory.write_word(0x0, 0x00150513);
+        memory.write_word(0x4, 0x00000063);
+        let mut processor: Processor = Processor::new(memory);
 
-                self.cycles += 1;
-                Ok(())
-            }
-            BNE { rs1, rs2, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let v3 = imm as i32 as u32;
+        assert!(processor.step().is_ok());
+        assert_eq!(processor.pc(), 0x4);
+        let write = RegisterWrite {
+            reg: IntRegister::A0,
+            old: 0,
+            new: 1,
+        };
+        assert_eq!(processor.last_write(), Some(write));
+        assert!(processor.step().is_ok());
+        assert_eq!(processor.last_write(), None);
+        assert!(processor.step().is_ok());
+        assert_eq!(processor.pc(), 0x4);
+        assert_eq!(processor.cycles(), 3);
+    }
 
-                if v1 != v2 {
-                    self.pc = self.pc.wrapping_add(v3);
-                }
+    #[test]
+    fn reject_instructions_outside_isa() {
+        // mul a0, a0, a0
+        let mut memory = Memory::new(0x100);
+        memory.write_word(0x0, 0x02A50533);
+        let mut processor: Processor = Processor::new(memory);
+        processor.set_isa("rv32i".parse().unwrap()).unwrap();
+        let err = ProcessorError::InvalidOpcode {
+            pc: 0x0,
+            raw: 0x02A50533,
+        };
+        assert_eq!(processor.step(), Err(err));
+        assert_eq!(
+            err.to_string(),
+            "invalid opcode 0x02a50533 at pc 0x00000000"
+        );
 
-                self.cycles += 1;
-                Ok(())
-            }
-            BLT { rs1, rs2, imm } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) as i32;
-                let v3 = imm as i32 as u32;
+        let mut symbols = SymbolTable::new();
+        symbols.insert(crate::symbol::Symbol {
+            name: "square".to_string(),
+            address: 0x0,
+            size: 0x8,
+        });
+        assert_eq!(
+            err.describe(&symbols),
+            "invalid opcode 0x02a50533 at pc 0x00000000 in <square>"
+        );
+    }
 
-                if v1 < v2 {
-                    self.pc = self.pc.wrapping_add(v3);
-                }
+    #[test]
+    fn report_checked_divisions() {
+        // div a0, a1, a2; rem a0, a1, a2; div a0, a1, a2
+        let mut memory = Memory::new(0x100);
+        memory.write_word(0x0, 0x02C5C533);
+        memory.write_word(0x4, 0x02C5E533);
+        memory.write_word(0x8, 0x02C5C533);
+        let mut processor: Processor = Processor::new(memory);
+        processor.set_check_division(true);
 
-                self.cycles += 1;
-                Ok(())
-            }
-            BGE { rs1, rs2, imm } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) as i32;
-                let v3 = imm as i32 as u32;
+        processor.registers_mut().write(IntRegister::A1, 7);
+        processor.step().unwrap();
+        assert_eq!(processor.registers().read(IntRegister::A0), u32::MAX);
+        processor
+            .registers_mut()
+            .write(IntRegister::A1, 0x8000_0000);
+        processor.registers_mut().write(IntRegister::A2, u32::MAX);
+        processor.step().unwrap();
+        assert_eq!(processor.registers().read(IntRegister::A0), 0);
 
-                if v1 >= v2 {
-                    self.pc = self.pc.wrapping_add(v3);
-                }
+        let diagnostics = processor.take_diagnostics();
+        assert!(matches!(
+            diagnostics[0],
+            Diagnostic::DivideByZero { pc: 0x0, .. }
+        ));
+        assert!(matches!(
+            diagnostics[1],
+            Diagnostic::DivideOverflow { pc: 0x4, .. }
+        ));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "division by zero at 0x00000000 (div     a0, a1, a2)"
+        );
 
-                self.cycles += 1;
-                Ok(())
-            }
-            BLTU { rs1, rs2, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let v3 = imm as i32 as u32;
+        processor.registers_mut().write(IntRegister::A2, 2);
+        processor.step().unwrap();
+        assert!(processor.take_diagnostics().is_empty());
+    }
 
-                if v1 < v2 {
-                    self.pc = self.pc.wrapping_add(v3);
-                }
+    #[test]
+    fn shift_edge_cases() {
+        use Instruction::*;
+        use IntRegister::*;
 
-                self.cycles += 1;
-                Ok(())
-            }
-            BGEU { rs1, rs2, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let v3 = imm as i32 as u32;
+        let mut processor: Processor = Processor::new(Memory::new(0x100));
+        let mut shift = |inst, value: u32, amount: u32| {
+            processor.registers_mut().write(A1, value);
+            processor.registers_mut().write(A2, amount);
+            processor.execute(inst).unwrap();
+            processor.registers().read(A0)
+        };
+        let (rd, rs1, rs2) = (A0, A1, A2);
 
-                if v1 >= v2 {
-                    self.pc = self.pc.wrapping_add(v3);
-                }
+        // Only the low 5 bits of rs2 are used
+        assert_eq!(shift(SLL { rd, rs1, rs2 }, 1, 0), 1);
+        assert_eq!(shift(SLL { rd, rs1, rs2 }, 1, 31), 0x8000_0000);
+        assert_eq!(shift(SLL { rd, rs1, rs2 }, 1, 32), 1);
+        assert_eq!(shift(SLL { rd, rs1, rs2 }, 1, 33), 2);
+        assert_eq!(shift(SLL { rd, rs1, rs2 }, 1, u32::MAX), 0x8000_0000);
+        assert_eq!(shift(SRL { rd, rs1, rs2 }, 0x8000_0000, 31), 1);
+        assert_eq!(shift(SRL { rd, rs1, rs2 }, 0x8000_0000, 32), 0x8000_0000);
+        assert_eq!(
+            shift(SRL { rd, rs1, rs2 }, 0x8000_0000, 0xFFFF_FFE1),
+            0x4000_0000
+        );
+        assert_eq!(shift(SRA { rd, rs1, rs2 }, 0x8000_0000, 31), u32::MAX);
+        assert_eq!(shift(SRA { rd, rs1, rs2 }, 0x8000_0000, 32), 0x8000_0000);
+        assert_eq!(shift(SRA { rd, rs1, rs2 }, 0x8000_0000, 36), 0xF800_0000);
+        assert_eq!(shift(SRA { rd, rs1, rs2 }, 0x7FFF_FFFF, 30), 1);
 
-                self.cycles += 1;
-                Ok(())
-            }
-            JAL { rd, imm } => {
-                let val = self.pc.wrapping_add(imm as u32);
-                self.registers.write(rd, self.pc.wrapping_add(4));
-                self.pc = val;
-                self.cycles += 1;
-                Ok(())
-            }
-            AUIPC { rd, imm } => {
-                let val = self.pc.wrapping_add((imm as u32) << 12);
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            JALR { rd, rs1, imm } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = imm as i32 as u32;
-                let val = v1.wrapping_add(v2) & !0b1;
-                self.registers.write(rd, self.pc.wrapping_add(4));
-                self.pc = val;
-                self.cycles += 1;
-                Ok(())
-            }
-            FENCE { .. } => {
-                self.cycles += 1;
-                Ok(())
-            }
-            FENCETSO => {
-                self.cycles += 1;
-                Ok(())
-            }
-            ECALL => {
-                self.cycles += 1;
-                Err(ProcessorError::Ecall)
-            }
-            EBREAK => {
-                self.cycles += 1;
-                Err(ProcessorError::Ebreak)
-            }
-            MUL { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) as i32;
-                let val = v1.wrapping_mul(v2) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            MULH { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i64;
-                let v2 = self.registers.read(rs2) as i64;
-                let val = ((v1.wrapping_mul(v2) as u64) >> 32) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            MULHSU { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i64;
-                let v2 = self.registers.read(rs2) as u64 as i64;
-                let val = ((v1.wrapping_mul(v2) as u64) >> 32) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            MULHU { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as u64;
-                let v2 = self.registers.read(rs2) as u64;
-                let val = (v1.wrapping_mul(v2) >> 32) as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            DIV { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) as i32;
-                let val = if v2 == 0 { -1 } else { v1.wrapping_div(v2) } as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            DIVU { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let v3 = u32::MAX;
-                let val = if v2 == 0 { v3 } else { v1.wrapping_div(v2) };
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            REM { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1) as i32;
-                let v2 = self.registers.read(rs2) as i32;
-                let val = if v2 == 0 { v1 } else { v1.wrapping_rem(v2) } as u32;
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
-            REMU { rd, rs1, rs2 } => {
-                let v1 = self.registers.read(rs1);
-                let v2 = self.registers.read(rs2);
-                let val = if v2 == 0 { v1 } else { v1.wrapping_rem(v2) };
-                self.registers.write(rd, val);
-                self.cycles += 1;
-                Ok(())
-            }
+        // Immediate shifts, with right shifts of negative values
+        let (rd, rs1) = (A0, A1);
+        assert_eq!(shift(SLLI { rd, rs1, shamt: 0 }, 5, 0), 5);
+        assert_eq!(shift(SLLI { rd, rs1, shamt: 31 }, 3, 0), 0x8000_0000);
+        assert_eq!(
+            shift(SRLI { rd, rs1, shamt: 4 }, 0xF000_0000, 0),
+            0x0F00_0000
+        );
+        assert_eq!(shift(SRLI { rd, rs1, shamt: 31 }, u32::MAX, 0), 1);
+        assert_eq!(
+            shift(SRAI { rd, rs1, shamt: 4 }, 0xF000_0000, 0),
+            0xFF00_0000
+        );
+        assert_eq!(shift(SRAI { rd, rs1, shamt: 31 }, 0x8000_0000, 0), u32::MAX);
+        assert_eq!(
+            shift(SRAI { rd, rs1, shamt: 0 }, 0x8000_0000, 0),
+            0x8000_0000
+        );
+        assert_eq!(
+            shift(SRAI { rd, rs1, shamt: 1 }, 0x4000_0000, 0),
+            0x2000_0000
+        );
+
+        // Shift amounts of 32 or more are not valid immediate shifts
+        assert!(Instruction::try_from(0x02059513).is_err()); // slli a0, a1, 32
+        assert!(Instruction::try_from(0x4205D513).is_err()); // srai a0, a1, 32
+    }
+
+    #[test]
+    fn multiply_high_sign_extends() {
+        // mulh a0, a1, a2; mulhsu a0, a1, a2; mulhu a0, a1, a2
+        let mut memory = Memory::new(0x100);
+        memory.write_word(0x0, 0x02C59533);
+        memory.write_word(0x4, 0x02C5A533);
+        memory.write_word(0x8, 0x02C5B533);
+        let mut processor: Processor = Processor::new(memory);
+        let mut multiply = |rs1: i32, rs2: u32| {
+            processor.registers_mut().write(IntRegister::A1, rs1 as u32);
+            processor.registers_mut().write(IntRegister::A2, rs2);
+            processor.step().unwrap();
+            processor.registers().read(IntRegister::A0)
+        };
+
+        // -2 * 3 = -6 and -2 * (2^32 - 1) = -2^33 + 2, but 0xFFFFFFFE * 3
+        // unsigned is 0x2_FFFFFFFA
+        assert_eq!(multiply(-2, 3), u32::MAX);
+        assert_eq!(multiply(-2, u32::MAX), 0xFFFF_FFFE);
+        assert_eq!(multiply(-2, 3), 2);
+    }
+
+    #[test]
+    fn shift_right_immediate_keeps_sign() {
+        // srai a0, a1, 4
+        let mut memory = Memory::new(0x100);
+        memory.write_word(0x0, 0x4045D513);
+        let mut processor: Processor = Processor::new(memory);
+        processor
+            .registers_mut()
+            .write(IntRegister::A1, -64i32 as u32);
+        processor.step().unwrap();
+        assert_eq!(processor.registers().read(IntRegister::A0), -4i32 as u32);
+    }
+
+    #[test]
+    fn fault_on_misaligned_accesses() {
+        // lw a0, 2(zero); sh a0, 3(zero)
+        let mut memory = Memory::new(0x100);
+        memory.write_word(0x0, 0x00202503);
+        memory.write_word(0x4, 0x00A011A3);
+        let mut processor: Processor = Processor::new(memory.clone());
+        processor.step().unwrap();
+        processor.step().unwrap();
+
+        let mut processor: Processor = Processor::new(memory);
+        processor.set_strict_alignment(true);
+        let err = ProcessorError::MisalignedLoad {
+            pc: 0x0,
+            address: 0x2,
+        };
+        assert_eq!(processor.step(), Err(err));
+        assert_eq!(processor.pc(), 0x0);
+
+        processor.set_pc(0x4);
+        let err = ProcessorError::MisalignedStore {
+            pc: 0x4,
+            address: 0x3,
+        };
+        assert_eq!(processor.step(), Err(err));
+    }
+
+    #[test]
+    fn access_memory_big_endian() {
+        // sw a0, 0x40(zero); lbu a1, 0x40(zero); lhu a2, 0x42(zero); lw a3, 0x40(zero)
+        let mut memory = Memory::new(0x100);
+        let code = [0x04A02023, 0x04004583, 0x04205603, 0x04002683];
+        for (i, &inst) in code.iter().enumerate() {
+            memory.write_word(4 * i as u32, inst);
+        }
+        let mut processor: Processor = Processor::new(memory);
+        processor.set_endianness("big".parse().unwrap());
+        processor
+            .registers_mut()
+            .write(IntRegister::A0, 0x1122_3344);
+        for _ in 0..code.len() {
+            processor.step().unwrap();
         }
+
+        assert_eq!(processor.memory().read_word(0x40), 0x4433_2211);
+        let registers = processor.registers();
+        assert_eq!(registers.read(IntRegister::A1), 0x11);
+        assert_eq!(registers.read(IntRegister::A2), 0x3344);
+        assert_eq!(registers.read(IntRegister::A3), 0x1122_3344);
+    }
+
+    #[test]
+    fn trap_stores_into_code() {
+        // sw a0, 0x40(zero); sw a0, 4(zero)
+        let mut memory = Memory::new(0x100);
+        memory.write_word(0x0, 0x04A02023);
+        memory.write_word(0x4, 0x00A02223);
+        let mut processor: Processor = Processor::new(memory.clone());
+        processor.set_code(vec![0x0..0x4, 0x4..0x8]);
+        processor.step().unwrap();
+        processor.step().unwrap();
+
+        let mut processor: Processor = Processor::new(memory);
+        processor.set_code(vec![0x0..0x4, 0x4..0x8]);
+        processor.set_smc_policy(SmcPolicy::Trap);
+        processor.step().unwrap();
+        let err = ProcessorError::CodeWrite {
+            pc: 0x4,
+            address: 0x4,
+        };
+        assert_eq!(processor.step(), Err(err));
+        assert_eq!(processor.pc(), 0x4);
     }
 }
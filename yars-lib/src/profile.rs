@@ -0,0 +1,453 @@
+//! Profiles of guest programs.
+
+use crate::callstack::CallStack;
+use crate::symbol::SymbolTable;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+/// Call stacks sampled while a program runs, written in the folded format
+/// read by `inferno` and `flamegraph.pl`: one `outer;inner;leaf count` line
+/// per distinct stack.
+#[derive(Clone, Debug, Default)]
+pub struct FoldedStacks {
+    samples: HashMap<Vec<u32>, u64>,
+}
+
+impl FoldedStacks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the current stack, made of the call sites of every frame
+    /// followed by `pc`.
+    pub fn sample(&mut self, stack: &CallStack, pc: u32) {
+        let mut key = stack
+            .frames()
+            .iter()
+            .map(|frame| frame.call_site)
+            .collect::<Vec<_>>();
+        key.push(pc);
+        *self.samples.entry(key).or_insert(0) += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Writes the samples, naming each address after the function that
+    /// contains it.
+    pub fn write<W: Write>(&self, symbols: &SymbolTable, mut writer: W) -> io::Result<()> {
+        let mut folded = BTreeMap::new();
+        for (key, count) in &self.samples {
+            let names = key
+                .iter()
+                .map(|&address| match symbols.lookup(address) {
+                    Some(sym) => sym.symbol.name.clone(),
+                    None => format!("{:#x}", address),
+                })
+                .collect::<Vec<_>>();
+            *folded.entry(names.join(";")).or_insert(0) += count;
+        }
+
+        for (stack, count) in folded {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Instructions retired and cycles spent at each address, summed per
+/// function to find where a program spends its time.
+#[derive(Clone, Debug, Default)]
+pub struct FunctionProfile {
+    counts: HashMap<u32, (u64, u64)>,
+}
+
+/// Totals of one function in a [`FunctionProfile`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FunctionCost {
+    /// Function name, or the address itself outside of any symbol.
+    pub name: String,
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl FunctionProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the instruction at `pc` retired and took `cycles`.
+    pub fn record(&mut self, pc: u32, cycles: u64) {
+        let entry = self.counts.entry(pc).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += cycles;
+    }
+
+    /// Totals per function, most cycles first.
+    pub fn functions(&self, symbols: &SymbolTable) -> Vec<FunctionCost> {
+        let mut totals = HashMap::new();
+        for (&pc, &(instructions, cycles)) in &self.counts {
+            let name = match symbols.lookup(pc) {
+                Some(sym) => sym.symbol.name.clone(),
+                None => format!("{:#x}", pc),
+            };
+            let total = totals.entry(name).or_insert((0, 0));
+            total.0 += instructions;
+            total.1 += cycles;
+        }
+
+        let mut functions = totals
+            .into_iter()
+            .map(|(name, (instructions, cycles))| FunctionCost {
+                name,
+                instructions,
+                cycles,
+            })
+            .collect::<Vec<_>>();
+        functions.sort_by(|a, b| {
+            (b.cycles, b.instructions, &a.name).cmp(&(a.cycles, a.instructions, &b.name))
+        });
+        functions
+    }
+
+    /// Writes a table of the `top` functions with the most cycles, with
+    /// their share of all instructions and cycles.
+    pub fn write<W: Write>(
+        &self,
+        symbols: &SymbolTable,
+        top: usize,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let functions = self.functions(symbols);
+        let instructions = functions.iter().map(|f| f.instructions).sum::<u64>();
+        let cycles = functions.iter().map(|f| f.cycles).sum::<u64>();
+        let percent = |part: u64, total: u64| match total {
+            0 => 0.0,
+            total => 100.0 * part as f64 / total as f64,
+        };
+
+        writeln!(
+            writer,
+            "{:<24} {:>12} {:>7} {:>12} {:>7}",
+            "Function", "Instructions", "%", "Cycles", "%"
+        )?;
+        for function in functions.iter().take(top) {
+            writeln!(
+                writer,
+                "{:<24} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                function.name,
+                function.instructions,
+                percent(function.instructions, instructions),
+                function.cycles,
+                percent(function.cycles, cycles)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The PC sampled every few retired instructions and summed per function,
+/// which estimates where a long run spends its time at a fraction of the
+/// cost of recording every instruction like [`FunctionProfile`].
+#[derive(Clone, Debug, Default)]
+pub struct PcSamples {
+    samples: HashMap<u32, u64>,
+}
+
+impl PcSamples {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sample(&mut self, pc: u32) {
+        *self.samples.entry(pc).or_insert(0) += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.samples.values().sum()
+    }
+
+    /// Samples per function, named as in [`FunctionProfile::functions`],
+    /// most sampled first.
+    pub fn functions(&self, symbols: &SymbolTable) -> Vec<(String, u64)> {
+        let mut totals = HashMap::new();
+        for (&pc, &count) in &self.samples {
+            let name = match symbols.lookup(pc) {
+                Some(sym) => sym.symbol.name.clone(),
+                None => format!("{:#x}", pc),
+            };
+            *totals.entry(name).or_insert(0) += count;
+        }
+
+        let mut functions = totals.into_iter().collect::<Vec<_>>();
+        functions.sort_by(|a, b| (b.1, &a.0).cmp(&(a.1, &b.0)));
+        functions
+    }
+
+    /// Writes a table of every sampled function with its share of the
+    /// samples.
+    pub fn write<W: Write>(&self, symbols: &SymbolTable, mut writer: W) -> io::Result<()> {
+        let total = self.total();
+        writeln!(writer, "{:<24} {:>12} {:>7}", "Function", "Samples", "%")?;
+        for (name, count) in self.functions(symbols) {
+            let percent = 100.0 * count as f64 / total as f64;
+            writeln!(writer, "{:<24} {:>12} {:>6.2}%", name, count, percent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Calls between functions with how often they happened and how many
+/// instructions they took, built from the shadow [`CallStack`] as the
+/// program runs.
+#[derive(Clone, Debug)]
+pub struct CallGraph {
+    entry: u32,
+    instructions: u64,
+    /// Instructions executed in each function itself.
+    exclusive: BTreeMap<u32, u64>,
+    /// Calls and inclusive instruction counts per caller and callee.
+    calls: BTreeMap<(u32, u32), (u64, u64)>,
+    /// Caller, callee and instruction count at the call of each frame.
+    active: Vec<(u32, u32, u64)>,
+}
+
+impl CallGraph {
+    /// Creates an empty graph for a program entered at `entry`.
+    pub fn new(entry: u32) -> Self {
+        Self {
+            entry,
+            instructions: 0,
+            exclusive: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            active: Vec::new(),
+        }
+    }
+
+    /// Records an executed instruction, with `stack` already updated for it.
+    pub fn update(&mut self, stack: &CallStack) {
+        self.instructions += 1;
+        *self.exclusive.entry(self.current()).or_insert(0) += 1;
+
+        while self.active.len() > stack.depth() {
+            let (caller, callee, start) = self.active.pop().unwrap();
+            self.calls.entry((caller, callee)).or_insert((0, 0)).1 += self.instructions - start;
+        }
+        for frame in &stack.frames()[self.active.len()..] {
+            let caller = self.current();
+            self.calls
+                .entry((caller, frame.function))
+                .or_insert((0, 0))
+                .0 += 1;
+            self.active
+                .push((caller, frame.function, self.instructions));
+        }
+    }
+
+    fn current(&self) -> u32 {
+        self.active
+            .last()
+            .map_or(self.entry, |&(_, callee, _)| callee)
+    }
+
+    /// Calls as `(caller, callee, count, inclusive instructions)`, counting
+    /// the instructions of calls that have not returned yet up to now.
+    pub fn calls(&self) -> Vec<(u32, u32, u64, u64)> {
+        let mut calls = self.calls.clone();
+        for &(caller, callee, start) in &self.active {
+            calls.get_mut(&(caller, callee)).unwrap().1 += self.instructions - start;
+        }
+        calls
+            .into_iter()
+            .map(|((caller, callee), (count, inclusive))| (caller, callee, count, inclusive))
+            .collect()
+    }
+
+    /// Instructions executed in `function` itself, not counting its callees.
+    pub fn exclusive(&self, function: u32) -> u64 {
+        self.exclusive.get(&function).copied().unwrap_or(0)
+    }
+
+    /// Writes the graph in the callgrind format read by KCachegrind and
+    /// `callgrind_annotate`, with instructions as the only event.
+    pub fn write_callgrind<W: Write>(
+        &self,
+        symbols: &SymbolTable,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let name = |address: u32| match symbols.lookup(address) {
+            Some(sym) => sym.to_string(),
+            None => format!("{:#x}", address),
+        };
+
+        writeln!(writer, "# callgrind format")?;
+        writeln!(writer, "version: 1")?;
+        writeln!(writer, "creator: yars")?;
+        writeln!(writer, "events: Instructions")?;
+        writeln!(writer, "summary: {}", self.instructions)?;
+
+        let calls = self.calls();
+        let mut functions = self.exclusive.keys().copied().collect::<Vec<_>>();
+        functions.extend(calls.iter().map(|&(_, callee, _, _)| callee));
+        functions.sort_unstable();
+        functions.dedup();
+
+        for function in functions {
+            writeln!(writer, "\nfn={}", name(function))?;
+            writeln!(writer, "0 {}", self.exclusive(function))?;
+            for &(_, callee, count, inclusive) in calls.iter().filter(|c| c.0 == function) {
+                writeln!(writer, "cfn={}", name(callee))?;
+                writeln!(writer, "calls={} 0", count)?;
+                writeln!(writer, "0 {}", inclusive)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction;
+    use crate::register::IntRegister;
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn fold_sampled_stacks() {
+        let mut symbols = SymbolTable::new();
+        for (name, address) in &[("main", 0x1000), ("work", 0x1100)] {
+            symbols.insert(Symbol {
+                name: name.to_string(),
+                address: *address,
+                size: 0x100,
+            });
+        }
+
+        let mut stack = CallStack::new();
+        let mut folded = FoldedStacks::new();
+        folded.sample(&stack, 0x1000);
+        let call = Instruction::JAL {
+            rd: IntRegister::RA,
+            imm: 0xF8,
+        };
+        stack.update(0x1008, &call, 0x1100);
+        folded.sample(&stack, 0x1104);
+        folded.sample(&stack, 0x1108);
+        folded.sample(&stack, 0x3000);
+
+        let mut out = Vec::new();
+        folded.write(&symbols, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main 1\nmain;0x3000 1\nmain;work 2\n"
+        );
+    }
+
+    #[test]
+    fn sum_costs_per_function() {
+        let mut symbols = SymbolTable::new();
+        for (name, address) in &[("main", 0x1000), ("work", 0x1100)] {
+            symbols.insert(Symbol {
+                name: name.to_string(),
+                address: *address,
+                size: 0x100,
+            });
+        }
+
+        let mut profile = FunctionProfile::new();
+        profile.record(0x1000, 1);
+        for &pc in &[0x1100, 0x1104, 0x1104] {
+            profile.record(pc, 2);
+        }
+        profile.record(0x3000, 1);
+
+        let functions = profile.functions(&symbols);
+        let names = functions
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["work", "0x3000", "main"]);
+        assert_eq!((functions[0].instructions, functions[0].cycles), (3, 6));
+
+        let mut out = Vec::new();
+        profile.write(&symbols, 1, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("work                                3  60.00%            6  75.00%"));
+    }
+
+    #[test]
+    fn sum_samples_per_function() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Symbol {
+            name: "work".to_string(),
+            address: 0x1100,
+            size: 0x100,
+        });
+
+        let mut samples = PcSamples::new();
+        for &pc in &[0x1100, 0x1104, 0x1100, 0x3000] {
+            samples.sample(pc);
+        }
+        assert_eq!(samples.total(), 4);
+        assert_eq!(
+            samples.functions(&symbols),
+            [("work".to_string(), 3), ("0x3000".to_string(), 1)]
+        );
+
+        let mut out = Vec::new();
+        samples.write(&symbols, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("work                                3  75.00%\n"));
+    }
+
+    #[test]
+    fn count_calls_and_instructions() {
+        let call = |imm| Instruction::JAL {
+            rd: IntRegister::RA,
+            imm,
+        };
+        let ret = Instruction::JALR {
+            rd: IntRegister::Zero,
+            rs1: IntRegister::RA,
+            imm: 0,
+        };
+        let other = Instruction::ECALL;
+
+        // main calls work twice, work calls leaf once and returns
+        let trace = [
+            (0x1000, call(0x100), 0x1100),
+            (0x1100, call(0x100), 0x1200),
+            (0x1200, ret, 0x1104),
+            (0x1104, ret, 0x1004),
+            (0x1004, call(0xFC), 0x1100),
+            (0x1100, other, 0x1104),
+        ];
+        let mut stack = CallStack::new();
+        let mut graph = CallGraph::new(0x1000);
+        for (pc, inst, next) in &trace {
+            stack.update(*pc, inst, *next);
+            graph.update(&stack);
+        }
+
+        assert_eq!(
+            graph.calls(),
+            [(0x1000, 0x1100, 2, 4), (0x1100, 0x1200, 1, 1)]
+        );
+        assert_eq!(graph.exclusive(0x1000), 2);
+        assert_eq!(graph.exclusive(0x1100), 3);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Symbol {
+            name: "main".to_string(),
+            address: 0x1000,
+            size: 0x100,
+        });
+        let mut out = Vec::new();
+        graph.write_callgrind(&symbols, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\nfn=main\n0 2\ncfn=0x1100\ncalls=2 0\n0 4\n"));
+        assert!(out.contains("\nfn=0x1200\n0 1\n"));
+    }
+}
@@ -1,7 +1,12 @@
+use crate::xlen::Xlen;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum IntRegister {
     Zero = 0,
     RA,
@@ -37,8 +42,13 @@ pub enum IntRegister {
     T6,
 }
 
+/// Formats the ABI name of the register, or with `{:#}` its number, as in
+/// `x10`.
 impl fmt::Display for IntRegister {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return write!(f, "x{}", *self as u8);
+        }
         match self {
             IntRegister::Zero => write!(f, "zero"),
             IntRegister::RA => write!(f, "ra"),
@@ -118,22 +128,45 @@ impl TryFrom<u8> for IntRegister {
     }
 }
 
-#[derive(Copy, Clone, Debug, Default)]
-pub struct IntRegisterSet {
-    reg: [u32; 32],
+/// Parses an ABI name, including `fp` for `s0`, or a number, as in `x10`.
+impl FromStr for IntRegister {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let number = match name {
+            "fp" => Some(IntRegister::S0 as u8),
+            _ => name
+                .strip_prefix('x')
+                .filter(|number| !number.starts_with('+'))
+                .and_then(|number| number.parse().ok()),
+        };
+        match number {
+            Some(number) => IntRegister::try_from(number).ok(),
+            None => (0..32)
+                .map(|reg| IntRegister::try_from(reg).unwrap())
+                .find(|reg| reg.to_string() == name),
+        }
+        .ok_or_else(|| format!("expected a register such as a0 or x10, found {}", name))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IntRegisterSet<X: Xlen = u32> {
+    reg: [X; 32],
 }
 
-impl IntRegisterSet {
+impl<X: Xlen> IntRegisterSet<X> {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn read(&self, reg: IntRegister) -> u32 {
+    pub fn read(&self, reg: IntRegister) -> X {
         let reg = reg as usize;
         self.reg[reg]
     }
 
-    pub fn write(&mut self, reg: IntRegister, val: u32) {
+    pub fn write(&mut self, reg: IntRegister, val: X) {
         let reg = reg as usize;
         if reg != 0 {
             self.reg[reg] = val;
@@ -141,18 +174,31 @@ impl IntRegisterSet {
     }
 }
 
-impl fmt::Display for IntRegisterSet {
+/// Formats the registers four to a line, named by number with `{:#}`.
+impl<X: Xlen> fmt::Display for IntRegisterSet<X> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let w = X::BITS as usize / 4 + 2;
         for (i, r) in self.reg.chunks(4).enumerate() {
             let i = 4 * i as u8;
             let n = (i..i + 4)
-                .map(|n| format!("{}", IntRegister::try_from(n).unwrap()))
+                .map(|n| match f.alternate() {
+                    true => format!("{:#}", IntRegister::try_from(n).unwrap()),
+                    false => format!("{}", IntRegister::try_from(n).unwrap()),
+                })
                 .collect::<Vec<_>>();
 
             writeln!(
                 f,
-                "{:>4}={:#010X} {:>4}={:#010X} {:>4}={:#010X} {:>4}={:#010X}",
-                n[0], r[0], n[1], r[1], n[2], r[2], n[3], r[3],
+                "{:>4}={:#0w$X} {:>4}={:#0w$X} {:>4}={:#0w$X} {:>4}={:#0w$X}",
+                n[0],
+                r[0],
+                n[1],
+                r[1],
+                n[2],
+                r[2],
+                n[3],
+                r[3],
+                w = w,
             )?;
         }
         Ok(())
@@ -165,10 +211,29 @@ mod tests {
 
     #[test]
     fn read_write_int_register_set() {
-        let mut rs = IntRegisterSet::new();
+        let mut rs: IntRegisterSet = IntRegisterSet::new();
         rs.write(IntRegister::Zero, 1);
         rs.write(IntRegister::RA, 1);
         assert_eq!(rs.read(IntRegister::Zero), 0);
         assert_eq!(rs.read(IntRegister::RA), 1);
     }
+
+    #[test]
+    fn parse_and_display_register_names() {
+        assert_eq!("a0".parse(), Ok(IntRegister::A0));
+        assert_eq!("x10".parse(), Ok(IntRegister::A0));
+        assert_eq!("fp".parse(), Ok(IntRegister::S0));
+        assert_eq!("zero".parse(), Ok(IntRegister::Zero));
+        assert_eq!("x31".parse(), Ok(IntRegister::T6));
+        assert!("x32".parse::<IntRegister>().is_err());
+        assert!("x+1".parse::<IntRegister>().is_err());
+        assert!("A0".parse::<IntRegister>().is_err());
+
+        for reg in (0..32).map(|reg| IntRegister::try_from(reg).unwrap()) {
+            assert_eq!(reg.to_string().parse(), Ok(reg));
+            assert_eq!(format!("{:#}", reg).parse(), Ok(reg));
+        }
+        assert_eq!(format!("{:#}", IntRegister::SP), "x2");
+        assert!(format!("{:#}", IntRegisterSet::<u32>::new()).starts_with("  x0=0x00000000"));
+    }
 }
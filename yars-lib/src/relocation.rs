@@ -0,0 +1,215 @@
+//! Loading of relocatable objects (`ET_REL`), as an assembler emits them,
+//! so single-file programs run without a linker step. The allocated
+//! sections are laid out one after the other from a load address, and the
+//! relocations against them applied as a linker would, without relaxing
+//! any instruction sequence.
+
+use crate::memory::{Memory, Program, ProgramError, Section};
+use crate::symbol::SymbolTable;
+use goblin::elf::reloc::*;
+use goblin::elf::section_header::{SHF_ALLOC, SHF_EXECINSTR, SHT_NOBITS};
+use goblin::elf::section_header::{SHN_ABS, SHN_UNDEF};
+use goblin::elf::Elf;
+use std::collections::HashMap;
+
+/// Symbol the program starts at. Objects that do not define it start at
+/// their first executable section.
+pub const ENTRY: &str = "_start";
+
+/// A relocation resolved to the address it patches (P), the address of its
+/// symbol (S) and its addend (A).
+#[derive(Copy, Clone, Debug)]
+struct Resolved {
+    kind: u32,
+    place: u32,
+    symbol: u32,
+    addend: u32,
+}
+
+/// Places the allocated sections of the object in `buffer` from `base`,
+/// applies its relocations and returns the program.
+pub(crate) fn load_object(
+    memory: &mut Memory,
+    binary: &Elf,
+    buffer: &[u8],
+    base: u32,
+) -> Result<Program, ProgramError> {
+    let mut addresses = HashMap::new();
+    let mut sections = Vec::new();
+    let mut segments = Vec::new();
+    let mut code = Vec::new();
+    let mut entry = None;
+    let mut next = base;
+    for (index, sh) in binary.section_headers.iter().enumerate() {
+        if sh.sh_flags & SHF_ALLOC as u64 == 0 || sh.sh_size == 0 {
+            continue;
+        }
+        let align = (sh.sh_addralign as u32).max(1);
+        let address = next.wrapping_add(align - 1) & !(align - 1);
+        let size = sh.sh_size as u32;
+        if !memory.contains(address, size) {
+            return Err(ProgramError::OutOfMemory);
+        }
+
+        let target = &mut memory.as_bytes_mut()[address as usize..(address + size) as usize];
+        match sh.sh_type {
+            SHT_NOBITS => target.iter_mut().for_each(|byte| *byte = 0),
+            _ => {
+                let start = sh.sh_offset as usize;
+                let data = buffer
+                    .get(start..start + size as usize)
+                    .ok_or(ProgramError::UnsupportedBinary)?;
+                target.copy_from_slice(data);
+            }
+        }
+        if sh.sh_flags & SHF_EXECINSTR as u64 != 0 {
+            entry = entry.or(Some(address));
+            code.push(address..address + size);
+        }
+
+        addresses.insert(index, address);
+        segments.push(address..address + size);
+        sections.push(Section {
+            name: match binary.shdr_strtab.get(sh.sh_name) {
+                Some(Ok(name)) => name.to_string(),
+                _ => String::new(),
+            },
+            address,
+            size,
+        });
+        next = address + size;
+    }
+
+    let symbol = |index: usize| -> Option<u32> {
+        let sym = binary.syms.get(index)?;
+        match sym.st_shndx as u32 {
+            SHN_UNDEF => None,
+            SHN_ABS => Some(sym.st_value as u32),
+            shndx => addresses
+                .get(&(shndx as usize))
+                .map(|address| address.wrapping_add(sym.st_value as u32)),
+        }
+    };
+
+    let mut relocations = Vec::new();
+    for (index, relocs) in &binary.shdr_relocs {
+        let target = binary.section_headers[*index].sh_info as usize;
+        let section = match addresses.get(&target) {
+            Some(&section) => section,
+            None => continue,
+        };
+        for reloc in relocs.iter() {
+            if matches!(reloc.r_type, R_RISCV_RELAX | R_RISCV_ALIGN) {
+                continue;
+            }
+            let address = symbol(reloc.r_sym).ok_or_else(|| {
+                let name = binary
+                    .syms
+                    .get(reloc.r_sym)
+                    .and_then(|sym| binary.strtab.get(sym.st_name))
+                    .and_then(Result::ok)
+                    .unwrap_or_default();
+                ProgramError::UndefinedSymbol(name.to_string())
+            })?;
+            relocations.push(Resolved {
+                kind: reloc.r_type,
+                place: section.wrapping_add(reloc.r_offset as u32),
+                symbol: address,
+                addend: reloc.r_addend.unwrap_or(0) as u32,
+            });
+        }
+    }
+
+    // The low half of a PC-relative pair refers to the `auipc` holding the
+    // high half, whose value it completes
+    let high = relocations
+        .iter()
+        .filter(|reloc| reloc.kind == R_RISCV_PCREL_HI20)
+        .map(|reloc| (reloc.place, pc_relative(reloc)))
+        .collect::<HashMap<_, _>>();
+
+    for reloc in &relocations {
+        let value = reloc.symbol.wrapping_add(reloc.addend);
+        let place = reloc.place;
+        match reloc.kind {
+            R_RISCV_32 => memory.write_word(place, value),
+            R_RISCV_HI20 => patch(memory, place, |word| set_upper(word, value)),
+            R_RISCV_LO12_I => patch(memory, place, |word| set_i(word, value)),
+            R_RISCV_LO12_S => patch(memory, place, |word| set_s(word, value)),
+            R_RISCV_PCREL_HI20 => {
+                let value = pc_relative(reloc);
+                patch(memory, place, |word| set_upper(word, value))
+            }
+            R_RISCV_PCREL_LO12_I | R_RISCV_PCREL_LO12_S => {
+                let value = *high
+                    .get(&reloc.symbol)
+                    .ok_or(ProgramError::UnsupportedRelocation(reloc.kind))?;
+                match reloc.kind {
+                    R_RISCV_PCREL_LO12_I => patch(memory, place, |word| set_i(word, value)),
+                    _ => patch(memory, place, |word| set_s(word, value)),
+                }
+            }
+            R_RISCV_BRANCH => patch(memory, place, |word| set_b(word, pc_relative(reloc))),
+            R_RISCV_JAL => patch(memory, place, |word| set_j(word, pc_relative(reloc))),
+            R_RISCV_CALL | R_RISCV_CALL_PLT => {
+                let value = pc_relative(reloc);
+                patch(memory, place, |word| set_upper(word, value));
+                patch(memory, place + 4, |word| set_i(word, value));
+            }
+            kind => return Err(ProgramError::UnsupportedRelocation(kind)),
+        }
+    }
+
+    let symbols = SymbolTable::from_elf_with(binary, |index, _| symbol(index));
+    Ok(Program {
+        entry: symbols
+            .get(ENTRY)
+            .map(|sym| sym.address)
+            .or(entry)
+            .unwrap_or(base),
+        symbols,
+        segments,
+        code,
+        sections,
+    })
+}
+
+/// Value of `reloc` relative to the address it patches, S + A - P.
+fn pc_relative(reloc: &Resolved) -> u32 {
+    reloc
+        .symbol
+        .wrapping_add(reloc.addend)
+        .wrapping_sub(reloc.place)
+}
+
+fn patch<F: FnOnce(u32) -> u32>(memory: &mut Memory, address: u32, f: F) {
+    let word = memory.read_word(address);
+    memory.write_word(address, f(word));
+}
+
+/// Sets the 20-bit immediate of a `lui` or `auipc` to the upper part of
+/// `value`, rounded so that adding the sign-extended lower 12 bits gives
+/// `value` back.
+fn set_upper(word: u32, value: u32) -> u32 {
+    (word & 0xFFF) | (value.wrapping_add(0x800) & 0xFFFF_F000)
+}
+
+fn set_i(word: u32, value: u32) -> u32 {
+    (word & 0x000F_FFFF) | (value & 0xFFF) << 20
+}
+
+fn set_s(word: u32, value: u32) -> u32 {
+    (word & 0x01FF_F07F) | (value >> 5 & 0x7F) << 25 | (value & 0x1F) << 7
+}
+
+fn set_b(word: u32, value: u32) -> u32 {
+    let (imm12, imm105) = (value >> 12 & 1, value >> 5 & 0x3F);
+    let (imm41, imm11) = (value >> 1 & 0xF, value >> 11 & 1);
+    (word & 0x01FF_F07F) | imm12 << 31 | imm105 << 25 | imm41 << 8 | imm11 << 7
+}
+
+fn set_j(word: u32, value: u32) -> u32 {
+    let (imm20, imm101) = (value >> 20 & 1, value >> 1 & 0x3FF);
+    let (imm11, imm1912) = (value >> 11 & 1, value >> 12 & 0xFF);
+    (word & 0xFFF) | imm20 << 31 | imm101 << 21 | imm11 << 20 | imm1912 << 12
+}
@@ -0,0 +1,281 @@
+//! Recording of the external inputs a guest observes during a run, so the
+//! run can later be repeated bit-exactly by feeding the same inputs back.
+//!
+//! Logs are text files holding one input per line. Inputs the guest reads
+//! come in the order it read them; inputs the host delivers, console bytes
+//! and interrupt lines, are prefixed with `@` and the number of instructions
+//! retired when they were delivered, in hex for the bytes:
+//!
+//! ```text
+//! time 1600000000000000000
+//! rand 0x2a
+//! @1042 console 6c730a
+//! @5000 irq 3 raise
+//! @5100 irq 3 clear
+//! ```
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Input {
+    /// Nanoseconds since the Unix epoch, read from the clock.
+    Time(u64),
+    Random(u32),
+    /// Bytes queued for the guest to read from the console.
+    Console(Vec<u8>),
+    /// External interrupt `line` raised or cleared by the host.
+    Irq {
+        line: u32,
+        raised: bool,
+    },
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Time(time) => write!(f, "time {}", time),
+            Input::Random(value) => write!(f, "rand {:#x}", value),
+            Input::Console(bytes) => {
+                write!(f, "console ")?;
+                bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+            Input::Irq { line, raised } => match raised {
+                true => write!(f, "irq {} raise", line),
+                false => write!(f, "irq {} clear", line),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    inputs: Vec<Input>,
+    /// Inputs delivered by the host, with the instructions retired before.
+    delivered: Vec<(usize, Input)>,
+    replaying: bool,
+    position: usize,
+    /// Delivered inputs replayed so far.
+    delivered_position: usize,
+    diverged: bool,
+}
+
+/// Log of inputs shared by every source of a simulator. While recording,
+/// each input is appended; while replaying, inputs are returned from the log
+/// instead of being read from the host.
+#[derive(Clone, Debug, Default)]
+pub struct InputLog {
+    state: Arc<Mutex<State>>,
+}
+
+impl InputLog {
+    /// Creates an empty log that records inputs.
+    pub fn recorder() -> Self {
+        Self::default()
+    }
+
+    /// Reads a log written by [`InputLog::save`] to replay its inputs.
+    pub fn load<R: BufRead>(reader: R) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid input: {}", line),
+            )
+        };
+
+        let mut inputs = Vec::new();
+        let mut delivered = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (instret, input) = match line.strip_prefix('@') {
+                Some(line) => match line.split_once(' ') {
+                    Some((instret, input)) => (instret.parse().ok(), input),
+                    None => (None, line),
+                },
+                None => (None, line),
+            };
+            let input = match input.split_once(' ') {
+                Some(("time", time)) => time.parse().ok().map(Input::Time),
+                Some(("rand", value)) => value
+                    .strip_prefix("0x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .map(Input::Random),
+                Some(("console", hex)) => parse_hex(hex).map(Input::Console),
+                Some(("irq", irq)) => match irq.split_once(' ') {
+                    Some((line, "raise")) => line.parse().ok().map(|line| (line, true)),
+                    Some((line, "clear")) => line.parse().ok().map(|line| (line, false)),
+                    _ => None,
+                }
+                .map(|(line, raised)| Input::Irq { line, raised }),
+                _ => None,
+            };
+            match (instret, input) {
+                (None, Some(input @ Input::Time(_))) | (None, Some(input @ Input::Random(_))) => {
+                    inputs.push(input)
+                }
+                (Some(instret), Some(input @ Input::Console(_)))
+                | (Some(instret), Some(input @ Input::Irq { .. })) => {
+                    delivered.push((instret, input))
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        let state = State {
+            inputs,
+            delivered,
+            replaying: true,
+            ..State::default()
+        };
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        for input in &state.inputs {
+            writeln!(writer, "{}", input)?;
+        }
+        for (instret, input) in &state.delivered {
+            writeln!(writer, "@{} {}", instret, input)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.state.lock().unwrap().replaying
+    }
+
+    /// Number of inputs recorded, or replayed so far.
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        match state.replaying {
+            true => state.position + state.delivered_position,
+            false => state.inputs.len() + state.delivered.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the guest read an input the log did not have at that point,
+    /// meaning the replayed run no longer follows the recorded one. Inputs
+    /// are then read from the host again.
+    pub fn diverged(&self) -> bool {
+        self.state.lock().unwrap().diverged
+    }
+
+    pub(crate) fn time(&self, host: impl FnOnce() -> u64) -> u64 {
+        match self.next(
+            |input| matches!(input, Input::Time(_)),
+            || Input::Time(host()),
+        ) {
+            Input::Time(time) => time,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn random(&self, host: impl FnOnce() -> u32) -> u32 {
+        match self.next(
+            |input| matches!(input, Input::Random(_)),
+            || Input::Random(host()),
+        ) {
+            Input::Random(value) => value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn next(&self, kind: impl Fn(&Input) -> bool, host: impl FnOnce() -> Input) -> Input {
+        let mut state = self.state.lock().unwrap();
+        if !state.replaying {
+            let input = host();
+            state.inputs.push(input.clone());
+            return input;
+        }
+
+        match state.inputs.get(state.position).cloned() {
+            Some(input) if !state.diverged && kind(&input) => {
+                state.position += 1;
+                input
+            }
+            _ => {
+                state.diverged = true;
+                host()
+            }
+        }
+    }
+
+    /// Records `input`, delivered by the host after `instret` instructions,
+    /// and tells whether it should reach the guest. While replaying, inputs
+    /// from the host are dropped in favour of the logged ones.
+    pub(crate) fn deliver(&self, instret: usize, input: Input) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.replaying {
+            return false;
+        }
+        state.delivered.push((instret, input));
+        true
+    }
+
+    /// Takes the logged inputs the host delivered after at most `instret`
+    /// instructions, which have not been replayed yet.
+    pub(crate) fn due(&self, instret: usize) -> Vec<Input> {
+        let mut state = self.state.lock().unwrap();
+        let start = state.delivered_position;
+        let due = state.delivered[start..]
+            .iter()
+            .take_while(|(at, _)| *at <= instret)
+            .map(|(_, input)| input.clone())
+            .collect::<Vec<_>>();
+        state.delivered_position += due.len();
+        due
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entropy::Entropy;
+
+    #[test]
+    fn record_and_replay_inputs() {
+        let log = InputLog::recorder();
+        let mut entropy = Entropy::host().with_log(log.clone());
+        let recorded = (entropy.time_ns(), entropy.next_u32(), entropy.next_u32());
+        assert_eq!(log.len(), 3);
+
+        let mut file = Vec::new();
+        log.save(&mut file).unwrap();
+        let replay = InputLog::load(file.as_slice()).unwrap();
+        let mut entropy = Entropy::host().with_log(replay.clone());
+        let replayed = (entropy.time_ns(), entropy.next_u32(), entropy.next_u32());
+        assert_eq!(recorded, replayed);
+        assert!(!replay.diverged());
+
+        // Reading past the end of the log falls back to the host
+        entropy.time_ns();
+        assert!(replay.diverged());
+        assert!(InputLog::load("rand 12".as_bytes()).is_err());
+        // Host inputs need the instruction they were delivered at
+        assert!(InputLog::load("console 78".as_bytes()).is_err());
+        assert!(InputLog::load("@4 console 7".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::entropy::Entropy;
+use crate::memory::Memory;
+use crate::processor::{Endianness, Processor, ProcessorError};
+use crate::register::IntRegister;
+use crate::symbol::SymbolTable;
+use crate::xlen::Xlen;
+use std::convert::TryFrom;
+use std::ops::Range;
+
+pub const GLOBAL_POINTER: &str = "__global_pointer$";
+
+/// Register state at reset, following the conventions of the RISC-V ELF
+/// psABI and of firmware handing control to a kernel.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ResetConfig {
+    /// Initial stack pointer, rounded down to 16 bytes. Defaults to the end
+    /// of memory.
+    pub sp: Option<u32>,
+    /// Initial global pointer. Defaults to the `__global_pointer$` symbol.
+    pub gp: Option<u32>,
+    /// Passed in `a0`.
+    pub hart_id: u32,
+    /// Device tree pointer passed in `a1`.
+    pub dtb: u32,
+    /// Pattern written to every other register, to catch reads of
+    /// uninitialized registers.
+    pub poison: u32,
+    /// Writes values derived from this seed instead of `poison`, a
+    /// different one to every register and memory word.
+    pub poison_seed: Option<u64>,
+    /// Also fills memory outside the loaded segments with the poison, to
+    /// catch reads of memory a program assumes to be zeroed.
+    pub poison_memory: bool,
+}
+
+impl ResetConfig {
+    pub fn apply<X: Xlen>(&self, processor: &mut Processor<X>, symbols: &SymbolTable) {
+        let sp = self.sp.unwrap_or_else(|| processor.memory().size()) & !0xF;
+        let gp = self
+            .gp
+            .or_else(|| symbols.get(GLOBAL_POINTER).map(|sym| sym.address));
+
+        let mut poison = self.poison_source();
+        let registers = processor.registers_mut();
+        for reg in 1..32 {
+            let reg = IntRegister::try_from(reg).unwrap();
+            registers.write(reg, X::from_u32(poison()));
+        }
+
+        registers.write(IntRegister::SP, X::from_u32(sp));
+        if let Some(gp) = gp {
+            registers.write(IntRegister::GP, X::from_u32(gp));
+        }
+        registers.write(IntRegister::A0, X::from_u32(self.hart_id));
+        registers.write(IntRegister::A1, X::from_u32(self.dtb));
+    }
+
+    /// Fills every word of `memory` outside `segments` with the poison, if
+    /// `poison_memory` is set.
+    pub fn poison_memory(&self, memory: &mut Memory, segments: &[Range<u32>]) {
+        if !self.poison_memory {
+            return;
+        }
+
+        let mut poison = self.poison_source();
+        for (index, word) in memory.as_bytes_mut().chunks_mut(4).enumerate() {
+            let address = index as u32 * 4;
+            let loaded = segments
+                .iter()
+                .any(|s| address < s.end && s.start < address + word.len() as u32);
+            if !loaded {
+                let value = poison().to_le_bytes();
+                word.copy_from_slice(&value[..word.len()]);
+            }
+        }
+    }
+
+    fn poison_source(&self) -> impl FnMut() -> u32 {
+        let pattern = self.poison;
+        let mut entropy = self.poison_seed.map(Entropy::seeded);
+        move || match &mut entropy {
+            Some(entropy) => entropy.next_u32(),
+            None => pattern,
+        }
+    }
+}
+
+/// Builds the process entry stack below the current SP: `argc`, the
+/// NULL-terminated `argv` and `envp` arrays, an empty auxiliary vector and
+/// the strings they point to. As expected by newlib's crt0, `a0`, `a1` and
+/// `a2` also receive `argc`, `argv` and `envp`.
+pub fn push_args<X: Xlen>(
+    processor: &mut Processor<X>,
+    args: &[String],
+    env: &[String],
+) -> Result<(), ProcessorError> {
+    let word = X::BITS / 8;
+    let pc = processor.pc().as_u32();
+    let mut sp = processor.registers().read(IntRegister::SP).as_u32();
+    let mut pointers = Vec::new();
+
+    for string in args.iter().chain(env) {
+        let mut bytes = string.as_bytes().to_vec();
+        bytes.push(0);
+        sp = sp
+            .checked_sub(bytes.len() as u32)
+            .ok_or(ProcessorError::IllegalAccess { pc, address: 0 })?;
+        write(processor.memory_mut(), pc, sp, &bytes)?;
+        pointers.push(sp);
+    }
+
+    let (argv, envp) = pointers.split_at(args.len());
+    let mut vector = vec![args.len() as u32];
+    vector.extend(argv);
+    vector.push(0);
+    vector.extend(envp);
+    vector.extend(&[0, 0, 0]);
+
+    let size = vector.len() as u32 * word;
+    sp = sp
+        .checked_sub(size)
+        .ok_or(ProcessorError::IllegalAccess { pc, address: 0 })?
+        & !0xF;
+    let big = processor.endianness() == Endianness::Big;
+    let bytes = vector
+        .iter()
+        .flat_map(|value| {
+            let value = X::from_u32(*value).to_u64();
+            match big {
+                true => value.to_be_bytes()[8 - word as usize..].to_vec(),
+                false => value.to_le_bytes()[..word as usize].to_vec(),
+            }
+        })
+        .collect::<Vec<_>>();
+    write(processor.memory_mut(), pc, sp, &bytes)?;
+
+    let registers = processor.registers_mut();
+    registers.write(IntRegister::SP, X::from_u32(sp));
+    registers.write(IntRegister::A0, X::from_u32(args.len() as u32));
+    registers.write(IntRegister::A1, X::from_u32(sp + word));
+    registers.write(
+        IntRegister::A2,
+        X::from_u32(sp + word * (argv.len() as u32 + 2)),
+    );
+    Ok(())
+}
+
+fn write(memory: &mut Memory, pc: u32, address: u32, bytes: &[u8]) -> Result<(), ProcessorError> {
+    if !memory.contains(address, bytes.len() as u32) {
+        return Err(ProcessorError::IllegalAccess { pc, address });
+    }
+
+    let address = address as usize;
+    memory.as_bytes_mut()[address..address + bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn apply_reset_config() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Symbol {
+            name: GLOBAL_POINTER.to_string(),
+            address: 0x1800,
+            size: 0,
+        });
+
+        let config = ResetConfig {
+            sp: Some(0x7FFC),
+            hart_id: 3,
+            poison: 0xDEADBEEF,
+            ..ResetConfig::default()
+        };
+        let mut processor: Processor = Processor::new(Memory::new(0x8000));
+        config.apply(&mut processor, &symbols);
+
+        let registers = processor.registers();
+        assert_eq!(registers.read(IntRegister::Zero), 0);
+        assert_eq!(registers.read(IntRegister::SP), 0x7FF0);
+        assert_eq!(registers.read(IntRegister::GP), 0x1800);
+        assert_eq!(registers.read(IntRegister::A0), 3);
+        assert_eq!(registers.read(IntRegister::A1), 0);
+        assert_eq!(registers.read(IntRegister::T0), 0xDEADBEEF);
+    }
+
+    #[test]
+    fn poison_memory_outside_segments() {
+        let mut memory = Memory::new(0x20);
+        let config = ResetConfig {
+            poison: 0xDEADBEEF,
+            poison_memory: true,
+            ..ResetConfig::default()
+        };
+        config.poison_memory(&mut memory, &[0x8..0xA, 0xE..0x12]);
+        assert_eq!(memory.read_word(0x4), 0xDEADBEEF);
+        assert_eq!(memory.read_word(0x8), 0);
+        assert_eq!(memory.read_word(0xC), 0);
+        assert_eq!(memory.read_word(0x10), 0);
+        assert_eq!(memory.read_word(0x14), 0xDEADBEEF);
+
+        let random = |seed| {
+            let mut processor: Processor = Processor::new(Memory::new(0x10));
+            let config = ResetConfig {
+                poison_seed: Some(seed),
+                ..ResetConfig::default()
+            };
+            config.apply(&mut processor, &SymbolTable::new());
+            let registers = processor.registers();
+            (
+                registers.read(IntRegister::T0),
+                registers.read(IntRegister::T1),
+            )
+        };
+        let (t0, t1) = random(7);
+        assert_ne!(t0, t1);
+        assert_eq!(random(7), (t0, t1));
+    }
+
+    #[test]
+    fn push_args_and_env() {
+        let mut processor: Processor = Processor::new(Memory::new(0x1000));
+        let args = vec!["prog".to_string(), "-v".to_string()];
+        let env = vec!["HOME=/".to_string()];
+        push_args(&mut processor, &args, &env).unwrap();
+
+        let registers = processor.registers();
+        let sp = registers.read(IntRegister::SP);
+        let memory = processor.memory();
+        assert_eq!(sp % 16, 0);
+        assert_eq!(memory.read_word(sp), 2);
+        assert_eq!(registers.read(IntRegister::A1), sp + 4);
+        assert_eq!(registers.read(IntRegister::A2), sp + 16);
+        assert_eq!(memory.read_word(sp + 12), 0);
+
+        let string = |address: u32| {
+            let start = memory.read_word(address) as usize;
+            let bytes = &memory.as_bytes()[start..];
+            let end = bytes.iter().position(|&byte| byte == 0).unwrap();
+            String::from_utf8(bytes[..end].to_vec()).unwrap()
+        };
+        assert_eq!(string(sp + 4), "prog");
+        assert_eq!(string(sp + 8), "-v");
+        assert_eq!(string(sp + 16), "HOME=/");
+
+        let mut processor: Processor = Processor::new(Memory::new(0x1000));
+        processor.set_endianness(Endianness::Big);
+        push_args(&mut processor, &args, &env).unwrap();
+        let sp = processor.registers().read(IntRegister::SP);
+        assert_eq!(processor.memory().read_word(sp), 2u32.swap_bytes());
+    }
+}
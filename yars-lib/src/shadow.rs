@@ -0,0 +1,212 @@
+//! Return-address protection checks against a shadow [`CallStack`]: every
+//! return must go back to the instruction after the call it returns from,
+//! as a hardware shadow stack would enforce. When a return goes elsewhere,
+//! as after a stack buffer overflow, the store that last wrote the saved
+//! return address is reported along with it.
+
+use crate::callstack::{self, CallStack};
+use crate::instruction::Instruction;
+use crate::register::{IntRegister, IntRegisterSet};
+use crate::symbol::SymbolTable;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A return that did not go back to its caller.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ReturnMismatch {
+    /// Address of the return instruction.
+    pub pc: u32,
+    /// Address the return went to.
+    pub target: u32,
+    /// Address after the call, where the return should have gone.
+    pub expected: u32,
+    /// Address `ra` was last loaded from, if it was loaded from memory.
+    pub slot: Option<u32>,
+    /// Address of the last store to `slot`, if any.
+    pub store: Option<u32>,
+}
+
+impl ReturnMismatch {
+    /// Like the `Display` output, but naming the functions of the addresses.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let at = |address: u32| match symbols.lookup(address) {
+            Some(sym) => format!("{:#010x} <{}>", address, sym),
+            None => format!("{:#010x}", address),
+        };
+        let mut text = format!(
+            "return at {} went to {} instead of {}",
+            at(self.pc),
+            at(self.target),
+            at(self.expected)
+        );
+        match (self.slot, self.store) {
+            (Some(slot), Some(store)) => text.push_str(&format!(
+                "; ra was loaded from {:#010x}, last written by the store at {}",
+                slot,
+                at(store)
+            )),
+            (Some(slot), None) => text.push_str(&format!(
+                "; ra was loaded from {:#010x}, never written by the program",
+                slot
+            )),
+            (None, _) => text.push_str("; ra was not loaded from memory"),
+        }
+        text
+    }
+}
+
+impl fmt::Display for ReturnMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolTable::new()))
+    }
+}
+
+/// Checks returns against a shadow call stack, remembering the last store
+/// to every byte of memory to explain where a bad return address came from.
+#[derive(Clone, Debug, Default)]
+pub struct ShadowStack {
+    stack: CallStack,
+    /// Address and sequence number of the last store to each byte.
+    stores: HashMap<u32, (u32, u64)>,
+    sequence: u64,
+    /// Address `ra` was last loaded from, until it is written otherwise.
+    ra_slot: Option<u32>,
+}
+
+impl ShadowStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracks `inst` at `pc`, executed with `registers` and followed by
+    /// `next`, and returns a mismatch if it is a return to the wrong place.
+    /// Returns from the outermost function are not checked, since its
+    /// caller is unknown.
+    pub fn check(
+        &mut self,
+        pc: u32,
+        inst: &Instruction,
+        registers: &IntRegisterSet,
+        next: u32,
+    ) -> Option<ReturnMismatch> {
+        use Instruction::*;
+        self.sequence += 1;
+        let address = |rs1, imm: i16| registers.read(rs1).wrapping_add(imm as u32);
+        match *inst {
+            SB { rs1, imm, .. } => self.store(pc, address(rs1, imm), 1),
+            SH { rs1, imm, .. } => self.store(pc, address(rs1, imm), 2),
+            SW { rs1, imm, .. } => self.store(pc, address(rs1, imm), 4),
+            LW {
+                rd: IntRegister::RA,
+                rs1,
+                imm,
+            } => self.ra_slot = Some(address(rs1, imm)),
+            _ if writes_ra(inst) => self.ra_slot = None,
+            _ => {}
+        }
+
+        let expected = self.stack.frames().last().map(|frame| frame.return_address);
+        self.stack.update(pc, inst, next);
+        match expected {
+            Some(expected) if callstack::is_return(inst) && next != expected => {
+                let store = self.ra_slot.and_then(|slot| {
+                    (slot..slot.wrapping_add(4))
+                        .filter_map(|byte| self.stores.get(&byte))
+                        .max_by_key(|&&(_, sequence)| sequence)
+                        .map(|&(store, _)| store)
+                });
+                Some(ReturnMismatch {
+                    pc,
+                    target: next,
+                    expected,
+                    slot: self.ra_slot,
+                    store,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn store(&mut self, pc: u32, address: u32, size: u32) {
+        for byte in 0..size {
+            let entry = (pc, self.sequence);
+            self.stores.insert(address.wrapping_add(byte), entry);
+        }
+    }
+}
+
+/// Whether `inst` writes `ra`, other than loading it.
+fn writes_ra(inst: &Instruction) -> bool {
+    inst.rd() == Some(IntRegister::RA)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn report_smashed_return_address() {
+        let mut registers = IntRegisterSet::new();
+        registers.write(IntRegister::SP, 0x8000);
+        let call = Instruction::JAL {
+            rd: IntRegister::RA,
+            imm: 0x100,
+        };
+        let ret = Instruction::JALR {
+            rd: IntRegister::Zero,
+            rs1: IntRegister::RA,
+            imm: 0,
+        };
+        let spill = Instruction::SW {
+            rs1: IntRegister::SP,
+            rs2: IntRegister::RA,
+            imm: 12,
+        };
+        let overflow = Instruction::SB {
+            rs1: IntRegister::A0,
+            rs2: IntRegister::A1,
+            imm: 0,
+        };
+        let reload = Instruction::LW {
+            rd: IntRegister::RA,
+            rs1: IntRegister::SP,
+            imm: 12,
+        };
+
+        let mut shadow = ShadowStack::new();
+        // A correct call and return
+        assert_eq!(shadow.check(0x1000, &call, &registers, 0x1100), None);
+        assert_eq!(shadow.check(0x1100, &ret, &registers, 0x1004), None);
+
+        // The callee spills ra, then a byte copy overwrites its slot
+        shadow.check(0x1004, &call, &registers, 0x1104);
+        shadow.check(0x1104, &spill, &registers, 0x1108);
+        registers.write(IntRegister::A0, 0x800D);
+        shadow.check(0x1200, &overflow, &registers, 0x1204);
+        shadow.check(0x1108, &reload, &registers, 0x110C);
+        let mismatch = shadow.check(0x110C, &ret, &registers, 0x4141).unwrap();
+        assert_eq!(
+            mismatch,
+            ReturnMismatch {
+                pc: 0x110C,
+                target: 0x4141,
+                expected: 0x1008,
+                slot: Some(0x800C),
+                store: Some(0x1200),
+            }
+        );
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Symbol {
+            name: "strcpy".to_string(),
+            address: 0x1200,
+            size: 0x20,
+        });
+        assert_eq!(
+            mismatch.describe(&symbols),
+            "return at 0x0000110c went to 0x00004141 instead of 0x00001008; ra was loaded \
+             from 0x0000800c, last written by the store at 0x00001200 <strcpy>"
+        );
+    }
+}
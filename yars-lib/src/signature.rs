@@ -0,0 +1,18 @@
+//! Conventions of the RISC-V architectural test suite, as driven by RISCOF:
+//! the test results are the words between `begin_signature` and
+//! `end_signature`, and the program halts by writing to `tohost`.
+
+use std::io::{self, Write};
+
+pub const BEGIN_SIGNATURE: &str = "begin_signature";
+pub const END_SIGNATURE: &str = "end_signature";
+pub const TOHOST: &str = "tohost";
+
+/// Writes a signature in the format expected by RISCOF, one word per line
+/// as 8 lowercase hex digits.
+pub fn write<W: Write>(words: &[u32], mut writer: W) -> io::Result<()> {
+    for word in words {
+        writeln!(writer, "{:08x}", word)?;
+    }
+    Ok(())
+}
@@ -1,13 +1,277 @@
-use crate::memory::{Memory, ProgramError};
-use crate::processor::{Processor, ProcessorError};
+use crate::device::{Console, Device, DeviceError, DeviceEventKind};
+use crate::entropy::Entropy;
+use crate::envcall::{Environment, Marker};
+use crate::fault::{Fault, Location};
+use crate::instruction::{Category, Instruction};
+use crate::interrupt::InterruptController;
+use crate::isa::{Isa, IsaError};
+use crate::latency::{Jitter, LatencyJitter};
+use crate::memory::{Memory, ProgramError, Section};
+use crate::plugin::Extension;
+use crate::processor::{Processor, ProcessorError, RetiredInstruction};
 use crate::register::IntRegister;
+use crate::replay::{Input, InputLog};
+use crate::reset::{self, ResetConfig};
+use crate::signature::{BEGIN_SIGNATURE, END_SIGNATURE, TOHOST};
+use crate::snapshot::{self, DeviceState, SnapshotError};
+use crate::symbol::SymbolTable;
+use crate::trap::TrapInfo;
+use goblin::error::Error;
 use std::convert::TryFrom;
-use std::io::Write;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::Range;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HookAction {
+    Continue,
+    /// Moves past the instruction without executing it. Returned by a pre
+    /// hook, [`Simulator::step`] still returns the instruction, but it is
+    /// not counted in the retired instructions or the cycles.
+    Skip,
+    Abort,
+}
+
+/// What the callback set with [`Simulator::set_interval`] asks for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IntervalAction {
+    Continue,
+    /// Returns [`ExitEvent::Paused`] from [`Simulator::run`], which picks up
+    /// where it left off when called again.
+    Pause,
+    /// Stops the program as an aborting hook does.
+    Cancel,
+}
+
+/// What an `ebreak` does.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum EbreakMode {
+    /// Ends the program with [`ProcessorError::Ebreak`], as the riscv-tests
+    /// and semihosting conventions expect.
+    #[default]
+    Exit,
+    /// Stops at a breakpoint planted in the program: the error is still
+    /// returned, but with the PC past the `ebreak`, so the host can inspect
+    /// the program and then continue it.
+    Break,
+}
+
+impl fmt::Display for EbreakMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EbreakMode::Exit => write!(f, "exit"),
+            EbreakMode::Break => write!(f, "break"),
+        }
+    }
+}
+
+impl FromStr for EbreakMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "exit" => Ok(EbreakMode::Exit),
+            "break" => Ok(EbreakMode::Break),
+            _ => Err("expected exit or break".to_string()),
+        }
+    }
+}
+
+/// Reason [`Simulator::run`] returned.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExitEvent {
+    EcallExit,
+    /// An `ebreak`. With [`EbreakMode::Break`], the PC is left past it and
+    /// calling [`Simulator::run`] again continues the program.
+    Ebreak,
+    Fault {
+        err: ProcessorError,
+        pc: u32,
+    },
+    CycleLimit,
+    /// The deadline of [`RunOptions`] passed.
+    Timeout,
+    /// The program wrote a non-zero value to `tohost`.
+    HostExit(u32),
+    /// The instruction at `pc` jumps to itself with no interrupt pending,
+    /// so the program can never make progress. See [`Simulator::is_stuck`].
+    Hang {
+        pc: u32,
+    },
+    /// The callback set with [`Simulator::set_interval`] asked to pause.
+    Paused,
+}
+
+/// Limits on a run besides the program stopping by itself, for
+/// [`Simulator::run_with`] and [`Simulator::step_with`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RunOptions {
+    /// Stops with [`ExitEvent::CycleLimit`] once the processor has retired
+    /// this many instructions in all.
+    pub max_instret: Option<usize>,
+    /// Stops with [`ExitEvent::CycleLimit`] once the processor has counted
+    /// this many cycles, which exceed the instructions with latency jitter.
+    pub max_cycles: Option<usize>,
+    /// Stops with [`ExitEvent::Timeout`] once this instant has passed. It is
+    /// only checked every 4096 instructions, to keep the clock out of the
+    /// way.
+    pub deadline: Option<Instant>,
+}
+
+/// What [`Simulator::step_with`] did.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Step {
+    /// The instruction executed, including the `ecall` or `ebreak` ending
+    /// the program, or `None` if it faulted or the run stopped before it.
+    pub retired: Option<RetiredInstruction>,
+    /// Why the run stopped, if it did.
+    pub exit: Option<ExitEvent>,
+}
+
+/// A guest function given to [`Simulator::call`], by symbol or address.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Callee<'a> {
+    Symbol(&'a str),
+    Address(u32),
+}
+
+impl<'a> From<&'a str> for Callee<'a> {
+    fn from(symbol: &'a str) -> Self {
+        Callee::Symbol(symbol)
+    }
+}
+
+impl From<u32> for Callee<'_> {
+    fn from(address: u32) -> Self {
+        Callee::Address(address)
+    }
+}
+
+/// Reason a function called with [`Simulator::call`] did not return.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CallError {
+    UnknownSymbol(String),
+    /// The stack has no room for the return trampoline and arguments.
+    StackOverflow,
+    /// The function stopped the program instead of returning.
+    Stopped(ExitEvent),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::UnknownSymbol(name) => write!(f, "unknown symbol {}", name),
+            CallError::StackOverflow => write!(f, "no room on the stack for the call"),
+            CallError::Stopped(exit) => write!(f, "function did not return: {:?}", exit),
+        }
+    }
+}
+
+/// Callback invoked with the processor state, the address of the current
+/// instruction and the instruction itself. Pre-execution hooks may skip the
+/// instruction; both kinds may abort the simulation.
+pub trait HookFn: FnMut(&Processor, u32, &Instruction) -> HookAction + Send {
+    fn clone_box(&self) -> Hook;
+}
+
+impl<F> HookFn for F
+where
+    F: FnMut(&Processor, u32, &Instruction) -> HookAction + Clone + Send + 'static,
+{
+    fn clone_box(&self) -> Hook {
+        Box::new(self.clone())
+    }
+}
+
+pub type Hook = Box<dyn HookFn>;
+
+/// Callback invoked with the processor state every few instructions, set
+/// with [`Simulator::set_interval`].
+pub trait IntervalFn: FnMut(&Processor) -> IntervalAction + Send {
+    fn clone_box(&self) -> IntervalHook;
+}
+
+impl<F> IntervalFn for F
+where
+    F: FnMut(&Processor) -> IntervalAction + Clone + Send + 'static,
+{
+    fn clone_box(&self) -> IntervalHook {
+        Box::new(self.clone())
+    }
+}
+
+pub type IntervalHook = Box<dyn IntervalFn>;
 
 pub struct Simulator<W: Write> {
     processor: Processor,
     logger: Option<W>,
+    symbols: SymbolTable,
+    segments: Vec<Range<u32>>,
+    sections: Vec<Section>,
+    tohost: Option<u32>,
+    pseudo: bool,
+    ebreak: EbreakMode,
+    environment: Environment,
+    seed: Option<u64>,
+    input_log: Option<InputLog>,
+    /// Console mapped with [`Simulator::map_console`], which receives the
+    /// input pushed to the simulator.
+    console: Option<Console>,
+    pre_hooks: Vec<Hook>,
+    post_hooks: Vec<Hook>,
+    /// Instructions between calls of the interval callback, and the callback.
+    interval: Option<(usize, IntervalHook)>,
+    /// Instructions executed by [`Simulator::run`] since the callback was last
+    /// called.
+    since_interval: usize,
+    /// Trap that ended the last call to [`Simulator::run`].
+    trap: Option<TrapInfo>,
+    /// Bits to flip in the next instruction word fetched.
+    fetch_fault: u32,
+    jitter: Option<Jitter>,
+    extensions: Vec<Box<dyn Extension>>,
+    /// Device state from the snapshot the simulator resumed from, restored
+    /// as the devices are mapped again.
+    saved_devices: Vec<DeviceState>,
+}
+
+impl<W: Write + Clone> Clone for Simulator<W> {
+    fn clone(&self) -> Self {
+        Self {
+            processor: self.processor.clone(),
+            logger: self.logger.clone(),
+            symbols: self.symbols.clone(),
+            segments: self.segments.clone(),
+            sections: self.sections.clone(),
+            tohost: self.tohost,
+            pseudo: self.pseudo,
+            ebreak: self.ebreak,
+            environment: self.environment.clone(),
+            seed: self.seed,
+            input_log: self.input_log.clone(),
+            console: self.console.clone(),
+            pre_hooks: self.pre_hooks.iter().map(|hook| hook.clone_box()).collect(),
+            post_hooks: self
+                .post_hooks
+                .iter()
+                .map(|hook| hook.clone_box())
+                .collect(),
+            interval: self
+                .interval
+                .as_ref()
+                .map(|(every, callback)| (*every, callback.clone_box())),
+            since_interval: self.since_interval,
+            trap: self.trap.clone(),
+            fetch_fault: self.fetch_fault,
+            jitter: self.jitter.clone(),
+            extensions: self.extensions.clone(),
+            saved_devices: self.saved_devices.clone(),
+        }
+    }
 }
 
 impl<W: Write> Simulator<W> {
@@ -17,62 +281,1243 @@ impl<W: Write> Simulator<W> {
         pc: Option<u32>,
         logger: Option<W>,
     ) -> Result<Self, ProgramError> {
+        let program = std::fs::read(program)
+            .map_err(Error::IO)
+            .map_err(ProgramError::Goblin)?;
+        Self::from_bytes(&program, memsize, pc, logger)
+    }
+
+    pub fn from_bytes(
+        program: &[u8],
+        memsize: u32,
+        pc: Option<u32>,
+        logger: Option<W>,
+    ) -> Result<Self, ProgramError> {
+        Self::from_bytes_at(program, 0, memsize, pc, logger)
+    }
+
+    /// Like [`Simulator::from_bytes`], but loads the program `offset` bytes
+    /// away from where it was linked; see [`Memory::load_program_bytes_at`].
+    pub fn from_bytes_at(
+        program: &[u8],
+        offset: u32,
+        memsize: u32,
+        pc: Option<u32>,
+        logger: Option<W>,
+    ) -> Result<Self, ProgramError> {
+        Self::from_linked_bytes(program, offset, memsize, pc, logger, |_| None)
+    }
+
+    /// Like [`Simulator::from_bytes_at`], but also loads the shared objects
+    /// a dynamically linked program needs; see
+    /// [`Memory::load_linked_program`].
+    pub fn from_linked_bytes<F>(
+        program: &[u8],
+        offset: u32,
+        memsize: u32,
+        pc: Option<u32>,
+        logger: Option<W>,
+        library: F,
+    ) -> Result<Self, ProgramError>
+    where
+        F: FnMut(&str) -> Option<Vec<u8>>,
+    {
         let mut memory = Memory::new(memsize);
-        let def_pc = memory.load_program(program)?;
+        let program = memory.load_linked_program(program, offset, library)?;
         let mut processor = Processor::new(memory);
-        processor.set_pc(if let Some(pc) = pc { pc } else { def_pc });
-        Ok(Self { processor, logger })
+        processor.set_pc(if let Some(pc) = pc { pc } else { program.entry });
+        processor.set_code(program.code);
+        ResetConfig::default().apply(&mut processor, &program.symbols);
+        Ok(Self {
+            processor,
+            logger,
+            tohost: program.symbols.get(TOHOST).map(|sym| sym.address),
+            symbols: program.symbols,
+            segments: program.segments,
+            sections: program.sections,
+            pseudo: false,
+            ebreak: EbreakMode::default(),
+            environment: Environment::new(),
+            seed: None,
+            input_log: None,
+            console: None,
+            pre_hooks: Vec::new(),
+            post_hooks: Vec::new(),
+            interval: None,
+            since_interval: 0,
+            trap: None,
+            fetch_fault: 0,
+            jitter: None,
+            extensions: Vec::new(),
+            saved_devices: Vec::new(),
+        })
+    }
+
+    pub fn resume_from<P: AsRef<Path>>(
+        snapshot: P,
+        logger: Option<W>,
+    ) -> Result<Self, SnapshotError> {
+        let reader = BufReader::new(File::open(snapshot)?);
+        let (processor, symbols, saved_devices) = snapshot::load(reader)?;
+        Ok(Self {
+            processor,
+            logger,
+            tohost: symbols.get(TOHOST).map(|sym| sym.address),
+            symbols,
+            segments: Vec::new(),
+            sections: Vec::new(),
+            pseudo: false,
+            ebreak: EbreakMode::default(),
+            environment: Environment::new(),
+            seed: None,
+            input_log: None,
+            console: None,
+            pre_hooks: Vec::new(),
+            post_hooks: Vec::new(),
+            interval: None,
+            since_interval: 0,
+            trap: None,
+            fetch_fault: 0,
+            jitter: None,
+            extensions: Vec::new(),
+            saved_devices,
+        })
+    }
+
+    pub fn save_snapshot<P: AsRef<Path>>(&self, snapshot: P) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(snapshot)?);
+        snapshot::save(&self.processor, &self.symbols, writer)
+    }
+
+    pub fn add_pre_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&Processor, u32, &Instruction) -> HookAction + Clone + Send + 'static,
+    {
+        self.pre_hooks.push(Box::new(hook));
+    }
+
+    pub fn add_post_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&Processor, u32, &Instruction) -> HookAction + Clone + Send + 'static,
+    {
+        self.post_hooks.push(Box::new(hook));
+    }
+
+    /// Calls `callback` after every `instructions` executed by
+    /// [`Simulator::run`], so an embedder can keep a user interface
+    /// responsive or stop a long-running guest. The count carries over
+    /// between calls to `run`. `instructions` must not be zero.
+    pub fn set_interval<F>(&mut self, instructions: usize, callback: F)
+    where
+        F: FnMut(&Processor) -> IntervalAction + Clone + Send + 'static,
+    {
+        assert!(
+            instructions > 0,
+            "instructions between callbacks must not be zero"
+        );
+        self.interval = Some((instructions, Box::new(callback)));
+        self.since_interval = 0;
+    }
+
+    pub fn clear_interval(&mut self) {
+        self.interval = None;
+    }
+
+    /// Executes the words the decoder does not know with `extension`, if
+    /// no extension added before implements them.
+    pub fn add_extension(&mut self, extension: Box<dyn Extension>) {
+        self.extensions.push(extension);
+    }
+
+    /// Handles the yars environment call described in [`crate::envcall`]
+    /// instead of stopping at it.
+    pub fn set_env_calls(&mut self, env_calls: bool) {
+        self.environment.enabled = env_calls;
+    }
+
+    /// Stops or resumes writing executed instructions to the logger, as
+    /// the trace markers of the environment call do.
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.environment.tracing = tracing;
+    }
+
+    /// Markers set by the program through the environment call since the
+    /// last call.
+    pub fn take_markers(&mut self) -> Vec<Marker> {
+        std::mem::take(&mut self.environment.markers)
+    }
+
+    pub fn set_pseudo(&mut self, pseudo: bool) {
+        self.pseudo = pseudo;
+    }
+
+    /// Sets what an `ebreak` does, as a program exit or as a breakpoint.
+    /// `ebreak`s still end functions run with [`Simulator::call`].
+    pub fn set_ebreak_mode(&mut self, mode: EbreakMode) {
+        self.ebreak = mode;
+    }
+
+    /// Makes the simulation deterministic by deriving the time and random
+    /// numbers observed through [`Simulator::entropy`] from `seed`.
+    pub fn set_deterministic(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    /// Makes every instruction take a random number of extra cycles within
+    /// `jitter`, drawn from the seed given to
+    /// [`Simulator::set_deterministic`] before, if any.
+    pub fn set_latency_jitter(&mut self, jitter: Option<LatencyJitter>) {
+        let seed = self.seed;
+        self.jitter = jitter.map(|bounds| Jitter::new(bounds, seed));
+    }
+
+    /// Records the inputs observed through [`Simulator::entropy`] to `log`,
+    /// or replays them from it. Sources created before are not affected.
+    pub fn set_input_log(&mut self, log: Option<InputLog>) {
+        self.input_log = log;
+    }
+
+    /// Returns the source devices should use for nondeterministic inputs.
+    pub fn entropy(&self) -> Entropy {
+        let entropy = match self.seed {
+            Some(seed) => Entropy::seeded(seed),
+            None => Entropy::host(),
+        };
+        match &self.input_log {
+            Some(log) => entropy.with_log(log.clone()),
+            None => entropy,
+        }
+    }
+
+    pub fn processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    pub fn processor_mut(&mut self) -> &mut Processor {
+        &mut self.processor
+    }
+
+    /// Reinitializes the integer registers according to `config` and, if
+    /// it asks for it, poisons memory outside the loaded segments.
+    pub fn reset(&mut self, config: &ResetConfig) {
+        config.apply(&mut self.processor, &self.symbols);
+        config.poison_memory(self.processor.memory_mut(), &self.segments);
+    }
+
+    /// Places `args` and `env` on the stack as the program's `argv` and
+    /// `envp`. See [`reset::push_args`].
+    pub fn set_args(&mut self, args: &[String], env: &[String]) -> Result<(), ProcessorError> {
+        reset::push_args(&mut self.processor, args, env)
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.processor.pc()
+    }
+
+    pub fn set_pc(&mut self, pc: u32) {
+        self.processor.set_pc(pc);
+    }
+
+    pub fn read_reg(&self, reg: IntRegister) -> u32 {
+        self.processor.registers().read(reg)
+    }
+
+    pub fn write_reg(&mut self, reg: IntRegister, val: u32) {
+        self.processor.registers_mut().write(reg, val);
+    }
+
+    /// Fills `buffer` with the memory contents starting at `address`. Fails
+    /// with `IllegalAccess` if the range is not backed by memory.
+    pub fn read_mem(&self, address: u32, buffer: &mut [u8]) -> Result<(), ProcessorError> {
+        let range = self.mem_range(address, buffer.len())?;
+        buffer.copy_from_slice(&self.processor.memory().as_bytes()[range]);
+        Ok(())
+    }
+
+    pub fn write_mem(&mut self, address: u32, data: &[u8]) -> Result<(), ProcessorError> {
+        let range = self.mem_range(address, data.len())?;
+        self.processor.memory_mut().as_bytes_mut()[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Flips the bit of a register or memory byte chosen by `fault` now, or
+    /// of the next instruction word fetched. The cycle of `fault` is
+    /// ignored; see [`crate::fault::run_with_fault`] to inject it when it is due.
+    pub fn inject(&mut self, fault: &Fault) -> Result<(), ProcessorError> {
+        match fault.location {
+            Location::Register(reg) => {
+                let val = self.read_reg(reg) ^ (1 << fault.bit);
+                self.write_reg(reg, val);
+            }
+            Location::Memory(address) => {
+                let mut byte = [0u8];
+                self.read_mem(address, &mut byte)?;
+                self.write_mem(address, &[byte[0] ^ (1 << fault.bit)])?;
+            }
+            Location::Instruction => self.fetch_fault ^= 1 << fault.bit,
+        }
+        Ok(())
+    }
+
+    fn mem_range(&self, address: u32, len: usize) -> Result<Range<usize>, ProcessorError> {
+        match u32::try_from(len) {
+            Ok(size) if self.processor.memory().contains(address, size) => {
+                Ok(address as usize..address as usize + len)
+            }
+            _ => Err(ProcessorError::IllegalAccess {
+                pc: self.pc(),
+                address,
+            }),
+        }
+    }
+
+    /// Loads an additional program image into memory, merging its symbols
+    /// and moving the PC to its entry point.
+    pub fn load_program<P: AsRef<Path>>(&mut self, program: P) -> Result<(), ProgramError> {
+        let program = self.processor.memory_mut().load_program(program)?;
+        for symbol in program.symbols.iter() {
+            self.symbols.insert(symbol.clone());
+        }
+        self.segments.extend(program.segments);
+        self.sections.extend(program.sections);
+        self.tohost = self.symbols.get(TOHOST).map(|sym| sym.address);
+        self.processor.set_pc(program.entry);
+        Ok(())
+    }
+
+    /// Maps `device` at `base`. After resuming from a snapshot, a device
+    /// with the name of one saved at `base` gets its state back.
+    pub fn map_device(
+        &mut self,
+        base: u32,
+        size: u32,
+        mut device: Box<dyn Device>,
+    ) -> Result<(), DeviceError> {
+        let saved = self
+            .saved_devices
+            .iter()
+            .position(|saved| saved.base == base && saved.name == device.name());
+        if let Some(saved) = saved {
+            device.restore(&self.saved_devices[saved].state);
+        }
+        self.processor.map_device(base, size, device)?;
+        if let Some(saved) = saved {
+            self.saved_devices.remove(saved);
+        }
+        Ok(())
+    }
+
+    /// Maps `console` at `base`, as [`Simulator::map_device`] does, and
+    /// makes it the one [`Simulator::push_console`] queues input for.
+    pub fn map_console(&mut self, base: u32, console: Console) -> Result<(), DeviceError> {
+        self.map_device(base, Console::SIZE, Box::new(console.clone()))?;
+        self.console = Some(console);
+        Ok(())
+    }
+
+    /// Queues `bytes` for the guest to read from the console mapped with
+    /// [`Simulator::map_console`], recording them to the input log before
+    /// the next instruction. While replaying, the logged input is delivered
+    /// instead, at the instruction it was recorded before.
+    pub fn push_console(&mut self, bytes: &[u8]) {
+        if self.deliver(Input::Console(bytes.to_vec())) {
+            if let Some(console) = &self.console {
+                console.push_input(bytes);
+            }
+        }
+    }
+
+    pub fn set_isa(&mut self, isa: Isa) -> Result<(), IsaError> {
+        self.processor.set_isa(isa)
+    }
+
+    pub fn map_interrupt_controller(&mut self, base: u32) -> Result<(), DeviceError> {
+        self.processor.map_interrupt_controller(base)
+    }
+
+    /// Asserts external interrupt `line` (1 to 31) until it is cleared by
+    /// the host or completed by the guest.
+    /// Like console input, the change is recorded to the input log, and
+    /// replaced by the logged one while replaying.
+    pub fn raise_irq(&mut self, line: u32) -> Result<(), DeviceError> {
+        self.set_irq(line, true)
+    }
+
+    pub fn clear_irq(&mut self, line: u32) -> Result<(), DeviceError> {
+        self.set_irq(line, false)
+    }
+
+    fn set_irq(&mut self, line: u32, raised: bool) -> Result<(), DeviceError> {
+        // Rejects invalid lines before they are recorded
+        InterruptController::new().raise(line)?;
+        if self.deliver(Input::Irq { line, raised }) {
+            self.apply_irq(line, raised);
+        }
+        Ok(())
+    }
+
+    fn apply_irq(&mut self, line: u32, raised: bool) {
+        let interrupts = self.processor.interrupts_mut();
+        let _ = match raised {
+            true => interrupts.raise(line),
+            false => interrupts.clear(line),
+        };
+        self.processor
+            .log_device_event(DeviceEventKind::Irq { line, raised });
+    }
+
+    /// Records an input the host delivers now, and tells whether it should
+    /// reach the guest.
+    fn deliver(&self, input: Input) -> bool {
+        match &self.input_log {
+            Some(log) => log.deliver(self.processor.instret(), input),
+            None => true,
+        }
+    }
+
+    /// Delivers the logged host inputs due before the next instruction.
+    fn replay_delivered(&mut self) {
+        let due = match &self.input_log {
+            Some(log) if log.is_replaying() => log.due(self.processor.instret()),
+            _ => return,
+        };
+        for input in due {
+            match input {
+                Input::Console(bytes) => {
+                    if let Some(console) = &self.console {
+                        console.push_input(&bytes);
+                    }
+                }
+                Input::Irq { line, raised } => self.apply_irq(line, raised),
+                Input::Time(_) | Input::Random(_) => {}
+            }
+        }
+    }
+
+    /// Trap frame of the ecall, ebreak or fault that ended the last call to
+    /// [`Simulator::run`], with the registers as the trapping instruction
+    /// left them.
+    pub fn trap(&self) -> Option<&TrapInfo> {
+        self.trap.as_ref()
+    }
+
+    pub fn logger_mut(&mut self) -> Option<&mut W> {
+        self.logger.as_mut()
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Address ranges of the loaded program segments. Empty when resuming
+    /// from a snapshot.
+    pub fn segments(&self) -> &[Range<u32>] {
+        &self.segments
+    }
+
+    /// Allocated sections of the loaded program. Empty when resuming from a
+    /// snapshot.
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    /// Whether `retired`, the instruction just executed, left the program
+    /// stuck: a `jal` or a taken branch to itself repeats forever, since
+    /// nothing it reads changes, unless an enabled interrupt is pending for
+    /// the guest to react to. WFI is not supported, so it is never reported.
+    pub fn is_stuck(&self, retired: &RetiredInstruction) -> bool {
+        let jump = match retired.inst {
+            Instruction::JAL { .. } => true,
+            inst => inst.category() == Category::Branch,
+        };
+        jump && self.processor.pc() == retired.pc && self.processor.interrupts().claimable() == 0
+    }
+
+    /// Value last written to the `tohost` symbol, if the program has one and
+    /// it is no longer zero. Like the signature, it is read in the byte
+    /// order of the processor.
+    pub fn tohost(&self) -> Option<u32> {
+        let address = self.tohost?;
+        let memory = self.processor.memory();
+        let order = self.processor.endianness();
+        match memory.contains(address, 4) {
+            true => Some(order.swap(memory.read_word(address), 4)).filter(|&value| value != 0),
+            false => None,
+        }
+    }
+
+    /// Words between the `begin_signature` and `end_signature` symbols.
+    pub fn signature(&self) -> Option<Vec<u32>> {
+        let begin = self.symbols.get(BEGIN_SIGNATURE)?.address;
+        let end = self.symbols.get(END_SIGNATURE)?.address;
+        let memory = self.processor.memory();
+        let order = self.processor.endianness();
+        match end >= begin && memory.contains(begin, end - begin) {
+            true => Some(
+                (begin..end)
+                    .step_by(4)
+                    .map(|a| order.swap(memory.read_word(a), 4))
+                    .collect(),
+            ),
+            false => None,
+        }
     }
 
     pub fn cycles(&self) -> usize {
         self.processor.cycles()
     }
 
-    pub fn step(&mut self) -> Result<(), ProcessorError> {
+    /// Digest of the architectural state: the PC, the registers and every
+    /// non-zero page of memory. Two runs ending in the same state have the
+    /// same hash, which is stable across builds and platforms.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write(&self.pc().to_le_bytes());
+        for reg in 0..32 {
+            let reg = IntRegister::try_from(reg).unwrap();
+            hash.write(&self.read_reg(reg).to_le_bytes());
+        }
+        for (address, page) in self.processor.memory().pages() {
+            hash.write(&address.to_le_bytes());
+            hash.write(page);
+        }
+        hash.0
+    }
+
+    pub fn iter(&mut self) -> Steps<'_, W> {
+        Steps {
+            simulator: self,
+            done: false,
+        }
+    }
+
+    pub fn step(&mut self) -> Result<RetiredInstruction, ProcessorError> {
+        self.replay_delivered();
         let pc = self.processor.pc();
-        let inst = self.processor.fetch()?;
+        let mut inst = match self.processor.fetch() {
+            Err(ProcessorError::InvalidOpcode { raw, .. }) if !self.extensions.is_empty() => {
+                Instruction::CUSTOM { raw }
+            }
+            result => result?,
+        };
+        let mut raw = self.processor.memory().read_word(pc);
+        if self.fetch_fault != 0 {
+            raw ^= std::mem::take(&mut self.fetch_fault);
+            inst = match Instruction::try_from(raw) {
+                Ok(inst) => inst,
+                Err(_) if !self.extensions.is_empty() => Instruction::CUSTOM { raw },
+                Err(_) => return Err(ProcessorError::InvalidOpcode { pc, raw }),
+            };
+        }
+        let retired = RetiredInstruction { pc, raw, inst };
+
+        match Self::run_hooks(&mut self.pre_hooks, &self.processor, pc, &inst) {
+            HookAction::Continue => {}
+            HookAction::Skip => {
+                self.processor.set_pc(pc.wrapping_add(4));
+                return Ok(retired);
+            }
+            HookAction::Abort => return Err(ProcessorError::Abort),
+        }
 
-        if let Some(logger) = &mut self.logger {
-            let raw_inst = self.processor.memory().read_word(pc);
+        let logger = match self.environment.tracing {
+            true => self.logger.as_mut(),
+            false => None,
+        };
+        if let Some(logger) = logger {
             let registers = self.processor.registers();
 
-            let rd_id = ((raw_inst >> 7) & 0b11111) as u8;
-            let rs1_id = ((raw_inst >> 15) & 0b11111) as u8;
-            let rs2_id = ((raw_inst >> 20) & 0b11111) as u8;
+            let rd_id = ((raw >> 7) & 0b11111) as u8;
+            let rs1_id = ((raw >> 15) & 0b11111) as u8;
+            let rs2_id = ((raw >> 20) & 0b11111) as u8;
 
             let rs1 = registers.read(IntRegister::try_from(rs1_id).unwrap());
             let rs2 = registers.read(IntRegister::try_from(rs2_id).unwrap());
 
-            self.processor.execute(inst)?;
+            let result = Self::execute(&mut self.processor, &mut self.extensions, inst);
+            let result = Self::take_breakpoint(&mut self.processor, self.ebreak, result);
+            self.environment.call(result, &mut self.processor)?;
 
             let registers = self.processor.registers();
             let rd = registers.read(IntRegister::try_from(rd_id).unwrap());
+            let inst = inst.display_at(pc).with_symbols(&self.symbols);
+            let inst = match self.pseudo {
+                true => format!("{:#}", inst),
+                false => format!("{}", inst),
+            };
 
             writeln!(
                 logger,
                 "[PC={:08X}] [{:08X}] [x{:02}={:08X}] \
                  [x{:02}={:08X}] [x{:02}={:08X}] {}",
-                pc, raw_inst, rd_id, rd, rs1_id, rs1, rs2_id, rs2, inst
+                pc, raw, rd_id, rd, rs1_id, rs1, rs2_id, rs2, inst
             )
             .unwrap();
         } else {
-            self.processor.execute(inst)?;
+            let result = Self::execute(&mut self.processor, &mut self.extensions, inst);
+            let result = Self::take_breakpoint(&mut self.processor, self.ebreak, result);
+            self.environment.call(result, &mut self.processor)?;
         }
 
-        if pc == self.processor.pc() {
-            self.processor.set_pc(pc.wrapping_add(4));
+        if let Some(jitter) = &mut self.jitter {
+            self.processor.add_cycles(jitter.extra(&inst));
         }
 
-        Ok(())
+        match Self::run_hooks(&mut self.post_hooks, &self.processor, pc, &inst) {
+            HookAction::Abort => Err(ProcessorError::Abort),
+            _ => Ok(retired),
+        }
     }
 
-    pub fn run(&mut self) -> Result<(), ProcessorError> {
+    /// Executes `inst`, handing custom instructions to the first extension
+    /// that implements them.
+    fn execute(
+        processor: &mut Processor,
+        extensions: &mut [Box<dyn Extension>],
+        inst: Instruction,
+    ) -> Result<(), ProcessorError> {
+        let raw = match inst {
+            Instruction::CUSTOM { raw } => raw,
+            _ => return processor.execute(inst),
+        };
+
+        let pc = processor.pc();
+        for extension in extensions {
+            if let Some(result) = extension.execute(processor, raw) {
+                result?;
+                if processor.pc() == pc {
+                    processor.set_pc(pc.wrapping_add(4));
+                }
+                processor.retire();
+                return Ok(());
+            }
+        }
+        Err(ProcessorError::InvalidOpcode { pc, raw })
+    }
+
+    /// Moves past the `ebreak` that gave `result`, if it is a breakpoint.
+    fn take_breakpoint(
+        processor: &mut Processor,
+        mode: EbreakMode,
+        result: Result<(), ProcessorError>,
+    ) -> Result<(), ProcessorError> {
+        if let (Err(ProcessorError::Ebreak), EbreakMode::Break) = (result, mode) {
+            processor.set_pc(processor.pc().wrapping_add(4));
+        }
+        result
+    }
+
+    fn run_hooks(
+        hooks: &mut [Hook],
+        processor: &Processor,
+        pc: u32,
+        inst: &Instruction,
+    ) -> HookAction {
+        hooks
+            .iter_mut()
+            .map(|hook| hook(processor, pc, inst))
+            .find(|action| *action != HookAction::Continue)
+            .unwrap_or(HookAction::Continue)
+    }
+
+    /// Calls a guest function with `args` as the psABI lays them out: the
+    /// first eight in `a0`-`a7` and the rest on the stack. It returns to an
+    /// `ebreak` trampoline stored below the stack pointer, and the value it
+    /// left in `a0` is returned. The PC, `ra`, `sp` and the memory used for
+    /// the call are restored afterwards; other registers are left as the
+    /// function left them.
+    pub fn call<'a, C: Into<Callee<'a>>>(
+        &mut self,
+        function: C,
+        args: &[u32],
+    ) -> Result<u32, CallError> {
+        let entry = match function.into() {
+            Callee::Address(address) => address,
+            Callee::Symbol(name) => match self.symbols.get(name) {
+                Some(symbol) => symbol.address,
+                None => return Err(CallError::UnknownSymbol(name.to_string())),
+            },
+        };
+
+        let (pc, ra, sp) = (
+            self.pc(),
+            self.read_reg(IntRegister::RA),
+            self.read_reg(IntRegister::SP),
+        );
+        let (in_registers, stacked) = args.split_at(args.len().min(8));
+        let trampoline = (sp & !15).checked_sub(16).ok_or(CallError::StackOverflow)?;
+        let frame = trampoline
+            .checked_sub(4 * stacked.len() as u32)
+            .ok_or(CallError::StackOverflow)?
+            & !15;
+        let mut saved = vec![0u8; (trampoline + 4 - frame) as usize];
+        self.read_mem(frame, &mut saved)
+            .map_err(|_| CallError::StackOverflow)?;
+
+        // ebreak
+        self.write_mem(trampoline, &0x0010_0073u32.to_le_bytes())
+            .unwrap();
+        for (i, arg) in in_registers.iter().enumerate() {
+            let reg = IntRegister::try_from(IntRegister::A0 as u8 + i as u8).unwrap();
+            self.write_reg(reg, *arg);
+        }
+        let order = self.processor.endianness();
+        for (i, arg) in stacked.iter().enumerate() {
+            let address = frame + 4 * i as u32;
+            self.write_mem(address, &order.swap(*arg, 4).to_le_bytes())
+                .unwrap();
+        }
+        self.write_reg(IntRegister::RA, trampoline);
+        self.write_reg(IntRegister::SP, frame);
+        self.processor.set_pc(entry);
+
+        let mode = std::mem::take(&mut self.ebreak);
+        let result = match self.run(None) {
+            ExitEvent::Ebreak if self.pc() == trampoline => Ok(self.read_reg(IntRegister::A0)),
+            exit => Err(CallError::Stopped(exit)),
+        };
+        self.ebreak = mode;
+
+        self.write_mem(frame, &saved).unwrap();
+        self.write_reg(IntRegister::RA, ra);
+        self.write_reg(IntRegister::SP, sp);
+        self.processor.set_pc(pc);
+        result
+    }
+
+    /// Runs until the program exits, faults, writes to `tohost`, the interval
+    /// callback pauses it or, if `limit` is given, until that many
+    /// instructions have retired. Instructions skipped by a hook do not
+    /// count.
+    pub fn run(&mut self, limit: Option<usize>) -> ExitEvent {
+        let options = RunOptions {
+            max_instret: limit.map(|limit| self.processor.instret() + limit),
+            ..RunOptions::default()
+        };
+        self.run_with(&options)
+    }
+
+    /// Runs until the program stops by itself or a limit of `options` is
+    /// reached.
+    pub fn run_with(&mut self, options: &RunOptions) -> ExitEvent {
         loop {
-            match self.step() {
-                Ok(()) => continue,
-                Err(ProcessorError::Ecall) | Err(ProcessorError::Ebreak) => break Ok(()),
-                e => break e,
+            if let Some(exit) = self.step_with(options).exit {
+                return exit;
+            }
+        }
+    }
+
+    /// Executes one instruction and tells whether the run it is part of
+    /// stops there, as [`Simulator::run_with`] would. Hosts that need to look
+    /// at every instruction run the program with this instead.
+    pub fn step_with(&mut self, options: &RunOptions) -> Step {
+        let stop = |exit| Step {
+            retired: None,
+            exit: Some(exit),
+        };
+        self.trap = None;
+        let instret = self.processor.instret();
+        if matches!(options.max_instret, Some(max) if instret >= max)
+            || matches!(options.max_cycles, Some(max) if self.cycles() >= max)
+        {
+            return stop(ExitEvent::CycleLimit);
+        }
+        if instret.is_multiple_of(4096)
+            && matches!(options.deadline, Some(t) if Instant::now() >= t)
+        {
+            return stop(ExitEvent::Timeout);
+        }
+
+        let pc = self.processor.pc();
+        let retired = match self.step() {
+            Ok(retired) => retired,
+            Err(err) => {
+                let exit = match err {
+                    ProcessorError::Ecall => ExitEvent::EcallExit,
+                    ProcessorError::Ebreak => ExitEvent::Ebreak,
+                    _ => ExitEvent::Fault { err, pc },
+                };
+                // A breakpoint is not a trap, as the program goes on
+                let breakpoint = err == ProcessorError::Ebreak && self.ebreak == EbreakMode::Break;
+                if !breakpoint {
+                    self.trap = TrapInfo::new(err, &self.processor);
+                }
+                let retired = match err {
+                    ProcessorError::Ecall | ProcessorError::Ebreak => Some(RetiredInstruction {
+                        pc,
+                        raw: self.processor.memory().read_word(pc),
+                        inst: match err {
+                            ProcessorError::Ecall => Instruction::ECALL,
+                            _ => Instruction::EBREAK,
+                        },
+                    }),
+                    _ => None,
+                };
+                return Step {
+                    retired,
+                    exit: Some(exit),
+                };
+            }
+        };
+
+        let tohost = match retired.inst.category() {
+            Category::Store => self.tohost(),
+            _ => None,
+        };
+        let exit = match (self.is_stuck(&retired), tohost) {
+            (true, _) => Some(ExitEvent::Hang { pc }),
+            (false, Some(value)) => Some(ExitEvent::HostExit(value)),
+            (false, None) => self.check_interval(pc),
+        };
+        Step {
+            retired: Some(retired),
+            exit,
+        }
+    }
+
+    /// Calls the interval callback if it is due.
+    fn check_interval(&mut self, pc: u32) -> Option<ExitEvent> {
+        let (every, callback) = self.interval.as_mut()?;
+        self.since_interval += 1;
+        if self.since_interval < *every {
+            return None;
+        }
+        self.since_interval = 0;
+        match callback(&self.processor) {
+            IntervalAction::Continue => None,
+            IntervalAction::Pause => Some(ExitEvent::Paused),
+            IntervalAction::Cancel => {
+                let err = ProcessorError::Abort;
+                Some(ExitEvent::Fault { err, pc })
+            }
+        }
+    }
+}
+
+/// 64-bit FNV-1a hash.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xCBF2_9CE4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+}
+
+/// Iterator over the instructions executed by a simulator, created with
+/// [`Simulator::iter`]. It stops after yielding the first error, which
+/// includes the `Ecall`/`Ebreak` ending the program.
+pub struct Steps<'a, W: Write> {
+    simulator: &'a mut Simulator<W>,
+    done: bool,
+}
+
+impl<W: Write> Iterator for Steps<'_, W> {
+    type Item = Result<RetiredInstruction, ProcessorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.simulator.step();
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::processor::Endianness;
+    use crate::symbol::Symbol;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    pub const BASE: u32 = 0x1000;
+
+    /// Writes a minimal statically linked RV32 executable holding `code` at
+    /// `BASE` and returns its path.
+    pub fn program(name: &str, code: &[u32]) -> PathBuf {
+        let size = (code.len() * 4) as u32;
+        let mut elf = vec![0x7F, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        for half in &[2u16, 243] {
+            elf.extend_from_slice(&half.to_le_bytes());
+        }
+        for word in &[1u32, BASE, 52, 0, 0] {
+            elf.extend_from_slice(&word.to_le_bytes());
+        }
+        for half in &[52u16, 32, 1, 40, 0, 0] {
+            elf.extend_from_slice(&half.to_le_bytes());
+        }
+        for word in &[1u32, 84, BASE, BASE, size, size, 5, 4] {
+            elf.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in code {
+            elf.extend_from_slice(&word.to_le_bytes());
+        }
+
+        let path = std::env::temp_dir().join(format!("yars-{}-{}.elf", std::process::id(), name));
+        std::fs::write(&path, elf).unwrap();
+        path
+    }
+
+    pub fn simulator(name: &str, code: &[u32]) -> Simulator<Vec<u8>> {
+        Simulator::new(program(name, code), 0x10000, None, None).unwrap()
+    }
+
+    #[test]
+    fn pre_hook_skips_instruction() {
+        // addi a0, zero, 1; addi a0, a0, 1; ecall
+        let mut sim = simulator("pre-hook", &[0x00100513, 0x00150513, 0x00000073]);
+        let values = Arc::new(Mutex::new(Vec::new()));
+        let recorded = values.clone();
+
+        sim.add_pre_hook(|_, pc, _| match pc {
+            0x1004 => HookAction::Skip,
+            _ => HookAction::Continue,
+        });
+        sim.add_post_hook(move |processor, _, _| {
+            let a0 = processor.registers().read(IntRegister::A0);
+            recorded.lock().unwrap().push(a0);
+            HookAction::Continue
+        });
+
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(*values.lock().unwrap(), vec![1]);
+        assert_eq!((sim.processor().instret(), sim.cycles()), (2, 2));
+    }
+
+    #[test]
+    fn hook_aborts_simulation() {
+        let mut sim = simulator("abort-hook", &[0x00100513, 0x00150513, 0x00000073]);
+        sim.add_post_hook(|_, _, _| HookAction::Abort);
+        let err = ProcessorError::Abort;
+        assert_eq!(sim.run(None), ExitEvent::Fault { err, pc: 0x1000 });
+        assert_eq!(sim.cycles(), 1);
+    }
+
+    #[test]
+    fn iterate_over_retired_instructions() {
+        let mut sim = simulator("iter", &[0x00100513, 0x00150513, 0x00000073]);
+        let pcs = sim
+            .iter()
+            .take_while(Result::is_ok)
+            .map(|retired| retired.unwrap().pc)
+            .collect::<Vec<_>>();
+
+        assert_eq!(pcs, vec![0x1000, 0x1004]);
+        assert_eq!(sim.iter().last(), Some(Err(ProcessorError::Ecall)));
+    }
+
+    #[test]
+    fn clone_template_simulator() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let mut template = simulator("template", &[0x00150513, 0x00000073]);
+        template.add_post_hook(|_, _, _| HookAction::Continue);
+        template.write_reg(IntRegister::A0, 41);
+
+        let mut sim = template.clone();
+        assert_send(&sim);
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.read_reg(IntRegister::A0), 42);
+        assert_eq!(template.read_reg(IntRegister::A0), 41);
+    }
+
+    #[test]
+    fn hash_architectural_state() {
+        // sw a0, 0x100(zero); ecall
+        let mut a = simulator("hash", &[0x10A02023, 0x00000073]);
+        let b = a.clone();
+        assert_eq!(a.state_hash(), b.state_hash());
+
+        a.write_reg(IntRegister::A0, 7);
+        let hash = a.state_hash();
+        assert_ne!(hash, b.state_hash());
+        a.step().unwrap();
+        assert_ne!(a.state_hash(), hash);
+    }
+
+    #[test]
+    fn stop_at_instruction_limit() {
+        // addi a0, a0, 1; j -4
+        let mut sim = simulator("limit", &[0x00150513, 0xFFDFF06F]);
+        assert_eq!(sim.run(Some(100)), ExitEvent::CycleLimit);
+        assert_eq!(sim.cycles(), 100);
+    }
+
+    #[test]
+    fn pause_and_cancel_from_interval() {
+        // addi a0, a0, 1; j -4
+        let mut sim = simulator("interval", &[0x00150513, 0xFFDFF06F]);
+        sim.set_interval(10, |processor: &Processor| {
+            match processor.registers().read(IntRegister::A0) {
+                15 => IntervalAction::Pause,
+                a0 if a0 >= 20 => IntervalAction::Cancel,
+                _ => IntervalAction::Continue,
             }
+        });
+        assert_eq!(sim.run(None), ExitEvent::Paused);
+        assert_eq!(sim.cycles(), 30);
+
+        let err = ProcessorError::Abort;
+        let pc = BASE + 4;
+        assert_eq!(sim.run(None), ExitEvent::Fault { err, pc });
+        assert_eq!(sim.cycles(), 40);
+    }
+
+    #[test]
+    fn call_guest_functions() {
+        // sum3: add a0, a0, a1; add a0, a0, a2; ret
+        // ninth: lw a0, 0(sp); ret
+        let code = [0x00B50533, 0x00C50533, 0x00008067, 0x00012503, 0x00008067];
+        let mut sim = simulator("call", &code);
+        sim.symbols.insert(Symbol {
+            name: "sum3".to_string(),
+            address: BASE,
+            size: 12,
+        });
+        let (pc, sp) = (sim.pc(), sim.read_reg(IntRegister::SP));
+
+        assert_eq!(sim.call("sum3", &[1, 2, 39]), Ok(42));
+        let args = (1..=9).collect::<Vec<_>>();
+        assert_eq!(sim.call(BASE + 12, &args), Ok(9));
+        assert_eq!((sim.pc(), sim.read_reg(IntRegister::SP)), (pc, sp));
+        assert_eq!(
+            sim.call("missing", &[]),
+            Err(CallError::UnknownSymbol("missing".to_string()))
+        );
+        // Zeroed memory past the code is not a valid instruction
+        assert!(matches!(
+            sim.call(BASE + 20, &[]),
+            Err(CallError::Stopped(ExitEvent::Fault { .. }))
+        ));
+    }
+
+    #[test]
+    fn halt_on_tohost_and_dump_signature() {
+        // lui t0, 2; li t1, 1; sw t1, 0(t0); j 0
+        let code = [0x000022B7, 0x00100313, 0x0062A023, 0x0000006F];
+        let mut sim = simulator("tohost", &code);
+        for (name, address) in &[(BEGIN_SIGNATURE, 0x2000), (END_SIGNATURE, 0x2008)] {
+            sim.symbols.insert(Symbol {
+                name: name.to_string(),
+                address: *address,
+                size: 0,
+            });
+        }
+        sim.tohost = Some(0x2000);
+
+        assert_eq!(sim.tohost(), None);
+        assert_eq!(sim.run(Some(100)), ExitEvent::HostExit(1));
+        assert_eq!(sim.cycles(), 3);
+        assert_eq!(sim.signature(), Some(vec![1, 0]));
+    }
+
+    #[test]
+    fn exchange_words_big_endian() {
+        // lui t0, 2; li t1, 1; sw t1, 0(t0); j 0
+        let code = [0x000022B7, 0x00100313, 0x0062A023, 0x0000006F];
+        let mut sim = simulator("tohost-be", &code);
+        sim.processor_mut().set_endianness(Endianness::Big);
+        for (name, address) in &[(BEGIN_SIGNATURE, 0x2000), (END_SIGNATURE, 0x2008)] {
+            sim.symbols.insert(Symbol {
+                name: name.to_string(),
+                address: *address,
+                size: 0,
+            });
         }
+        sim.tohost = Some(0x2000);
+        sim.write_mem(0x2004, &[0, 0, 0, 2]).unwrap();
+
+        assert_eq!(sim.run(Some(100)), ExitEvent::HostExit(1));
+        assert_eq!(sim.signature(), Some(vec![1, 2]));
+
+        // ninth: lw a0, 0(sp); ret
+        let mut sim = simulator("call-be", &[0x00012503, 0x00008067]);
+        sim.processor_mut().set_endianness(Endianness::Big);
+        let args = (1..=9).collect::<Vec<_>>();
+        assert_eq!(sim.call(BASE, &args), Ok(9));
+    }
+
+    #[test]
+    fn guest_claims_raised_interrupt() {
+        // lui a1, 0x8; lw a0, 8(a1); ecall
+        let mut sim = simulator("irq", &[0x000085B7, 0x0085A503, 0x00000073]);
+        sim.map_interrupt_controller(0x8000).unwrap();
+        let enable = crate::interrupt::ENABLE;
+        sim.processor_mut()
+            .interrupts_mut()
+            .write(enable, 4, u32::MAX);
+        sim.raise_irq(7).unwrap();
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.read_reg(IntRegister::A0), 7);
+    }
+
+    #[test]
+    fn replay_delivered_inputs() {
+        // lui a1, 0x8; lw a0, 8(a1); beqz a0, -4; lui a2, 0x9; lw a3, 0(a2);
+        // ecall
+        let code = [
+            0x000085B7, 0x0085A503, 0xFE050EE3, 0x00009637, 0x00062683, 0x00000073,
+        ];
+        let start = |name, log: &InputLog| {
+            let mut sim = simulator(name, &code);
+            sim.set_input_log(Some(log.clone()));
+            sim.map_interrupt_controller(0x8000).unwrap();
+            sim.map_console(0x9000, Console::new()).unwrap();
+            let enable = crate::interrupt::ENABLE;
+            sim.processor_mut()
+                .interrupts_mut()
+                .write(enable, 4, u32::MAX);
+            sim
+        };
+
+        let log = InputLog::recorder();
+        let mut sim = start("record", &log);
+        assert_eq!(sim.run(Some(9)), ExitEvent::CycleLimit);
+        sim.push_console(b"x");
+        sim.raise_irq(7).unwrap();
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        let recorded = (sim.processor().instret(), sim.read_reg(IntRegister::A3));
+        assert_eq!(recorded, (14, b'x' as u32));
+
+        let mut file = Vec::new();
+        log.save(&mut file).unwrap();
+        assert_eq!(file, b"@9 console 78\n@9 irq 7 raise\n");
+        let replay = InputLog::load(file.as_slice()).unwrap();
+        let mut sim = start("replay", &replay);
+        // Inputs from the host are dropped in favour of the logged ones
+        sim.raise_irq(3).unwrap();
+        assert_eq!(sim.processor().interrupts().pending(), 0);
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(
+            (sim.processor().instret(), sim.read_reg(IntRegister::A3)),
+            recorded
+        );
+        assert!(!replay.diverged());
+    }
+
+    #[test]
+    fn continue_after_breakpoint() {
+        // ebreak; li a0, 5; ecall
+        let code = [0x00100073, 0x00500513, 0x00000073];
+        let mut sim = simulator("ebreak", &code);
+        assert_eq!(sim.run(None), ExitEvent::Ebreak);
+        assert_eq!(sim.pc(), 0x1000);
+
+        let mut sim = simulator("breakpoint", &code);
+        sim.set_ebreak_mode("break".parse().unwrap());
+        assert_eq!(sim.run(None), ExitEvent::Ebreak);
+        assert_eq!((sim.pc(), sim.trap()), (0x1004, None));
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.read_reg(IntRegister::A0), 5);
+    }
+
+    #[test]
+    fn detect_jump_to_self() {
+        // addi a0, zero, 1; beq a0, zero, 0; bne a0, zero, 0
+        let code = [0x00100513, 0x00050063, 0x00051063];
+        let mut sim = simulator("hang", &code);
+        assert_eq!(sim.run(None), ExitEvent::Hang { pc: 0x1008 });
+        assert_eq!(sim.cycles(), 3);
+
+        // j 0, with an interrupt the guest could still take
+        let mut sim = simulator("hang-irq", &[0x0000006F]);
+        let enable = crate::interrupt::ENABLE;
+        sim.processor_mut()
+            .interrupts_mut()
+            .write(enable, 4, u32::MAX);
+        sim.raise_irq(3).unwrap();
+        assert_eq!(sim.run(Some(10)), ExitEvent::CycleLimit);
+        sim.clear_irq(3).unwrap();
+        assert_eq!(sim.run(None), ExitEvent::Hang { pc: 0x1000 });
+    }
+
+    #[test]
+    fn load_program_at_offset() {
+        // auipc a0, 0; ecall
+        let elf = std::fs::read(program("offset", &[0x00000517, 0x00000073])).unwrap();
+        let mut sim =
+            Simulator::<Vec<u8>>::from_bytes_at(&elf, 0x4000, 0x10000, None, None).unwrap();
+        assert_eq!(sim.pc(), 0x5000);
+        assert_eq!(sim.segments()[0], 0x5000..0x5008);
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.read_reg(IntRegister::A0), 0x5000);
+
+        // Wraps around, as when moving a high image down to address 0
+        let sim =
+            Simulator::<Vec<u8>>::from_bytes_at(&elf, 0u32.wrapping_sub(BASE), 0x100, None, None)
+                .unwrap();
+        assert_eq!(sim.pc(), 0);
+        assert!(Simulator::<Vec<u8>>::from_bytes_at(&elf, 0x10000, 0x10000, None, None).is_err());
+    }
+
+    #[test]
+    fn load_relocatable_object() {
+        // Assembled from testdata/object.s, with every kind of relocation
+        let object = include_bytes!("../testdata/object.o");
+        let mut sim =
+            Simulator::<Vec<u8>>::from_bytes_at(object, 0x4000, 0x10000, None, None).unwrap();
+        assert_eq!(sim.pc(), 0x4000);
+        assert_eq!(sim.segments(), &[0x4000..0x4058, 0x4058..0x4068]);
+        assert_eq!(sim.symbols().get("twice").unwrap().address, 0x4050);
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.read_reg(IntRegister::A0), 64);
+
+        let mut result = [0; 4];
+        let address = sim.symbols().get("result").unwrap().address;
+        sim.read_mem(address, &mut result).unwrap();
+        assert_eq!(u32::from_le_bytes(result), 64);
+    }
+
+    #[test]
+    fn load_dynamically_linked_program() {
+        // Built from testdata/pie.s and testdata/libtwice.s
+        let pie = include_bytes!("../testdata/pie");
+        let library = |name: &str| match name {
+            "libtwice.so" => Some(include_bytes!("../testdata/libtwice.so").to_vec()),
+            _ => None,
+        };
+        let mut sim =
+            Simulator::<Vec<u8>>::from_linked_bytes(pie, 0x4000, 0x20000, None, None, library)
+                .unwrap();
+        let twice = sim.symbols().get("twice").unwrap().address;
+        assert!(twice > sim.segments()[0].end);
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.read_reg(IntRegister::A0), 64);
+
+        let missing = Simulator::<Vec<u8>>::from_bytes_at(pie, 0x4000, 0x20000, None, None);
+        assert!(
+            matches!(missing, Err(ProgramError::MissingLibrary(name)) if name == "libtwice.so")
+        );
+    }
+
+    #[test]
+    fn set_up_inputs_before_running() {
+        // lw a0, 0(a1); ecall
+        let mut sim = simulator("inputs", &[0x0005A503, 0x00000073]);
+        sim.write_reg(IntRegister::A1, 0x2000);
+        sim.write_mem(0x2000, &0x1234_5678u32.to_le_bytes())
+            .unwrap();
+        assert_eq!(sim.run(None), ExitEvent::EcallExit);
+        assert_eq!(sim.read_reg(IntRegister::A0), 0x1234_5678);
+
+        let mut buffer = [0u8; 2];
+        sim.read_mem(0x2002, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x34, 0x12]);
+        assert_eq!(
+            sim.write_mem(0xFFFF, &[0, 0]),
+            Err(ProcessorError::IllegalAccess {
+                pc: sim.pc(),
+                address: 0xFFFF
+            })
+        );
     }
 }
@@ -0,0 +1,357 @@
+//! Versioned on-disk snapshot container.
+//!
+//! A snapshot starts with the `YARSSNAP` magic and a format version, followed
+//! by a sequence of tagged sections (`tag`, `length`, `payload`). Readers skip
+//! sections they do not know, so new state can be appended without breaking
+//! older snapshots. All integers are little-endian.
+//!
+//! Version 2 added the retired instruction count and the ISA to the CPU
+//! section, and the DEV section with the interrupt controller and the state
+//! of the mapped devices. Version 1 snapshots still load, counting one
+//! retired instruction per cycle.
+
+use crate::device::Device;
+use crate::memory::{Memory, PAGE_SIZE};
+use crate::processor::Processor;
+use crate::register::{IntRegister, IntRegisterSet};
+use crate::symbol::{Symbol, SymbolTable};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: &[u8; 8] = b"YARSSNAP";
+pub const VERSION: u32 = 2;
+
+const TAG_CPU: &[u8; 4] = b"CPU ";
+const TAG_MEMORY: &[u8; 4] = b"MEM ";
+const TAG_SYMBOLS: &[u8; 4] = b"SYM ";
+const TAG_DEVICES: &[u8; 4] = b"DEV ";
+
+/// Saved state of a device, restored when a device of the same name is
+/// mapped at the same address.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceState {
+    pub base: u32,
+    pub name: String,
+    pub state: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    BadMagic,
+    Corrupted,
+    UnsupportedVersion(u32),
+    Io(io::Error),
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+pub fn save<W: Write>(
+    processor: &Processor,
+    symbols: &SymbolTable,
+    mut writer: W,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+
+    let mut cpu = Vec::new();
+    cpu.extend_from_slice(&processor.pc().to_le_bytes());
+    cpu.extend_from_slice(&(processor.cycles() as u64).to_le_bytes());
+    cpu.extend_from_slice(&(processor.instret() as u64).to_le_bytes());
+    for reg in 0..32 {
+        let reg = IntRegister::try_from(reg).unwrap();
+        cpu.extend_from_slice(&processor.registers().read(reg).to_le_bytes());
+    }
+    write_bytes(&mut cpu, processor.isa().to_string().as_bytes());
+    write_section(&mut writer, TAG_CPU, &cpu)?;
+
+    let memory = processor.memory();
+    let mut mem = Vec::new();
+    mem.extend_from_slice(&memory.size().to_le_bytes());
+    for (index, page) in memory.as_bytes().chunks(PAGE_SIZE).enumerate() {
+        if page.iter().any(|&byte| byte != 0) {
+            mem.extend_from_slice(&((index * PAGE_SIZE) as u32).to_le_bytes());
+            mem.extend_from_slice(&(page.len() as u32).to_le_bytes());
+            mem.extend_from_slice(page);
+        }
+    }
+    write_section(&mut writer, TAG_MEMORY, &mem)?;
+
+    let mut sym = Vec::new();
+    for symbol in symbols.iter() {
+        sym.extend_from_slice(&symbol.address.to_le_bytes());
+        sym.extend_from_slice(&symbol.size.to_le_bytes());
+        sym.extend_from_slice(&(symbol.name.len() as u32).to_le_bytes());
+        sym.extend_from_slice(symbol.name.as_bytes());
+    }
+    write_section(&mut writer, TAG_SYMBOLS, &sym)?;
+
+    let mut dev = Vec::new();
+    let irq = processor.interrupt_base();
+    dev.extend_from_slice(&(irq.is_some() as u32).to_le_bytes());
+    dev.extend_from_slice(&irq.unwrap_or(0).to_le_bytes());
+    write_bytes(&mut dev, &processor.interrupts().save());
+    for (base, _, device) in processor.devices().iter() {
+        dev.extend_from_slice(&base.to_le_bytes());
+        write_bytes(&mut dev, device.name().as_bytes());
+        write_bytes(&mut dev, &device.save());
+    }
+    write_section(&mut writer, TAG_DEVICES, &dev)?;
+
+    writer.flush()
+}
+
+/// Loads the processor and symbols of a snapshot, with the saved state of
+/// its devices for the caller to restore once it maps them again.
+pub fn load<R: Read>(
+    mut reader: R,
+) -> Result<(Processor, SymbolTable, Vec<DeviceState>), SnapshotError> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+
+    let version = read_u32(&mut reader)?;
+    if !(1..=VERSION).contains(&version) {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let mut cpu = None;
+    let mut memory = None;
+    let mut symbols = SymbolTable::new();
+    let mut interrupts = None;
+    let mut devices = Vec::new();
+
+    loop {
+        let mut tag = [0u8; 4];
+        match reader.read_exact(&mut tag) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+
+        let length = read_u32(&mut reader)? as usize;
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload)?;
+        let mut payload = &payload[..];
+
+        match &tag {
+            TAG_CPU => {
+                let pc = read_u32(&mut payload)?;
+                let cycles = read_u64(&mut payload)? as usize;
+                let instret = match version {
+                    1 => cycles,
+                    _ => read_u64(&mut payload)? as usize,
+                };
+                let mut registers = IntRegisterSet::new();
+                for reg in 0..32 {
+                    let reg = IntRegister::try_from(reg).unwrap();
+                    registers.write(reg, read_u32(&mut payload)?);
+                }
+                let isa = match version {
+                    1 => None,
+                    _ => {
+                        let isa = String::from_utf8(read_bytes(&mut payload)?)
+                            .map_err(|_| SnapshotError::Corrupted)?;
+                        Some(isa.parse().map_err(|_| SnapshotError::Corrupted)?)
+                    }
+                };
+                cpu = Some((pc, cycles, instret, registers, isa));
+            }
+            TAG_MEMORY => {
+                let mut mem = Memory::new(read_u32(&mut payload)?);
+                while !payload.is_empty() {
+                    let address = read_u32(&mut payload)? as usize;
+                    let length = read_u32(&mut payload)? as usize;
+                    let bytes = mem
+                        .as_bytes_mut()
+                        .get_mut(address..address + length)
+                        .ok_or(SnapshotError::Corrupted)?;
+                    payload.read_exact(bytes)?;
+                }
+                memory = Some(mem);
+            }
+            TAG_SYMBOLS => {
+                while !payload.is_empty() {
+                    let address = read_u32(&mut payload)?;
+                    let size = read_u32(&mut payload)?;
+                    let mut name = vec![0u8; read_u32(&mut payload)? as usize];
+                    payload.read_exact(&mut name)?;
+                    let name = String::from_utf8(name).map_err(|_| SnapshotError::Corrupted)?;
+                    symbols.insert(Symbol {
+                        name,
+                        address,
+                        size,
+                    });
+                }
+            }
+            TAG_DEVICES => {
+                let mapped = read_u32(&mut payload)? != 0;
+                let base = read_u32(&mut payload)?;
+                interrupts = Some((Some(base).filter(|_| mapped), read_bytes(&mut payload)?));
+                while !payload.is_empty() {
+                    let base = read_u32(&mut payload)?;
+                    let name = String::from_utf8(read_bytes(&mut payload)?)
+                        .map_err(|_| SnapshotError::Corrupted)?;
+                    let state = read_bytes(&mut payload)?;
+                    devices.push(DeviceState { base, name, state });
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    match (cpu, memory) {
+        (Some((pc, cycles, instret, registers, isa)), Some(memory)) => {
+            let mut processor = Processor::restore(pc, cycles, instret, memory, registers);
+            if let Some(isa) = isa {
+                processor
+                    .set_isa(isa)
+                    .map_err(|_| SnapshotError::Corrupted)?;
+            }
+            if let Some((base, state)) = interrupts {
+                if let Some(base) = base {
+                    processor
+                        .map_interrupt_controller(base)
+                        .map_err(|_| SnapshotError::Corrupted)?;
+                }
+                processor.interrupts_mut().restore(&state);
+            }
+            Ok((processor, symbols, devices))
+        }
+        _ => Err(SnapshotError::Corrupted),
+    }
+}
+
+fn write_section<W: Write>(writer: &mut W, tag: &[u8; 4], payload: &[u8]) -> io::Result<()> {
+    writer.write_all(tag)?;
+    writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Writes `bytes` after their length.
+fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(bytes);
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; read_u32(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Console;
+    use crate::isa::Isa;
+    use std::convert::TryInto;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut memory = Memory::new(3 * PAGE_SIZE as u32);
+        memory.write_word(PAGE_SIZE as u32 + 4, 0xCAFEBABE);
+        let mut processor = Processor::new(memory);
+        processor.set_pc(0x40);
+
+        let mut buffer = Vec::new();
+        save(&processor, &SymbolTable::new(), &mut buffer).unwrap();
+        let (restored, _, _) = load(&buffer[..]).unwrap();
+
+        assert_eq!(restored.pc(), 0x40);
+        assert_eq!(restored.memory().size(), processor.memory().size());
+        assert_eq!(
+            restored.memory().read_word(PAGE_SIZE as u32 + 4),
+            0xCAFEBABE
+        );
+        assert_eq!(
+            restored.registers().read(IntRegister::SP),
+            processor.registers().read(IntRegister::SP)
+        );
+    }
+
+    #[test]
+    fn keep_counters_isa_and_devices() {
+        let mut processor = Processor::new(Memory::new(PAGE_SIZE as u32));
+        processor.add_cycles(7);
+        processor.set_isa("rv32i".parse().unwrap()).unwrap();
+        processor.map_interrupt_controller(0x8000).unwrap();
+        processor.interrupts_mut().raise(3).unwrap();
+        let console = Console::new();
+        console.push_input(b"hi");
+        processor
+            .map_device(0x9000, Console::SIZE, Box::new(console))
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        save(&processor, &SymbolTable::new(), &mut buffer).unwrap();
+        let (restored, _, devices) = load(&buffer[..]).unwrap();
+
+        assert_eq!((restored.cycles(), restored.instret()), (7, 0));
+        assert_eq!(restored.isa().to_string(), "rv32i");
+        assert_eq!(restored.interrupt_base(), Some(0x8000));
+        assert_eq!(restored.interrupts().pending(), 1 << 3);
+        assert_eq!(
+            devices,
+            vec![DeviceState {
+                base: 0x9000,
+                name: "console".to_string(),
+                state: b"hi".to_vec(),
+            }]
+        );
+
+        let mut console = Console::new();
+        console.restore(&devices[0].state);
+        assert_eq!(console.read(0, 1), u32::from(b'h'));
+    }
+
+    #[test]
+    fn load_version_1() {
+        let mut buffer = Vec::new();
+        save(
+            &Processor::new(Memory::new(PAGE_SIZE as u32)),
+            &SymbolTable::new(),
+            &mut buffer,
+        )
+        .unwrap();
+        // Rewrites the CPU section without the instruction count and ISA
+        let cpu_start = MAGIC.len() + 4;
+        let length = u32::from_le_bytes(buffer[cpu_start + 4..cpu_start + 8].try_into().unwrap());
+        let cpu_end = cpu_start + 8 + length as usize;
+        let mut cpu = buffer[cpu_start + 8..cpu_end].to_vec();
+        cpu.drain(12..20);
+        cpu.truncate(12 + 32 * 4);
+        cpu[4..12].copy_from_slice(&5u64.to_le_bytes());
+        let mut old = MAGIC.to_vec();
+        old.extend_from_slice(&1u32.to_le_bytes());
+        write_section(&mut old, TAG_CPU, &cpu).unwrap();
+        old.extend_from_slice(&buffer[cpu_end..]);
+
+        let (restored, _, _) = load(&old[..]).unwrap();
+        assert_eq!((restored.cycles(), restored.instret()), (5, 5));
+        assert_eq!(restored.isa(), &Isa::default());
+    }
+
+    #[test]
+    fn reject_unknown_magic() {
+        let buffer = b"NOTASNAP\x01\x00\x00\x00";
+        assert!(matches!(load(&buffer[..]), Err(SnapshotError::BadMagic)));
+    }
+}
@@ -0,0 +1,146 @@
+//! Stack usage of guest programs, to size stacks from a simulated run.
+
+use crate::callstack::CallStack;
+use crate::symbol::SymbolTable;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Lowest stack pointer reached by a program, and the largest frame of each
+/// function, measured from the stack pointer at the call to the lowest one
+/// seen while the function was the innermost on the [`CallStack`].
+#[derive(Clone, Debug)]
+pub struct StackUsage {
+    top: u32,
+    lowest: u32,
+    /// Functions on the stack when `lowest` was reached, outermost first.
+    deepest: Vec<u32>,
+    /// Function and stack pointer of each frame of the call stack, with the
+    /// program entry at the bottom.
+    entries: Vec<(u32, u32)>,
+    frames: HashMap<u32, u32>,
+}
+
+impl StackUsage {
+    /// Starts tracking a program entered at `entry` with `sp` as its stack
+    /// pointer.
+    pub fn new(entry: u32, sp: u32) -> Self {
+        Self {
+            top: sp,
+            lowest: sp,
+            deepest: vec![entry],
+            entries: vec![(entry, sp)],
+            frames: HashMap::new(),
+        }
+    }
+
+    /// Records the stack pointer after an instruction, with `stack` already
+    /// updated for that instruction.
+    pub fn update(&mut self, stack: &CallStack, sp: u32) {
+        let depth = stack.depth() + 1;
+        self.entries.truncate(depth);
+        for frame in &stack.frames()[self.entries.len() - 1..] {
+            self.entries.push((frame.function, sp));
+        }
+
+        let (function, entry_sp) = *self.entries.last().unwrap();
+        let frame = self.frames.entry(function).or_insert(0);
+        *frame = (*frame).max(entry_sp.saturating_sub(sp));
+
+        if sp < self.lowest {
+            self.lowest = sp;
+            self.deepest = self.entries.iter().map(|&(function, _)| function).collect();
+        }
+    }
+
+    /// Largest number of bytes used below the initial stack pointer.
+    pub fn max_usage(&self) -> u32 {
+        self.top - self.lowest
+    }
+
+    pub fn lowest(&self) -> u32 {
+        self.lowest
+    }
+
+    /// Functions on the stack when it was deepest, outermost first.
+    pub fn deepest(&self) -> &[u32] {
+        &self.deepest
+    }
+
+    /// Largest frame of every function that was called, in bytes, largest
+    /// first.
+    pub fn frames(&self) -> Vec<(u32, u32)> {
+        let mut frames = self
+            .frames
+            .iter()
+            .map(|(&f, &size)| (f, size))
+            .collect::<Vec<_>>();
+        frames.sort_by(|a, b| (b.1, a.0).cmp(&(a.1, b.0)));
+        frames
+    }
+
+    /// Writes the maximum usage, the deepest call stack and the frame size
+    /// of each function.
+    pub fn write<W: Write>(&self, symbols: &SymbolTable, mut writer: W) -> io::Result<()> {
+        let name = |address: u32| match symbols.lookup(address) {
+            Some(sym) => sym.to_string(),
+            None => format!("{:#x}", address),
+        };
+
+        writeln!(
+            writer,
+            "Stack usage: {} bytes (sp from {:#010x} down to {:#010x})",
+            self.max_usage(),
+            self.top,
+            self.lowest
+        )?;
+        let deepest = self.deepest.iter().map(|&f| name(f)).collect::<Vec<_>>();
+        writeln!(writer, "Deepest stack: {}", deepest.join(" -> "))?;
+        writeln!(writer, "{:<24} {:>12}", "Function", "Frame bytes")?;
+        for (function, size) in self.frames() {
+            writeln!(writer, "{:<24} {:>12}", name(function), size)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction;
+    use crate::register::IntRegister;
+
+    #[test]
+    fn measure_frames_and_deepest_stack() {
+        let call = |imm| Instruction::JAL {
+            rd: IntRegister::RA,
+            imm,
+        };
+        let ret = Instruction::JALR {
+            rd: IntRegister::Zero,
+            rs1: IntRegister::RA,
+            imm: 0,
+        };
+
+        let mut stack = CallStack::new();
+        let mut usage = StackUsage::new(0x1000, 0x8000);
+        usage.update(&stack, 0x7FF0);
+        stack.update(0x1004, &call(0xFC), 0x1100);
+        usage.update(&stack, 0x7FF0);
+        usage.update(&stack, 0x7FD0);
+        stack.update(0x1104, &call(0xFC), 0x1200);
+        usage.update(&stack, 0x7FD0);
+        usage.update(&stack, 0x7FC8);
+        stack.update(0x1204, &ret, 0x1108);
+        usage.update(&stack, 0x7FD0);
+        stack.update(0x1108, &ret, 0x1008);
+        usage.update(&stack, 0x7FF0);
+
+        assert_eq!(usage.max_usage(), 0x38);
+        assert_eq!(usage.lowest(), 0x7FC8);
+        assert_eq!(usage.deepest(), [0x1000, 0x1100, 0x1200]);
+        assert_eq!(
+            usage.frames(),
+            [(0x1100, 0x20), (0x1000, 0x10), (0x1200, 0x8)]
+        );
+    }
+}
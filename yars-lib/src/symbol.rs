@@ -0,0 +1,142 @@
+use goblin::elf::sym::{Sym, STT_FUNC, STT_NOTYPE, STT_OBJECT};
+use goblin::elf::Elf;
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_elf(binary: &Elf) -> Self {
+        Self::from_elf_with(binary, |_, sym| {
+            match sym.st_shndx != 0 && sym.st_value != 0 {
+                true => Some(sym.st_value as u32),
+                false => None,
+            }
+        })
+    }
+
+    /// Like [`SymbolTable::from_elf`], but with the address of the symbol at
+    /// each index given by `address`, which skips the symbol if it gives
+    /// `None`.
+    pub(crate) fn from_elf_with<F>(binary: &Elf, address: F) -> Self
+    where
+        F: Fn(usize, &Sym) -> Option<u32>,
+    {
+        let mut symbols = binary
+            .syms
+            .iter()
+            .enumerate()
+            .filter(|(_, sym)| matches!(sym.st_type(), STT_FUNC | STT_OBJECT | STT_NOTYPE))
+            .filter_map(|(index, sym)| {
+                let address = address(index, &sym)?;
+                let name = binary.strtab.get(sym.st_name)?.ok()?;
+                match name.is_empty() || name.starts_with('$') {
+                    true => None,
+                    false => Some(Symbol {
+                        name: name.to_string(),
+                        address,
+                        size: sym.st_size as u32,
+                    }),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        symbols.sort_by_key(|sym| sym.address);
+        Self { symbols }
+    }
+
+    pub fn insert(&mut self, symbol: Symbol) {
+        let index = self
+            .symbols
+            .partition_point(|s| s.address <= symbol.address);
+        self.symbols.insert(index, symbol);
+    }
+
+    /// Moves every symbol `offset` bytes, wrapping around.
+    pub fn relocate(&mut self, offset: u32) {
+        for symbol in &mut self.symbols {
+            symbol.address = symbol.address.wrapping_add(offset);
+        }
+        self.symbols.sort_by_key(|sym| sym.address);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|sym| sym.name == name)
+    }
+
+    pub fn lookup(&self, address: u32) -> Option<SymbolOffset<'_>> {
+        let index = self.symbols.partition_point(|sym| sym.address <= address);
+        let symbol = self.symbols[..index].last()?;
+        let offset = address - symbol.address;
+
+        match symbol.size == 0 || offset < symbol.size {
+            true => Some(SymbolOffset { symbol, offset }),
+            false => None,
+        }
+    }
+}
+
+/// A symbol together with the offset of an address inside it, formatted in
+/// the usual `name+0x10` notation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SymbolOffset<'a> {
+    pub symbol: &'a Symbol,
+    pub offset: u32,
+}
+
+impl fmt::Display for SymbolOffset<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            0 => write!(f, "{}", self.symbol.name),
+            offset => write!(f, "{}+{:#x}", self.symbol.name, offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, address: u32, size: u32) -> Symbol {
+        let name = name.to_string();
+        Symbol {
+            name,
+            address,
+            size,
+        }
+    }
+
+    #[test]
+    fn lookup_inside_and_outside_symbols() {
+        let mut table = SymbolTable::new();
+        table.insert(symbol("main", 0x100, 0x20));
+        table.insert(symbol("_start", 0x80, 0));
+
+        assert_eq!(format!("{}", table.lookup(0x100).unwrap()), "main");
+        assert_eq!(format!("{}", table.lookup(0x104).unwrap()), "main+0x4");
+        assert_eq!(format!("{}", table.lookup(0x90).unwrap()), "_start+0x10");
+        assert!(table.lookup(0x120).is_none());
+        assert!(table.lookup(0x10).is_none());
+    }
+}
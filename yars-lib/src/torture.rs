@@ -0,0 +1,418 @@
+//! Random programs for torture testing the executor. Generated programs are
+//! well-formed: they only branch forward, only access a small data window
+//! and always end with an `ecall`. [`check`] runs them on a [`Processor`]
+//! and on a separate, deliberately simple reference model, and reports the
+//! first difference in the final state.
+
+use crate::instruction::{Category, Instruction};
+use crate::memory::Memory;
+use crate::processor::{Processor, ProcessorError};
+use crate::register::IntRegister;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Address of the first instruction of a program.
+pub const CODE_BASE: u32 = 0x1000;
+/// Start of the window all loads and stores fall into.
+pub const DATA_BASE: u32 = 0x8000;
+/// Size of the data window, small enough to be reached from its base with
+/// a 12-bit offset.
+pub const DATA_SIZE: u32 = 0x800;
+
+const MEMORY_SIZE: u32 = 0x10000;
+
+/// Register holding [`DATA_BASE`], never written after the prologue.
+const BASE: IntRegister = IntRegister::GP;
+
+/// Farthest a branch or jump may skip ahead, in instructions.
+const MAX_SKIP: u32 = 16;
+
+/// Difference between the processor and the reference model.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mismatch {
+    Fault {
+        pc: u32,
+        err: ProcessorError,
+    },
+    Register {
+        reg: IntRegister,
+        expected: u32,
+        actual: u32,
+    },
+    Memory {
+        address: u32,
+        expected: u8,
+        actual: u8,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Fault { pc, err } => match err.pc() {
+                Some(_) => write!(f, "{}", err),
+                None => write!(f, "{} at {:#010x}", err, pc),
+            },
+            Mismatch::Register {
+                reg,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} is {:#010x}, expected {:#010x}",
+                reg, actual, expected
+            ),
+            Mismatch::Memory {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "byte at {:#010x} is {:#04x}, expected {:#04x}",
+                address, actual, expected
+            ),
+        }
+    }
+}
+
+/// Generates a program of `length` random instructions, not counting the
+/// prologue setting every register to a random value and the final
+/// `ecall`. Words returned by `next` are used as the source of randomness.
+#[rustfmt::skip]
+pub fn generate<F: FnMut() -> u32>(mut next: F, length: usize) -> Vec<Instruction> {
+    use Instruction::*;
+    let mut program = Vec::with_capacity(length + 64);
+
+    program.push(LUI { rd: BASE, imm: (DATA_BASE >> 12) as i32 });
+    for reg in 1..32 {
+        let rd = IntRegister::try_from(reg).unwrap();
+        if rd != BASE {
+            let value = next();
+            let lo = ((value << 20) as i32 >> 20) as i16;
+            let hi = value.wrapping_sub(lo as i32 as u32) as i32 >> 12;
+            program.push(LUI { rd, imm: hi });
+            program.push(ADDI { rd, rs1: rd, imm: lo });
+        }
+    }
+
+    // Indices of the jalr of each auipc/jalr pair
+    let mut jalrs = Vec::new();
+    let end = program.len() + length;
+    while program.len() < end {
+        let index = program.len();
+        let inst = Instruction::random(&mut next);
+        let inst = match inst.category() {
+            Category::System => continue,
+            Category::Load | Category::Store => confine(inst, next()),
+            _ => keep_base(inst),
+        };
+
+        // Targets lie between the next instruction and the final ecall
+        let skip = |next: u32, min: u32| {
+            let max = (end - index) as u32;
+            match max < min {
+                true => None,
+                false => Some(4 * (min + next % (max - min + 1).min(MAX_SKIP))),
+            }
+        };
+
+        let inst = match inst {
+            BEQ { rs1, rs2, .. } => skip(next(), 1).map(|imm| BEQ { rs1, rs2, imm: imm as i16 }),
+            BNE { rs1, rs2, .. } => skip(next(), 1).map(|imm| BNE { rs1, rs2, imm: imm as i16 }),
+            BLT { rs1, rs2, .. } => skip(next(), 1).map(|imm| BLT { rs1, rs2, imm: imm as i16 }),
+            BGE { rs1, rs2, .. } => skip(next(), 1).map(|imm| BGE { rs1, rs2, imm: imm as i16 }),
+            BLTU { rs1, rs2, .. } => skip(next(), 1).map(|imm| BLTU { rs1, rs2, imm: imm as i16 }),
+            BGEU { rs1, rs2, .. } => skip(next(), 1).map(|imm| BGEU { rs1, rs2, imm: imm as i16 }),
+            JAL { rd, .. } => skip(next(), 1).map(|imm| JAL { rd, imm: imm as i32 }),
+            JALR { rd, rs1, .. } => {
+                // Jumps relative to an auipc right before it
+                let rs1 = match rs1 {
+                    IntRegister::Zero | BASE => IntRegister::T1,
+                    rs1 => rs1,
+                };
+                match skip(next(), 2) {
+                    Some(imm) if index + 1 < end => {
+                        program.push(AUIPC { rd: rs1, imm: 0 });
+                        jalrs.push(index + 1);
+                        Some(JALR { rd, rs1, imm: imm as i16 })
+                    }
+                    _ => None,
+                }
+            }
+            inst => Some(inst),
+        };
+
+        if let Some(inst) = inst {
+            program.push(inst);
+        }
+    }
+
+    // Branching straight to a jalr would skip the auipc it depends on
+    for (index, inst) in program.iter_mut().enumerate() {
+        let lands = |imm: i32| jalrs.contains(&(index + imm as usize / 4));
+        *inst = match *inst {
+            BEQ { rs1, rs2, imm } if lands(imm.into()) => BEQ { rs1, rs2, imm: imm + 4 },
+            BNE { rs1, rs2, imm } if lands(imm.into()) => BNE { rs1, rs2, imm: imm + 4 },
+            BLT { rs1, rs2, imm } if lands(imm.into()) => BLT { rs1, rs2, imm: imm + 4 },
+            BGE { rs1, rs2, imm } if lands(imm.into()) => BGE { rs1, rs2, imm: imm + 4 },
+            BLTU { rs1, rs2, imm } if lands(imm.into()) => BLTU { rs1, rs2, imm: imm + 4 },
+            BGEU { rs1, rs2, imm } if lands(imm.into()) => BGEU { rs1, rs2, imm: imm + 4 },
+            JAL { rd, imm } if lands(imm) => JAL { rd, imm: imm + 4 },
+            JALR { rd, rs1, imm } if jalrs.contains(&index) && lands(imm as i32 - 4) => {
+                JALR { rd, rs1, imm: imm + 4 }
+            }
+            inst => inst,
+        };
+    }
+
+    program.push(ECALL);
+    program
+}
+
+/// Moves a load or store into the data window, at an offset aligned to the
+/// size of the access.
+#[rustfmt::skip]
+fn confine(inst: Instruction, next: u32) -> Instruction {
+    use Instruction::*;
+    let offset = |size: u32| ((next % (DATA_SIZE / size)) * size) as i16;
+    let rs1 = BASE;
+    match keep_base(inst) {
+        LB { rd, .. } => LB { rd, rs1, imm: offset(1) },
+        LH { rd, .. } => LH { rd, rs1, imm: offset(2) },
+        LW { rd, .. } => LW { rd, rs1, imm: offset(4) },
+        LBU { rd, .. } => LBU { rd, rs1, imm: offset(1) },
+        LHU { rd, .. } => LHU { rd, rs1, imm: offset(2) },
+        SB { rs2, .. } => SB { rs1, rs2, imm: offset(1) },
+        SH { rs2, .. } => SH { rs1, rs2, imm: offset(2) },
+        SW { rs2, .. } => SW { rs1, rs2, imm: offset(4) },
+        inst => inst,
+    }
+}
+
+/// Redirects writes to the base register to `a0`.
+fn keep_base(inst: Instruction) -> Instruction {
+    let raw = u32::from(inst);
+    let writes = !matches!(
+        inst.category(),
+        Category::Store | Category::Branch | Category::Sync | Category::System
+    );
+    match writes && (raw >> 7) & 0x1F == BASE as u32 {
+        true => {
+            let raw = (raw & !(0x1F << 7)) | (IntRegister::A0 as u32) << 7;
+            Instruction::try_from(raw).unwrap()
+        }
+        false => inst,
+    }
+}
+
+/// Runs `program` on a processor and on the reference model, and compares
+/// the registers and the data window once both reach the final `ecall`.
+pub fn check(program: &[Instruction]) -> Result<(), Mismatch> {
+    let mut memory = Memory::new(MEMORY_SIZE);
+    for (index, inst) in program.iter().enumerate() {
+        memory.write_word(CODE_BASE + 4 * index as u32, u32::from(*inst));
+    }
+    let mut processor = Processor::<u32>::new(memory);
+    processor.set_pc(CODE_BASE);
+
+    loop {
+        match processor.step() {
+            Ok(_) => {}
+            Err(ProcessorError::Ecall) => break,
+            Err(err) => {
+                let pc = processor.pc();
+                return Err(Mismatch::Fault { pc, err });
+            }
+        }
+    }
+
+    let expected = Reference::run(program);
+    for reg in 0..32 {
+        let reg = IntRegister::try_from(reg).unwrap();
+        let (expected, actual) = (expected.regs[reg as usize], processor.registers().read(reg));
+        if expected != actual {
+            return Err(Mismatch::Register {
+                reg,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    for offset in 0..DATA_SIZE {
+        let address = DATA_BASE + offset;
+        let expected = expected.data[offset as usize];
+        let actual = processor.memory().read_byte(address);
+        if expected != actual {
+            return Err(Mismatch::Memory {
+                address,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Straightforward interpreter of generated programs, written independently
+/// of the executor to cross-check it.
+struct Reference {
+    regs: [u32; 32],
+    data: Vec<u8>,
+}
+
+impl Reference {
+    fn run(program: &[Instruction]) -> Self {
+        use Instruction::*;
+        let mut state = Self {
+            regs: [0; 32],
+            data: vec![0; DATA_SIZE as usize],
+        };
+
+        let mut pc = CODE_BASE;
+        loop {
+            let inst = program[((pc - CODE_BASE) / 4) as usize];
+            let mut next = pc.wrapping_add(4);
+            let x = |reg: IntRegister| state.regs[reg as usize];
+
+            let result = match inst {
+                LUI { imm, .. } => Some((imm as u32) << 12),
+                AUIPC { imm, .. } => Some(pc.wrapping_add((imm as u32) << 12)),
+                JAL { imm, .. } => {
+                    next = pc.wrapping_add(imm as u32);
+                    Some(pc.wrapping_add(4))
+                }
+                JALR { rs1, imm, .. } => {
+                    next = x(rs1).wrapping_add(imm as i32 as u32) & !1;
+                    Some(pc.wrapping_add(4))
+                }
+                BEQ { rs1, rs2, imm } => branch(&mut next, pc, imm, x(rs1) == x(rs2)),
+                BNE { rs1, rs2, imm } => branch(&mut next, pc, imm, x(rs1) != x(rs2)),
+                BLT { rs1, rs2, imm } => {
+                    let taken = (x(rs1) as i32) < (x(rs2) as i32);
+                    branch(&mut next, pc, imm, taken)
+                }
+                BGE { rs1, rs2, imm } => {
+                    let taken = (x(rs1) as i32) >= (x(rs2) as i32);
+                    branch(&mut next, pc, imm, taken)
+                }
+                BLTU { rs1, rs2, imm } => branch(&mut next, pc, imm, x(rs1) < x(rs2)),
+                BGEU { rs1, rs2, imm } => branch(&mut next, pc, imm, x(rs1) >= x(rs2)),
+                LB { rs1, imm, .. } => Some(state.load(x(rs1), imm, 1) as u8 as i8 as u32),
+                LH { rs1, imm, .. } => Some(state.load(x(rs1), imm, 2) as u16 as i16 as u32),
+                LW { rs1, imm, .. } => Some(state.load(x(rs1), imm, 4)),
+                LBU { rs1, imm, .. } => Some(state.load(x(rs1), imm, 1)),
+                LHU { rs1, imm, .. } => Some(state.load(x(rs1), imm, 2)),
+                SB { rs1, rs2, imm } => state.store(x(rs1), imm, 1, x(rs2)),
+                SH { rs1, rs2, imm } => state.store(x(rs1), imm, 2, x(rs2)),
+                SW { rs1, rs2, imm } => state.store(x(rs1), imm, 4, x(rs2)),
+                ADDI { rs1, imm, .. } => Some(x(rs1).wrapping_add(imm as i32 as u32)),
+                SLTI { rs1, imm, .. } => Some(((x(rs1) as i32) < imm as i32) as u32),
+                SLTIU { rs1, imm, .. } => Some((x(rs1) < imm as i32 as u32) as u32),
+                XORI { rs1, imm, .. } => Some(x(rs1) ^ imm as i32 as u32),
+                ORI { rs1, imm, .. } => Some(x(rs1) | imm as i32 as u32),
+                ANDI { rs1, imm, .. } => Some(x(rs1) & imm as i32 as u32),
+                SLLI { rs1, shamt, .. } => Some(x(rs1) << shamt),
+                SRLI { rs1, shamt, .. } => Some(x(rs1) >> shamt),
+                SRAI { rs1, shamt, .. } => Some(((x(rs1) as i32) >> shamt) as u32),
+                ADD { rs1, rs2, .. } => Some(x(rs1).wrapping_add(x(rs2))),
+                SUB { rs1, rs2, .. } => Some(x(rs1).wrapping_sub(x(rs2))),
+                SLL { rs1, rs2, .. } => Some(x(rs1) << (x(rs2) & 31)),
+                SLT { rs1, rs2, .. } => Some(((x(rs1) as i32) < (x(rs2) as i32)) as u32),
+                SLTU { rs1, rs2, .. } => Some((x(rs1) < x(rs2)) as u32),
+                XOR { rs1, rs2, .. } => Some(x(rs1) ^ x(rs2)),
+                SRL { rs1, rs2, .. } => Some(x(rs1) >> (x(rs2) & 31)),
+                SRA { rs1, rs2, .. } => Some(((x(rs1) as i32) >> (x(rs2) & 31)) as u32),
+                OR { rs1, rs2, .. } => Some(x(rs1) | x(rs2)),
+                AND { rs1, rs2, .. } => Some(x(rs1) & x(rs2)),
+                FENCE { .. } | FENCETSO => None,
+                ECALL | EBREAK | CUSTOM { .. } => return state,
+                MUL { rs1, rs2, .. } => Some(x(rs1).wrapping_mul(x(rs2))),
+                MULH { rs1, rs2, .. } => {
+                    let product = x(rs1) as i32 as i64 * x(rs2) as i32 as i64;
+                    Some((product >> 32) as u32)
+                }
+                MULHSU { rs1, rs2, .. } => {
+                    let product = x(rs1) as i32 as i128 * x(rs2) as i128;
+                    Some((product >> 32) as u32)
+                }
+                MULHU { rs1, rs2, .. } => {
+                    let product = x(rs1) as u64 * x(rs2) as u64;
+                    Some((product >> 32) as u32)
+                }
+                DIV { rs1, rs2, .. } => Some(match (x(rs1) as i32, x(rs2) as i32) {
+                    (_, 0) => u32::MAX,
+                    (i32::MIN, -1) => i32::MIN as u32,
+                    (a, b) => (a / b) as u32,
+                }),
+                DIVU { rs1, rs2, .. } => Some(match (x(rs1), x(rs2)) {
+                    (_, 0) => u32::MAX,
+                    (a, b) => a / b,
+                }),
+                REM { rs1, rs2, .. } => Some(match (x(rs1) as i32, x(rs2) as i32) {
+                    (a, 0) => a as u32,
+                    (i32::MIN, -1) => 0,
+                    (a, b) => (a % b) as u32,
+                }),
+                REMU { rs1, rs2, .. } => Some(match (x(rs1), x(rs2)) {
+                    (a, 0) => a,
+                    (a, b) => a % b,
+                }),
+            };
+
+            if let Some(value) = result {
+                let rd = (u32::from(inst) >> 7) & 0x1F;
+                if rd != 0 {
+                    state.regs[rd as usize] = value;
+                }
+            }
+            pc = next;
+        }
+    }
+
+    fn load(&self, base: u32, imm: i16, size: u32) -> u32 {
+        let offset = (base.wrapping_add(imm as i32 as u32) - DATA_BASE) as usize;
+        (0..size as usize).fold(0, |value, i| {
+            value | (self.data[offset + i] as u32) << (8 * i)
+        })
+    }
+
+    fn store(&mut self, base: u32, imm: i16, size: u32, value: u32) -> Option<u32> {
+        let offset = (base.wrapping_add(imm as i32 as u32) - DATA_BASE) as usize;
+        for i in 0..size as usize {
+            self.data[offset + i] = (value >> (8 * i)) as u8;
+        }
+        None
+    }
+}
+
+/// Moves `next` to the target of a taken branch. Branches write no register.
+fn branch(next: &mut u32, pc: u32, imm: i16, taken: bool) -> Option<u32> {
+    if taken {
+        *next = pc.wrapping_add(imm as i32 as u32);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entropy::Entropy;
+
+    #[test]
+    fn generated_programs_match_the_reference() {
+        for seed in 0..200 {
+            let mut entropy = Entropy::seeded(seed);
+            let program = generate(|| entropy.next_u32(), 100);
+            assert_eq!(program.last(), Some(&Instruction::ECALL));
+            assert_eq!(check(&program), Ok(()), "seed {}", seed);
+        }
+
+        let fault = Mismatch::Fault {
+            pc: CODE_BASE,
+            err: ProcessorError::Ebreak,
+        };
+        assert_eq!(check(&[Instruction::EBREAK]), Err(fault));
+    }
+}
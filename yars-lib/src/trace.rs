@@ -0,0 +1,186 @@
+//! Architectural effects of retired instructions, in a form that can be
+//! compared against traces of other simulators.
+
+pub mod reader;
+
+use crate::instruction::Instruction;
+use crate::processor::{Processor, RetiredInstruction};
+use crate::register::IntRegister;
+use crate::trace::reader::LogEntry;
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Commit {
+    pub pc: u32,
+    pub raw: u32,
+    /// Register written by the instruction and its new value. Writes to
+    /// `zero` are not recorded.
+    pub rd: Option<(IntRegister, u32)>,
+    /// Address and value written to memory.
+    pub store: Option<(u32, u32)>,
+}
+
+impl Commit {
+    /// Builds the commit of an instruction from the processor state right
+    /// after it was executed.
+    pub fn from_retired(retired: &RetiredInstruction, processor: &Processor) -> Self {
+        let registers = processor.registers();
+        let rd = destination(&retired.inst).map(|rd| (rd, registers.read(rd)));
+
+        let store = match retired.inst {
+            Instruction::SB { rs1, rs2, imm } => Some((rs1, rs2, imm, 0xFF)),
+            Instruction::SH { rs1, rs2, imm } => Some((rs1, rs2, imm, 0xFFFF)),
+            Instruction::SW { rs1, rs2, imm } => Some((rs1, rs2, imm, 0xFFFF_FFFF)),
+            _ => None,
+        }
+        .map(|(rs1, rs2, imm, mask)| {
+            let address = registers.read(rs1).wrapping_add(imm as u32);
+            (address, registers.read(rs2) & mask)
+        });
+
+        Self {
+            pc: retired.pc,
+            raw: retired.raw,
+            rd,
+            store,
+        }
+    }
+
+    /// Parses a line of any supported trace format.
+    pub fn parse(line: &str) -> Option<Self> {
+        Self::parse_yars(line).or_else(|| Self::parse_spike(line))
+    }
+
+    /// Parses a line of the instruction log written by [`Simulator`] (e.g.
+    /// `yars run --log`), which does not include stored values.
+    ///
+    /// [`Simulator`]: crate::simulator::Simulator
+    pub fn parse_yars(line: &str) -> Option<Self> {
+        LogEntry::parse(line)?.commit()
+    }
+
+    /// Compares two commits, ignoring the effects that only one of them
+    /// records, as happens when they come from different trace formats.
+    pub fn matches(&self, other: &Self) -> bool {
+        fn agree<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            a.is_none() || b.is_none() || a == b
+        }
+
+        self.pc == other.pc
+            && self.raw == other.raw
+            && agree(self.rd, other.rd)
+            && agree(self.store, other.store)
+    }
+
+    /// Parses a line of a Spike log, as written with `--log-commits` or
+    /// `-l`. Returns `None` for lines that do not describe an instruction.
+    pub fn parse_spike(line: &str) -> Option<Self> {
+        let (_, rest) = line.trim_start().strip_prefix("core")?.split_once(':')?;
+        let mut tokens = rest.split_whitespace().peekable();
+
+        // Commit logs start with the privilege level
+        if tokens.peek()?.len() == 1 {
+            tokens.next();
+        }
+
+        let pc = parse_hex(tokens.next()?)?;
+        let raw = tokens.next()?.strip_prefix('(')?.strip_suffix(')')?;
+        let mut commit = Self {
+            pc,
+            raw: parse_hex(raw)?,
+            rd: None,
+            store: None,
+        };
+
+        while let Some(token) = tokens.next() {
+            if token == "mem" {
+                let address = parse_hex(tokens.next()?)?;
+                // Loads only log the address
+                if let Some(value) = tokens.peek().and_then(|value| parse_hex(value)) {
+                    commit.store = Some((address, value));
+                    tokens.next();
+                }
+            } else if let Some(reg) = token.strip_prefix('x').and_then(|n| n.parse::<u8>().ok()) {
+                let value = parse_hex(tokens.next()?)?;
+                let reg = IntRegister::try_from(reg).ok()?;
+                if reg != IntRegister::Zero {
+                    commit.rd = Some((reg, value));
+                }
+            }
+        }
+
+        Some(commit)
+    }
+}
+
+impl fmt::Display for Commit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x} ({:#010x})", self.pc, self.raw)?;
+        if let Some((reg, value)) = self.rd {
+            write!(f, " {:<4}{:#010x}", reg.to_string(), value)?;
+        }
+        if let Some((address, value)) = self.store {
+            write!(f, " mem {:#010x} {:#x}", address, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Register written by an instruction, if any.
+fn destination(inst: &Instruction) -> Option<IntRegister> {
+    inst.rd().filter(|&rd| rd != IntRegister::Zero)
+}
+
+fn parse_hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value.strip_prefix("0x")?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::tests::simulator;
+
+    #[test]
+    fn parse_and_compare_spike_commits() {
+        let commit = Commit::parse_spike("core   0: 3 0x00001000 (0x00500513) x10 0x00000005");
+        assert_eq!(
+            commit,
+            Some(Commit {
+                pc: 0x1000,
+                raw: 0x00500513,
+                rd: Some((IntRegister::A0, 5)),
+                store: None,
+            })
+        );
+
+        let store = Commit::parse_spike("core   0: 3 0x00001004 (0x00a02223) mem 0x00000004 0x05");
+        assert_eq!(store.unwrap().store, Some((4, 5)));
+        let disasm = Commit::parse_spike("core   0: 0x00001008 (0x00b54533) xor     a0, a0, a1");
+        assert_eq!(disasm.unwrap().rd, None);
+        assert_eq!(
+            Commit::parse_spike("core   0: exception trap_illegal"),
+            None
+        );
+
+        // li a0, 5; sb a0, 4(zero)
+        let mut sim = simulator("commit", &[0x00500513, 0x00A00223, 0x00000073]);
+        let first = sim.step().unwrap();
+        assert_eq!(
+            Commit::from_retired(&first, sim.processor()),
+            commit.unwrap()
+        );
+        let second = sim.step().unwrap();
+        let second = Commit::from_retired(&second, sim.processor());
+        assert_eq!(second.store, Some((4, 5)));
+        assert!(second.matches(&Commit::parse_spike("core   0: 0x00001004 (0x00a00223)").unwrap()));
+
+        let logged =
+            "[PC=00001000] [00500513] [x10=00000005] [x00=00000000] [x00=00000000] li a0, 5";
+        assert_eq!(Commit::parse(logged), commit);
+        assert_eq!(
+            second.to_string(),
+            "0x00001004 (0x00a00223) mem 0x00000004 0x5"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Reading traces back into records, for tools that analyze them after the
+//! run. Each reader goes through a trace line by line, skipping the lines
+//! that do not describe an instruction, such as program output or load
+//! information.
+
+use crate::instruction::Instruction;
+use crate::register::IntRegister;
+use crate::trace::{destination, Commit};
+use std::convert::TryFrom;
+use std::io::{self, BufRead};
+
+/// Line of the instruction log written by [`Simulator`] (e.g.
+/// `yars run --log`).
+///
+/// [`Simulator`]: crate::simulator::Simulator
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogEntry {
+    pub pc: u32,
+    pub raw: u32,
+    /// Registers named by the `rd`, `rs1` and `rs2` bits of the instruction
+    /// word, whether or not the instruction uses them, and their values:
+    /// after the instruction for `rd`, before it for the others.
+    pub rd: (IntRegister, u32),
+    pub rs1: (IntRegister, u32),
+    pub rs2: (IntRegister, u32),
+    pub disassembly: String,
+}
+
+impl LogEntry {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(6, ']').map(str::trim_start);
+        let pc = fields.next()?.strip_prefix("[PC=")?;
+        let pc = u32::from_str_radix(pc, 16).ok()?;
+        let raw = u32::from_str_radix(fields.next()?.strip_prefix('[')?, 16).ok()?;
+        let mut register = || {
+            let (reg, value) = fields.next()?.strip_prefix("[x")?.split_once('=')?;
+            let reg = IntRegister::try_from(reg.parse::<u8>().ok()?).ok()?;
+            Some((reg, u32::from_str_radix(value, 16).ok()?))
+        };
+        let (rd, rs1, rs2) = (register()?, register()?, register()?);
+
+        Some(Self {
+            pc,
+            raw,
+            rd,
+            rs1,
+            rs2,
+            disassembly: fields.next().unwrap_or_default().trim_end().to_string(),
+        })
+    }
+
+    /// The logged instruction, if it decodes.
+    pub fn instruction(&self) -> Option<Instruction> {
+        Instruction::try_from(self.raw).ok()
+    }
+
+    /// The effects of the instruction the log records, which leave out
+    /// stored values.
+    pub fn commit(&self) -> Option<Commit> {
+        let inst = self.instruction()?;
+        Some(Commit {
+            pc: self.pc,
+            raw: self.raw,
+            rd: destination(&inst).map(|rd| (rd, self.rd.1)),
+            store: None,
+        })
+    }
+}
+
+/// Iterator over the records of a trace, also yielding the errors met
+/// reading it.
+pub struct Records<R, T> {
+    lines: io::Lines<R>,
+    parse: fn(&str) -> Option<T>,
+}
+
+impl<R: BufRead, T> Iterator for Records<R, T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in &mut self.lines {
+            match line {
+                Ok(line) => match (self.parse)(&line) {
+                    Some(record) => return Some(Ok(record)),
+                    None => continue,
+                },
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        None
+    }
+}
+
+/// Reads the entries of a yars instruction log.
+pub fn log_entries<R: BufRead>(reader: R) -> Records<R, LogEntry> {
+    Records {
+        lines: reader.lines(),
+        parse: LogEntry::parse,
+    }
+}
+
+/// Reads the commits of a Spike log.
+pub fn spike_commits<R: BufRead>(reader: R) -> Records<R, Commit> {
+    Records {
+        lines: reader.lines(),
+        parse: Commit::parse_spike,
+    }
+}
+
+/// Reads the commits of a trace in any supported format.
+pub fn commits<R: BufRead>(reader: R) -> Records<R, Commit> {
+    Records {
+        lines: reader.lines(),
+        parse: Commit::parse,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_traces() {
+        let log = "\
+            Loading program...\n\
+            [PC=00001000] [00500513] [x10=00000005] [x00=00000000] [x05=00000000] li a0, 5\n\
+            hello\n\
+            [PC=00001004] [00A02223] [x04=00000000] [x00=00000000] [x10=00000005] sw a0, 4(zero)\n";
+        let entries: Vec<_> = log_entries(log.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].rd, (IntRegister::A0, 5));
+        assert_eq!(entries[0].disassembly, "li a0, 5");
+        assert_eq!(entries[1].rs2, (IntRegister::A0, 5));
+        assert_eq!(
+            entries[1].instruction(),
+            Some(Instruction::SW {
+                rs1: IntRegister::Zero,
+                rs2: IntRegister::A0,
+                imm: 4
+            })
+        );
+        // Stores have no destination, even though their rd bits are logged
+        assert_eq!(entries[1].commit().unwrap().rd, None);
+
+        let spike_log = "\
+            core   0: 0x00000000 (0x00000297) auipc   t0, 0x0\n\
+            core   0: 3 0x00001000 (0x00500513) x10 0x00000005\n\
+            core   0: exception trap_illegal_instruction\n";
+        let mixed = format!("{}{}", log, spike_log);
+        let spike: Vec<_> = spike_commits(spike_log.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(spike.len(), 2);
+        assert_eq!(spike[1], entries[0].commit().unwrap());
+        assert_eq!(commits(mixed.as_bytes()).count(), 4);
+        assert_eq!(log_entries(spike_log.as_bytes()).count(), 0);
+    }
+}
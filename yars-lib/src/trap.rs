@@ -0,0 +1,179 @@
+//! Trap frames describing why a program stopped, laid out after the machine
+//! trap CSRs (`mcause`, `mtval`, `mepc`) along with the registers at the
+//! time, as a `ucontext_t` would hold them. Every trap stops the simulation
+//! in the hart's machine mode, so this is what a trap handler would have
+//! been given.
+
+use crate::instruction::{Category, Instruction};
+use crate::processor::{Processor, ProcessorError};
+use crate::register::IntRegisterSet;
+use crate::symbol::SymbolTable;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Exception causes, numbered as in `mcause`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TrapCause {
+    InstructionAddressMisaligned = 0,
+    InstructionAccessFault = 1,
+    IllegalInstruction = 2,
+    Breakpoint = 3,
+    LoadAddressMisaligned = 4,
+    LoadAccessFault = 5,
+    StoreAddressMisaligned = 6,
+    StoreAccessFault = 7,
+    EcallFromMMode = 11,
+}
+
+impl TrapCause {
+    /// Value of `mcause` for this cause.
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+impl fmt::Display for TrapCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TrapCause::*;
+        match self {
+            InstructionAddressMisaligned => write!(f, "instruction address misaligned"),
+            InstructionAccessFault => write!(f, "instruction access fault"),
+            IllegalInstruction => write!(f, "illegal instruction"),
+            Breakpoint => write!(f, "breakpoint"),
+            LoadAddressMisaligned => write!(f, "load address misaligned"),
+            LoadAccessFault => write!(f, "load access fault"),
+            StoreAddressMisaligned => write!(f, "store address misaligned"),
+            StoreAccessFault => write!(f, "store access fault"),
+            EcallFromMMode => write!(f, "environment call from M-mode"),
+        }
+    }
+}
+
+/// Privilege level a trap was taken from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Privilege {
+    User,
+    Supervisor,
+    Machine,
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Privilege::User => write!(f, "U"),
+            Privilege::Supervisor => write!(f, "S"),
+            Privilege::Machine => write!(f, "M"),
+        }
+    }
+}
+
+/// A trap with the state of the hart when it was taken.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrapInfo {
+    pub cause: TrapCause,
+    /// Faulting address, or the instruction word for illegal instructions,
+    /// as `mtval` holds them.
+    pub tval: u32,
+    /// Address of the instruction that trapped.
+    pub epc: u32,
+    pub privilege: Privilege,
+    pub registers: IntRegisterSet,
+    /// The error the trap was reported as.
+    pub error: ProcessorError,
+}
+
+impl TrapInfo {
+    /// Builds the trap frame for `error`, raised by the last instruction
+    /// `processor` executed. Aborts requested by hooks are not traps and
+    /// give `None`.
+    pub fn new(error: ProcessorError, processor: &Processor) -> Option<Self> {
+        use ProcessorError::*;
+        let epc = error.pc().unwrap_or_else(|| processor.pc());
+        let (cause, tval) = match error {
+            Abort => return None,
+            Ebreak => (TrapCause::Breakpoint, epc),
+            Ecall => (TrapCause::EcallFromMMode, 0),
+            IllegalFetch { pc } => (TrapCause::InstructionAccessFault, pc),
+            MisalignedFetch { pc } => (TrapCause::InstructionAddressMisaligned, pc),
+            InvalidOpcode { raw, .. } => (TrapCause::IllegalInstruction, raw),
+            MisalignedLoad { address, .. } => (TrapCause::LoadAddressMisaligned, address),
+            MisalignedStore { address, .. } => (TrapCause::StoreAddressMisaligned, address),
+            CodeWrite { address, .. } => (TrapCause::StoreAccessFault, address),
+            IllegalAccess { pc, address } => {
+                let raw = processor.memory().read_word(pc);
+                match Instruction::try_from(raw).map(|inst| inst.category()) {
+                    Ok(Category::Store) => (TrapCause::StoreAccessFault, address),
+                    _ => (TrapCause::LoadAccessFault, address),
+                }
+            }
+        };
+        Some(Self {
+            cause,
+            tval,
+            epc,
+            privilege: Privilege::Machine,
+            registers: *processor.registers(),
+            error,
+        })
+    }
+
+    /// Renders the trap and the registers, naming the function `epc` is in,
+    /// as a crash report would.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let function = match symbols.lookup(self.epc) {
+            Some(sym) => format!(" <{}>", sym),
+            None => String::new(),
+        };
+        format!(
+            "{} in {}-mode\nmcause={:#010X} mtval={:#010X} mepc={:#010X}{}\n{}",
+            self.cause,
+            self.privilege,
+            self.cause.code(),
+            self.tval,
+            self.epc,
+            function,
+            self.registers
+        )
+    }
+}
+
+impl fmt::Display for TrapInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (mcause {}, mtval {:#010x}) at {:#010x} in {}-mode",
+            self.cause,
+            self.cause.code(),
+            self.tval,
+            self.epc,
+            self.privilege
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::register::IntRegister;
+    use crate::simulator::tests::{simulator, BASE};
+    use crate::simulator::ExitEvent;
+
+    #[test]
+    fn report_trap_frame() {
+        // lui t0, 0x80000; addi a0, zero, 5; sw a0, 4(t0)
+        let mut sim = simulator("trap", &[0x800002B7, 0x00500513, 0x00A2A223]);
+        assert!(matches!(sim.run(None), ExitEvent::Fault { .. }));
+
+        let trap = sim.trap().unwrap();
+        assert_eq!(trap.cause, TrapCause::StoreAccessFault);
+        assert_eq!(trap.cause.code(), 7);
+        assert_eq!(trap.tval, 0x8000_0004);
+        assert_eq!(trap.epc, BASE + 8);
+        assert_eq!(trap.privilege, Privilege::Machine);
+        assert_eq!(trap.registers.read(IntRegister::A0), 5);
+        assert_eq!(
+            trap.to_string(),
+            "store access fault (mcause 7, mtval 0x80000004) at 0x00001008 in M-mode"
+        );
+    }
+}
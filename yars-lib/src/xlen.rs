@@ -0,0 +1,183 @@
+//! Native register width of a hart.
+//!
+//! The processor and register file are generic over [`Xlen`], which is
+//! implemented for `u32` (RV32) and `u64` (RV64). The trait methods follow
+//! the semantics of the base and M-extension instructions they are named
+//! after, so the executor does not need width-specific code paths.
+
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitXor, Not};
+
+pub trait Xlen:
+    Copy
+    + Default
+    + Eq
+    + Ord
+    + fmt::Debug
+    + fmt::LowerHex
+    + fmt::UpperHex
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + 'static
+{
+    const BITS: u32;
+
+    /// Zero-extends a 32-bit value.
+    fn from_u32(val: u32) -> Self;
+    /// Sign-extends a 32-bit value.
+    fn from_i32(val: i32) -> Self;
+    fn to_u64(self) -> u64;
+    /// Truncates the value to its low 32 bits.
+    fn as_u32(self) -> u32;
+
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    fn sll(self, shamt: u32) -> Self;
+    fn srl(self, shamt: u32) -> Self;
+    fn sra(self, shamt: u32) -> Self;
+    fn slt(self, rhs: Self) -> bool;
+
+    fn mul(self, rhs: Self) -> Self;
+    fn mulh(self, rhs: Self) -> Self;
+    fn mulhsu(self, rhs: Self) -> Self;
+    fn mulhu(self, rhs: Self) -> Self;
+    fn div(self, rhs: Self) -> Self;
+    fn divu(self, rhs: Self) -> Self;
+    fn rem(self, rhs: Self) -> Self;
+    fn remu(self, rhs: Self) -> Self;
+}
+
+macro_rules! impl_xlen {
+    ($t:ty, $s:ty, $wide:ty, $swide:ty) => {
+        impl Xlen for $t {
+            const BITS: u32 = <$t>::BITS;
+
+            fn from_u32(val: u32) -> Self {
+                val as $t
+            }
+
+            fn from_i32(val: i32) -> Self {
+                val as $s as $t
+            }
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn as_u32(self) -> u32 {
+                self as u32
+            }
+
+            fn wrapping_add(self, rhs: Self) -> Self {
+                <$t>::wrapping_add(self, rhs)
+            }
+
+            fn wrapping_sub(self, rhs: Self) -> Self {
+                <$t>::wrapping_sub(self, rhs)
+            }
+
+            fn sll(self, shamt: u32) -> Self {
+                self.wrapping_shl(shamt)
+            }
+
+            fn srl(self, shamt: u32) -> Self {
+                self.wrapping_shr(shamt)
+            }
+
+            fn sra(self, shamt: u32) -> Self {
+                (self as $s).wrapping_shr(shamt) as $t
+            }
+
+            fn slt(self, rhs: Self) -> bool {
+                (self as $s) < (rhs as $s)
+            }
+
+            fn mul(self, rhs: Self) -> Self {
+                self.wrapping_mul(rhs)
+            }
+
+            fn mulh(self, rhs: Self) -> Self {
+                let val = (self as $s as $swide).wrapping_mul(rhs as $s as $swide);
+                (val >> Self::BITS) as $t
+            }
+
+            fn mulhsu(self, rhs: Self) -> Self {
+                let val = (self as $s as $swide).wrapping_mul(rhs as $swide);
+                (val >> Self::BITS) as $t
+            }
+
+            fn mulhu(self, rhs: Self) -> Self {
+                let val = (self as $wide).wrapping_mul(rhs as $wide);
+                (val >> Self::BITS) as $t
+            }
+
+            fn div(self, rhs: Self) -> Self {
+                match rhs {
+                    0 => <$t>::MAX,
+                    _ => (self as $s).wrapping_div(rhs as $s) as $t,
+                }
+            }
+
+            fn divu(self, rhs: Self) -> Self {
+                match rhs {
+                    0 => <$t>::MAX,
+                    _ => self / rhs,
+                }
+            }
+
+            fn rem(self, rhs: Self) -> Self {
+                match rhs {
+                    0 => self,
+                    _ => (self as $s).wrapping_rem(rhs as $s) as $t,
+                }
+            }
+
+            fn remu(self, rhs: Self) -> Self {
+                match rhs {
+                    0 => self,
+                    _ => self % rhs,
+                }
+            }
+        }
+    };
+}
+
+impl_xlen!(u32, i32, u64, i64);
+impl_xlen!(u64, i64, u128, i128);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn semantics<X: Xlen>() -> Vec<X> {
+        let minus_one = X::from_i32(-1);
+        let min = X::from_u32(1).sll(X::BITS - 1);
+        vec![
+            minus_one.sra(X::BITS + 1),
+            minus_one.srl(X::BITS - 1),
+            minus_one.mulh(minus_one),
+            minus_one.mulhu(X::from_u32(2)),
+            minus_one.mulhsu(X::from_u32(2)),
+            min.div(minus_one),
+            min.rem(minus_one),
+            X::from_u32(7).div(X::default()),
+            X::from_u32(7).rem(X::default()),
+        ]
+    }
+
+    #[test]
+    fn rv32_semantics() {
+        let min = 0x8000_0000;
+        let expected = vec![u32::MAX, 1, 0, 1, u32::MAX, min, 0, u32::MAX, 7];
+        assert_eq!(semantics::<u32>(), expected);
+    }
+
+    #[test]
+    fn rv64_semantics() {
+        let min = 0x8000_0000_0000_0000;
+        let expected = vec![u64::MAX, 1, 0, 1, u64::MAX, min, 0, u64::MAX, 7];
+        assert_eq!(semantics::<u64>(), expected);
+    }
+}
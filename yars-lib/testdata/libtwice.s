@@ -0,0 +1,30 @@
+# Shared object needed by the PIE in pie.s, built with:
+#   llvm-mc -triple=riscv32 -mattr=+m -filetype=obj libtwice.s -o libtwice.o
+#   ld.lld -m elf32lriscv -shared -soname libtwice.so libtwice.o -o libtwice.so
+
+    .option pic
+    .text
+    .globl twice
+    .type twice, @function
+twice:
+    la      t0, scale               # GOT entry, R_RISCV_32 or RELATIVE
+    lw      t0, 0(t0)
+    mul     a0, a0, t0
+    lla     t1, calls
+    lw      t2, 0(t1)
+    addi    t2, t2, 1
+    sw      t2, 0(t1)
+    ret
+    .size twice, .-twice
+
+    .data
+    .globl scale
+    .type scale, @object
+scale:
+    .word   2
+    .size scale, 4
+calls:
+    .word   0
+table:
+    .word   twice                   # R_RISCV_32 against a global
+    .word   calls                   # R_RISCV_RELATIVE
@@ -0,0 +1,39 @@
+# Relocatable object loaded by the ET_REL loader test, built with:
+#   llvm-mc -triple=riscv32 -mattr=+m,+relax -filetype=obj object.s -o object.o
+# It exits with a0 = values[0] + values[1] + *pointer + twice(1) = 64.
+
+    .text
+    .globl _start
+_start:
+    la      a0, values              # R_RISCV_PCREL_HI20, R_RISCV_PCREL_LO12_I
+    lw      t0, 0(a0)
+    lui     t1, %hi(values)         # R_RISCV_HI20
+    lw      t1, %lo(values+4)(t1)   # R_RISCV_LO12_I
+    add     s0, t0, t1
+    lui     t2, %hi(pointer)
+    lw      t2, %lo(pointer)(t2)
+    lw      t2, 0(t2)
+    add     s0, s0, t2
+    li      a0, 1
+    call    twice                   # R_RISCV_CALL_PLT
+    add     s0, s0, a0
+    lui     t3, %hi(result)
+    sw      s0, %lo(result)(t3)     # R_RISCV_LO12_S
+    bnez    s0, done                # R_RISCV_BRANCH
+    j       _start                  # R_RISCV_JAL
+done:
+    mv      a0, s0
+    ecall
+
+    .globl twice
+twice:
+    slli    a0, a0, 1
+    ret
+
+    .data
+values:
+    .word   20, 22
+pointer:
+    .word   values                  # R_RISCV_32
+result:
+    .word   0
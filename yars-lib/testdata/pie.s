@@ -0,0 +1,26 @@
+# PIE linked against libtwice.so, built with:
+#   llvm-mc -triple=riscv32 -mattr=+m -filetype=obj pie.s -o pie.o
+#   ld.lld -m elf32lriscv -pie -e _start pie.o libtwice.so -o pie
+# It exits with a0 = twice(21) + scale + *pointer = 42 + 2 + 20 = 64.
+
+    .option pic
+    .text
+    .globl _start
+_start:
+    li      a0, 21
+    call    twice@plt               # R_RISCV_JUMP_SLOT
+    mv      s0, a0
+    la      t0, scale               # GOT entry for the library's data
+    lw      t0, 0(t0)
+    add     s0, s0, t0
+    lla     t1, pointer
+    lw      t1, 0(t1)               # R_RISCV_RELATIVE
+    lw      t1, 0(t1)
+    add     a0, s0, t1
+    ecall
+
+    .data
+local:
+    .word   20
+pointer:
+    .word   local
@@ -0,0 +1,80 @@
+use std::convert::TryFrom;
+use wasm_bindgen::prelude::*;
+
+use yars_lib::processor::ProcessorError;
+use yars_lib::register::IntRegister;
+use yars_lib::simulator::ExitEvent;
+
+#[wasm_bindgen]
+pub struct Simulator {
+    inner: yars_lib::simulator::Simulator<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl Simulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(program: &[u8], memsize: u32, trace: bool) -> Result<Simulator, JsValue> {
+        let logger = if trace { Some(Vec::new()) } else { None };
+        let inner = yars_lib::simulator::Simulator::from_bytes(program, memsize, None, logger)
+            .map_err(|err| JsValue::from_str(&format!("{:?}", err)))?;
+        Ok(Self { inner })
+    }
+
+    /// Executes one instruction. Returns `false` once the program finished.
+    pub fn step(&mut self) -> Result<bool, JsValue> {
+        match self.inner.step() {
+            Ok(_) => Ok(true),
+            Err(ProcessorError::Ecall) | Err(ProcessorError::Ebreak) => Ok(false),
+            Err(err) => Err(JsValue::from_str(&format!("{:?}", err))),
+        }
+    }
+
+    /// Executes up to `steps` instructions, so the page stays responsive.
+    pub fn run(&mut self, steps: u32) -> Result<bool, JsValue> {
+        match self.inner.run(Some(steps as usize)) {
+            ExitEvent::CycleLimit | ExitEvent::Timeout | ExitEvent::Paused => Ok(true),
+            ExitEvent::EcallExit | ExitEvent::Ebreak | ExitEvent::HostExit(_) => Ok(false),
+            ExitEvent::Hang { pc } => Err(JsValue::from_str(&format!("hang at {:#010x}", pc))),
+            ExitEvent::Fault { err, pc } => {
+                Err(JsValue::from_str(&format!("{:?} at {:#010x}", err, pc)))
+            }
+        }
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.inner.pc()
+    }
+
+    pub fn cycles(&self) -> usize {
+        self.inner.cycles()
+    }
+
+    pub fn register(&self, index: u8) -> Result<u32, JsValue> {
+        let reg = IntRegister::try_from(index)
+            .map_err(|_| JsValue::from_str("invalid register index"))?;
+        Ok(self.inner.read_reg(reg))
+    }
+
+    pub fn registers(&self) -> Vec<u32> {
+        let registers = self.inner.processor().registers();
+        (0..32)
+            .map(|reg| registers.read(IntRegister::try_from(reg).unwrap()))
+            .collect()
+    }
+
+    pub fn read_memory(&self, address: u32, length: u32) -> Result<Vec<u8>, JsValue> {
+        let mut buffer = vec![0u8; length as usize];
+        self.inner
+            .read_mem(address, &mut buffer)
+            .map_err(|_| JsValue::from_str("memory range out of bounds"))?;
+        Ok(buffer)
+    }
+
+    /// Returns and clears the instruction log collected since the last call.
+    pub fn take_trace(&mut self) -> String {
+        match self.inner.logger_mut() {
+            Some(log) => String::from_utf8_lossy(&std::mem::take(log)).into_owned(),
+            None => String::new(),
+        }
+    }
+}